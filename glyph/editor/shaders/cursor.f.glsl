@@ -4,6 +4,7 @@
 uniform bool is_blinking;
 uniform float time;
 uniform float last_stroke;
+uniform vec4 color;
 
 void main() {
   if (is_blinking) {
@@ -11,8 +12,8 @@ void main() {
     float threshold = float(t < BLINK_THRESHOLD);
     float blink = mod(floor(t / PERIOD), float(2));
     // gl_FragColor = vec4(1.0) * min(threshold + blink, 1.0);
-    gl_FragColor = vec4(1.0, 1.0, 1.0, 1.0);
+    gl_FragColor = color;
   } else {
-    gl_FragColor = vec4(1.0, 1.0, 1.0, 1.0);
+    gl_FragColor = color;
   }
 } 
@@ -0,0 +1,7 @@
+attribute vec3 aPos;
+attribute float y_translate;
+
+void main() {
+  // Spans the whole pane, so it only scrolls vertically
+  gl_Position = vec4(aPos.x, aPos.y + (y_translate * -1.0), aPos.z, 1.0);
+}
@@ -1,3 +1,5 @@
+uniform vec4 color;
+
 void main() {
-    gl_FragColor = vec4(0.05882353, 0.7490196, 1.0, 0.2);
-} 
+    gl_FragColor = color;
+} 
\ No newline at end of file
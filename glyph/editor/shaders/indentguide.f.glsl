@@ -0,0 +1,5 @@
+uniform vec4 color;
+
+void main() {
+    gl_FragColor = color;
+}
@@ -0,0 +1,9 @@
+attribute vec3 aPos;
+attribute vec4 vertex_color;
+
+varying vec4 v_color;
+
+void main() {
+  gl_Position = vec4(aPos.xyz, 1.0);
+  v_color = vertex_color;
+}
@@ -1,6 +1,11 @@
-use std::ptr::null;
+use std::{
+    path::{Path, PathBuf},
+    ptr::null,
+};
 
 use gl::types::{GLint, GLuint, GLvoid};
+use lru::LruCache;
+use unicode_width::UnicodeWidthChar;
 
 use crate::constants::MAX_WIDTH;
 
@@ -12,8 +17,11 @@ pub struct Glyph {
     pub bitmap_h: f32,
     pub bitmap_l: f32,
     pub bitmap_t: f32,
-    pub tx: f32, // x offset of glyph in texture coordinates
-    pub ty: f32, // y offset of glyph in texture coordinates
+    pub tx: f32,     // x offset of glyph in texture coordinates
+    pub ty: f32,     // y offset of glyph in texture coordinates
+    pub tw: f32,     // width of glyph in texture coordinates
+    pub th: f32,     // height of glyph in texture coordinates
+    pub page: usize, // the texture page the glyph is on
 }
 
 impl Default for Glyph {
@@ -27,33 +35,166 @@ impl Default for Glyph {
             bitmap_t: 0.0,
             tx: 0.0,
             ty: 0.0,
+            tw: 0.0,
+            th: 0.0,
+            page: 0,
         }
     }
 }
 
+/// Which face of the font text is drawn with. Fonts that don't have a file
+/// for a style next to the regular one get it made up, slanted for italic
+/// and thickened for bold
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FontStyle {
+    #[default]
+    Normal,
+    Bold,
+    Italic,
+    BoldItalic,
+}
+
+/// Every style, in the order of their discriminants
+pub const FONT_STYLES: [FontStyle; 4] = [
+    FontStyle::Normal,
+    FontStyle::Bold,
+    FontStyle::Italic,
+    FontStyle::BoldItalic,
+];
+
+impl FontStyle {
+    #[inline]
+    pub fn is_bold(self) -> bool {
+        matches!(self, FontStyle::Bold | FontStyle::BoldItalic)
+    }
+
+    #[inline]
+    pub fn is_italic(self) -> bool {
+        matches!(self, FontStyle::Italic | FontStyle::BoldItalic)
+    }
+
+    /// What the file of this style ends with, ex. `FiraCode-Bold.ttf`
+    fn suffix(self) -> &'static str {
+        match self {
+            FontStyle::Normal => "Regular",
+            FontStyle::Bold => "Bold",
+            FontStyle::Italic => "Italic",
+            FontStyle::BoldItalic => "BoldItalic",
+        }
+    }
+}
+
+/// The file of `style` next to the regular font at `path`, ex.
+/// `FiraCode-Italic.ttf` for `FiraCode.ttf` or `FiraCode-Regular.ttf`
+fn style_path(path: &Path, style: FontStyle) -> Option<PathBuf> {
+    if style == FontStyle::Normal {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    let stem = stem.strip_suffix("-Regular").unwrap_or(stem);
+    let mut name = format!("{}-{}", stem, style.suffix());
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        name = format!("{}.{}", name, ext);
+    }
+    Some(path.with_file_name(name)).filter(|path| path.is_file())
+}
+
+/// The font's faces, text is drawn from the one for its highlight's style
+pub struct AtlasSet {
+    pub normal: Atlas,
+    pub bold: Atlas,
+    pub italic: Atlas,
+    pub bold_italic: Atlas,
+}
+
+impl AtlasSet {
+    pub fn new(font_path: &Path, height: u32, uniform_tex: GLint) -> Result<Self, String> {
+        Ok(Self {
+            normal: Atlas::new(font_path, height, uniform_tex, FontStyle::Normal)?,
+            bold: Atlas::new(font_path, height, uniform_tex, FontStyle::Bold)?,
+            italic: Atlas::new(font_path, height, uniform_tex, FontStyle::Italic)?,
+            bold_italic: Atlas::new(font_path, height, uniform_tex, FontStyle::BoldItalic)?,
+        })
+    }
+
+    #[inline]
+    pub fn get(&self, style: FontStyle) -> &Atlas {
+        match style {
+            FontStyle::Normal => &self.normal,
+            FontStyle::Bold => &self.bold,
+            FontStyle::Italic => &self.italic,
+            FontStyle::BoldItalic => &self.bold_italic,
+        }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, style: FontStyle) -> &mut Atlas {
+        match style {
+            FontStyle::Normal => &mut self.normal,
+            FontStyle::Bold => &mut self.bold,
+            FontStyle::Italic => &mut self.italic,
+            FontStyle::BoldItalic => &mut self.bold_italic,
+        }
+    }
+
+    /// Start a pass over the text in every style, see `GlyphCache::begin_pass`
+    pub fn begin_pass(&mut self) {
+        self.normal.begin_pass();
+        self.bold.begin_pass();
+        self.italic.begin_pass();
+        self.bold_italic.begin_pass();
+    }
+
+    /// Goes up when any of the atlases' revisions do
+    #[inline]
+    pub fn revision(&self) -> u64 {
+        self.normal.revision()
+            + self.bold.revision()
+            + self.italic.revision()
+            + self.bold_italic.revision()
+    }
+}
+
 pub struct Atlas {
-    pub tex: GLuint,
-    pub w: u32,
-    pub h: u32,
     pub max_h: f32,
     pub max_w: f32,
-    pub glyphs: Vec<Glyph>,
+    glyphs: GlyphCache,
 }
 
 const CHAR_END: usize = 128;
 
 impl Atlas {
-    pub fn new(font_path: &str, height: u32, uniform_tex: GLint) -> Result<Self, String> {
-        let ft_lib = freetype::Library::init().unwrap();
-        let face = ft_lib.new_face(font_path, 0).unwrap();
-        let mut tex: GLuint = 0;
+    /// Rasterize the visible ASCII characters in `style`, anything else is
+    /// added by `load` when it's first drawn
+    pub fn new(
+        font_path: &Path,
+        height: u32,
+        uniform_tex: GLint,
+        style: FontStyle,
+    ) -> Result<Self, String> {
+        let (font_path, synthetic) = match style_path(font_path, style) {
+            Some(path) => (path, FontStyle::Normal),
+            None => (font_path.to_path_buf(), style),
+        };
+        let ft_lib = freetype::Library::init().map_err(|e| e.to_string())?;
+        let face = ft_lib
+            .new_face(&font_path, 0)
+            .map_err(|e| format!("{}: {}", font_path.display(), e))?;
 
         face.set_pixel_sizes(0, height).map_err(|e| e.to_string())?;
+        if synthetic.is_italic() {
+            // Lean the glyphs right by about 12 degrees, in 16.16 fixed point
+            let mut slant = freetype::Matrix {
+                xx: 0x10000,
+                xy: 0x3800,
+                yx: 0,
+                yy: 0x10000,
+            };
+            face.set_transform(&mut slant, &mut freetype::Vector { x: 0, y: 0 });
+        }
 
         let g = face.glyph();
 
-        let mut glyphs: Vec<Glyph> = vec![Default::default(); CHAR_END];
-
         let mut roww: u32 = 0;
         let mut rowh: u32 = 0;
         let mut w: u32 = 0;
@@ -84,11 +225,292 @@ impl Atlas {
         h += rowh;
 
         unsafe {
-            // Create a texture that will be used to hold all ASCII glyphs
             gl::ActiveTexture(gl::TEXTURE0);
+            gl::Uniform1i(uniform_tex, 0);
+        }
+
+        let mut atlas = Self {
+            max_h,
+            max_w: max_w as f32,
+            glyphs: GlyphCache::new(face, synthetic, w, h, max_w as f32),
+        };
+
+        // Paste all glyph bitmaps into the texture, remembering the offset
+        atlas.load((32..CHAR_END as u8).map(char::from));
+
+        Ok(atlas)
+    }
+
+    /// The glyph of `ch` if it's been loaded. Control characters never are,
+    /// they have no pixels and don't move the text
+    #[inline]
+    pub fn glyph(&self, ch: char) -> Option<&Glyph> {
+        self.glyphs.get(ch as u32)
+    }
+
+    /// Rasterize every char of `text` that isn't in the atlas yet, and mark
+    /// the ones that are as used in this pass
+    pub fn load(&mut self, text: impl IntoIterator<Item = char>) {
+        for ch in text {
+            if !ch.is_control() {
+                self.glyphs.get_or_load(ch as u32);
+            }
+        }
+    }
+
+    /// The texture `glyph` is drawn from
+    #[inline]
+    pub fn tex(&self, glyph: &Glyph) -> GLuint {
+        self.glyphs.pages[glyph.page].tex
+    }
+
+    #[inline]
+    pub fn begin_pass(&mut self) {
+        self.glyphs.begin_pass();
+    }
+
+    /// Changes every time glyphs move in the texture, text queued with an
+    /// older revision has to be queued again
+    #[inline]
+    pub fn revision(&self) -> u64 {
+        self.glyphs.revision
+    }
+}
+
+/// How many pages a face gets before the one drawn from longest ago is
+/// cleared for new glyphs
+const MAX_PAGES: usize = 8;
+/// The tallest a page grows, in pixels
+const MAX_PAGE_HEIGHT: u32 = 2048;
+
+/// The glyphs of a face rasterized so far, on as many texture pages as they
+/// take. A page is only cleared for new glyphs when none of the ones on it
+/// were drawn in the current pass, so text never loses glyphs it's using
+pub struct GlyphCache {
+    // Ordered by when they were last drawn
+    glyphs: LruCache<u32, Glyph>,
+    pages: Vec<Page>,
+    // The page new glyphs go on, and how tall new pages start out
+    current: usize,
+    page_h: u32,
+    face: freetype::Face,
+    // What the face doesn't have a file for and is faked, see `FontStyle`
+    synthetic: FontStyle,
+    // How wide a column is, chars the font can't render still take theirs
+    max_w: f32,
+    pass: u64,
+    // Goes up when a page grows or is cleared, which moves glyphs' texture
+    // coordinates
+    revision: u64,
+}
+
+impl GlyphCache {
+    fn new(face: freetype::Face, synthetic: FontStyle, w: u32, h: u32, max_w: f32) -> Self {
+        Self {
+            glyphs: LruCache::unbounded(),
+            pages: vec![Page::new(w, h)],
+            current: 0,
+            page_h: h,
+            face,
+            synthetic,
+            max_w,
+            pass: 0,
+            revision: 0,
+        }
+    }
+
+    /// The glyph of `codepoint` if it's been loaded
+    #[inline]
+    pub fn get(&self, codepoint: u32) -> Option<&Glyph> {
+        self.glyphs.peek(&codepoint)
+    }
+
+    /// The glyph of `codepoint`, rasterized the first time it's asked for.
+    /// Codepoints the font can't render get an empty glyph as wide as the
+    /// columns they take
+    pub fn get_or_load(&mut self, codepoint: u32) -> &Glyph {
+        let page = match self.glyphs.get(&codepoint) {
+            Some(glyph) => glyph.page,
+            None => self.load(codepoint),
+        };
+        self.pages[page].used = self.pass;
+        self.glyphs
+            .peek(&codepoint)
+            .expect("the glyph was just loaded")
+    }
+
+    /// Start a pass over the text, every glyph it loads is kept until the
+    /// next one starts
+    #[inline]
+    pub fn begin_pass(&mut self) {
+        self.pass += 1;
+    }
+
+    /// Rasterize `codepoint`, or put a placeholder in its place when it
+    /// can't be. Returns the page it's on
+    fn load(&mut self, codepoint: u32) -> usize {
+        match self.rasterize(codepoint) {
+            Ok(page) => page,
+            Err(err) => {
+                let ch = char::from_u32(codepoint);
+                eprintln!(
+                    "Can't draw {:?}: {}",
+                    ch.unwrap_or(char::REPLACEMENT_CHARACTER),
+                    err
+                );
+                let width = ch.and_then(|ch| ch.width()).unwrap_or(1);
+                self.glyphs.put(
+                    codepoint,
+                    Glyph {
+                        advance_x: self.max_w * width as f32,
+                        ..Default::default()
+                    },
+                );
+                0
+            }
+        }
+    }
+
+    /// Render `codepoint` into the next free spot of a page, returning the
+    /// page
+    fn rasterize(&mut self, codepoint: u32) -> Result<usize, String> {
+        if self.synthetic.is_bold() {
+            // The outline has to be thickened before it's rendered
+            self.face
+                .load_char(codepoint as usize, freetype::face::LoadFlag::DEFAULT)
+                .map_err(|e| e.to_string())?;
+            unsafe { freetype::ffi::FT_GlyphSlot_Embolden(self.face.raw_mut().glyph) };
+            self.face
+                .glyph()
+                .render_glyph(freetype::RenderMode::Normal)
+                .map_err(|e| e.to_string())?;
+        } else {
+            self.face
+                .load_char(codepoint as usize, freetype::face::LoadFlag::RENDER)
+                .map_err(|e| e.to_string())?;
+        }
+        let g = self.face.glyph();
+        let bitmap = g.bitmap();
+        let (width, rows) = (bitmap.width() as u32, bitmap.rows() as u32);
+        // Rows of the bitmap can be padded, this is without the padding
+        let pitch = bitmap.pitch().unsigned_abs() as usize;
+        let pixels: Vec<u8> = (0..rows as usize)
+            .flat_map(|row| &bitmap.buffer()[row * pitch..row * pitch + width as usize])
+            .copied()
+            .collect();
+        let mut glyph = Glyph {
+            bitmap_w: width as f32,
+            bitmap_h: rows as f32,
+            bitmap_l: g.bitmap_left() as f32,
+            bitmap_t: g.bitmap_top() as f32,
+            // 1 unit = 1/64 pixels so bitshift
+            // by 6 to get advance in pixels
+            advance_x: (g.advance().x >> 6) as f32,
+            advance_y: (g.advance().y >> 6) as f32,
+            ..Default::default()
+        };
+        // Wide chars like CJK take two columns whatever the font says
+        if char::from_u32(codepoint).and_then(|ch| ch.width()) == Some(2) {
+            glyph.advance_x = glyph.advance_x.max(2.0 * self.max_w);
+        }
+
+        let (page, x, y) = self.place(width, rows)?;
+        let page_ref = &mut self.pages[page];
+        page_ref.write(x, y, width, rows, &pixels);
+        glyph.tx = x as f32 / page_ref.w as f32;
+        glyph.ty = y as f32 / page_ref.h as f32;
+        glyph.tw = width as f32 / page_ref.w as f32;
+        glyph.th = rows as f32 / page_ref.h as f32;
+        glyph.page = page;
+        self.glyphs.put(codepoint, glyph);
+        Ok(page)
+    }
+
+    /// Find room for a `width` by `rows` bitmap, growing the current page
+    /// or moving on to another when it's full. Returns the page and where
+    /// on it the bitmap goes
+    fn place(&mut self, width: u32, rows: u32) -> Result<(usize, u32, u32), String> {
+        let mut max_size: GLint = 0;
+        unsafe { gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_size) };
+        let max_h = MAX_PAGE_HEIGHT.min(max_size as u32);
+        loop {
+            let page = &mut self.pages[self.current];
+            if let Some((x, y)) = page.place(width, rows) {
+                return Ok((self.current, x, y));
+            }
+            let h = page.h;
+            if page.grow(max_h) {
+                // The glyphs keep their place in pixels so only their y
+                // texture coordinates change
+                let scale = h as f32 / page.h as f32;
+                for (_, glyph) in self.glyphs.iter_mut() {
+                    if glyph.page == self.current {
+                        glyph.ty *= scale;
+                        glyph.th *= scale;
+                    }
+                }
+                self.revision += 1;
+            } else if page.is_empty() {
+                return Err("the glyph is bigger than the atlas".to_string());
+            } else {
+                self.current = self.next_page();
+            }
+        }
+    }
+
+    /// The page to put glyphs on once the current one is full: a new one,
+    /// or once there are `MAX_PAGES` the one drawn from longest ago as long
+    /// as it wasn't in this pass. When every page was, this pass needs
+    /// another one
+    fn next_page(&mut self) -> usize {
+        let oldest = (0..self.pages.len())
+            .filter(|page| self.pages[*page].used < self.pass)
+            .min_by_key(|page| self.pages[*page].used);
+        match oldest {
+            Some(page) if self.pages.len() >= MAX_PAGES => {
+                let evicted: Vec<u32> = self
+                    .glyphs
+                    .iter()
+                    .filter(|(_, glyph)| glyph.page == page)
+                    .map(|(codepoint, _)| *codepoint)
+                    .collect();
+                for codepoint in evicted {
+                    self.glyphs.pop(&codepoint);
+                }
+                self.pages[page].clear();
+                self.revision += 1;
+                page
+            }
+            _ => {
+                let w = self.pages[0].w;
+                self.pages.push(Page::new(w, self.page_h));
+                self.pages.len() - 1
+            }
+        }
+    }
+}
+
+/// A texture glyphs are packed into left to right, in rows
+struct Page {
+    tex: GLuint,
+    w: u32,
+    h: u32,
+    // A copy of the texture, uploaded again when it grows
+    pixels: Vec<u8>,
+    // Where the next glyph goes, and the height of the row it's going on
+    ox: u32,
+    oy: u32,
+    row_h: u32,
+    // The last pass a glyph on the page was drawn in
+    used: u64,
+}
+
+impl Page {
+    fn new(w: u32, h: u32) -> Self {
+        let mut tex: GLuint = 0;
+        unsafe {
             gl::GenTextures(1, &mut tex as *mut GLuint);
             gl::BindTexture(gl::TEXTURE_2D, tex);
-            gl::Uniform1i(uniform_tex, 0);
 
             gl::TexImage2D(
                 gl::TEXTURE_2D,
@@ -98,7 +520,6 @@ impl Atlas {
                 h as i32,
                 0,
                 gl::ALPHA,
-                // gl::RED,
                 gl::UNSIGNED_BYTE,
                 null(),
             );
@@ -123,72 +544,111 @@ impl Atlas {
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
         }
 
-        // Paste all glyph bitmaps into the texture, remembering the offset
-        let mut ox: i32 = 0;
-        let mut oy: i32 = 0;
-
-        rowh = 0;
+        Self {
+            tex,
+            w,
+            h,
+            pixels: vec![0; (w * h) as usize],
+            ox: 0,
+            oy: 0,
+            row_h: 0,
+            used: 0,
+        }
+    }
 
-        for i in 32..CHAR_END {
-            face.load_char(i, freetype::face::LoadFlag::RENDER)
-                .map_err(|e| e.to_string())?;
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.ox == 0 && self.oy == 0
+    }
 
-            if ox + g.bitmap().width() + 1 >= MAX_WIDTH as i32 {
-                ox = 0;
-                oy += rowh as i32;
-                rowh = 0;
-            }
+    /// Where a `width` by `rows` bitmap goes, on a new row if it doesn't fit
+    /// on this one. None when the page is out of rows
+    fn place(&mut self, width: u32, rows: u32) -> Option<(u32, u32)> {
+        if self.ox > 0 && self.ox + width + 1 >= self.w {
+            self.ox = 0;
+            self.oy += self.row_h;
+            self.row_h = 0;
+        }
+        if self.oy + rows > self.h {
+            return None;
+        }
+        let spot = (self.ox, self.oy);
+        self.row_h = std::cmp::max(self.row_h, rows);
+        self.ox += width + 1;
+        Some(spot)
+    }
 
-            unsafe {
-                gl::TexSubImage2D(
-                    gl::TEXTURE_2D,
-                    0,
-                    ox as i32,
-                    oy as i32,
-                    g.bitmap().width() as i32,
-                    g.bitmap().rows() as i32,
-                    gl::ALPHA,
-                    gl::UNSIGNED_BYTE,
-                    g.bitmap().buffer().as_ptr() as *const GLvoid,
-                );
-            }
+    /// Copy a `width` by `rows` bitmap to (x, y)
+    fn write(&mut self, x: u32, y: u32, width: u32, rows: u32, pixels: &[u8]) {
+        for (row, src) in pixels.chunks(width.max(1) as usize).enumerate() {
+            let dst = (y as usize + row) * self.w as usize + x as usize;
+            self.pixels[dst..dst + src.len()].copy_from_slice(src);
+        }
 
-            glyphs[i] = Glyph {
-                bitmap_w: g.bitmap().width() as f32,
-                bitmap_h: g.bitmap().rows() as f32,
-                bitmap_l: g.bitmap_left() as f32,
-                bitmap_t: g.bitmap_top() as f32,
-                tx: ox as f32 / w as f32,
-                ty: oy as f32 / h as f32,
-                // 1 unit = 1/64 pixels so bitshift
-                // by 6 to get advance in pixels
-                advance_x: (g.advance().x >> 6) as f32,
-                advance_y: (g.advance().y >> 6) as f32,
-            };
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.tex);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                x as i32,
+                y as i32,
+                width as i32,
+                rows as i32,
+                gl::ALPHA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const GLvoid,
+            );
+        }
+    }
 
-            rowh = std::cmp::max(rowh, g.bitmap().rows() as u32);
-            ox += g.bitmap().width() + 1;
+    /// Double the height of the texture, false if that's more than `max_h`
+    fn grow(&mut self, max_h: u32) -> bool {
+        let h = self.h * 2;
+        if h > max_h {
+            return false;
         }
 
-        // println!(
-        //     "Generated a {} x {} ({} kb) texture atlas\n",
-        //     w,
-        //     h,
-        //     w * h / 1024
-        // );
+        self.pixels.resize((self.w * h) as usize, 0);
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.tex);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::ALPHA as i32,
+                self.w as i32,
+                h as i32,
+                0,
+                gl::ALPHA,
+                gl::UNSIGNED_BYTE,
+                self.pixels.as_ptr() as *const GLvoid,
+            );
+        }
+        self.h = h;
+        true
+    }
 
-        Ok(Self {
-            tex,
-            w,
-            h,
-            glyphs,
-            max_h,
-            max_w: max_w as f32,
-        })
+    /// Start the page over with no glyphs on it
+    fn clear(&mut self) {
+        self.pixels.fill(0);
+        (self.ox, self.oy, self.row_h) = (0, 0, 0);
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.tex);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                self.w as i32,
+                self.h as i32,
+                gl::ALPHA,
+                gl::UNSIGNED_BYTE,
+                self.pixels.as_ptr() as *const GLvoid,
+            );
+        }
     }
 }
 
-impl Drop for Atlas {
+impl Drop for Page {
     fn drop(&mut self) {
         unsafe {
             gl::DeleteTextures(1, &self.tex);
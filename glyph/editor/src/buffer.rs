@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use lsp::Url;
+
+/// Identifies a buffer for the whole session, ids are never reused so state
+/// keyed by a closed buffer can't be mistaken for a newer one's
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BufferId(u64);
+
+struct BufferInfo {
+    path: Option<PathBuf>,
+    url: Option<Url>,
+}
+
+/// The open buffers and the files they belong to. State about a buffer
+/// (diagnostics, highlighting, LSP documents) should be looked up through
+/// here instead of by path or URL, so a file that's reopened or known by
+/// another name resolves to the right buffer
+#[derive(Default)]
+pub struct Buffers {
+    next_id: u64,
+    buffers: HashMap<BufferId, BufferInfo>,
+    by_path: HashMap<PathBuf, BufferId>,
+    by_url: HashMap<Url, BufferId>,
+}
+
+impl Buffers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a buffer for the file at `path`, or a scratch buffer without one.
+    /// A file that's already open keeps its buffer
+    pub fn open(&mut self, path: Option<&Path>) -> BufferId {
+        let path = path.map(canonical_path);
+        if let Some(id) = path.as_ref().and_then(|path| self.by_path.get(path)) {
+            return *id;
+        }
+
+        let id = BufferId(self.next_id);
+        self.next_id += 1;
+
+        let url = path
+            .as_ref()
+            .and_then(|path| Url::from_file_path(path).ok());
+        if let Some(path) = &path {
+            self.by_path.insert(path.clone(), id);
+        }
+        if let Some(url) = &url {
+            self.by_url.insert(url.clone(), id);
+        }
+        self.buffers.insert(id, BufferInfo { path, url });
+
+        id
+    }
+
+    /// Forget the buffer, returns false if it wasn't open
+    pub fn close(&mut self, id: BufferId) -> bool {
+        let info = match self.buffers.remove(&id) {
+            Some(info) => info,
+            None => return false,
+        };
+        if let Some(path) = info.path {
+            self.by_path.remove(&path);
+        }
+        if let Some(url) = info.url {
+            self.by_url.remove(&url);
+        }
+        true
+    }
+
+    #[inline]
+    pub fn is_open(&self, id: BufferId) -> bool {
+        self.buffers.contains_key(&id)
+    }
+
+    pub fn path(&self, id: BufferId) -> Option<&Path> {
+        self.buffers.get(&id)?.path.as_deref()
+    }
+
+    pub fn url(&self, id: BufferId) -> Option<&Url> {
+        self.buffers.get(&id)?.url.as_ref()
+    }
+
+    pub fn find_path(&self, path: &Path) -> Option<BufferId> {
+        self.by_path.get(&canonical_path(path)).copied()
+    }
+
+    pub fn find_url(&self, url: &Url) -> Option<BufferId> {
+        self.by_url.get(url).copied()
+    }
+}
+
+/// Resolve symlinks and relative parts of `path`, files that don't exist yet
+/// are resolved through their directory
+fn canonical_path(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            fs::canonicalize(parent)
+                .map(|parent| parent.join(name))
+                .unwrap_or_else(|_| path.to_path_buf())
+        }
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let mut buffers = Buffers::new();
+        let dir = std::env::temp_dir();
+        let path = dir.join("glyph_buffers_lookup.rs");
+
+        let id = buffers.open(Some(&path));
+        let canonical = canonical_path(&path);
+        assert_eq!(buffers.path(id), Some(canonical.as_path()));
+
+        let url = Url::from_file_path(&canonical).unwrap();
+        assert_eq!(buffers.url(id), Some(&url));
+        assert_eq!(buffers.find_url(&url), Some(id));
+        // Another name for the same file
+        assert_eq!(
+            buffers.find_path(&dir.join(".").join("glyph_buffers_lookup.rs")),
+            Some(id)
+        );
+        assert_eq!(buffers.open(Some(&path)), id);
+
+        let scratch = buffers.open(None);
+        assert_ne!(scratch, id);
+        assert_eq!(buffers.path(scratch), None);
+        assert_eq!(buffers.url(scratch), None);
+    }
+
+    #[test]
+    fn ids_are_not_reused() {
+        let mut buffers = Buffers::new();
+        let path = std::env::temp_dir().join("glyph_buffers_reopen.rs");
+
+        let id = buffers.open(Some(&path));
+        let url = buffers.url(id).cloned().unwrap();
+        assert!(buffers.close(id));
+        assert!(!buffers.close(id));
+
+        // Nothing about the closed buffer is left behind
+        assert!(!buffers.is_open(id));
+        assert_eq!(buffers.path(id), None);
+        assert_eq!(buffers.find_path(&path), None);
+        assert_eq!(buffers.find_url(&url), None);
+
+        // Reopening the file makes a new buffer
+        let reopened = buffers.open(Some(&path));
+        assert_ne!(reopened, id);
+        assert_eq!(buffers.find_url(&url), Some(reopened));
+    }
+}
@@ -0,0 +1,332 @@
+use std::{collections::HashMap, fmt};
+
+use sdl2::{
+    event::Event,
+    keyboard::{Keycode, Mod},
+};
+
+/// Commands that ship with the editor, they're defined with the same syntax
+/// as the user's so they double as examples
+const BUILTIN_COMMANDS: &str = r#"
+# Open a blank line without entering insert mode
+command BlankBelow = "o<esc>"
+command BlankAbove = "O<esc>"
+# Surround the current line with blank lines
+command Pad = ":BlankAbove<cr>j:BlankBelow<cr>"
+"#;
+
+#[derive(Debug, PartialEq)]
+pub enum CommandError {
+    /// A definition that couldn't be parsed, with its line number
+    Parse(usize, String),
+    NotFound(String),
+    /// The command ends up running itself
+    Recursive(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Parse(line, msg) => write!(f, "line {}: {}", line, msg),
+            CommandError::NotFound(name) => write!(f, "Not an editor command: {}", name),
+            CommandError::Recursive(name) => write!(f, "Recursive command: {}", name),
+        }
+    }
+}
+
+/// User defined ex commands, each expands to a sequence of keys.
+/// Definitions look like:
+///
+/// ```text
+/// command Name = "keys"
+/// ```
+///
+/// Keys use Vim's notation for special keys (`<esc>`, `<cr>`, `<bs>`,
+/// `<tab>`, `<c-w>`, `<c-u>`, `<c-v>`, `<left>`, `<right>`, `<up>`,
+/// `<down>`, `<home>`, `<end>`, `<del>`, `<pgup>`, `<pgdn>`). `:Name<cr>`
+/// runs another user command, any other `:` is typed like the rest of the
+/// keys so `:w<cr>` saves before the keys after it. If it fails the rest of
+/// the command doesn't run
+pub struct UserCommands {
+    commands: HashMap<String, String>,
+}
+
+impl UserCommands {
+    pub fn new() -> Self {
+        let mut commands = Self {
+            commands: HashMap::new(),
+        };
+        commands
+            .define_all(BUILTIN_COMMANDS)
+            .expect("Built-in commands should be valid");
+        commands
+    }
+
+    /// Add the definitions in `src`, redefining any commands with the same name.
+    /// Nothing is added if any of them are invalid
+    pub fn define_all(&mut self, src: &str) -> Result<(), CommandError> {
+        let mut defined = Vec::new();
+        for (i, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            defined.push(parse_definition(line).map_err(|msg| CommandError::Parse(i + 1, msg))?);
+        }
+
+        self.commands.extend(defined);
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.commands.contains_key(name)
+    }
+
+    /// Expand a command into the key events it runs. The user commands it
+    /// runs are checked up front so a missing or recursive one doesn't run
+    /// halfway, the other `:` commands can only fail as they're run
+    pub fn expand(&self, name: &str) -> Result<Vec<Event>, CommandError> {
+        let mut events = Vec::new();
+        self.expand_into(name, &mut Vec::new(), &mut events)?;
+        Ok(events)
+    }
+
+    fn expand_into<'a>(
+        &'a self,
+        name: &'a str,
+        running: &mut Vec<&'a str>,
+        events: &mut Vec<Event>,
+    ) -> Result<(), CommandError> {
+        if running.contains(&name) {
+            return Err(CommandError::Recursive(name.to_string()));
+        }
+        let keys = self
+            .commands
+            .get(name)
+            .ok_or_else(|| CommandError::NotFound(name.to_string()))?;
+
+        running.push(name);
+        let mut rest = keys.as_str();
+        while !rest.is_empty() {
+            // Other user commands are expanded in place instead of going
+            // through the command line
+            let call = rest
+                .strip_prefix(':')
+                .and_then(|cmd| cmd.split_once("<cr>"))
+                .filter(|(name, _)| self.commands.contains_key(*name));
+            if let Some((name, after)) = call {
+                self.expand_into(name, running, events)?;
+                rest = after;
+                continue;
+            }
+
+            let (event, after) = next_key(rest);
+            events.push(event);
+            rest = after;
+        }
+        running.pop();
+
+        Ok(())
+    }
+}
+
+impl Default for UserCommands {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_definition(line: &str) -> Result<(String, String), String> {
+    let def = line
+        .strip_prefix("command ")
+        .ok_or_else(|| "expected `command`".to_string())?;
+    let (name, keys) = def
+        .split_once('=')
+        .ok_or_else(|| "expected `=` after the command name".to_string())?;
+
+    let name = name.trim();
+    // Same as Vim, this keeps them apart from the built-in ex commands
+    if !name.starts_with(|c: char| c.is_ascii_uppercase())
+        || !name.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        return Err(format!(
+            "invalid name `{}`, it should be alphanumeric and start with an uppercase letter",
+            name
+        ));
+    }
+
+    let keys = keys
+        .trim()
+        .strip_prefix('"')
+        .and_then(|keys| keys.strip_suffix('"'))
+        .ok_or_else(|| "expected the keys in double quotes".to_string())?;
+    if keys.is_empty() {
+        return Err(format!("`{}` has no keys", name));
+    }
+
+    Ok((name.to_string(), keys.to_string()))
+}
+
+/// All the keys in `keys`, without running any commands. Keys are written
+/// the same way as in user commands
+pub fn key_events(mut keys: &str) -> Vec<Event> {
+    let mut events = Vec::new();
+    while !keys.is_empty() {
+        let (event, rest) = next_key(keys);
+        events.push(event);
+        keys = rest;
+    }
+    events
+}
+
+/// Split off the first key of `keys`
+fn next_key(keys: &str) -> (Event, &str) {
+    const SPECIAL: &[(&str, Keycode, Mod)] = &[
+        ("<esc>", Keycode::Escape, Mod::NOMOD),
+        ("<cr>", Keycode::Return, Mod::NOMOD),
+        ("<bs>", Keycode::Backspace, Mod::NOMOD),
+        ("<tab>", Keycode::Tab, Mod::NOMOD),
+        ("<c-w>", Keycode::W, Mod::LCTRLMOD),
+        ("<c-u>", Keycode::U, Mod::LCTRLMOD),
+        ("<c-v>", Keycode::V, Mod::LCTRLMOD),
+        ("<left>", Keycode::Left, Mod::NOMOD),
+        ("<right>", Keycode::Right, Mod::NOMOD),
+        ("<up>", Keycode::Up, Mod::NOMOD),
+        ("<down>", Keycode::Down, Mod::NOMOD),
+        ("<home>", Keycode::Home, Mod::NOMOD),
+        ("<end>", Keycode::End, Mod::NOMOD),
+        ("<del>", Keycode::Delete, Mod::NOMOD),
+        ("<pgup>", Keycode::PageUp, Mod::NOMOD),
+        ("<pgdn>", Keycode::PageDown, Mod::NOMOD),
+    ];
+
+    for (name, keycode, keymod) in SPECIAL {
+        if let Some(rest) = keys.strip_prefix(name) {
+            let event = Event::KeyDown {
+                timestamp: 0,
+                window_id: 0,
+                keycode: Some(*keycode),
+                scancode: None,
+                keymod: *keymod,
+                repeat: false,
+            };
+            return (event, rest);
+        }
+    }
+
+    let len = keys.chars().next().map_or(0, char::len_utf8);
+    let event = Event::TextInput {
+        timestamp: 0,
+        window_id: 0,
+        text: keys[..len].to_string(),
+    };
+    (event, &keys[len..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(events: Vec<Event>) -> Vec<String> {
+        events
+            .into_iter()
+            .map(|event| match event {
+                Event::TextInput { text, .. } => text,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => format!("<{:?}>", keycode),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse() {
+        let mut commands = UserCommands::new();
+        commands
+            .define_all("# comment\n\ncommand Clear = \"ggdG\"\n")
+            .unwrap();
+        assert!(commands.contains("Clear"));
+
+        assert_eq!(
+            commands.define_all("command clear = \"dd\""),
+            Err(CommandError::Parse(
+                1,
+                "invalid name `clear`, it should be alphanumeric and start with an uppercase letter"
+                    .into()
+            ))
+        );
+        assert!(matches!(
+            commands.define_all("command Ok = \"dd\"\ncommand Bad = dd"),
+            Err(CommandError::Parse(2, _))
+        ));
+        // A bad definition keeps the others in the same source from being added
+        assert!(!commands.contains("Ok"));
+    }
+
+    #[test]
+    fn expand() {
+        let mut commands = UserCommands::new();
+        commands
+            .define_all("command Line = \"dd\"\ncommand Two = \":Line<cr>ix<esc>:Line<cr>\"")
+            .unwrap();
+        assert_eq!(
+            keys(commands.expand("Two").unwrap()),
+            vec!["d", "d", "i", "x", "<Escape>", "d", "d"]
+        );
+        assert_eq!(
+            commands.expand("Nope").err(),
+            Some(CommandError::NotFound("Nope".into()))
+        );
+    }
+
+    #[test]
+    fn ex_commands() {
+        let mut commands = UserCommands::new();
+        commands
+            .define_all("command Save = \"gg=G:w<cr>\"\ncommand Colon = \"f:x\"")
+            .unwrap();
+        // Only defined names run user commands, the rest are typed
+        assert_eq!(
+            keys(commands.expand("Save").unwrap()),
+            vec!["g", "g", "=", "G", ":", "w", "<Return>"]
+        );
+        assert_eq!(keys(commands.expand("Colon").unwrap()), vec!["f", ":", "x"]);
+    }
+
+    #[test]
+    fn special_keys() {
+        assert_eq!(
+            keys(key_events("a<left><del><pgdn><c-v>")),
+            vec!["a", "<Left>", "<Delete>", "<PageDown>", "<V>"]
+        );
+        // Not a key, typed as it is
+        assert_eq!(keys(key_events("<x>")), vec!["<", "x", ">"]);
+    }
+
+    #[test]
+    fn recursive() {
+        let mut commands = UserCommands::new();
+        commands
+            .define_all("command A = \"x:B<cr>\"\ncommand B = \":A<cr>\"\ncommand C = \":C<cr>\"")
+            .unwrap();
+        assert_eq!(
+            commands.expand("A").err(),
+            Some(CommandError::Recursive("A".into()))
+        );
+        assert_eq!(
+            commands.expand("C").err(),
+            Some(CommandError::Recursive("C".into()))
+        );
+    }
+
+    #[test]
+    fn same_command_twice_is_not_recursive() {
+        let mut commands = UserCommands::new();
+        commands
+            .define_all("command Both = \":BlankBelow<cr>:BlankBelow<cr>\"")
+            .unwrap();
+        assert!(commands.expand("Both").is_ok());
+    }
+}
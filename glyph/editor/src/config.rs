@@ -0,0 +1,180 @@
+use std::{fmt, fs, io, path::PathBuf};
+
+use serde::Deserialize;
+
+use crate::{CommandError, FontOptions, IndentSettings, UserCommands};
+
+/// The settings in `config.toml`, the command line flags of the same name
+/// override them
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EditorConfig {
+    /// Relative paths are resolved like `FontOptions::path`
+    pub font_path: PathBuf,
+    pub font_size: u32,
+    pub wrap: bool,
+    pub cursor_line: bool,
+    /// Indent with tabs instead of spaces
+    pub tabs: bool,
+    pub tab_width: u8,
+    /// Type the closing bracket or quote along with the opening one
+    pub auto_pairs: bool,
+    /// User commands, one `command Name = "keys"` per line like
+    /// [`UserCommands`] takes them
+    pub commands: String,
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        let font = FontOptions::default();
+        let indent = IndentSettings::default();
+        Self {
+            font_path: font.path,
+            font_size: font.size,
+            wrap: false,
+            cursor_line: true,
+            tabs: !indent.use_spaces,
+            tab_width: indent.width,
+            auto_pairs: true,
+            commands: String::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    /// A number that has to be more than 0
+    Zero(&'static str),
+    Commands(CommandError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Toml(e) => write!(f, "{}", e),
+            ConfigError::Zero(name) => write!(f, "{} should be more than 0", name),
+            ConfigError::Commands(e) => write!(f, "commands: {}", e),
+        }
+    }
+}
+
+impl EditorConfig {
+    pub fn from_toml(src: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(src).map_err(ConfigError::Toml)?;
+        if config.font_size == 0 {
+            return Err(ConfigError::Zero("font_size"));
+        }
+        if config.tab_width == 0 {
+            return Err(ConfigError::Zero("tab_width"));
+        }
+        UserCommands::new()
+            .define_all(&config.commands)
+            .map_err(ConfigError::Commands)?;
+        Ok(config)
+    }
+
+    /// The config file in the config directory, the defaults if there isn't
+    /// one
+    pub fn load() -> Result<Self, ConfigError> {
+        match editor_config_path().filter(|path| path.exists()) {
+            Some(path) => Self::from_toml(&fs::read_to_string(path).map_err(ConfigError::Io)?),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn font(&self) -> FontOptions {
+        FontOptions {
+            path: self.font_path.clone(),
+            size: self.font_size,
+        }
+    }
+
+    pub fn indent(&self) -> IndentSettings {
+        IndentSettings {
+            use_spaces: !self.tabs,
+            width: self.tab_width,
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/glyph` or `~/.config/glyph`
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("glyph"))
+}
+
+/// Where the config file goes, `config.toml` in [`config_dir`]
+pub fn editor_config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_toml() {
+        assert_eq!(
+            EditorConfig::from_toml("").unwrap(),
+            EditorConfig::default()
+        );
+
+        let config = EditorConfig::from_toml(
+            r#"
+font_path = "/usr/share/fonts/Hack.ttf"
+font_size = 32
+tabs = true
+tab_width = 4
+auto_pairs = false
+commands = '''
+command Save = "gg=G:w<cr>"
+'''
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.font().path,
+            PathBuf::from("/usr/share/fonts/Hack.ttf")
+        );
+        assert_eq!(config.font().size, 32);
+        assert_eq!(
+            config.indent(),
+            IndentSettings {
+                use_spaces: false,
+                width: 4
+            }
+        );
+        assert!(!config.auto_pairs);
+        assert_eq!(config.commands, "command Save = \"gg=G:w<cr>\"\n");
+        // The rest are the defaults
+        assert!(config.cursor_line);
+        assert!(!config.wrap);
+    }
+
+    #[test]
+    fn from_toml_errors() {
+        assert!(matches!(
+            EditorConfig::from_toml("font_size = 0"),
+            Err(ConfigError::Zero("font_size"))
+        ));
+        assert!(matches!(
+            EditorConfig::from_toml("font_size = \"big\""),
+            Err(ConfigError::Toml(_))
+        ));
+        assert!(matches!(
+            EditorConfig::from_toml("commands = 'command save = \"dd\"'"),
+            Err(ConfigError::Commands(CommandError::Parse(1, _)))
+        ));
+        // Typos aren't ignored
+        assert!(matches!(
+            EditorConfig::from_toml("font-size = 32"),
+            Err(ConfigError::Toml(_))
+        ));
+    }
+}
@@ -1,14 +1,117 @@
-use lsp::{Client, LspSender};
+use lsp::{text_doc_did_save, LspSender, Url};
 use ropey::{Rope, RopeSlice};
-use sdl2::{event::Event, keyboard::Keycode};
-use std::{cell::Cell, cmp::Ordering, ops::Range};
+use sdl2::{
+    event::Event,
+    keyboard::{Keycode, Mod},
+};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    mem,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering as AtomicOrdering},
+};
+use syntax::tree_sitter::{InputEdit, Language, Parser, Point};
+use unicode_width::UnicodeWidthChar;
 
 use crate::{
-    vim::{Cmd, NewLine},
+    bracket_indents, closing_bracket, key_events, path_at, tree_indents,
+    vim::{Case, Cmd, ExCmd, NewLine},
     vim::{Move, Vim},
-    EditorEvent, MoveWord, MoveWordKind,
+    Clipboard, CommandError, EditorEvent, FileLocation, IndentSettings, MoveWord, MoveWordKind,
+    Register, Registers, SignNamespace, Signs, UserCommands,
 };
 
+/// How the lines of a file end. The text is kept with `\n` and the file's
+/// line endings are put back when it's written
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    /// The line ending of the first line in `text`
+    pub fn detect(text: &str) -> Self {
+        match text.find(['\r', '\n']).map(|i| &text[i..]) {
+            Some(rest) if rest.starts_with("\r\n") => LineEnding::CrLf,
+            Some(rest) if rest.starts_with('\r') => LineEnding::Cr,
+            _ => LineEnding::Lf,
+        }
+    }
+
+    #[inline]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    /// `text` with its lines ending in `\n`. A `\r` on its own is only a line
+    /// ending in files that use them
+    fn normalize(self, text: String) -> String {
+        if !text.contains('\r') {
+            return text;
+        }
+        let text = text.replace("\r\n", "\n");
+        match self {
+            LineEnding::Cr => text.replace('\r', "\n"),
+            LineEnding::Lf | LineEnding::CrLf => text,
+        }
+    }
+}
+
+/// Write `text` to a file next to `path` then move it over `path`, so the
+/// old file is left as it was if writing fails partway
+fn write_replacing(text: &Rope, path: &Path, line_ending: LineEnding) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Not a file name"))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(name);
+    tmp_name.push(".glyph~");
+    let tmp = path.with_file_name(tmp_name);
+
+    let write = || {
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        match line_ending {
+            LineEnding::Lf => text.write_to(&mut file)?,
+            _ => {
+                for chunk in text.chunks() {
+                    file.write_all(chunk.replace('\n', line_ending.as_str()).as_bytes())?;
+                }
+            }
+        }
+        file.flush()?;
+        // Keep the mode of the file being replaced, ex. executable scripts
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&tmp, metadata.permissions())?;
+        }
+        fs::rename(&tmp, path)
+    };
+    write().inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+/// Revisions are handed out from one counter so no two editors share one
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(0);
+
+#[inline]
+fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, AtomicOrdering::Relaxed)
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Mode {
     Insert,
@@ -16,6 +119,15 @@ pub enum Mode {
     Visual,
 }
 
+/// How much clicking the mouse selects, a char for a single click, the word
+/// for a double click and the line for a triple click
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SelectUnit {
+    Char,
+    Word,
+    Line,
+}
+
 #[derive(Clone, Debug)]
 pub enum Edit {
     Insertion { start: Cell<u32>, str_idx: u32 },
@@ -43,6 +155,107 @@ impl Edit {
     }
 }
 
+/// How lines are laid out as rows on the screen. Soft wrapping splits a line
+/// across several rows, the window owns the layout and installs it with
+/// `Editor::set_display_rows()` so `gj` and `gk` can move through the rows
+pub trait DisplayRows {
+    /// The columns the rows of `line` start at, the first is always 0
+    fn row_starts(&self, line: RopeSlice) -> Vec<usize>;
+}
+
+/// Every line fits in a single row
+pub struct NoWrap;
+
+impl DisplayRows for NoWrap {
+    fn row_starts(&self, _line: RopeSlice) -> Vec<usize> {
+        vec![0]
+    }
+}
+
+/// How far rows after the first one of a wrapped line are indented
+pub const WRAP_INDENT: usize = 2;
+
+/// Typing one of these first on a line dedents it
+const CLOSING_BRACKETS: [char; 3] = [')', ']', '}'];
+
+/// Chars that have their closing char inserted after them when they're typed
+const AUTO_PAIRS: [(char, char); 6] = [
+    ('(', ')'),
+    ('[', ']'),
+    ('{', '}'),
+    ('"', '"'),
+    ('\'', '\''),
+    ('`', '`'),
+];
+
+/// Wrap lines longer than `col` columns at the last whitespace that fits,
+/// words longer than a row are split. The rows after the first are indented
+/// by `WRAP_INDENT` so they have that many fewer columns
+pub struct WordWrap {
+    col: usize,
+}
+
+impl WordWrap {
+    pub fn new(col: usize) -> Self {
+        Self { col: col.max(1) }
+    }
+}
+
+impl DisplayRows for WordWrap {
+    fn row_starts(&self, line: RopeSlice) -> Vec<usize> {
+        let chars: Vec<char> = line.chars().take_while(|c| *c != '\n').collect();
+        let mut starts = vec![0];
+        let mut width = self.col;
+
+        let mut start = 0;
+        loop {
+            // The first char that doesn't fit in the row, there's always at
+            // least one char in a row
+            let mut cols = 0;
+            let Some(end) = (start..chars.len()).find(|i| {
+                cols += char_cols(chars[*i]);
+                cols > width
+            }) else {
+                break;
+            };
+            let end = end.max(start + 1);
+            if end == chars.len() {
+                break;
+            }
+            // Break after the last whitespace in the row
+            start = (start + 1..=end)
+                .rev()
+                .find(|i| chars[i - 1].is_whitespace())
+                .unwrap_or(end);
+            starts.push(start);
+            width = self.col.saturating_sub(WRAP_INDENT).max(1);
+        }
+
+        starts
+    }
+}
+
+/// How many columns `ch` takes on the screen, wide chars like CJK take two.
+/// Tabs are only one here, `char_to_display_col` takes them to the next
+/// tab stop
+#[inline]
+fn char_cols(ch: char) -> usize {
+    ch.width().unwrap_or(1)
+}
+
+/// A change to the text, kept so the syntax tree and highlights can be
+/// updated instead of redoing the whole buffer
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextEdit {
+    /// Char indices, `old_end` is before the change and `new_end` after it
+    pub start: usize,
+    pub old_end: usize,
+    pub new_end: usize,
+    /// What replaced `start..old_end`, empty when text was removed
+    pub text: String,
+    pub input_edit: InputEdit,
+}
+
 pub struct Editor {
     // In insert mode this is the next position to be written (1 + self.lines[line]).
     cursor: usize,
@@ -51,24 +264,95 @@ pub struct Editor {
     // provided by the rope buffer, this is has the trade off of always doing the O(logn) calculation, vs.
     // the O(1) access of a vec
     lines: Vec<u32>,
+    // Where each line starts, the sums of the lengths of the lines before
+    // it. Edits only move the lines after them, so these are cut back to the
+    // edited line and added up again as far as `line_pos` needs
+    line_offsets: RefCell<Vec<u32>>,
     text: Rope,
+    // What the file's lines end with, they're all `\n` in `text`
+    line_ending: LineEnding,
     mode: Mode,
 
     // Vim stuff
     vim: Vim,
     selection: Option<(u32, u32)>,
-
-    // Undo/redo
-    had_space: bool,
-    edits: Vec<Edit>,
-    redos: Vec<Edit>,
+    registers: Registers,
+    // The register named for the command being run, ex. `a` for `"add`
+    register: Option<char>,
+
+    // Undo/redo, each group is undone in a single step. An insert session is
+    // one group, every normal mode operation gets its own
+    edits: Vec<Vec<Edit>>,
+    redos: Vec<Vec<Edit>>,
     edit_vecs: Vec<Vec<char>>,
+    // Forces the next edit to start a new undo group instead of
+    // joining the current one
+    edit_boundary: bool,
+    // Where text was first typed in the current insert session, `Ctrl-U`
+    // deletes back to here
+    insert_start: Option<(usize, usize)>,
+    // Screen rows for `gj` and `gk`, `display_col` is the column in the
+    // row they keep going back to
+    display_rows: Box<dyn DisplayRows>,
+    display_col: Option<usize>,
+    // The screen column `j` and `k` keep going back to when they pass
+    // through shorter lines
+    desired_col: Option<usize>,
+    // How many lines PageUp and PageDown move, the window sets this to
+    // the lines it can show
+    page_lines: usize,
+
+    // Changed-since-save tracking, `save_point` is the amount of
+    // undo groups when the buffer was last saved
+    signs: Signs,
+    save_point: Option<usize>,
+    // The file `save` writes to
+    path: Option<PathBuf>,
+
+    // Treat whitespace-only lines as paragraph boundaries for `{` and `}`
+    whitespace_paragraph_boundary: bool,
+    // What `<tab>` inserts and `>` and `<` shift lines by
+    indent: IndentSettings,
+    // Type the closing bracket or quote along with the opening one
+    auto_pairs: bool,
+    // The last key typed in insert mode inserted a pair, backspace deletes
+    // both of its chars
+    auto_closed: bool,
+    // What a double or triple click selected, dragging from there keeps it
+    // selected and goes by the same unit
+    select_anchor: Option<(Range<usize>, SelectUnit)>,
 
     /// Store EditorEvent::Multiple data here instead of the enum because
     /// it bloats the enum's size: 1 byte -> 16 bytes!!!
     multiple_events_data: [EditorEvent; 3],
 
     lsp_sender: Option<LspSender>,
+
+    // The grammar `=` reindents with, brackets are counted without one
+    language: Option<Language>,
+
+    commands: UserCommands,
+
+    // The path under the cursor when `gf` was used, for the window to open
+    goto_file: Option<FileLocation>,
+    // The `:` command for the window to run, like `:w`
+    ex_cmd: Option<ExCmd>,
+    // The keys left of the user command being run, it stops at each `:`
+    // command the window runs and goes on from `resume_command`.
+    // `command_groups` is how many undo groups there were before it
+    command_keys: VecDeque<Event>,
+    command_groups: Option<usize>,
+
+    // The last `/` or `?` pattern, `n` goes the way it was searched and `N`
+    // the other way. Its matches are highlighted until `:noh` or Escape
+    search: Option<String>,
+    search_reverse: bool,
+    search_highlight: bool,
+
+    // Changes to the text since the last call to `take_text_edits()`
+    text_edits: Vec<TextEdit>,
+    // Changes every time the text does, see `revision()`
+    revision: u64,
 }
 
 fn text_to_lines<I>(text: I) -> Vec<u32>
@@ -100,24 +384,58 @@ where
 
 impl Editor {
     pub fn with_text(initial_text: Option<String>) -> Self {
+        let line_ending = initial_text
+            .as_deref()
+            .map_or(LineEnding::default(), LineEnding::detect);
         let (lines, text) = match initial_text {
-            Some(text) => (text_to_lines(text.chars()), Rope::from_str(&text)),
+            Some(text) => {
+                let text = line_ending.normalize(text);
+                (text_to_lines(text.chars()), Rope::from_str(&text))
+            }
             None => (vec![0], Rope::new()),
         };
         Self {
             cursor: 0,
             lines,
+            line_ending,
+            line_offsets: RefCell::new(Vec::new()),
             line: 0,
             text,
             mode: Mode::Insert,
             vim: Vim::new(),
             selection: None,
-            had_space: false,
+            registers: Registers::new(),
+            register: None,
             edits: Vec::new(),
             redos: Vec::new(),
             edit_vecs: Vec::new(),
+            edit_boundary: false,
+            insert_start: None,
+            display_rows: Box::new(NoWrap),
+            display_col: None,
+            desired_col: None,
+            page_lines: 1,
+            signs: Signs::new(),
+            save_point: Some(0),
+            path: None,
+            whitespace_paragraph_boundary: false,
+            indent: IndentSettings::default(),
+            auto_pairs: true,
+            auto_closed: false,
+            select_anchor: None,
             multiple_events_data: [EditorEvent::Nothing; 3],
             lsp_sender: None,
+            language: None,
+            commands: UserCommands::new(),
+            goto_file: None,
+            ex_cmd: None,
+            command_keys: VecDeque::new(),
+            command_groups: None,
+            search: None,
+            search_reverse: false,
+            search_highlight: false,
+            text_edits: Vec::new(),
+            revision: next_revision(),
         }
     }
 
@@ -125,8 +443,94 @@ impl Editor {
         Editor::with_text(None)
     }
 
-    pub fn configure_lsp(&mut self, lsp_client: &Client) {
-        self.lsp_sender = Some(lsp_client.sender().clone())
+    pub fn configure_lsp(&mut self, lsp_sender: &LspSender) {
+        self.lsp_sender = Some(lsp_sender.clone())
+    }
+
+    /// Yanks are copied to `clipboard`, `"+` and `Ctrl-V` in insert mode
+    /// paste from it
+    pub fn set_clipboard(&mut self, clipboard: Rc<dyn Clipboard>) {
+        self.registers.set_clipboard(clipboard);
+    }
+
+    #[inline]
+    pub fn set_language(&mut self, language: Option<Language>) {
+        self.language = language;
+    }
+
+    /// Add user commands, see [`UserCommands`] for the syntax
+    pub fn define_commands(&mut self, src: &str) -> Result<(), CommandError> {
+        self.commands.define_all(src)
+    }
+
+    /// Run a user command from normal mode, its edits are undone in one step.
+    /// Returns `EditorEvent::ExCmd` if it stopped at a `:` command for the
+    /// window to run, the rest of it runs from `resume_command` after that
+    pub fn run_command(&mut self, name: &str) -> Result<EditorEvent, CommandError> {
+        let events = self.commands.expand(name)?;
+
+        if self.command_groups.is_none() {
+            self.switch_mode(Mode::Normal);
+            self.command_groups = Some(self.edits.len());
+        }
+        // Run from inside another one, it goes before the rest of that one
+        for event in events.into_iter().rev() {
+            self.command_keys.push_front(event);
+        }
+        Ok(self.resume_command())
+    }
+
+    /// Go on with the user command up to its next `:` command the window
+    /// runs, returns `EditorEvent::ExCmd` if it stopped at one.
+    /// `EditorEvent::Nothing` if there isn't a command running
+    pub fn resume_command(&mut self) -> EditorEvent {
+        if self.command_groups.is_none() {
+            return EditorEvent::Nothing;
+        }
+        while let Some(event) = self.command_keys.pop_front() {
+            if self.event(event) == EditorEvent::ExCmd {
+                return EditorEvent::ExCmd;
+            }
+        }
+        self.finish_command();
+        EditorEvent::DrawText
+    }
+
+    /// Drop the rest of the user command, when one of its `:` commands failed
+    pub fn cancel_command(&mut self) {
+        self.command_keys.clear();
+        self.finish_command();
+    }
+
+    fn finish_command(&mut self) {
+        let Some(groups) = self.command_groups.take() else {
+            return;
+        };
+        self.switch_mode(Mode::Normal);
+
+        if self.edits.len() > groups + 1 {
+            let group = self.edits.drain(groups..).flatten().collect();
+            self.edits.push(group);
+        }
+        self.edit_boundary = true;
+    }
+
+    /// Type `keys` as if they came from the keyboard, written like the keys
+    /// of a user command (see [`UserCommands`]). This drives the editor
+    /// without a window, so Vim commands can be tested end to end along with
+    /// `text_all`, `line`, `cursor`, `mode` and `selection`. Returns what the
+    /// keys did with `EditorEvent::Multiple` replaced by its events, leaving
+    /// out `EditorEvent::Nothing`
+    pub fn feed_keys(&mut self, keys: &str) -> Vec<EditorEvent> {
+        let mut evts = Vec::new();
+        for event in key_events(keys) {
+            match self.event(event) {
+                EditorEvent::Multiple => evts.extend(self.take_multiple_event_data()),
+                evt => evts.push(evt),
+            }
+        }
+        evts.retain(|evt| *evt != EditorEvent::Nothing);
+        evts
     }
 
     pub fn event(&mut self, event: Event) -> EditorEvent {
@@ -137,11 +541,31 @@ impl Editor {
         //     self.line,
         //     self.lines
         // );
-        match self.mode {
+        let mode = self.mode;
+        let evt = match self.mode {
             Mode::Normal => self.normal_mode(event),
             Mode::Insert => self.insert_mode(event),
             Mode::Visual => self.visual_mode(event),
+        };
+        if self.mode == mode {
+            return evt;
         }
+
+        // Send the mode change along with what the event did
+        let changed = EditorEvent::ModeChanged(self.mode);
+        match evt {
+            EditorEvent::Multiple => {
+                if let Some(slot) = self
+                    .multiple_events_data
+                    .iter_mut()
+                    .find(|evt| **evt == EditorEvent::Nothing)
+                {
+                    *slot = changed;
+                }
+            }
+            evt => self.set_multiple_event_data([evt, changed, EditorEvent::Nothing]),
+        }
+        EditorEvent::Multiple
     }
 }
 
@@ -161,7 +585,10 @@ impl Editor {
                 let end = self.pos();
 
                 if start == end {
-                    self.selection = Some((start as u32, start as u32));
+                    // The command may have left visual mode
+                    if matches!(self.mode, Mode::Visual) {
+                        self.selection = Some((start as u32, start as u32));
+                    }
                     self.set_multiple_event_data([
                         EditorEvent::DrawSelection,
                         result,
@@ -195,12 +622,26 @@ impl Editor {
 // This impl contains utilities for insert mode
 impl Editor {
     fn insert_mode(&mut self, event: Event) -> EditorEvent {
+        let auto_closed = mem::take(&mut self.auto_closed);
+        if matches!(event, Event::KeyDown { .. } | Event::TextInput { .. })
+            && !matches!(
+                event,
+                Event::KeyDown {
+                    keycode: Some(
+                        Keycode::Up | Keycode::Down | Keycode::PageUp | Keycode::PageDown
+                    ),
+                    ..
+                }
+            )
+        {
+            self.desired_col = None;
+        }
         match event {
             Event::KeyDown {
                 keycode: Some(Keycode::Tab),
                 ..
             } => {
-                self.insert("  ");
+                self.insert(&self.indent.unit());
                 EditorEvent::DrawText
             }
             Event::KeyDown {
@@ -213,17 +654,86 @@ impl Editor {
             Event::KeyDown {
                 keycode: Some(Keycode::Backspace),
                 ..
-            } => self.backspace(),
+            } => {
+                if auto_closed {
+                    self.delete();
+                }
+                self.backspace()
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::W),
+                keymod,
+                ..
+            } if keymod == Mod::LCTRLMOD => self.delete_word_before_cursor(),
+            Event::KeyDown {
+                keycode: Some(Keycode::V),
+                keymod,
+                ..
+            } if keymod == Mod::LCTRLMOD => match self.registers.paste(Some('+')) {
+                Some(register) if !register.text.is_empty() => {
+                    self.insert_lines(&register.text);
+                    EditorEvent::DrawText
+                }
+                _ => EditorEvent::Nothing,
+            },
+            Event::KeyDown {
+                keycode: Some(Keycode::U),
+                keymod,
+                ..
+            } if keymod == Mod::LCTRLMOD => {
+                let start = match self.insert_start {
+                    Some((line, cursor)) if line == self.line && cursor < self.cursor => cursor,
+                    _ => 0,
+                };
+                self.delete_before_cursor(start)
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::Delete),
+                ..
+            } => self.delete(),
             Event::KeyDown {
                 keycode: Some(Keycode::Return),
                 ..
             } => {
                 self.enter();
+                // Each typed line is its own undoable change
+                self.edit_boundary = true;
                 EditorEvent::DrawText
             }
+            Event::KeyDown {
+                keycode:
+                    Some(
+                        keycode @ (Keycode::Left
+                        | Keycode::Right
+                        | Keycode::Up
+                        | Keycode::Down
+                        | Keycode::Home
+                        | Keycode::End
+                        | Keycode::PageUp
+                        | Keycode::PageDown),
+                    ),
+                ..
+            } => {
+                match keycode {
+                    Keycode::Left => self.left(1),
+                    Keycode::Right => {
+                        self.right(1);
+                    }
+                    Keycode::Up => self.up(1),
+                    Keycode::Down => self.down(1),
+                    Keycode::Home => self.move_pos(0),
+                    Keycode::End => self.move_pos(usize::MAX),
+                    Keycode::PageUp => self.up(self.page_lines),
+                    _ => self.down(self.page_lines),
+                }
+                // Like Vim, moving around starts a new undoable change
+                self.edit_boundary = true;
+                self.insert_start = None;
+                EditorEvent::DrawCursor
+            }
             Event::TextInput { text, .. } => {
                 if let Mode::Insert = self.mode {
-                    self.insert(&text);
+                    self.type_text(&text);
                     EditorEvent::DrawText
                 } else {
                     EditorEvent::Nothing
@@ -237,13 +747,33 @@ impl Editor {
 // This impl contains utilities for normal mode
 impl Editor {
     fn normal_mode(&mut self, event: Event) -> EditorEvent {
+        // Escape hides the search highlights unless it closes the command line
+        let escape = self.vim.command_line().is_none()
+            && matches!(
+                event,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                }
+            );
         match self.vim.event(event) {
+            None if escape && self.search_highlight => {
+                self.search_highlight = false;
+                EditorEvent::DrawCursor
+            }
             None => EditorEvent::Nothing,
             Some(cmd) => self.handle_cmd(&cmd),
         }
     }
 
     fn handle_cmd(&mut self, cmd: &Cmd) -> EditorEvent {
+        // Only consecutive `gj` and `gk` keep their column
+        if !cmd.is_display_movement() {
+            self.display_col = None;
+        }
+        if !cmd.is_vertical_movement() {
+            self.desired_col = None;
+        }
         match self.mode {
             Mode::Normal => self.handle_cmd_normal(cmd),
             Mode::Visual => self.handle_cmd_visual(cmd),
@@ -262,26 +792,78 @@ impl Editor {
                 EditorEvent::Nothing
             }
             Cmd::Change(None) | Cmd::Delete(None) => {
+                let groups = self.edits.len();
                 self.delete_selection();
                 if matches!(cmd, Cmd::Change(None)) {
                     self.switch_mode(Mode::Insert);
+                    self.join_edit_group(groups);
                 } else {
                     self.switch_mode(Mode::Normal);
+                    self.clamp_cursor();
                 }
                 EditorEvent::DrawText
             }
             Cmd::Yank(None) => {
-                todo!()
+                if let Some(range) = self.selection_chars() {
+                    let register = Register {
+                        text: self.text.slice(range.clone()).to_string(),
+                        linewise: false,
+                    };
+                    self.registers.yank(self.register, register);
+                    self.switch_mode(Mode::Normal);
+                    let line = self.text.char_to_line(range.start);
+                    self.goto(line, range.start - self.text.line_to_char(line));
+                }
+                EditorEvent::DrawCursor
+            }
+            Cmd::Indent(None) | Cmd::Dedent(None) => {
+                if let Some(range) = self.selection_chars() {
+                    let first = self.text.char_to_line(range.start);
+                    let last = self.text.char_to_line(range.end - 1);
+                    self.shift_lines(first..=last, matches!(cmd, Cmd::Dedent(None)));
+                    self.switch_mode(Mode::Normal);
+                    self.goto(first, range.start - self.text.line_to_char(first));
+                }
+                EditorEvent::DrawText
+            }
+            Cmd::Reindent(None) => {
+                if let Some(range) = self.selection_chars() {
+                    let first = self.text.char_to_line(range.start);
+                    let last = self.text.char_to_line(range.end - 1);
+                    self.reindent(first..=last);
+                    self.switch_mode(Mode::Normal);
+                }
+                EditorEvent::DrawText
+            }
+            Cmd::Comment(None) => {
+                if let Some(range) = self.selection_chars() {
+                    let first = self.text.char_to_line(range.start);
+                    let last = self.text.char_to_line(range.end - 1);
+                    self.toggle_comments(first..=last);
+                    self.switch_mode(Mode::Normal);
+                }
+                EditorEvent::DrawText
+            }
+            Cmd::Case(case, None) => {
+                if let Some(range) = self.selection_chars() {
+                    self.change_case(range.clone(), *case);
+                    self.switch_mode(Mode::Normal);
+                    let line = self.text.char_to_line(range.start);
+                    self.goto(line, range.start - self.text.line_to_char(line));
+                }
+                EditorEvent::DrawText
             }
+            Cmd::Register { name, cmd } => self.register_cmd(*name, cmd),
             // Command parser should only return repeated movement commands
             Cmd::Repeat { count, cmd } => self.repeated_cmd(*count, cmd),
             Cmd::Move(mv) => {
                 self.movement(mv);
                 EditorEvent::DrawCursor
             }
-            _ => panic!(
-                "Only Delete/Change/Yank/Repetition/Movement commands are valid in visual mode"
-            ),
+            // Only operators on the selection, repetition and movement commands are valid
+            // in visual mode,
+            // the parser still produces single key commands like `o` so ignore them
+            _ => EditorEvent::Nothing,
         }
     }
 
@@ -300,6 +882,7 @@ impl Editor {
                 EditorEvent::DrawCursor
             }
             Cmd::Repeat { count, cmd } => self.repeated_cmd(*count, cmd),
+            Cmd::Register { name, cmd } => self.register_cmd(*name, cmd),
             Cmd::Delete(None) => {
                 self.delete_line(self.line);
                 EditorEvent::DrawText
@@ -308,16 +891,106 @@ impl Editor {
                 self.delete_mv(mv);
                 EditorEvent::DrawText
             }
+            Cmd::DeleteChar => {
+                self.delete_chars_forward(1);
+                EditorEvent::DrawText
+            }
             Cmd::Change(None) => {
                 self.switch_mode(Mode::Insert);
                 self.delete_line(self.line);
                 EditorEvent::DrawText
             }
             Cmd::Change(Some(mv)) => {
+                // Delete before switching, `delete_range` isn't valid in insert mode
+                let groups = self.edits.len();
+                self.change_mv(mv);
                 self.switch_mode(Mode::Insert);
-                self.delete_mv(mv);
+                self.join_edit_group(groups);
+                EditorEvent::DrawText
+            }
+            Cmd::Yank(None) => {
+                let register = self.lines_register(self.line, self.line);
+                self.registers.yank(self.register, register);
+                EditorEvent::Nothing
+            }
+            Cmd::Yank(Some(mv)) => {
+                self.yank_mv(mv);
+                EditorEvent::DrawCursor
+            }
+            Cmd::Paste { before } => {
+                self.paste(*before);
+                EditorEvent::DrawText
+            }
+            Cmd::Reindent(None) => {
+                self.reindent(self.line..=self.line);
+                EditorEvent::DrawText
+            }
+            Cmd::Reindent(Some(mv)) => {
+                let lines = self.mv_lines(mv);
+                self.reindent(lines);
+                EditorEvent::DrawText
+            }
+            Cmd::Comment(None) => {
+                self.toggle_comments(self.line..=self.line);
+                EditorEvent::DrawText
+            }
+            Cmd::Comment(Some(mv)) => {
+                let lines = self.mv_lines(mv);
+                self.toggle_comments(lines);
+                EditorEvent::DrawText
+            }
+            Cmd::Case(case, None) => {
+                let start = self.text.line_to_char(self.line);
+                let end = start + self.line_count(self.line);
+                self.change_case(start..end, *case);
+                self.goto(self.line, 0);
+                EditorEvent::DrawText
+            }
+            Cmd::Case(case, Some(mv)) => {
+                self.case_mv(mv, *case);
                 EditorEvent::DrawText
             }
+            // Only parsed in visual mode
+            Cmd::Indent(_) | Cmd::Dedent(_) => EditorEvent::Nothing,
+            Cmd::GotoFile => {
+                let line = self.text.line(self.line).to_string();
+                self.goto_file = path_at(&line, self.cursor);
+                EditorEvent::GotoFile
+            }
+            // On the first non-blank char like Vim, past the end is the last
+            // line
+            Cmd::Ex(ExCmd::Line(_) | ExCmd::LastLine | ExCmd::RelativeLine(_)) => {
+                let line = match cmd {
+                    Cmd::Ex(ExCmd::Line(line)) => line.saturating_sub(1),
+                    Cmd::Ex(ExCmd::RelativeLine(n)) => self.line.saturating_add_signed(*n),
+                    _ => usize::MAX,
+                };
+                let line = line.min(self.lines.len() - 1);
+                let indent = self
+                    .text
+                    .line(line)
+                    .chars()
+                    .take_while(|c| *c == ' ' || *c == '\t')
+                    .count();
+                self.goto(line, indent);
+                EditorEvent::DrawCursor
+            }
+            Cmd::Ex(ExCmd::NoHighlight) => {
+                self.search_highlight = false;
+                EditorEvent::DrawCursor
+            }
+            Cmd::Ex(cmd) => {
+                self.ex_cmd = Some(cmd.clone());
+                EditorEvent::ExCmd
+            }
+            Cmd::Search { pattern, reverse } => {
+                if !pattern.is_empty() {
+                    self.search = Some(pattern.clone());
+                }
+                self.search_reverse = *reverse;
+                self.movement(&Move::NextMatch(false));
+                EditorEvent::DrawCursor
+            }
             Cmd::Move(mv) => {
                 self.movement(mv);
                 EditorEvent::DrawCursor
@@ -344,14 +1017,40 @@ impl Editor {
                 }
                 EditorEvent::DrawCursor
             }
-            r => todo!("Unimplemented: {:?}", r),
         }
     }
 
+    fn register_cmd(&mut self, name: char, cmd: &Cmd) -> EditorEvent {
+        self.register = Some(name);
+        let ret = self.handle_cmd(cmd);
+        self.register = None;
+        ret
+    }
+
     fn repeated_cmd(&mut self, count: u16, cmd: &Cmd) -> EditorEvent {
+        // These take the count themselves so they're a single undo step and
+        // stop at the end of the line or buffer
+        match cmd {
+            Cmd::DeleteChar => {
+                self.delete_chars_forward(count as usize);
+                return EditorEvent::DrawText;
+            }
+            Cmd::Delete(None) if matches!(self.mode, Mode::Normal) => {
+                let last = (self.line + count as usize - 1).min(self.lines.len() - 1);
+                self.delete_lines(self.line, last);
+                return EditorEvent::DrawText;
+            }
+            _ => {}
+        }
+
         let mut ret = EditorEvent::DrawCursor;
         for _ in 0..count {
             ret = self.handle_cmd(cmd);
+            // Vim commands can't run in insert mode, so ones that switch to it
+            // like `c` or `o` only apply once
+            if self.is_insert() {
+                break;
+            }
         }
         ret
     }
@@ -401,10 +1100,16 @@ impl Editor {
             }
             Move::Up => self.up(1),
             Move::Down => self.down(1),
+            Move::DisplayUp => self.display_move(false),
+            Move::DisplayDown => self.display_move(true),
             Move::Left => self.left(1),
             Move::Right => return self.right(1),
             Move::LineStart => self.move_pos(0),
             Move::LineEnd => self.move_pos(usize::MAX),
+            Move::Repeat { .. } if self.paragraph_motion(mv).is_some() => {
+                let target = self.paragraph_motion(mv).unwrap();
+                self.goto_paragraph(target);
+            }
             Move::Repeat { count, mv } => {
                 // TODO: We can be smarter about this and pass
                 // the count into the movement, ex. `10l` -> `self.right(10).
@@ -420,13 +1125,15 @@ impl Editor {
             Move::Find(c, reverse) => {
                 self.cursor = self.find_line(*c, !reverse).unwrap_or(self.cursor);
             }
-            Move::ParagraphBegin => {
-                self.line = self.prev_paragraph();
-                self.sync_line_cursor();
+            Move::ParagraphBegin | Move::ParagraphEnd => {
+                let target = self.paragraph_motion(mv).unwrap();
+                self.goto_paragraph(target);
             }
-            Move::ParagraphEnd => {
-                self.line = self.next_paragraph();
-                self.sync_line_cursor();
+            Move::NextMatch(back) => {
+                self.search_highlight = self.search.is_some();
+                if let Some(pos) = self.next_match(self.search_reverse == *back) {
+                    self.goto_pos(pos);
+                }
             }
         };
         false
@@ -446,389 +1153,1086 @@ impl Editor {
         }
     }
 
-    fn delete_mv(&mut self, mv: &Move) {
-        let cursor = self.cursor;
-        let line = self.line;
-        let start = self.pos();
-        let truncated_eol = self.movement(mv);
-        let mut end = self.pos();
-
-        if truncated_eol {
-            end = self.pos() + 1;
+    /// The selected chars, including the one at the end like Vim does
+    fn selection_chars(&self) -> Option<Range<usize>> {
+        let (a, b) = self.selection?;
+        let (start, end) = (a.min(b) as usize, a.max(b) as usize);
+        let len = self.text.len_chars();
+        if start >= len {
+            return None;
         }
+        Some(start..(end + 1).min(len))
+    }
 
-        match start.cmp(&end) {
-            Ordering::Equal => self.delete_range(start..(start + 1)),
-            Ordering::Less => self.delete_range(start..end),
-            Ordering::Greater => self.delete_range(end..start),
+    /// Indent or dedent the non-empty lines in `lines` by one level as a
+    /// single undo step
+    fn shift_lines(&mut self, lines: RangeInclusive<usize>, dedent: bool) {
+        let groups = self.edits.len();
+        for line in lines.clone() {
+            let start = self.text.line_to_char(line);
+            if dedent {
+                let count = self.indent.level_len(self.text.line(line).chars());
+                if count == 0 {
+                    continue;
+                }
+                let removed = self.text.slice(start..start + count).chars().collect();
+                self.remove_text(start..start + count);
+                self.push_deletion(start, removed);
+            } else {
+                if self.lines[line] == 0 {
+                    continue;
+                }
+                let unit = self.indent.unit();
+                self.insert_text(start, &unit);
+                self.push_insertion(start, &unit);
+            }
+            self.join_edit_group(groups);
         }
+        self.close_normal_mode_group();
 
-        // Return cursor back to starting position
-        // TODO: This breaks if we delete backwards for example `d{`
-        self.cursor = cursor;
-        self.line = line;
+        let (first, last) = (*lines.start(), *lines.end());
+        self.splice_lines(first, last, last);
+        self.signs.mark(SignNamespace::Changed, lines);
     }
 
-    fn insert(&mut self, text: &str) {
-        let pos = self.pos();
+    /// Rewrite the leading whitespace of `lines` to their indent in the
+    /// syntax tree, or from the brackets without a grammar, as a single undo
+    /// step. The cursor goes to the first char of the first line
+    fn reindent(&mut self, lines: RangeInclusive<usize>) {
+        let text = self.text.to_string();
+        let tree = self.language.and_then(|language| {
+            let mut parser = Parser::new();
+            parser.set_language(language).ok()?;
+            parser.parse(&text, None)
+        });
+        let levels = match &tree {
+            Some(tree) => tree_indents(tree, &text, lines.clone()),
+            None => bracket_indents(&text, lines.clone()),
+        };
 
-        self.text.insert(pos, text);
-        self.cursor += text.len();
-        self.lines[self.line] += text.len() as u32;
-
-        let char = text.chars().next().unwrap();
-        match self.edits.last_mut() {
-            _ if self.had_space => {
-                let vec = vec![char];
-                self.edit_vecs.push(vec);
-                let idx = self.edit_vecs.len() - 1;
-                self.edits.push(Edit::Insertion {
-                    start: Cell::new(pos as u32),
-                    str_idx: idx as u32,
-                });
-                self.had_space = false;
+        let groups = self.edits.len();
+        for (line, level) in lines.clone().zip(levels) {
+            let level = match level {
+                Some(level) => level,
+                None => continue,
+            };
+            let start = self.text.line_to_char(line);
+            let whitespace = self
+                .text
+                .line(line)
+                .chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .count();
+            let indent = self.indent.unit().repeat(level);
+            if self.text.slice(start..start + whitespace) == indent.as_str() {
+                continue;
             }
-            Some(Edit::Insertion { str_idx: str, .. }) => {
-                let is_space = text == " ";
-                self.edit_vecs[*str as usize].push(char);
-                if is_space {
-                    self.had_space = true;
-                }
+
+            if whitespace > 0 {
+                let removed = self.text.slice(start..start + whitespace).chars().collect();
+                self.remove_text(start..start + whitespace);
+                self.push_deletion(start, removed);
+                self.join_edit_group(groups);
             }
-            None | Some(Edit::Deletion { .. }) => {
-                self.edit_vecs.push(vec![char]);
-                self.edits.push(Edit::Insertion {
-                    start: Cell::new(pos as u32),
-                    str_idx: self.edit_vecs.len() as u32 - 1,
-                })
+            if !indent.is_empty() {
+                self.insert_text(start, &indent);
+                self.push_insertion(start, &indent);
+                self.join_edit_group(groups);
             }
+            self.signs.mark(SignNamespace::Changed, line..=line);
         }
-        // Invalidate redo stack if we make an edit
-        if !self.redos.is_empty() {
-            self.redos.clear()
-        }
-    }
+        self.close_normal_mode_group();
 
-    fn backspace(&mut self) -> EditorEvent {
-        if self.cursor == 0 && self.line == 0 {
-            return EditorEvent::Nothing;
-        }
+        let (first, last) = (*lines.start(), *lines.end());
+        self.splice_lines(first, last, last);
+        let indent = self
+            .text
+            .line(first)
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .count();
+        self.goto(first, indent);
+    }
 
-        let pos = self.pos();
-        let removed: Option<char> = if self.text.len_chars() > 0 {
-            let c = self.text.char(if pos == 0 { 0 } else { pos - 1 });
-            self.text.remove(pos - 1..pos);
-            Some(c)
-        } else {
-            None
+    /// Comment out `lines` with the language's line comment after their
+    /// indent, or uncomment them when every one already is, as a single undo
+    /// step. Blank lines are left alone, and files without line comments
+    /// aren't changed. The cursor goes to the first char of the first line
+    fn toggle_comments(&mut self, lines: RangeInclusive<usize>) {
+        let Some(prefix) = self.language.and_then(syntax::line_comment) else {
+            return;
         };
-        self.cursor = if self.cursor > 0 {
-            self.lines[self.line] -= 1;
-            self.cursor - 1
-        } else if self.line > 0 {
-            // Backspacing into previous line
-            let merge_line = self.lines.remove(self.line);
-            self.line -= 1;
-            self.lines[self.line] += merge_line;
-            self.lines[self.line] as usize
-        } else {
-            0
+        // The start of each non-blank line's text, after its indent
+        let starts: Vec<(usize, usize)> = lines
+            .clone()
+            .filter_map(|line| {
+                let indent = self
+                    .text
+                    .line(line)
+                    .chars()
+                    .take_while(|c| *c == ' ' || *c == '\t')
+                    .count();
+                (indent < self.lines[line] as usize)
+                    .then(|| (line, self.text.line_to_char(line) + indent))
+            })
+            .collect();
+        let commented = |start: usize| {
+            self.text
+                .chars_at(start)
+                .take(prefix.len())
+                .eq(prefix.chars())
         };
-        if let Some(c) = removed {
-            match self.edits.last_mut() {
-                Some(Edit::Deletion { start, str_idx }) => {
-                    let val = start.get();
-                    if val > 0 {
-                        start.set(val - 1)
-                    }
-                    self.edit_vecs[*str_idx as usize].push(c);
-                }
-                None | Some(Edit::Insertion { .. }) => {
-                    self.edit_vecs.push(vec![c]);
-                    self.edits.push(Edit::Deletion {
-                        start: Cell::new(pos as u32 - 1),
-                        str_idx: self.edit_vecs.len() as u32 - 1,
-                    });
+        let uncomment = !starts.is_empty() && starts.iter().all(|(_, start)| commented(*start));
+
+        let groups = self.edits.len();
+        // From the last line so the earlier starts stay put
+        for &(line, start) in starts.iter().rev() {
+            if uncomment {
+                let mut end = start + prefix.chars().count();
+                if end < self.text.len_chars() && self.text.char(end) == ' ' {
+                    end += 1;
                 }
+                let removed = self.text.slice(start..end).chars().collect();
+                self.remove_text(start..end);
+                self.push_deletion(start, removed);
+            } else {
+                let comment = format!("{} ", prefix);
+                self.insert_text(start, &comment);
+                self.push_insertion(start, &comment);
             }
+            self.join_edit_group(groups);
+            self.signs.mark(SignNamespace::Changed, line..=line);
         }
-        EditorEvent::DrawText
+        self.close_normal_mode_group();
+
+        let (first, last) = (*lines.start(), *lines.end());
+        self.splice_lines(first, last, last);
+        let indent = self
+            .text
+            .line(first)
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .count();
+        self.goto(first, indent);
     }
 
-    /// Delete chars in a range.
-    ///
-    /// ### Normal mode
-    /// If the range spans multiple lines then we just apply it to the entire line,
-    /// this is the same behaviour demonstrated by Vim. For example, try the command
-    /// `3dj` this will delete the next 3 lines in totality. It doesn't split the lines up.
-    ///
-    /// ### Visual mode
-    /// Behaves as expected, cutting and splicing lines instead of deleting them in totality
-    #[inline]
-    fn delete_range(&mut self, range: Range<usize>) {
-        let (start, end) = match self.mode {
-            // Start and ending lines
-            Mode::Normal => (
-                self.text.char_to_line(range.start),
-                self.text.char_to_line(range.end),
-            ),
-            Mode::Visual => (range.start, range.end),
-            Mode::Insert => panic!("delete_range should not be called in insert mode"),
-        };
+    /// The lines a linewise operator with the movement `mv` applies to
+    fn mv_lines(&mut self, mv: &Move) -> RangeInclusive<usize> {
+        let (line, cursor) = (self.line, self.cursor);
+        self.movement(mv);
+        let target = self.line;
+        self.line = line;
+        self.cursor = cursor;
+        line.min(target)..=line.max(target)
+    }
 
-        if start == end {
-            self.text.remove(range);
-            self.lines[start] = self.line_count(start) as u32;
-        } else if matches!(self.mode, Mode::Normal) {
-            let start = self.text.line_to_char(start);
-            let end = self.text.line_to_char(end) + self.text.line(end).len_chars();
+    /// Change the case of the chars in `range` as a single undo step
+    fn change_case(&mut self, range: Range<usize>, case: Case) {
+        self.map_chars(range, |c, new| match case {
+            Case::Lower => new.extend(c.to_lowercase()),
+            Case::Upper => new.extend(c.to_uppercase()),
+            Case::Toggle if c.is_lowercase() => new.extend(c.to_uppercase()),
+            Case::Toggle => new.extend(c.to_lowercase()),
+        });
+    }
 
-            self.text.remove(start..end);
+    /// Replace each char in `range` with what `f` pushes for it as a single
+    /// undo step. A char can become more than one, like `ß` uppercasing to
+    /// `SS`, but `f` mustn't add or remove new lines
+    fn map_chars(&mut self, range: Range<usize>, mut f: impl FnMut(char, &mut String)) {
+        let old: Vec<char> = self.text.slice(range.clone()).chars().collect();
+        let mut new = String::with_capacity(old.len());
+        for c in &old {
+            f(*c, &mut new);
+        }
+        if new.chars().eq(old.iter().copied()) {
+            return;
+        }
 
-            let mut i = start;
-            for _ in start..(end + 1) {
-                if self.lines.is_empty() {
-                    break;
-                }
-                self.lines.remove(i);
-                if i >= self.lines.len() && !self.lines.is_empty() {
-                    i -= 1;
-                }
-            }
-        } else {
-            let line_pos = self.text.char_to_line(start);
+        let first = self.text.char_to_line(range.start);
+        let last = self.text.char_to_line(range.end);
+        let groups = self.edits.len();
+        self.remove_text(range.clone());
+        self.push_deletion(range.start, old);
+        self.join_edit_group(groups);
+        self.insert_text(range.start, &new);
+        self.push_insertion(range.start, &new);
+
+        // Only the lengths of the lines can change
+        self.splice_lines(first, last, last);
+        self.signs.mark(SignNamespace::Changed, first..=last);
+    }
 
-            self.text.remove(start..end);
+    fn delete_mv(&mut self, mv: &Move) {
+        if let Some(target) = self.paragraph_motion(mv) {
+            self.delete_paragraph(target);
+            return;
+        }
 
-            // TODO: Be smarter about this and only compute the lines affected
-            self.lines = text_to_lines(self.text.chars());
+        let cursor = self.cursor;
+        let range = self.mv_range(mv);
+        self.delete_mv_range(range, cursor);
+    }
 
-            self.line = line_pos;
-            self.cursor = start - self.text.line_to_char(line_pos);
+    /// Delete the `range` of a movement that started at column `cursor`
+    fn delete_mv_range(&mut self, range: Range<usize>, cursor: usize) {
+        let first = self.text.char_to_line(range.start);
+        let last = self.text.char_to_line(range.end);
+        self.delete_range(range.clone());
+
+        // Deleting within a line leaves the cursor where the deleted text
+        // started, which is before it for backwards movements like `db`.
+        // Deleting lines keeps the column on the line that moves up
+        if first == last {
+            self.goto(first, range.start - self.text.line_to_char(first));
+        } else {
+            self.goto(first, cursor);
         }
     }
 
-    fn delete_line(&mut self, line: usize) {
-        let pos = self.line_pos();
-        if self.lines.len() > 1 {
-            let len =
-                // Include new line character, except if we one the last line which doesn't have it
-                if line == (self.lines.len() - 1) { 0 } else { 1 } + self.lines.remove(line);
+    /// `delete_mv` except `cw` on a word stops at the end of it like `ce`,
+    /// leaving the whitespace after it. On whitespace it's the same as `dw`
+    fn change_mv(&mut self, mv: &Move) {
+        let word = match mv {
+            Move::Word(_) => true,
+            Move::Repeat { mv, .. } => matches!(**mv, Move::Word(_)),
+            _ => false,
+        };
+        let on_word = matches!(self.text.get_char(self.pos()), Some(c) if !c.is_whitespace());
+        if !word || !on_word {
+            self.delete_mv(mv);
+            return;
+        }
 
-            self.text.remove(pos..(pos + len as usize))
-        } else {
-            self.lines[0] = 0;
-            // Including \n from the last line
-            self.text.remove(0..self.text.len_chars());
-            self.cursor = 0;
+        // `w` stops at the start of the next word, so back off the blanks
+        // before it. The word under the cursor keeps at least its first char
+        let cursor = self.cursor;
+        let mut range = self.mv_range(mv);
+        while range.end > range.start + 1 && self.text.char(range.end - 1).is_whitespace() {
+            range.end -= 1;
         }
+        self.delete_mv_range(range, cursor);
     }
 
-    /// Insert a new line and splitting the current one based on the cursor position
-    fn enter(&mut self) {
-        match self.lines[self.line] {
-            0 => {
-                if self.cursor == 0 {
-                    self.new_line();
-                    return;
-                }
-            }
-            r => {
-                if self.cursor == r as usize {
-                    self.new_line();
-                    return;
-                }
-            }
+    /// Delete up to `count` chars starting at the cursor, without going past
+    /// the end of the line
+    fn delete_chars_forward(&mut self, count: usize) {
+        let len = self.lines[self.line] as usize;
+        if self.cursor >= len {
+            return;
         }
-        let pos = self.pos();
-        self.text.insert(pos, "\n");
-
-        let new_line_count = self.lines[self.line] as usize - self.cursor;
-        self.lines[self.line] = self.cursor as u32;
+        let start = self.pos();
+        let end = start + count.min(len - self.cursor);
+        self.delete_range(start..end);
+        self.clamp_cursor();
+    }
 
-        self.line += 1;
+    /// The chars an operator with the movement `mv` applies to, the cursor
+    /// is left where it was
+    fn mv_range(&mut self, mv: &Move) -> Range<usize> {
+        let cursor = self.cursor;
+        let line = self.line;
+        let start = self.pos();
+        let truncated_eol = self.movement(mv);
+        let mut end = self.pos();
+        let len = self.text.len_chars();
 
-        if self.line >= self.lines.len() {
-            self.lines.push(new_line_count as u32)
-        } else {
-            self.lines.insert(self.line, new_line_count as u32);
+        if truncated_eol {
+            end = (self.pos() + 1).min(len);
         }
+        self.line = line;
+        self.cursor = cursor;
 
-        self.cursor = 0;
-    }
-
-    fn add_whitespace(&mut self, pos: usize, count: usize) {
-        for i in 0..count {
-            self.text.insert_char(pos + i, ' ');
+        match start.cmp(&end) {
+            // Nothing at the end of the buffer
+            Ordering::Equal if start >= len => start..start,
+            Ordering::Equal => start..(start + 1),
+            Ordering::Less => start..end,
+            Ordering::Greater => end..start,
         }
     }
 
-    // Insert a new line
-    fn new_line(&mut self) {
-        let is_last = self.line == self.lines.len() - 1;
-        let mut pos =
-            self.line_pos() + self.lines[self.line] as usize + if is_last { 0 } else { 1 };
-        if is_last {
-            self.text.insert(pos, "\n");
-            pos += 1;
-        }
-        let count = self
-            .text
-            .line(self.line)
-            .chars()
-            .enumerate()
-            .find_map(|(i, c)| if c != ' ' { Some(i) } else { None })
-            .unwrap_or(0);
-        self.add_whitespace(pos, count);
-        if !is_last {
-            self.text.insert(pos + count, "\n");
+    /// Like deleting, a movement that ends on another line changes whole
+    /// lines. The cursor goes to the start of what was changed
+    fn case_mv(&mut self, mv: &Move, case: Case) {
+        let mut range = self.mv_range(mv);
+        if range.is_empty() {
+            return;
         }
 
-        self.cursor = count;
-        self.line += 1;
-
-        if self.line >= self.lines.len() {
-            self.lines.push(count as u32)
+        let first = self.text.char_to_line(range.start);
+        let mut last = self.text.char_to_line(range.end);
+        // Same as Vim, a movement that stops at the start of a line like `w`
+        // on the last word ends on the line before it
+        if last > first && range.end == self.text.line_to_char(last) {
+            last -= 1;
+            range.end = self.text.line_to_char(last) + self.line_count(last);
+        }
+        if first == last {
+            self.change_case(range.clone(), case);
+            self.goto(first, range.start - self.text.line_to_char(first));
         } else {
-            self.lines.insert(self.line, count as u32);
+            let start = self.text.line_to_char(first);
+            let end = self.text.line_to_char(last) + self.line_count(last);
+            self.change_case(start..end, case);
+            self.goto(first, 0);
         }
     }
 
-    fn new_line_before(&mut self) {
-        let pos = self.line_pos();
-        // The new line character of previous line
-        let pos = if pos == 0 { 0 } else { pos };
-
-        let count = self
-            .text
-            .line(self.line)
-            .chars()
-            .enumerate()
-            .find_map(|(i, c)| if c != ' ' { Some(i) } else { None })
-            .unwrap_or(0);
+    /// Like deleting, a movement that ends on another line yanks whole lines
+    fn yank_mv(&mut self, mv: &Move) {
+        let range = self.mv_range(mv);
+        if range.is_empty() {
+            return;
+        }
 
-        self.cursor = count;
+        let first_line = self.text.char_to_line(range.start);
+        let last_line = self.text.char_to_line(range.end);
+        let register = if first_line == last_line {
+            Register {
+                text: self.text.slice(range).to_string(),
+                linewise: false,
+            }
+        } else {
+            self.lines_register(first_line, last_line)
+        };
+        self.registers.yank(self.register, register);
+    }
 
-        self.add_whitespace(pos, count);
-        self.text.insert(pos + count, "\n");
+    /// Put the text in the register after the cursor, or before it if
+    /// `before`. Lines go below or above the cursor's line
+    fn paste(&mut self, before: bool) {
+        let register = match self.registers.paste(self.register) {
+            Some(register) if !register.text.is_empty() => register,
+            _ => return,
+        };
+        let text = register.text.as_str();
+        let new_lines = text.matches('\n').count();
+
+        if register.linewise {
+            let line = if before { self.line } else { self.line + 1 };
+            if line < self.lines.len() {
+                let pos = self.text.line_to_char(line);
+                self.insert_text(pos, text);
+                self.push_insertion(pos, text);
+                self.splice_lines(line, line, line + new_lines);
+            } else {
+                // The last line has no new line character to put the text after,
+                // so move the register's to the front
+                let pos = self.text.len_chars();
+                let text = format!("\n{}", &text[..text.len() - 1]);
+                self.insert_text(pos, &text);
+                self.push_insertion(pos, &text);
+                self.splice_lines(line - 1, line - 1, line - 1 + new_lines);
+            }
 
-        self.line = if self.line == 0 { 0 } else { self.line };
+            self.signs.shift(line, new_lines as isize);
+            self.signs
+                .mark(SignNamespace::Changed, line..=(line + new_lines - 1));
 
-        self.lines.insert(self.line, count as u32);
+            // Same as Vim, go to the first non-blank char of the first pasted line
+            self.line = line;
+            self.cursor = self
+                .text
+                .line(line)
+                .chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .count();
+            self.clamp_cursor();
+        } else {
+            // Pasting after the new line character would put the text on the next line
+            self.clamp_cursor();
+            let line = self.line;
+            let pos = if before || self.lines[line] == 0 {
+                self.pos()
+            } else {
+                self.pos() + 1
+            };
+            self.insert_text(pos, text);
+            self.push_insertion(pos, text);
+            self.splice_lines(line, line, line + new_lines);
+
+            self.signs.shift(line + 1, new_lines as isize);
+            self.signs
+                .mark(SignNamespace::Changed, line..=(line + new_lines));
+
+            // The cursor ends up on the last pasted char, or the first if
+            // the text has multiple lines
+            let cursor_pos = if new_lines == 0 {
+                pos + text.chars().count() - 1
+            } else {
+                pos
+            };
+            self.line = self.text.char_to_line(cursor_pos);
+            self.cursor = cursor_pos - self.text.line_to_char(self.line);
+            self.clamp_cursor();
+        }
     }
-}
 
-// This impl contains movement utilities
-impl Editor {
-    fn word_indicies(
-        &mut self,
-        mut start: usize,
-        mut end: usize,
-        chars: Vec<char>,
-        skip_punctuation: bool,
-    ) -> Vec<(usize, usize)> {
-        let len = chars.len();
-        let mut idxs: Vec<(usize, usize)> = Vec::new();
-        let mut searching_start = false;
+    /// Lines `first..=last` as a linewise register holds them
+    fn lines_register(&self, first: usize, last: usize) -> Register {
+        let start = self.text.line_to_char(first);
+        let end = self.text.line_to_char(last) + self.text.line(last).len_chars();
+        let mut text = self.text.slice(start..end).to_string();
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        Register {
+            text,
+            linewise: true,
+        }
+    }
 
-        while end < len && start < len {
-            if searching_start {
-                if chars[start] == ' ' {
-                    start += 1;
-                } else {
-                    searching_start = false;
-                    end = start + 1;
-                }
-            } else {
-                if Editor::is_word_separator(chars[end], skip_punctuation) {
-                    idxs.push((start, end));
-                    searching_start = true;
-                    start = end;
-                }
-                end += 1;
+    /// `{` and `}` are exclusive, so the blank line they land on is kept. Like Vim
+    /// they delete whole lines when the cursor is at the start of the line, otherwise
+    /// only the chars between the cursor and the blank line
+    fn delete_paragraph(&mut self, target: usize) {
+        let pos = self.pos();
+        match target.cmp(&self.line) {
+            Ordering::Equal => {}
+            Ordering::Greater if !self.is_paragraph_boundary(target) => {
+                // Ran into the end of the buffer, delete everything up to it
+                self.delete_chars(pos..self.text.len_chars());
+            }
+            Ordering::Greater if self.cursor == 0 => {
+                let line = self.line;
+                self.delete_lines(line, target - 1);
+                self.line = line.min(self.lines.len() - 1);
+                self.cursor = 0;
+            }
+            Ordering::Greater => {
+                let end = self.text.line_to_char(target) - 1;
+                self.delete_chars(pos..end);
+            }
+            Ordering::Less if self.cursor == 0 => {
+                self.delete_lines(target, self.line - 1);
+                self.line = target;
+                self.cursor = 0;
+            }
+            Ordering::Less => {
+                self.delete_chars(self.text.line_to_char(target)..pos);
             }
         }
+        self.clamp_cursor();
+    }
 
-        if !searching_start {
-            idxs.push((start, end));
+    fn insert(&mut self, text: &str) {
+        let pos = self.pos();
+        if self.insert_start.is_none() {
+            self.insert_start = Some((self.line, self.cursor));
         }
 
-        idxs
+        self.insert_text(pos, text);
+        let len = text.chars().count();
+        self.cursor += len;
+        self.lines[self.line] += len as u32;
+        self.signs
+            .mark(SignNamespace::Changed, self.line..=self.line);
+
+        self.push_insertion(pos, text);
     }
 
-    fn next_word(&mut self, mv: MoveWord, line: usize, mut cursor: usize, match_first_word: bool) {
-        use MoveWordKind::*;
-        let is_not_last = match mv.kind {
-            Next | End => line < (self.lines.len() - 1),
-            Prev => line > 0,
+    /// Insert typed text. With `auto_pairs` an opening bracket or quote gets
+    /// its closing one after the cursor, and typing a closing one over the
+    /// same char moves past it instead. A closing bracket is dedented when
+    /// it's the first char on the line
+    fn type_text(&mut self, text: &str) {
+        let mut chars = text.chars();
+        let (Some(ch), None) = (chars.next(), chars.next()) else {
+            self.insert(text);
+            return;
         };
+        if CLOSING_BRACKETS.contains(&ch) {
+            self.dedent_closing();
+        }
+        if !self.auto_pairs {
+            self.insert(text);
+            return;
+        }
 
-        if self.lines[line] == 0 {
-            if is_not_last {
-                match mv.kind {
-                    Next | End => self.next_word(mv, line + 1, 0, true),
-                    Prev => self.next_word(mv, line - 1, usize::MAX, true),
-                }
-            }
+        let pos = self.pos();
+        let next = (self.cursor < self.lines[self.line] as usize).then(|| self.text.char(pos));
+        if next == Some(ch) && AUTO_PAIRS.iter().any(|(_, close)| *close == ch) {
+            self.cursor += 1;
             return;
         }
 
-        let chars: Vec<char> = match mv.kind {
-            Next | End => self.text.line(line).chars().collect(),
-            Prev => {
-                let mut chars: Vec<char> = self.text.line(line).chars().collect();
-                chars.reverse();
-                chars
+        let prev = (self.cursor > 0).then(|| self.text.char(pos - 1));
+        match AUTO_PAIRS.iter().find(|(open, _)| *open == ch) {
+            // A quote right after a word is an apostrophe or ends a string
+            Some((open, close)) if open != close || !prev.is_some_and(char::is_alphanumeric) => {
+                self.insert(&format!("{}{}", open, close));
+                self.cursor -= 1;
+                self.auto_closed = true;
             }
+            _ => self.insert(text),
+        }
+    }
+
+    /// Like `insert` for text that can have new lines in it, the cursor ends
+    /// up after it
+    fn insert_lines(&mut self, text: &str) {
+        let pos = self.pos();
+        if self.insert_start.is_none() {
+            self.insert_start = Some((self.line, self.cursor));
+        }
+
+        let new_lines = text.matches('\n').count();
+        self.insert_text(pos, text);
+        self.push_insertion(pos, text);
+        self.splice_lines(self.line, self.line, self.line + new_lines);
+        self.signs.shift(self.line + 1, new_lines as isize);
+        self.signs
+            .mark(SignNamespace::Changed, self.line..=(self.line + new_lines));
+
+        let end = pos + text.chars().count();
+        self.line = self.text.char_to_line(end);
+        self.cursor = end - self.text.line_to_char(self.line);
+    }
+
+    fn backspace(&mut self) -> EditorEvent {
+        if self.cursor == 0 && self.line == 0 {
+            return EditorEvent::Nothing;
+        }
+
+        let pos = self.pos();
+        let removed: Option<char> = if self.text.len_chars() > 0 {
+            let c = self.text.char(if pos == 0 { 0 } else { pos - 1 });
+            self.remove_text(pos - 1..pos);
+            Some(c)
+        } else {
+            None
         };
-        let len = chars.len();
-        if cursor > len {
-            cursor = len - 1;
+        self.cursor = if self.cursor > 0 {
+            self.lines[self.line] -= 1;
+            self.cursor - 1
+        } else if self.line > 0 {
+            // Backspacing into previous line
+            let merge_line = self.lines.remove(self.line);
+            self.signs.shift(self.line, -1);
+            self.line -= 1;
+            self.lines[self.line] += merge_line;
+            self.lines[self.line] as usize
+        } else {
+            0
+        };
+        self.signs
+            .mark(SignNamespace::Changed, self.line..=self.line);
+        if let Some(c) = removed {
+            self.push_deletion(pos - 1, vec![c]);
+        }
+        EditorEvent::DrawText
+    }
+
+    /// Forward delete, removes the char under the cursor or joins the next line
+    /// when at the end of this one
+    fn delete(&mut self) -> EditorEvent {
+        let pos = self.pos();
+        if pos >= self.text.len_chars() {
+            return EditorEvent::Nothing;
         }
 
-        let start = self
+        let c = self.text.char(pos);
+        self.remove_text(pos..pos + 1);
+        if self.cursor < self.lines[self.line] as usize {
+            self.lines[self.line] -= 1;
+        } else {
+            let merge_line = self.lines.remove(self.line + 1);
+            self.signs.shift(self.line + 1, -1);
+            self.lines[self.line] += merge_line;
+        }
+        self.signs
+            .mark(SignNamespace::Changed, self.line..=self.line);
+        self.push_deletion(pos, vec![c]);
+        EditorEvent::DrawText
+    }
+
+    /// `Ctrl-W`, delete the whitespace before the cursor and then the word or
+    /// run of punctuation before that. Stops at the start of the line like Vim
+    fn delete_word_before_cursor(&mut self) -> EditorEvent {
+        let line_start = self.text.line_to_char(self.line);
+        let chars: Vec<char> = self
             .text
-            .line(line)
+            .slice(line_start..line_start + self.cursor)
             .chars()
-            .enumerate()
-            .skip(if matches!(mv.kind, Prev) {
-                len - cursor
-            } else {
-                cursor
-            })
-            .find_map(|(i, c)| {
-                if Editor::is_word_separator(c, mv.skip_punctuation) {
-                    None
-                } else {
-                    Some(i)
-                }
-            });
+            .collect();
 
-        if start.is_none() {
-            if is_not_last {
-                match mv.kind {
-                    Next | End => self.next_word(mv, line + 1, 0, true),
-                    Prev => self.next_word(mv, line - 1, usize::MAX, true),
-                };
+        let mut start = chars.len();
+        while start > 0 && chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        if start > 0 {
+            let is_separator = Editor::is_word_separator(chars[start - 1], false);
+            while start > 0
+                && !chars[start - 1].is_whitespace()
+                && Editor::is_word_separator(chars[start - 1], false) == is_separator
+            {
+                start -= 1;
             }
-            return;
         }
 
-        let start = unsafe { start.unwrap_unchecked() };
+        self.delete_before_cursor(start)
+    }
 
-        let end = start + 1;
-        if end >= len {
-            if is_not_last {
-                match mv.kind {
-                    Next | End => self.next_word(mv, line + 1, 0, true),
-                    Prev => self.next_word(mv, line - 1, usize::MAX, true),
-                };
-            }
-            return;
+    /// Delete the current line's chars from the column `start` up to the cursor
+    fn delete_before_cursor(&mut self, start: usize) -> EditorEvent {
+        if start >= self.cursor {
+            return EditorEvent::Nothing;
         }
 
-        let idxs: Vec<(usize, usize)> = {
-            let idxs = self.word_indicies(start, end, chars, mv.skip_punctuation);
+        let line_start = self.text.line_to_char(self.line);
+        let range = line_start + start..line_start + self.cursor;
+        let removed = self.text.slice(range.clone()).chars().collect();
+        self.remove_text(range.clone());
+        self.lines[self.line] -= range.len() as u32;
+        self.cursor = start;
+        self.signs
+            .mark(SignNamespace::Changed, self.line..=self.line);
+        self.push_deletion(range.start, removed);
+        EditorEvent::DrawText
+    }
+
+    /// Delete chars in a range.
+    ///
+    /// ### Normal mode
+    /// If the range spans multiple lines then we just apply it to the entire line,
+    /// this is the same behaviour demonstrated by Vim. For example, try the command
+    /// `3dj` this will delete the next 3 lines in totality. It doesn't split the lines up.
+    ///
+    /// ### Visual mode
+    /// Behaves as expected, cutting and splicing lines instead of deleting them in totality
+    #[inline]
+    fn delete_range(&mut self, range: Range<usize>) {
+        if matches!(self.mode, Mode::Insert) {
+            panic!("delete_range should not be called in insert mode")
+        }
+        if range.is_empty() {
+            return;
+        }
+
+        let first_line = self.text.char_to_line(range.start);
+        let last_line = self.text.char_to_line(range.end);
+
+        if matches!(self.mode, Mode::Normal) && first_line == last_line {
+            let removed: Vec<char> = self.text.slice(range.clone()).chars().collect();
+            self.registers.delete(
+                self.register,
+                Register {
+                    text: removed.iter().collect(),
+                    linewise: false,
+                },
+            );
+            self.remove_text(range.clone());
+            self.push_deletion(range.start, removed);
+            self.lines[first_line] = self.line_count(first_line) as u32;
+            self.signs
+                .mark(SignNamespace::Changed, first_line..=first_line);
+        } else if matches!(self.mode, Mode::Normal) {
+            self.delete_lines(first_line, last_line);
+        } else {
+            self.delete_chars(range);
+        }
+    }
+
+    /// Delete the lines `first..=last` in their entirety
+    fn delete_lines(&mut self, first_line: usize, last_line: usize) {
+        let mut start = self.text.line_to_char(first_line);
+        let end = self.text.line_to_char(last_line) + self.text.line(last_line).len_chars();
+        // The last line has no new line character, take the one before
+        // the range instead so an empty line isn't left behind
+        if last_line == self.lines.len() - 1 && first_line > 0 {
+            start -= 1;
+        }
+        let register = self.lines_register(first_line, last_line);
+        self.registers.delete(self.register, register);
+
+        // Nothing to remove in an empty buffer, and an empty undo step would
+        // make the next `u` do nothing
+        if start < end {
+            let removed = self.text.slice(start..end).chars().collect();
+            self.remove_text(start..end);
+            self.push_deletion(start, removed);
+        }
+
+        self.lines.drain(first_line..=last_line);
+        if self.lines.is_empty() {
+            self.lines.push(0);
+        }
+
+        self.signs
+            .shift(first_line, -((last_line - first_line + 1) as isize));
+        let marked = first_line.min(self.lines.len().saturating_sub(1));
+        self.signs.mark(SignNamespace::Changed, marked..=marked);
+
+        // The line after the deleted ones moves up, or the one before them
+        // if they were at the end
+        let cursor = self.cursor;
+        self.goto(first_line, cursor);
+    }
+
+    /// Delete the chars in the range, splicing together the lines it starts
+    /// and ends on. The cursor is moved to the start of the range
+    fn delete_chars(&mut self, range: Range<usize>) {
+        let (start, end) = (range.start, range.end);
+        let first_line = self.text.char_to_line(start);
+        let last_line = self.text.char_to_line(end);
+
+        let removed: Vec<char> = self.text.slice(start..end).chars().collect();
+        self.registers.delete(
+            self.register,
+            Register {
+                text: removed.iter().collect(),
+                linewise: false,
+            },
+        );
+        self.remove_text(start..end);
+        self.push_deletion(start, removed);
+
+        self.splice_lines(first_line, last_line, first_line);
+        self.signs
+            .shift(first_line + 1, -((last_line - first_line) as isize));
+        self.signs
+            .mark(SignNamespace::Changed, first_line..=first_line);
+
+        self.line = first_line;
+        self.cursor = start - self.text.line_to_char(first_line);
+    }
+
+    fn delete_line(&mut self, line: usize) {
+        let register = self.lines_register(line, line);
+        self.registers.delete(self.register, register);
+
+        let pos = self.text.line_to_char(line);
+        if self.lines.len() > 1 {
+            let len = self.lines.remove(line) as usize;
+            // Include new line character, the last line doesn't have one so
+            // take the one from the line before it
+            let range = if line == self.lines.len() {
+                (pos - 1)..(pos + len)
+            } else {
+                pos..(pos + len + 1)
+            };
+
+            let removed = self.text.slice(range.clone()).chars().collect();
+            self.remove_text(range.clone());
+            self.push_deletion(range.start, removed);
+
+            self.signs.shift(line, -1);
+            let marked = line.min(self.lines.len() - 1);
+            self.signs.mark(SignNamespace::Changed, marked..=marked);
+
+            let cursor = self.cursor;
+            self.goto(line, cursor);
+        } else {
+            self.signs.clear(SignNamespace::Changed);
+            self.signs.mark(SignNamespace::Changed, 0..=0);
+            self.lines[0] = 0;
+            // Including \n from the last line
+            let removed: Vec<char> = self.text.chars().collect();
+            self.remove_text(0..self.text.len_chars());
+            if !removed.is_empty() {
+                self.push_deletion(0, removed);
+            }
+            self.cursor = 0;
+        }
+    }
+
+    /// Split the line at the cursor. The new line keeps the current one's
+    /// indentation, and is indented a level more after an opening bracket or
+    /// `=>`. Between a pair of brackets the closing one goes on a line of its
+    /// own under the cursor
+    fn enter(&mut self) {
+        let line = self.text.line(self.line);
+        let indent = self.indent_before(self.line, self.cursor);
+        let before = line.slice(..self.cursor).to_string();
+        let before = before.trim_end();
+        let after = (self.cursor < self.lines[self.line] as usize).then(|| line.char(self.cursor));
+
+        let mut text = format!("\n{}", indent);
+        if before.ends_with("=>") {
+            text.push_str(&self.indent.unit());
+        } else if let Some(close) = before.chars().last().and_then(closing_bracket) {
+            text.push_str(&self.indent.unit());
+            if after == Some(close) {
+                text.push('\n');
+                text.push_str(&indent);
+            }
+        }
+        let cursor = text.lines().nth(1).map_or(0, |line| line.chars().count());
+
+        let pos = self.pos();
+        let new_lines = text.matches('\n').count();
+        self.insert_text(pos, &text);
+        self.push_insertion(pos, &text);
+        self.splice_lines(self.line, self.line, self.line + new_lines);
+
+        self.signs.shift(self.line + 1, new_lines as isize);
+        // Splitting a line at its end leaves it as it was
+        let first = if after.is_some() {
+            self.line
+        } else {
+            self.line + 1
+        };
+        self.signs
+            .mark(SignNamespace::Changed, first..=(self.line + new_lines));
+
+        self.line += 1;
+        self.cursor = cursor;
+    }
+
+    /// Record text inserted at `start`, typing continues the last insertion
+    /// so it's extended instead of adding an edit per char
+    fn push_insertion(&mut self, start: usize, text: &str) {
+        let group = self.edit_group();
+        let last = match group.last() {
+            Some(Edit::Insertion { start, str_idx }) => {
+                Some((start.get() as usize, *str_idx as usize))
+            }
+            _ => None,
+        };
+
+        match last {
+            Some((last_start, str_idx)) if last_start + self.edit_vecs[str_idx].len() == start => {
+                self.edit_vecs[str_idx].extend(text.chars());
+            }
+            _ => {
+                self.edit_vecs.push(text.chars().collect());
+                let str_idx = self.edit_vecs.len() as u32 - 1;
+                self.edit_group().push(Edit::Insertion {
+                    start: Cell::new(start as u32),
+                    str_idx,
+                });
+            }
+        }
+        self.close_normal_mode_group();
+    }
+
+    /// Record chars removed at `start`, backspacing or deleting forward continues
+    /// the last deletion so it's extended instead of adding an edit per char
+    fn push_deletion(&mut self, start: usize, removed: Vec<char>) {
+        let group = self.edit_group();
+        let last = match group.last() {
+            Some(Edit::Deletion { start, str_idx }) => Some((start, *str_idx as usize)),
+            _ => None,
+        };
+
+        match last {
+            Some((last_start, str_idx)) if last_start.get() as usize == start + removed.len() => {
+                last_start.set(start as u32);
+                self.edit_vecs[str_idx].splice(0..0, removed);
+            }
+            Some((last_start, str_idx)) if last_start.get() as usize == start => {
+                self.edit_vecs[str_idx].extend(removed);
+            }
+            _ => {
+                self.edit_vecs.push(removed);
+                let str_idx = self.edit_vecs.len() as u32 - 1;
+                self.edit_group().push(Edit::Deletion {
+                    start: Cell::new(start as u32),
+                    str_idx,
+                });
+            }
+        }
+        self.close_normal_mode_group();
+    }
+
+    /// The undo group new edits are added to
+    fn edit_group(&mut self) -> &mut Vec<Edit> {
+        self.invalidate_save_point();
+        // Invalidate redo stack if we make an edit
+        if !self.redos.is_empty() {
+            self.redos.clear()
+        }
+        if self.edit_boundary || self.edits.is_empty() {
+            self.edits.push(Vec::new());
+            self.edit_boundary = false;
+        }
+        self.edits.last_mut().unwrap()
+    }
+
+    /// Continue the undo group made since there were `groups` groups in the
+    /// insert session, so `c` is undone along with the text typed afterwards
+    #[inline]
+    fn join_edit_group(&mut self, groups: usize) {
+        if self.edits.len() > groups {
+            self.edit_boundary = false;
+        }
+    }
+
+    /// Normal mode operations are undone one at a time
+    #[inline]
+    fn close_normal_mode_group(&mut self) {
+        if !self.is_insert() {
+            self.edit_boundary = true;
+        }
+    }
+
+    /// The whitespace `line` starts with, up to `col`
+    fn indent_before(&self, line: usize, col: usize) -> String {
+        self.text
+            .line(line)
+            .chars()
+            .take(col)
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect()
+    }
+
+    /// Open a line below the cursor's, indented like pressing Enter at the
+    /// end of the line
+    fn new_line(&mut self) {
+        self.cursor = self.lines[self.line] as usize;
+        self.enter();
+    }
+
+    /// Open a line above the cursor's with the same indentation, or a level
+    /// more when the line starts with a closing bracket
+    fn new_line_before(&mut self) {
+        let pos = self.line_pos();
+        let mut indent = self.indent_before(self.line, usize::MAX);
+        let first = self
+            .text
+            .line(self.line)
+            .chars()
+            .nth(indent.chars().count());
+        if first.is_some_and(|c| CLOSING_BRACKETS.contains(&c)) {
+            indent.push_str(&self.indent.unit());
+        }
+
+        let text = format!("{}\n", indent);
+        self.insert_text(pos, &text);
+        self.push_insertion(pos, &text);
+
+        self.cursor = indent.chars().count();
+        self.lines.insert(self.line, self.cursor as u32);
+
+        self.signs.shift(self.line, 1);
+        self.signs
+            .mark(SignNamespace::Changed, self.line..=self.line);
+    }
+
+    /// Typing a closing bracket as the first char of a line takes a level of
+    /// indentation off of it
+    fn dedent_closing(&mut self) {
+        let indent = self.indent_before(self.line, self.cursor);
+        if self.cursor == 0 || indent.chars().count() != self.cursor {
+            return;
+        }
+        let width = self.indent.level_len(indent.chars().rev());
+        self.delete_before_cursor(self.cursor - width);
+    }
+}
+
+// This impl contains movement utilities
+impl Editor {
+    fn word_indicies(
+        &mut self,
+        mut start: usize,
+        mut end: usize,
+        chars: Vec<char>,
+        skip_punctuation: bool,
+    ) -> Vec<(usize, usize)> {
+        let len = chars.len();
+        let mut idxs: Vec<(usize, usize)> = Vec::new();
+        let mut searching_start = false;
+
+        while end < len && start < len {
+            if searching_start {
+                if chars[start] == ' ' {
+                    start += 1;
+                } else {
+                    searching_start = false;
+                    end = start + 1;
+                }
+            } else {
+                if Editor::is_word_separator(chars[end], skip_punctuation) {
+                    idxs.push((start, end));
+                    searching_start = true;
+                    start = end;
+                }
+                end += 1;
+            }
+        }
+
+        if !searching_start {
+            idxs.push((start, end));
+        }
+
+        idxs
+    }
+
+    fn next_word(&mut self, mv: MoveWord, line: usize, mut cursor: usize, match_first_word: bool) {
+        use MoveWordKind::*;
+        let is_not_last = match mv.kind {
+            Next | End => line < (self.lines.len() - 1),
+            Prev => line > 0,
+        };
+
+        if self.lines[line] == 0 {
+            if is_not_last {
+                match mv.kind {
+                    Next | End => self.next_word(mv, line + 1, 0, true),
+                    Prev => self.next_word(mv, line - 1, usize::MAX, true),
+                }
+            }
+            return;
+        }
+
+        let chars: Vec<char> = match mv.kind {
+            Next | End => self.text.line(line).chars().collect(),
+            Prev => {
+                let mut chars: Vec<char> = self.text.line(line).chars().collect();
+                chars.reverse();
+                chars
+            }
+        };
+        let len = chars.len();
+        if cursor > len {
+            cursor = len - 1;
+        }
+
+        let start = self
+            .text
+            .line(line)
+            .chars()
+            .enumerate()
+            .skip(if matches!(mv.kind, Prev) {
+                len - cursor
+            } else {
+                cursor
+            })
+            .find_map(|(i, c)| {
+                if Editor::is_word_separator(c, mv.skip_punctuation) {
+                    None
+                } else {
+                    Some(i)
+                }
+            });
+
+        if start.is_none() {
+            if is_not_last {
+                match mv.kind {
+                    Next | End => self.next_word(mv, line + 1, 0, true),
+                    Prev => self.next_word(mv, line - 1, usize::MAX, true),
+                };
+            }
+            return;
+        }
+
+        let start = unsafe { start.unwrap_unchecked() };
+
+        let end = start + 1;
+        if end >= len {
+            if is_not_last {
+                match mv.kind {
+                    Next | End => self.next_word(mv, line + 1, 0, true),
+                    Prev => self.next_word(mv, line - 1, usize::MAX, true),
+                };
+            }
+            return;
+        }
+
+        let idxs: Vec<(usize, usize)> = {
+            let idxs = self.word_indicies(start, end, chars, mv.skip_punctuation);
             if matches!(mv.kind, Prev) {
                 idxs.into_iter()
                     .map(|(start, end)| (len - end, len - start))
@@ -897,36 +2301,111 @@ impl Editor {
         }
     }
 
-    /// Return line of the previous paragraph
+    /// Return line of the previous paragraph boundary, the first line
+    /// if there is none
     #[inline]
-    fn prev_paragraph(&mut self) -> usize {
-        if self.line == 0 {
-            return 0;
+    fn prev_paragraph(&self, mut line: usize) -> usize {
+        // Skip the blank lines we're on, then the paragraph above them
+        while line > 0 && self.is_paragraph_boundary(line) {
+            line -= 1;
         }
+        while line > 0 && !self.is_paragraph_boundary(line) {
+            line -= 1;
+        }
+        line
+    }
 
-        self.lines[0..self.line - 1]
-            .iter()
-            .enumerate()
-            .rev()
-            .find(|(_, c)| **c == 0)
-            .map_or(0, |(l, _)| l as usize)
+    /// Return line of the next paragraph boundary, the last line
+    /// if there is none
+    #[inline]
+    fn next_paragraph(&self, mut line: usize) -> usize {
+        let last = self.lines.len() - 1;
+        while line < last && self.is_paragraph_boundary(line) {
+            line += 1;
+        }
+        while line < last && !self.is_paragraph_boundary(line) {
+            line += 1;
+        }
+        line
     }
 
+    /// Empty lines separate paragraphs, optionally lines with only whitespace too
     #[inline]
-    fn next_paragraph(&mut self) -> usize {
-        if self.line == self.lines.len() - 1 {
-            return self.line;
+    fn is_paragraph_boundary(&self, line: usize) -> bool {
+        self.lines[line] == 0
+            || (self.whitespace_paragraph_boundary
+                && self.text.line(line).chars().all(char::is_whitespace))
+    }
+
+    /// Line a paragraph motion lands on, including repeated ones like `3}`
+    fn paragraph_motion(&self, mv: &Move) -> Option<usize> {
+        match mv {
+            Move::ParagraphBegin => Some(self.prev_paragraph(self.line)),
+            Move::ParagraphEnd => Some(self.next_paragraph(self.line)),
+            Move::Repeat { count, mv } => match **mv {
+                Move::ParagraphBegin => {
+                    Some((0..*count).fold(self.line, |line, _| self.prev_paragraph(line)))
+                }
+                Move::ParagraphEnd => {
+                    Some((0..*count).fold(self.line, |line, _| self.next_paragraph(line)))
+                }
+                _ => None,
+            },
+            _ => None,
         }
+    }
 
-        self.lines
-            .iter()
-            .enumerate()
-            .skip(self.line + 1)
-            .find(|(_, c)| **c == 0)
-            .map_or(self.lines.len() - 1, |(l, _)| l as usize)
+    fn goto_paragraph(&mut self, line: usize) {
+        let forwards = line > self.line;
+        self.line = line;
+        // With no boundary left `}` stops at the end of the last line
+        if forwards && !self.is_paragraph_boundary(line) {
+            self.move_pos(usize::MAX);
+        } else {
+            self.cursor = 0;
+        }
     }
 
-    #[inline]
+    /// Where the closest match of the search after the cursor starts, or
+    /// before it if not `forwards`. Goes around the end of the buffer
+    fn next_match(&self, forwards: bool) -> Option<usize> {
+        let matches = self.find_matches(0..self.text.len_lines());
+        let pos = self.pos();
+        let starts = matches.iter().map(|range| range.start);
+        if forwards {
+            starts.clone().find(|start| *start > pos).or(starts.min())
+        } else {
+            starts
+                .clone()
+                .rev()
+                .find(|start| *start < pos)
+                .or(starts.max())
+        }
+    }
+
+    /// The chars of every match of the search in `lines`, in order. Each
+    /// line is searched on its own, a match can't go on to the next one
+    fn find_matches(&self, lines: Range<usize>) -> Vec<Range<usize>> {
+        let pattern = match self.search.as_deref() {
+            Some(pattern) if !pattern.is_empty() => pattern,
+            _ => return Vec::new(),
+        };
+        let len = pattern.chars().count();
+        let mut matches = Vec::new();
+        for line in lines.start..lines.end.min(self.text.len_lines()) {
+            let start = self.text.line_to_char(line);
+            let slice = self.text.line(line);
+            // Only copied when the line is split across chunks of the rope
+            let text = Cow::from(slice);
+            matches.extend(text.match_indices(pattern).map(|(byte, _)| {
+                let start = start + slice.byte_to_char(byte);
+                start..start + len
+            }));
+        }
+        matches
+    }
+
+    #[inline]
     fn find_line(&mut self, char: char, forwards: bool) -> Option<usize> {
         if forwards {
             self.text
@@ -949,30 +2428,86 @@ impl Editor {
 
     #[inline]
     fn up(&mut self, count: usize) {
+        let col = self.sticky_col();
         if count > self.line {
             self.line = 0;
         } else {
             self.line -= count;
         }
-        self.sync_line_cursor();
+        self.cursor = self.display_col_to_char(self.line, col);
+        self.clamp_cursor();
     }
 
     #[inline]
     fn down(&mut self, count: usize) {
+        let col = self.sticky_col();
         if self.line + count >= self.lines.len() {
             self.line = self.lines.len() - 1;
         } else {
             self.line += count;
         }
-        self.sync_line_cursor();
+        self.cursor = self.display_col_to_char(self.line, col);
+        self.clamp_cursor();
+    }
+
+    /// The screen column to move up or down to, the cursor's unless it went
+    /// through shorter lines to get here
+    fn sticky_col(&mut self) -> usize {
+        match self.desired_col {
+            Some(col) => col,
+            None => {
+                let col = self.char_to_display_col(self.line, self.cursor);
+                self.desired_col = Some(col);
+                col
+            }
+        }
+    }
+
+    /// The screen column of the char at `char_idx` in `line`. Wide chars take
+    /// two columns and tabs go to the next tab stop
+    pub fn char_to_display_col(&self, line: usize, char_idx: usize) -> usize {
+        let stop = (self.indent.width as usize).max(1);
+        self.text
+            .line(line)
+            .chars()
+            .take(char_idx)
+            .fold(0, |col, ch| match ch {
+                '\t' => (col / stop + 1) * stop,
+                ch => col + char_cols(ch),
+            })
+    }
+
+    /// The char of `line` that covers the screen column `col`, or the end of
+    /// the line when it's shorter
+    fn display_col_to_char(&self, line: usize, col: usize) -> usize {
+        let stop = (self.indent.width as usize).max(1);
+        let len = self.lines[line] as usize;
+        let mut start = 0;
+        for (i, ch) in self.text.line(line).chars().take(len).enumerate() {
+            let end = match ch {
+                '\t' => (start / stop + 1) * stop,
+                ch => start + char_cols(ch),
+            };
+            if end > col {
+                return i;
+            }
+            start = end;
+        }
+        len
     }
 
     /// Returns true if attempted to move more characters than the line has
     #[inline]
     fn right(&mut self, count: usize) -> bool {
         let c = self.lines[self.line] as usize;
-        if self.cursor + count >= c {
-            self.cursor = if c == 0 { 0 } else { c - 1 };
+        // Insert mode can go on the new line character
+        let max = if self.is_insert() {
+            c
+        } else {
+            c.saturating_sub(1)
+        };
+        if self.cursor + count > max {
+            self.cursor = max;
             true
         } else {
             self.cursor += count;
@@ -1002,33 +2537,175 @@ impl Editor {
         }
     }
 
+    /// Move to the row on the screen below or above the cursor's, keeping the
+    /// column within the row
+    fn display_move(&mut self, down: bool) {
+        let starts = self.row_starts(self.line);
+        let row = starts
+            .iter()
+            .rposition(|start| *start <= self.cursor)
+            .unwrap_or(0);
+        let col = *self.display_col.get_or_insert(self.cursor - starts[row]);
+
+        let (line, starts, row) = if down {
+            if row + 1 < starts.len() {
+                (self.line, starts, row + 1)
+            } else if self.line + 1 < self.lines.len() {
+                (self.line + 1, self.row_starts(self.line + 1), 0)
+            } else {
+                return;
+            }
+        } else if row > 0 {
+            (self.line, starts, row - 1)
+        } else if self.line > 0 {
+            let starts = self.row_starts(self.line - 1);
+            let row = starts.len() - 1;
+            (self.line - 1, starts, row)
+        } else {
+            return;
+        };
+
+        self.line = line;
+        self.cursor = starts[row] + col;
+        // Stay in the row, the last one ends with the line
+        if let Some(next) = starts.get(row + 1) {
+            self.cursor = self.cursor.min(next - 1);
+        }
+        self.clamp_cursor();
+    }
+
+    /// The columns the display rows of `line` start at
+    pub fn row_starts(&self, line: usize) -> Vec<usize> {
+        self.row_starts_with(line, self.display_rows.as_ref())
+    }
+
+    /// The columns the rows of `line` start at when `rows` lays it out, for
+    /// views with a layout other than the installed one
+    pub fn row_starts_with(&self, line: usize, rows: &dyn DisplayRows) -> Vec<usize> {
+        let start = self.text.line_to_char(line);
+        let text = self.text.slice(start..start + self.lines[line] as usize);
+        let starts = rows.row_starts(text);
+        if starts.is_empty() {
+            vec![0]
+        } else {
+            starts
+        }
+    }
+
+    /// Keep the cursor within the line, only insert mode may put it
+    /// on the new line character
     #[inline]
-    fn sync_line_cursor(&mut self) {
+    fn clamp_cursor(&mut self) {
         let line_count = self.lines[self.line] as usize;
-        if line_count == 0 {
-            self.cursor = 0;
-        } else if self.cursor >= line_count {
-            self.cursor = line_count - 1;
+        let max = if matches!(self.mode, Mode::Insert) {
+            line_count
+        } else {
+            line_count.saturating_sub(1)
+        };
+        self.cursor = self.cursor.min(max);
+    }
+}
+
+// This impl contains the functions that change the text, everything should
+// go through these so the changes are recorded
+impl Editor {
+    fn insert_text(&mut self, pos: usize, text: &str) {
+        let start_byte = self.text.char_to_byte(pos);
+        let start_position = self.byte_to_point(start_byte);
+        self.line_changed(start_position.row);
+        self.text.insert(pos, text);
+        self.revision = next_revision();
+
+        let new_end_byte = start_byte + text.len();
+        self.text_edits.push(TextEdit {
+            start: pos,
+            old_end: pos,
+            new_end: pos + text.chars().count(),
+            text: text.to_string(),
+            input_edit: InputEdit {
+                start_byte,
+                old_end_byte: start_byte,
+                new_end_byte,
+                start_position,
+                old_end_position: start_position,
+                new_end_position: self.byte_to_point(new_end_byte),
+            },
+        });
+    }
+
+    fn remove_text(&mut self, range: Range<usize>) {
+        let start_byte = self.text.char_to_byte(range.start);
+        let old_end_byte = self.text.char_to_byte(range.end);
+        let start_position = self.byte_to_point(start_byte);
+        let old_end_position = self.byte_to_point(old_end_byte);
+        self.line_changed(start_position.row);
+        self.text.remove(range.clone());
+        self.revision = next_revision();
+
+        self.text_edits.push(TextEdit {
+            start: range.start,
+            old_end: range.end,
+            new_end: range.start,
+            text: String::new(),
+            input_edit: InputEdit {
+                start_byte,
+                old_end_byte,
+                new_end_byte: start_byte,
+                start_position,
+                old_end_position,
+                new_end_position: start_position,
+            },
+        });
+    }
+
+    #[inline]
+    fn byte_to_point(&self, byte: usize) -> Point {
+        let row = self.text.byte_to_line(byte);
+        Point {
+            row,
+            column: byte - self.text.line_to_byte(row),
         }
     }
+
+    /// The changes made to the text since this was last called
+    #[inline]
+    pub fn take_text_edits(&mut self) -> Vec<TextEdit> {
+        mem::take(&mut self.text_edits)
+    }
+
+    /// Changes every time the text does. It's never the same for two
+    /// editors, so comparing it also tells when a different one is shown
+    #[inline]
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
 }
 
 // This impl contains undo/redo utility functions
 impl Editor {
     #[inline]
     fn undo(&mut self) {
-        if let Some(edit) = self.edits.pop() {
-            let inversion = edit.invert();
-            self.redos.push(edit);
-            self.apply_edit(inversion)
+        if let Some(group) = self.edits.pop() {
+            for edit in group.iter().rev() {
+                self.apply_edit(edit.invert());
+            }
+            self.goto_edit_group(&group);
+            self.redos.push(group);
+            self.edit_boundary = true;
+            self.sync_save_point();
         }
     }
 
     #[inline]
     fn redo(&mut self) {
-        if let Some(edit) = self.redos.pop() {
-            self.edits.push(edit.clone());
-            self.apply_edit(edit);
+        if let Some(group) = self.redos.pop() {
+            for edit in group.iter() {
+                self.apply_edit(edit.clone());
+            }
+            self.goto_edit_group(&group);
+            self.edits.push(group);
+            self.edit_boundary = true;
+            self.sync_save_point();
         }
     }
 
@@ -1036,17 +2713,64 @@ impl Editor {
     fn apply_edit(&mut self, edit: Edit) {
         match edit {
             Edit::Deletion { start, str_idx } => {
-                let len = self.edit_vecs[str_idx as usize].len();
+                let chars = &self.edit_vecs[str_idx as usize];
+                let newlines = chars.iter().filter(|c| **c == '\n').count();
                 let start = start.get() as usize;
-                self.text.remove(start..(start + len));
+                let line = self.text.char_to_line(start);
+                self.remove_text(start..(start + chars.len()));
+                self.splice_lines(line, line + newlines, line);
+
+                self.signs.shift(line + 1, -(newlines as isize));
+                self.signs.mark(SignNamespace::Changed, line..=line);
             }
             Edit::Insertion { start, str_idx } => {
                 let str = self.edit_vecs[str_idx as usize].iter().collect::<String>();
-                self.text.insert(start.get() as usize, &str);
+                let newlines = str.chars().filter(|c| *c == '\n').count();
+                let line = self.text.char_to_line(start.get() as usize);
+                self.insert_text(start.get() as usize, &str);
+                self.splice_lines(line, line, line + newlines);
+
+                self.signs.shift(line + 1, newlines as isize);
+                self.signs
+                    .mark(SignNamespace::Changed, line..=(line + newlines));
             }
         };
-        // TODO: Be smarter about this and only compute the lines affected
-        self.lines = text_to_lines(self.text.chars());
+        // The edit may have removed the text under the cursor
+        self.line = self.line.min(self.lines.len() - 1);
+        self.clamp_cursor();
+    }
+
+    /// Put the cursor where the first text `group` changed starts, like Vim
+    /// after undoing or redoing it. None of the other edits come before it, so
+    /// its start is the same before and after them
+    fn goto_edit_group(&mut self, group: &[Edit]) {
+        let start = group
+            .iter()
+            .map(|(Edit::Insertion { start, .. } | Edit::Deletion { start, .. })| start.get())
+            .min();
+        if let Some(start) = start {
+            let start = (start as usize).min(self.text.len_chars());
+            let line = self.text.char_to_line(start);
+            self.goto(line, start - self.text.line_to_char(line));
+        }
+    }
+
+    /// Undoing/redoing back to the state the buffer was saved in
+    /// means nothing has changed since the save
+    #[inline]
+    fn sync_save_point(&mut self) {
+        if self.save_point == Some(self.edits.len()) {
+            self.signs.clear(SignNamespace::Changed);
+            self.edit_boundary = true;
+        }
+    }
+
+    /// Editing after undoing past the save point makes it unreachable
+    #[inline]
+    fn invalidate_save_point(&mut self) {
+        if matches!(self.save_point, Some(point) if self.edits.len() < point) {
+            self.save_point = None;
+        }
     }
 }
 
@@ -1054,6 +2778,13 @@ impl Editor {
 impl Editor {
     #[inline]
     fn switch_mode(&mut self, mode: Mode) {
+        // Entering or leaving insert mode closes the undo group so
+        // the whole insert session is undone at once
+        if (self.mode == Mode::Insert) != (mode == Mode::Insert) {
+            self.edit_boundary = true;
+            self.insert_start = None;
+            self.display_col = None;
+        }
         match (self.mode, mode) {
             (Mode::Insert, Mode::Normal) => {
                 // If we are switching from insert to normal mode and we are on the new-line character,
@@ -1073,6 +2804,7 @@ impl Editor {
             // Hitting `v` in visual mode should return to normal mode
             (Mode::Visual, Mode::Visual) => {
                 self.selection = None;
+                self.select_anchor = None;
                 self.mode = Mode::Normal;
                 self.vim.set_mode(mode);
             }
@@ -1080,6 +2812,7 @@ impl Editor {
             (_, Mode::Visual) => {}
             (Mode::Visual, _) => {
                 self.selection = None;
+                self.select_anchor = None;
                 self.mode = mode;
                 self.vim.set_mode(mode);
             }
@@ -1114,6 +2847,246 @@ impl Editor {
         }
     }
 
+    #[inline]
+    pub fn signs(&self) -> &Signs {
+        &self.signs
+    }
+
+    /// Record the current state as saved, clearing the changed lines
+    pub fn mark_saved(&mut self) {
+        self.signs.clear(SignNamespace::Changed);
+        self.save_point = Some(self.edits.len());
+        self.edit_boundary = true;
+    }
+
+    #[inline]
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    #[inline]
+    pub fn set_path(&mut self, path: Option<&Path>) {
+        self.path = path.map(Path::to_path_buf);
+    }
+
+    /// Write the text to its file, see `save_file`. It's an error if the
+    /// editor has no file
+    pub fn save(&mut self) -> io::Result<()> {
+        match self.path.clone() {
+            Some(path) => self.save_file(&path),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "No file name")),
+        }
+    }
+
+    /// Write the text to `path` and tell the language server it was saved
+    pub fn save_file(&mut self, path: &Path) -> io::Result<()> {
+        write_replacing(&self.text, path, self.line_ending)?;
+        self.mark_saved();
+
+        if let Some(sender) = &self.lsp_sender {
+            let uri = fs::canonicalize(path)
+                .ok()
+                .and_then(|path| Url::from_file_path(path).ok());
+            if let (Some(uri), Some(include_text)) = (uri.clone(), sender.save_include_text()) {
+                let text = include_text.then(|| self.text.to_string());
+                sender.send_message(Box::new(text_doc_did_save(uri, text)));
+            }
+            // Asked for again on save instead of after every change, so the
+            // colors don't shift around while typing
+            if let Some(uri) = uri {
+                sender.request_semantic_tokens(uri);
+            }
+        }
+        Ok(())
+    }
+
+    /// What the lines end with when the text is saved
+    #[inline]
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    #[inline]
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+
+    /// Whether the text has changed since it was last saved
+    #[inline]
+    pub fn is_modified(&self) -> bool {
+        self.save_point != Some(self.edits.len())
+    }
+
+    /// The path `gf` found under the cursor, `None` if there wasn't one
+    #[inline]
+    pub fn take_goto_file(&mut self) -> Option<FileLocation> {
+        self.goto_file.take()
+    }
+
+    /// The `:` command from [`EditorEvent::ExCmd`]
+    #[inline]
+    pub(crate) fn take_ex_cmd(&mut self) -> Option<ExCmd> {
+        self.ex_cmd.take()
+    }
+
+    /// Put the cursor on `line` at `col`, as close as the text allows
+    pub fn goto(&mut self, line: usize, col: usize) {
+        self.desired_col = None;
+        self.line = line.min(self.lines.len() - 1);
+        self.cursor = col;
+        self.clamp_cursor();
+    }
+
+    /// Put the cursor on `col` in `line` like a mouse click, kept inside the
+    /// text. Only insert mode can be past the last char of the line, and
+    /// typing after it starts a new undo step
+    pub fn set_cursor_line_col(&mut self, line: usize, col: usize) {
+        self.goto(line, col);
+        self.display_col = None;
+        self.edit_boundary = true;
+        self.insert_start = None;
+        self.auto_closed = false;
+    }
+
+    /// Move the end of the selection to `line` and `col` like dragging the
+    /// mouse does. Visual mode starts from the cursor once it's moved off
+    /// of it
+    pub fn select_to_line_col(&mut self, line: usize, col: usize) {
+        if self.mode == Mode::Insert {
+            self.switch_mode(Mode::Normal);
+        }
+        if let (Mode::Visual, Some((anchor, unit))) = (self.mode, self.select_anchor.clone()) {
+            self.set_cursor_line_col(line, col);
+            let range = self.unit_range(self.pos(), unit);
+            let (start, end) = if range.start >= anchor.start {
+                (anchor.start, anchor.end.max(range.end) - 1)
+            } else {
+                (anchor.end - 1, range.start)
+            };
+            self.goto_pos(end);
+            self.selection = Some((start as u32, end as u32));
+            return;
+        }
+        let start = self.pos();
+        self.set_cursor_line_col(line, col);
+        let end = self.pos();
+        if self.mode == Mode::Normal {
+            if start == end {
+                return;
+            }
+            self.selection = Some((start as u32, start as u32));
+            self.mode = Mode::Visual;
+            self.vim.set_mode(Mode::Visual);
+        }
+        if let Some(selection) = self.selection.as_mut() {
+            selection.1 = end as u32;
+        }
+    }
+
+    /// Select the word or line at `line` and `col` in visual mode like a
+    /// double or triple click, see `SelectUnit`
+    pub fn select_around(&mut self, line: usize, col: usize, unit: SelectUnit) {
+        if self.mode != Mode::Normal {
+            self.switch_mode(Mode::Normal);
+        }
+        self.set_cursor_line_col(line, col);
+        let range = self.unit_range(self.pos(), unit);
+        self.switch_mode(Mode::Visual);
+        self.goto_pos(range.end - 1);
+        self.selection = Some((range.start as u32, range.end as u32 - 1));
+        self.select_anchor = Some((range, unit));
+    }
+
+    /// The chars around `pos` that `unit` selects, the line includes its
+    /// new line
+    fn unit_range(&self, pos: usize, unit: SelectUnit) -> Range<usize> {
+        match unit {
+            SelectUnit::Char => pos..pos + 1,
+            SelectUnit::Word => self.word_range(pos),
+            SelectUnit::Line => {
+                let line = self.text.char_to_line(pos);
+                let end = self.text.line_to_char(line + 1);
+                self.text.line_to_char(line)..end.max(pos + 1)
+            }
+        }
+    }
+
+    /// The run of word chars, punctuation or whitespace that the char at
+    /// `pos` is in, like Vim's `iw`. It stops at the end of the line
+    fn word_range(&self, pos: usize) -> Range<usize> {
+        let class = |c: char| {
+            if c.is_whitespace() {
+                0
+            } else if Self::is_word_separator(c, false) {
+                1
+            } else {
+                2
+            }
+        };
+        let line = self.text.char_to_line(pos);
+        let line_start = self.text.line_to_char(line);
+        let line_end = line_start + self.lines[line] as usize;
+        if pos >= line_end {
+            return pos..pos + 1;
+        }
+        let kind = class(self.text.char(pos));
+        let start = (line_start..pos)
+            .rev()
+            .find(|&i| class(self.text.char(i)) != kind)
+            .map_or(line_start, |i| i + 1);
+        let end = (pos..line_end)
+            .find(|&i| class(self.text.char(i)) != kind)
+            .unwrap_or(line_end);
+        start..end
+    }
+
+    /// Put the cursor on the char at `pos`
+    fn goto_pos(&mut self, pos: usize) {
+        let line = self.text.char_to_line(pos);
+        self.goto(line, pos - self.text.line_to_char(line));
+    }
+
+    #[inline]
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.switch_mode(mode);
+    }
+
+    pub fn set_display_rows(&mut self, display_rows: impl DisplayRows + 'static) {
+        self.display_rows = Box::new(display_rows);
+    }
+
+    #[inline]
+    pub fn set_page_lines(&mut self, lines: usize) {
+        self.page_lines = lines.max(1);
+    }
+
+    #[inline]
+    pub fn set_whitespace_paragraph_boundary(&mut self, enabled: bool) {
+        self.whitespace_paragraph_boundary = enabled;
+    }
+
+    #[inline]
+    pub fn set_indent(&mut self, indent: IndentSettings) {
+        self.indent = indent;
+    }
+
+    #[inline]
+    pub fn indent(&self) -> IndentSettings {
+        self.indent
+    }
+
+    /// Whether typing an opening bracket or quote types the closing one too,
+    /// it's on by default
+    #[inline]
+    pub fn set_auto_pairs(&mut self, enabled: bool) {
+        self.auto_pairs = enabled;
+    }
+
+    #[inline]
+    pub fn auto_pairs(&self) -> bool {
+        self.auto_pairs
+    }
+
     #[inline]
     pub fn selection(&self) -> Option<(u32, u32)> {
         self.selection
@@ -1186,28 +3159,95 @@ impl Editor {
 
     #[inline]
     fn line_pos(&self) -> usize {
-        if self.lines.len() == 1 {
-            0
-        } else {
-            // Summation of every line before it + 1 for the new line character
+        let mut offsets = self.line_offsets.borrow_mut();
+        while offsets.len() <= self.line {
+            // Every line before it + 1 for the new line character
+            let offset = match offsets.len() {
+                0 => 0,
+                line => offsets[line - 1] + self.lines[line - 1] + 1,
+            };
+            offsets.push(offset);
+        }
+
+        #[cfg(test)]
+        debug_assert_eq!(
+            offsets[self.line] as usize,
             self.lines[0..self.line]
                 .iter()
                 .fold(0, |acc, line| acc + 1 + *line as usize)
-        }
+        );
+        offsets[self.line] as usize
     }
 
-    /// Calculate the amount of chars in the given line (excluding new line characters)
+    /// The text of `line` changed, the lines after it start somewhere else
+    #[inline]
+    fn line_changed(&mut self, line: usize) {
+        self.line_offsets.get_mut().truncate(line + 1);
+    }
+
+    /// Calculate the amount of chars in the given line (excluding new line characters).
+    /// This reads the rope so it can be used while `self.lines` is out of date
     #[inline]
     fn line_count(&self, idx: usize) -> usize {
-        if self.lines.is_empty() {
-            0
-        } else if idx == self.lines.len() - 1 {
-            // If it's the last line then we don't need to subtract the newline character from the count
-            self.text.line(idx).len_chars()
+        let line = self.text.line(idx);
+        let len = line.len_chars();
+        // The last line doesn't have a new line character to subtract
+        if len > 0 && line.char(len - 1) == '\n' {
+            len - 1
         } else {
-            // Subtract the new line character from the count
-            self.text.line(idx).len_chars() - 1
+            len
+        }
+    }
+
+    /// Replace the counts of lines `first..=old_last` with the counts the rope now
+    /// has for `first..=new_last`, so edits only recompute the lines they touched
+    fn splice_lines(&mut self, first: usize, old_last: usize, new_last: usize) {
+        let counts: Vec<u32> = (first..=new_last)
+            .map(|line| self.line_count(line) as u32)
+            .collect();
+        self.lines.splice(first..=old_last, counts);
+
+        #[cfg(test)]
+        debug_assert_eq!(self.lines, text_to_lines(self.text.chars()));
+    }
+
+    #[inline]
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// The keys of the normal or visual mode command being typed
+    #[inline]
+    pub fn pending_keys(&self) -> String {
+        self.vim.pending_display()
+    }
+
+    /// The text typed after the prompt, `None` when the command line isn't
+    /// open
+    #[inline]
+    pub fn command_line(&self) -> Option<&str> {
+        self.vim.command_line()
+    }
+
+    /// `:`, `/` or `?`, what the command line was opened with
+    #[inline]
+    pub fn command_prompt(&self) -> char {
+        self.vim.command_prompt()
+    }
+
+    /// The pattern being searched for while its matches are highlighted
+    #[inline]
+    pub fn search_pattern(&self) -> Option<&str> {
+        self.search.as_deref().filter(|_| self.search_highlight)
+    }
+
+    /// The chars of every match in `lines` to highlight, none after `:noh`
+    /// or Escape
+    pub fn search_matches(&self, lines: Range<usize>) -> Vec<Range<usize>> {
+        if !self.search_highlight {
+            return Vec::new();
         }
+        self.find_matches(lines)
     }
 
     #[inline]
@@ -1215,6 +3255,14 @@ impl Editor {
         matches!(self.mode, Mode::Insert)
     }
 
+    /// Yank the selection in visual mode, which copies it to the clipboard
+    pub fn copy_selection(&mut self) -> EditorEvent {
+        match self.mode {
+            Mode::Visual => self.handle_cmd(&Cmd::Yank(None)),
+            _ => EditorEvent::Nothing,
+        }
+    }
+
     fn is_word_separator(c: char, skip_punctuation: bool) -> bool {
         match c {
             ' ' => true,
@@ -1255,6 +3303,12 @@ impl Default for Editor {
 mod tests {
     use super::*;
 
+    fn normal_editor(text: &str) -> Editor {
+        let mut editor = Editor::with_text(Some(text.into()));
+        editor.switch_mode(Mode::Normal);
+        editor
+    }
+
     #[cfg(test)]
     mod text_to_lines {
         use super::*;
@@ -1265,242 +3319,2479 @@ mod tests {
         }
 
         #[test]
-        fn single_line() {
-            let text = "one line";
-            assert_eq!(vec![text.len() as u32], text_to_lines(text.chars()));
+        fn single_line() {
+            let text = "one line";
+            assert_eq!(vec![text.len() as u32], text_to_lines(text.chars()));
+        }
+
+        #[test]
+        fn multiple_lines() {
+            let text = "line 1\nline 2";
+            assert_eq!(vec![6, 6], text_to_lines(text.chars()));
+        }
+
+        #[test]
+        fn trailing_newline() {
+            let text = "line 1\n";
+            assert_eq!(vec![6, 0], text_to_lines(text.chars()));
+        }
+
+        #[test]
+        fn leading_newline() {
+            let text = "\nline 1\n";
+            assert_eq!(vec![0, 6, 0], text_to_lines(text.chars()));
+        }
+    }
+
+    #[cfg(test)]
+    mod movement {
+        use super::*;
+
+        #[test]
+        fn sticky_column() {
+            let long = "0123456789";
+            let mut text = vec![long, "ab", ""];
+            text.extend(["x"; 7]);
+            text.push(long);
+            let mut editor = normal_editor(&text.join("\n"));
+            editor.cursor = 8;
+
+            editor.feed_keys("j");
+            assert_eq!((editor.line, editor.cursor), (1, 1));
+            editor.feed_keys("j");
+            assert_eq!((editor.line, editor.cursor), (2, 0));
+            editor.feed_keys("k");
+            assert_eq!((editor.line, editor.cursor), (1, 1));
+
+            editor.feed_keys("k10j");
+            assert_eq!((editor.line, editor.cursor), (10, 8));
+            editor.feed_keys("10k");
+            assert_eq!((editor.line, editor.cursor), (0, 8));
+
+            // Moving along the line picks a new column
+            editor.feed_keys("jjhkk");
+            assert_eq!((editor.line, editor.cursor), (0, 0));
+
+            // Arrows in insert mode keep it too
+            editor.cursor = 9;
+            editor.feed_keys("i");
+            for key in [Keycode::Down, Keycode::Down, Keycode::Up, Keycode::Up] {
+                editor.event(Event::KeyDown {
+                    timestamp: 0,
+                    window_id: 0,
+                    keycode: Some(key),
+                    scancode: None,
+                    keymod: Mod::NOMOD,
+                    repeat: false,
+                });
+            }
+            assert_eq!((editor.line, editor.cursor), (0, 9));
+        }
+
+        #[test]
+        fn set_cursor_line_col() {
+            let mut editor = normal_editor("abc\nde\n");
+            editor.set_cursor_line_col(1, 1);
+            assert_eq!((editor.line, editor.cursor), (1, 1));
+            // Normal mode stays on the last char, past the end is the last line
+            editor.set_cursor_line_col(0, 10);
+            assert_eq!((editor.line, editor.cursor), (0, 2));
+            editor.set_cursor_line_col(10, 0);
+            assert_eq!((editor.line, editor.cursor), (2, 0));
+
+            // Insert mode can be after it, and `Ctrl-U` stops at the click
+            editor.feed_keys("ggixy");
+            editor.set_cursor_line_col(0, 10);
+            assert_eq!((editor.line, editor.cursor), (0, 5));
+            editor.feed_keys("z<c-u>");
+            assert_eq!(editor.text_all().to_string(), "xyabc\nde\n");
+        }
+
+        #[test]
+        fn search() {
+            let mut editor = normal_editor("fn a\nlet fn_b\n  fn c");
+            assert!(editor.search_matches(0..3).is_empty());
+
+            editor.feed_keys("/fn<cr>");
+            assert_eq!((editor.line, editor.cursor), (1, 4));
+            assert_eq!(editor.search_pattern(), Some("fn"));
+            assert_eq!(editor.search_matches(0..3), vec![0..2, 9..11, 16..18]);
+            // Only the lines asked for
+            assert_eq!(editor.search_matches(1..2), vec![9..11]);
+            editor.feed_keys("n");
+            assert_eq!((editor.line, editor.cursor), (2, 2));
+            // Around the end of the buffer
+            editor.feed_keys("n");
+            assert_eq!((editor.line, editor.cursor), (0, 0));
+            editor.feed_keys("N");
+            assert_eq!((editor.line, editor.cursor), (2, 2));
+
+            // `?` turns `n` and `N` around, an empty pattern is the last one
+            editor.feed_keys("?<cr>");
+            assert_eq!((editor.line, editor.cursor), (1, 4));
+            editor.feed_keys("N");
+            assert_eq!((editor.line, editor.cursor), (2, 2));
+
+            // Escape and `:noh` hide the matches until the next search
+            editor.feed_keys("<esc>");
+            assert_eq!(editor.search_pattern(), None);
+            assert!(editor.search_matches(0..3).is_empty());
+            editor.feed_keys("n");
+            assert_eq!(editor.search_matches(0..3).len(), 3);
+            editor.feed_keys(":noh<cr>");
+            assert!(editor.search_matches(0..3).is_empty());
+            assert_eq!(editor.take_ex_cmd(), None);
+
+            // Escape closing the command line leaves them alone
+            editor.feed_keys("n/x<esc>");
+            assert_eq!(editor.search_pattern(), Some("fn"));
+        }
+
+        #[test]
+        fn display_columns() {
+            let mut editor = normal_editor("\tab\n中文x\nabcdef");
+            editor.set_indent(IndentSettings {
+                use_spaces: false,
+                width: 4,
+            });
+            assert_eq!(editor.char_to_display_col(0, 1), 4);
+            assert_eq!(editor.char_to_display_col(0, 2), 5);
+            assert_eq!(editor.char_to_display_col(1, 2), 4);
+            assert_eq!(editor.display_col_to_char(1, 1), 0);
+            assert_eq!(editor.display_col_to_char(1, 4), 2);
+
+            // `j` and `k` keep the screen column, not the char
+            editor.goto(2, 5);
+            editor.feed_keys("k");
+            assert_eq!((editor.line, editor.cursor), (1, 2));
+            editor.feed_keys("k");
+            assert_eq!((editor.line, editor.cursor), (0, 2));
+            editor.feed_keys("jj");
+            assert_eq!((editor.line, editor.cursor), (2, 5));
+        }
+
+        #[test]
+        fn sync_lines() {
+            // Should not exceed line length
+            let mut editor = Editor::new();
+            editor.insert("1");
+            editor.insert("2");
+            editor.enter();
+            editor.insert("1");
+            editor.insert("2");
+            editor.insert("3");
+            editor.switch_mode(Mode::Normal);
+            editor.up(1);
+
+            assert_eq!(editor.cursor, 1);
+        }
+
+        /// Wraps lines after a fixed number of chars
+        struct FixedWidth(usize);
+
+        impl DisplayRows for FixedWidth {
+            fn row_starts(&self, line: RopeSlice) -> Vec<usize> {
+                (0..line.len_chars().max(1)).step_by(self.0).collect()
+            }
+        }
+
+        #[test]
+        fn word_wrap() {
+            let rows =
+                |col, text: &str| WordWrap::new(col).row_starts(Rope::from_str(text).slice(..));
+
+            assert_eq!(rows(10, "short\n"), vec![0]);
+            assert_eq!(rows(10, "0123456789\n"), vec![0]);
+            // Breaks after the spaces, the continuations have 8 columns
+            assert_eq!(rows(10, "aaa bbb ccc ddd eee\n"), vec![0, 8, 16]);
+            // Words that don't fit are split
+            assert_eq!(rows(5, "abcdefghijk"), vec![0, 5, 8]);
+            // Wide chars take two columns
+            assert_eq!(rows(5, "中文中文中文"), vec![0, 2, 3, 4, 5]);
+            assert_eq!(rows(1, "中中"), vec![0, 1]);
+        }
+
+        fn wrapped_editor(text: &str, width: usize) -> Editor {
+            let mut editor = normal_editor(text);
+            editor.set_display_rows(FixedWidth(width));
+            editor
+        }
+
+        #[test]
+        fn display_rows_within_line() {
+            // Rows: "abcd" "efgh" "ij"
+            let mut editor = wrapped_editor("abcdefghij", 4);
+            editor.cursor = 1;
+            editor.handle_cmd(&Cmd::Move(Move::DisplayDown));
+            assert_eq!((editor.line, editor.cursor), (0, 5));
+            editor.handle_cmd(&Cmd::Move(Move::DisplayDown));
+            assert_eq!((editor.line, editor.cursor), (0, 9));
+            editor.handle_cmd(&Cmd::Move(Move::DisplayUp));
+            editor.handle_cmd(&Cmd::Move(Move::DisplayUp));
+            assert_eq!((editor.line, editor.cursor), (0, 1));
+        }
+
+        #[test]
+        fn display_rows_across_lines() {
+            let mut editor = wrapped_editor("abcdef\nxyz", 4);
+            editor.cursor = 5;
+            editor.handle_cmd(&Cmd::Move(Move::DisplayDown));
+            assert_eq!((editor.line, editor.cursor), (1, 1));
+            // Nowhere to go
+            editor.handle_cmd(&Cmd::Move(Move::DisplayDown));
+            assert_eq!((editor.line, editor.cursor), (1, 1));
+
+            // Back into the last row of the wrapped line
+            editor.handle_cmd(&Cmd::Move(Move::DisplayUp));
+            assert_eq!((editor.line, editor.cursor), (0, 5));
+            editor.handle_cmd(&Cmd::Repeat {
+                count: 5,
+                cmd: Box::new(Cmd::Move(Move::DisplayUp)),
+            });
+            assert_eq!((editor.line, editor.cursor), (0, 1));
+        }
+
+        #[test]
+        fn display_rows_sticky_column() {
+            // Rows: "abcd" "ef" | "ghij"
+            let mut editor = wrapped_editor("abcdef\nghij", 4);
+            editor.cursor = 3;
+            editor.handle_cmd(&Cmd::Move(Move::DisplayDown));
+            assert_eq!((editor.line, editor.cursor), (0, 5));
+            editor.handle_cmd(&Cmd::Move(Move::DisplayDown));
+            assert_eq!((editor.line, editor.cursor), (1, 3));
+
+            // Any other command forgets the column
+            editor.handle_cmd(&Cmd::Move(Move::DisplayUp));
+            editor.handle_cmd(&Cmd::Move(Move::Left));
+            editor.handle_cmd(&Cmd::Move(Move::DisplayDown));
+            assert_eq!((editor.line, editor.cursor), (1, 0));
+        }
+
+        #[test]
+        fn display_rows_without_wrapping() {
+            let mut editor = normal_editor("abc\nd\nefg");
+            editor.cursor = 2;
+            editor.handle_cmd(&Cmd::Move(Move::DisplayDown));
+            assert_eq!((editor.line, editor.cursor), (1, 0));
+            editor.handle_cmd(&Cmd::Move(Move::DisplayDown));
+            assert_eq!((editor.line, editor.cursor), (2, 2));
+        }
+
+        fn paragraph_editor(text: &str, line: usize) -> Editor {
+            let mut editor = normal_editor(text);
+            editor.line = line;
+            editor
+        }
+
+        #[test]
+        fn paragraph_begin_blank_above() {
+            let mut editor = paragraph_editor("a\n\nb\nc", 2);
+            editor.movement(&Move::ParagraphBegin);
+            assert_eq!(editor.line, 1);
+
+            editor.movement(&Move::ParagraphBegin);
+            assert_eq!(editor.line, 0);
+        }
+
+        #[test]
+        fn paragraph_consecutive_blanks() {
+            let text = "a\n\n\n\nb\nc\n\n\nd";
+            let mut editor = paragraph_editor(text, 0);
+            editor.movement(&Move::ParagraphEnd);
+            assert_eq!(editor.line, 1);
+            editor.movement(&Move::ParagraphEnd);
+            assert_eq!(editor.line, 6);
+
+            editor.movement(&Move::ParagraphBegin);
+            assert_eq!(editor.line, 3);
+            editor.movement(&Move::ParagraphBegin);
+            assert_eq!(editor.line, 0);
+        }
+
+        #[test]
+        fn paragraph_blank_start_and_end() {
+            let text = "\n\na\nb\n\n";
+            let mut editor = paragraph_editor(text, 2);
+            editor.movement(&Move::ParagraphEnd);
+            assert_eq!(editor.line, 4);
+            editor.movement(&Move::ParagraphEnd);
+            assert_eq!(editor.line, 5);
+
+            editor.movement(&Move::ParagraphBegin);
+            assert_eq!(editor.line, 1);
+            editor.movement(&Move::ParagraphBegin);
+            assert_eq!(editor.line, 0);
+        }
+
+        #[test]
+        fn paragraph_end_of_buffer() {
+            let mut editor = paragraph_editor("a\n\nfoo\nbar", 2);
+            editor.movement(&Move::ParagraphEnd);
+            assert_eq!((editor.line, editor.cursor), (3, 2));
+        }
+
+        #[test]
+        fn paragraph_counts() {
+            let text = "a\n\nb\n\nc\n\nd";
+            let mut editor = paragraph_editor(text, 0);
+            editor.movement(&Move::Repeat {
+                count: 3,
+                mv: Box::new(Move::ParagraphEnd),
+            });
+            assert_eq!(editor.line, 5);
+
+            editor.movement(&Move::Repeat {
+                count: 2,
+                mv: Box::new(Move::ParagraphBegin),
+            });
+            assert_eq!(editor.line, 1);
+        }
+
+        #[test]
+        fn paragraph_whitespace_lines() {
+            let text = "a\n  \nb";
+            let mut editor = paragraph_editor(text, 0);
+            editor.movement(&Move::ParagraphEnd);
+            assert_eq!(editor.line, 2);
+
+            let mut editor = paragraph_editor(text, 0);
+            editor.set_whitespace_paragraph_boundary(true);
+            editor.movement(&Move::ParagraphEnd);
+            assert_eq!(editor.line, 1);
+        }
+    }
+
+    #[cfg(test)]
+    mod edit {
+        use super::*;
+
+        #[cfg(test)]
+        mod delete_range {
+            use super::*;
+
+            #[test]
+            fn single_line() {
+                let mut editor = Editor::new();
+                editor.insert("1");
+                editor.enter();
+                editor.insert("1");
+                let start = editor.pos();
+                editor.insert("2");
+                editor.insert("3");
+                let end = editor.pos();
+                editor.enter();
+                editor.insert("1");
+                editor.up(1);
+                editor.cursor = 0;
+
+                editor.switch_mode(Mode::Normal);
+                editor.delete_range(start..end);
+                assert_eq!(editor.text_str().unwrap(), "1\n1\n1");
+                assert_eq!(editor.lines, vec![1, 1, 1]);
+            }
+
+            #[test]
+            fn single_line_full() {
+                let mut editor = Editor::new();
+                editor.insert("1");
+                editor.enter();
+                let start = editor.pos();
+                editor.insert("1");
+                editor.insert("2");
+                editor.insert("3");
+                let end = editor.pos();
+                editor.enter();
+                editor.insert("1");
+                editor.up(1);
+                editor.cursor = 0;
+
+                editor.switch_mode(Mode::Normal);
+                editor.delete_range(start..end);
+                assert_eq!(editor.text_str().unwrap(), "1\n\n1");
+                assert_eq!(editor.lines, vec![1, 0, 1]);
+            }
+
+            #[test]
+            fn multi_line() {
+                let mut editor = Editor::new();
+                editor.insert("1");
+                editor.enter();
+                editor.insert("1");
+                editor.insert("2");
+                editor.insert("3");
+                editor.enter();
+                editor.insert("1");
+                editor.up(1);
+                let start = editor.pos();
+                editor.down(1);
+                let end = editor.pos();
+
+                editor.switch_mode(Mode::Normal);
+                editor.delete_range(start..end);
+                assert_eq!(editor.text_str().unwrap(), "1");
+                assert_eq!(editor.lines, vec![1]);
+            }
+
+            #[test]
+            fn entire_text() {
+                let mut editor = Editor::new();
+                editor.insert("1");
+                editor.insert("2");
+                editor.insert("3");
+                editor.enter();
+                editor.insert("1");
+                editor.insert("2");
+                editor.insert("3");
+                editor.enter();
+                editor.insert("1");
+                editor.insert("2");
+                editor.insert("3");
+
+                // move to start
+                editor.cursor = 0;
+                editor.line = 0;
+                let start = editor.pos();
+                editor.line = editor.lines.len() - 1;
+                let end = editor.pos();
+
+                editor.switch_mode(Mode::Normal);
+                editor.delete_range(start..end);
+                assert_eq!(editor.text_str().unwrap(), "");
+                assert_eq!(editor.lines, vec![0]);
+            }
+        }
+
+        #[test]
+        fn delete_line_first() {
+            let mut editor = Editor::new();
+            editor.insert("1");
+            editor.enter();
+            editor.insert("1");
+            editor.enter();
+            editor.insert("1");
+            editor.up(2);
+            editor.delete_line(0);
+
+            assert_eq!(editor.lines, vec![1, 1]);
+        }
+
+        #[test]
+        fn delete_line_middle() {
+            let mut editor = Editor::new();
+            editor.insert("1");
+            editor.enter();
+            editor.insert("1");
+            editor.insert("1");
+            editor.insert("1");
+            editor.enter();
+            editor.insert("1");
+            editor.insert("2");
+            editor.up(1);
+            editor.delete_line(1);
+
+            assert_eq!(editor.lines, vec![1, 2]);
+        }
+
+        #[test]
+        fn delete_line_last() {
+            let mut editor = Editor::new();
+            editor.insert("1");
+            editor.enter();
+            editor.insert("1");
+            editor.enter();
+            editor.insert("1");
+            editor.insert("2");
+            editor.delete_line(2);
+
+            assert_eq!(editor.lines, vec![1, 1]);
+        }
+
+        #[test]
+        fn delete_line_last_takes_newline() {
+            let mut editor = normal_editor("a\nb");
+            editor.line = 1;
+            editor.delete_line(1);
+
+            assert_eq!(editor.text_str().unwrap(), "a");
+            assert_eq!(editor.lines, vec![1]);
+            assert_eq!(editor.line, 0);
+        }
+
+        #[test]
+        fn delete_mv_end_of_buffer() {
+            let mut editor = normal_editor("");
+            editor.delete_mv(&Move::Right);
+            assert_eq!(editor.text_str().unwrap(), "");
+
+            let mut editor = normal_editor("a\n");
+            editor.line = 1;
+            editor.delete_mv(&Move::Right);
+            assert_eq!(editor.text_str().unwrap(), "a\n");
+        }
+
+        #[test]
+        fn goto_file() {
+            let mut editor = normal_editor("a\nsee src/lib.rs:3 here");
+            editor.line = 1;
+            editor.cursor = 6;
+            assert_eq!(editor.handle_cmd(&Cmd::GotoFile), EditorEvent::GotoFile);
+            assert_eq!(
+                editor.take_goto_file(),
+                Some(FileLocation {
+                    path: "src/lib.rs".into(),
+                    line: Some(3),
+                    col: None
+                })
+            );
+            assert_eq!(editor.take_goto_file(), None);
+            // Nothing was edited
+            assert!(!editor.is_modified());
+        }
+
+        #[test]
+        fn ex_cmd() {
+            let mut editor = normal_editor("a\n  b\nc");
+            let mut last = EditorEvent::Nothing;
+            for event in crate::commands::key_events(":2<cr>") {
+                last = editor.event(event);
+            }
+            assert_eq!(last, EditorEvent::DrawCursor);
+            assert_eq!((editor.line, editor.cursor), (1, 2));
+            assert_eq!(editor.take_ex_cmd(), None);
+
+            // Past the end goes to the last line
+            for event in crate::commands::key_events(":99<cr>") {
+                editor.event(event);
+            }
+            assert_eq!(editor.line, 2);
+
+            editor.feed_keys(":-1<cr>");
+            assert_eq!((editor.line, editor.cursor), (1, 2));
+            editor.feed_keys(":$<cr>");
+            assert_eq!(editor.line, 2);
+            editor.feed_keys(":-9<cr>");
+            assert_eq!(editor.line, 0);
+            editor.feed_keys(":+9<cr>");
+            assert_eq!(editor.line, 2);
+
+            for event in crate::commands::key_events(":w<cr>") {
+                last = editor.event(event);
+            }
+            assert_eq!(last, EditorEvent::ExCmd);
+            assert_eq!(editor.take_ex_cmd(), Some(ExCmd::Write));
+        }
+
+        #[test]
+        fn ex_cmd_long_file() {
+            let text: String = (0..500).map(|i| format!("    line {}\n", i)).collect();
+            let mut editor = normal_editor(&text);
+            editor.feed_keys(":250<cr>");
+            assert_eq!((editor.line, editor.cursor), (249, 4));
+            editor.feed_keys(":+5<cr>");
+            assert_eq!(editor.line, 254);
+            editor.feed_keys(":-100<cr>");
+            assert_eq!((editor.line, editor.cursor), (154, 4));
+            editor.feed_keys(":1000<cr>");
+            assert_eq!(editor.line, editor.lines.len() - 1);
+        }
+
+        #[test]
+        fn delete_mv_removed_lines() {
+            let mut editor = normal_editor("a\nb\nc");
+            editor.line = 2;
+            editor.delete_mv(&Move::Up);
+
+            assert_eq!(editor.text_str().unwrap(), "a");
+            assert_eq!(editor.line, 0);
+        }
+
+        #[test]
+        fn delete_paragraph_end() {
+            let mut editor = normal_editor("a\nb\n\nc");
+            editor.delete_mv(&Move::ParagraphEnd);
+            assert_eq!(editor.text_str().unwrap(), "\nc");
+            assert_eq!((editor.line, editor.cursor), (0, 0));
+
+            // Mid-line only deletes up to the blank line
+            let mut editor = normal_editor("ab\ncd\n\ne");
+            editor.cursor = 1;
+            editor.delete_mv(&Move::ParagraphEnd);
+            assert_eq!(editor.text_str().unwrap(), "a\n\ne");
+
+            // No blank line left deletes to the end
+            let mut editor = normal_editor("a\n\nb\nc");
+            editor.line = 2;
+            editor.delete_mv(&Move::ParagraphEnd);
+            assert_eq!(editor.text_str().unwrap(), "a\n\n");
+        }
+
+        #[test]
+        fn delete_paragraph_begin() {
+            let mut editor = normal_editor("a\n\nb\nc\nd");
+            editor.line = 4;
+            editor.delete_mv(&Move::ParagraphBegin);
+            assert_eq!(editor.text_str().unwrap(), "a\nd");
+            assert_eq!((editor.line, editor.cursor), (1, 0));
+
+            let mut editor = normal_editor("a\n\nb\ncd");
+            editor.line = 3;
+            editor.cursor = 1;
+            editor.delete_mv(&Move::ParagraphBegin);
+            assert_eq!(editor.text_str().unwrap(), "a\nd");
+        }
+
+        #[test]
+        fn change_mv() {
+            let mut editor = normal_editor("one two");
+            editor.handle_cmd(&Cmd::Change(Some(Move::Word(false))));
+
+            // Like `ce`, the space stays
+            assert_eq!(editor.text_str().unwrap(), " two");
+            assert!(editor.is_insert());
+        }
+
+        #[test]
+        fn repeated_change() {
+            let mut editor = normal_editor("one");
+            editor.handle_cmd(&Cmd::Repeat {
+                count: 9,
+                cmd: Box::new(Cmd::Change(Some(Move::Right))),
+            });
+
+            assert!(editor.is_insert());
+        }
+
+        #[test]
+        fn undo_clamps_cursor() {
+            let mut editor = Editor::new();
+            editor.insert("a");
+            editor.insert("b");
+            editor.switch_mode(Mode::Normal);
+            editor.undo();
+
+            assert_eq!(editor.text_str().unwrap(), "");
+            assert_eq!(editor.cursor, 0);
+        }
+
+        #[test]
+        fn undo_delete_line() {
+            let mut editor = normal_editor("a\nb");
+            editor.delete_line(0);
+            assert_eq!(editor.text_str().unwrap(), "b");
+
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "a\nb");
+            assert_eq!(editor.lines, vec![1, 1]);
+        }
+
+        #[test]
+        fn undo_insert_session() {
+            let mut editor = normal_editor("");
+            editor.feed_keys("ihello world<esc>u");
+            assert_eq!(editor.text_all(), "");
+
+            // Each session is its own step, split at every newline typed
+            editor.feed_keys("ione<esc>atwo<cr>three<esc>u");
+            assert_eq!(editor.text_all(), "onetwo\n");
+            editor.feed_keys("u");
+            assert_eq!(editor.text_all(), "one");
+            editor.feed_keys("u");
+            assert_eq!(editor.text_all(), "");
+        }
+
+        #[test]
+        fn undo_insert_newline() {
+            let mut editor = normal_editor("");
+            editor.feed_keys("ione<cr>two<esc>u");
+            assert_eq!(editor.text_all(), "one\n");
+            editor.feed_keys("u");
+            assert_eq!(editor.text_all(), "");
+        }
+
+        #[test]
+        fn undo_moves_cursor() {
+            let mut editor = normal_editor("abc\ndef");
+            editor.feed_keys("G$xgg");
+            assert_eq!(editor.text_all(), "abc\nde");
+            editor.feed_keys("u");
+            assert_eq!(editor.text_all(), "abc\ndef");
+            assert_eq!((editor.line, editor.cursor), (1, 2));
+
+            editor.feed_keys("gg");
+            editor.redo();
+            assert_eq!(editor.text_all(), "abc\nde");
+            // Clamped to the shorter line
+            assert_eq!((editor.line, editor.cursor), (1, 1));
+        }
+
+        #[test]
+        fn undo_insert_moves_cursor() {
+            let mut editor = normal_editor("one\ntwo");
+            editor.feed_keys("GA three<esc>gg");
+            assert_eq!(editor.text_all(), "one\ntwo three");
+            editor.feed_keys("u");
+            assert_eq!(editor.text_all(), "one\ntwo");
+            assert_eq!((editor.line, editor.cursor), (1, 2));
+        }
+
+        fn key(editor: &mut Editor, keycode: Keycode) -> EditorEvent {
+            editor.event(Event::KeyDown {
+                timestamp: 0,
+                window_id: 0,
+                keycode: Some(keycode),
+                scancode: None,
+                keymod: Mod::NOMOD,
+                repeat: false,
+            })
+        }
+
+        #[test]
+        fn insert_mode_arrows() {
+            let mut editor = Editor::with_text(Some("abc\nd".into()));
+            assert_eq!(key(&mut editor, Keycode::End), EditorEvent::DrawCursor);
+            assert_eq!((editor.line, editor.cursor), (0, 3));
+            key(&mut editor, Keycode::Right);
+            assert_eq!((editor.line, editor.cursor), (0, 3));
+            key(&mut editor, Keycode::Down);
+            assert_eq!((editor.line, editor.cursor), (1, 1));
+            key(&mut editor, Keycode::Left);
+            key(&mut editor, Keycode::Left);
+            assert_eq!((editor.line, editor.cursor), (1, 0));
+            key(&mut editor, Keycode::Up);
+            key(&mut editor, Keycode::Right);
+            assert_eq!((editor.line, editor.cursor), (0, 1));
+            key(&mut editor, Keycode::Home);
+            assert_eq!((editor.line, editor.cursor), (0, 0));
+        }
+
+        #[test]
+        fn insert_mode_page_keys() {
+            let mut editor = Editor::with_text(Some("0\n1\n2\n3\n4".into()));
+            editor.set_page_lines(3);
+            key(&mut editor, Keycode::PageDown);
+            assert_eq!(editor.line, 3);
+            key(&mut editor, Keycode::PageDown);
+            assert_eq!(editor.line, 4);
+            key(&mut editor, Keycode::PageUp);
+            assert_eq!(editor.line, 1);
+        }
+
+        #[test]
+        fn delete_forward() {
+            let mut editor = Editor::with_text(Some("ab\ncd".into()));
+            editor.cursor = 1;
+            assert_eq!(key(&mut editor, Keycode::Delete), EditorEvent::DrawText);
+            assert_eq!(editor.text_str().unwrap(), "a\ncd");
+            assert_eq!(editor.lines, vec![1, 2]);
+
+            // Joins the next line at the end of this one
+            key(&mut editor, Keycode::Delete);
+            assert_eq!(editor.text_str().unwrap(), "acd");
+            assert_eq!(editor.lines, vec![3]);
+            assert_eq!((editor.line, editor.cursor), (0, 1));
+
+            key(&mut editor, Keycode::Delete);
+            key(&mut editor, Keycode::Delete);
+            assert_eq!(
+                key(&mut editor, Keycode::Delete),
+                EditorEvent::Nothing,
+                "Delete at the end of the buffer does nothing"
+            );
+            assert_eq!(editor.text_str().unwrap(), "a");
+
+            editor.switch_mode(Mode::Normal);
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "ab\ncd");
+            assert_eq!(editor.lines, vec![2, 2]);
+        }
+
+        #[test]
+        fn backspace_clears_redos() {
+            let mut editor = Editor::with_text(Some("x".into()));
+            editor.cursor = 1;
+            editor.insert("a");
+            editor.switch_mode(Mode::Normal);
+            editor.undo();
+            editor.switch_mode(Mode::Insert);
+            editor.cursor = 1;
+            editor.backspace();
+            editor.switch_mode(Mode::Normal);
+            editor.redo();
+
+            assert_eq!(editor.text_str().unwrap(), "");
+        }
+
+        #[test]
+        fn backspace_beginning_in_between_line() {
+            let mut editor = Editor::new();
+            editor.insert("1");
+            editor.insert("2");
+            editor.insert("3");
+            editor.enter();
+            editor.insert("1");
+            editor.enter();
+            editor.insert("1");
+            editor.up(1);
+            editor.left(1);
+
+            assert_eq!(editor.backspace(), EditorEvent::DrawText);
+            assert_eq!(editor.lines, vec![4, 1]);
+        }
+
+        #[test]
+        fn enter_in_between() {
+            let mut editor = Editor::new();
+            editor.insert("1");
+            editor.insert("2");
+            editor.insert("3");
+            editor.cursor = 2;
+
+            editor.enter();
+            assert_eq!(editor.lines, vec![2, 1]);
+        }
+
+        #[test]
+        fn enter_beginning() {
+            let mut editor = Editor::new();
+            editor.insert("1");
+            editor.insert("2");
+            editor.insert("3");
+            editor.cursor = 0;
+
+            editor.enter();
+            assert_eq!(editor.lines, vec![0, 3]);
+        }
+
+        #[test]
+        fn enter_end() {
+            let mut editor = Editor::new();
+            editor.insert("1");
+            editor.insert("2");
+            editor.insert("3");
+            editor.cursor = 3;
+
+            editor.enter();
+            assert_eq!(editor.lines, vec![3, 0]);
+        }
+    }
+
+    #[cfg(test)]
+    mod undo {
+        use super::*;
+
+        fn type_text(editor: &mut Editor, text: &str) {
+            for c in text.chars() {
+                match c {
+                    '\n' => editor.enter(),
+                    c => editor.insert(&c.to_string()),
+                }
+            }
+        }
+
+        #[test]
+        fn insert_session() {
+            let mut editor = Editor::with_text(Some("start".into()));
+            editor.cursor = 5;
+            type_text(&mut editor, " one two\nthree");
+            editor.switch_mode(Mode::Normal);
+            assert_eq!(editor.text_str().unwrap(), "start one two\nthree");
+
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "start");
+
+            editor.redo();
+            assert_eq!(editor.text_str().unwrap(), "start one two\nthree");
+        }
+
+        #[test]
+        fn separate_sessions() {
+            let mut editor = Editor::new();
+            type_text(&mut editor, "one");
+            editor.switch_mode(Mode::Normal);
+            editor.handle_cmd(&Cmd::SwitchMove(Move::LineEnd));
+            type_text(&mut editor, " two");
+            editor.switch_mode(Mode::Normal);
+
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "one");
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "");
+        }
+
+        #[test]
+        fn multi_byte() {
+            let mut editor = Editor::new();
+            type_text(&mut editor, "é→\n𝔸b");
+            // An input method can send more than one char at a time
+            editor.insert("日本");
+            assert_eq!(editor.text_str().unwrap(), "é→\n𝔸b日本");
+            assert_eq!((editor.line, editor.cursor), (1, 4));
+            assert_eq!(editor.lines, vec![2, 4]);
+
+            editor.backspace();
+            editor.backspace();
+            editor.backspace();
+            assert_eq!(editor.text_str().unwrap(), "é→\n𝔸");
+            assert_eq!(editor.lines, vec![2, 1]);
+            editor.backspace();
+            editor.backspace();
+            assert_eq!(editor.text_str().unwrap(), "é→");
+            assert_eq!((editor.line, editor.cursor), (0, 2));
+            assert_eq!(editor.lines, text_to_lines(editor.text.chars()));
+
+            editor.insert("ö");
+            editor.switch_mode(Mode::Normal);
+            assert_eq!(editor.text_str().unwrap(), "é→ö");
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "");
+            assert_eq!(editor.lines, text_to_lines(editor.text.chars()));
+        }
+
+        #[test]
+        fn backspace_in_session() {
+            let mut editor = Editor::new();
+            type_text(&mut editor, "abc");
+            editor.switch_mode(Mode::Normal);
+            editor.handle_cmd(&Cmd::SwitchMove(Move::LineEnd));
+            editor.backspace();
+            editor.backspace();
+            type_text(&mut editor, "xy");
+            editor.switch_mode(Mode::Normal);
+            assert_eq!(editor.text_str().unwrap(), "axy");
+
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "abc");
+        }
+
+        #[test]
+        fn normal_mode_ops() {
+            let mut editor = normal_editor("a\nb\nc");
+            editor.handle_cmd(&Cmd::Delete(None));
+            editor.handle_cmd(&Cmd::Delete(None));
+            assert_eq!(editor.text_str().unwrap(), "c");
+
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "b\nc");
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "a\nb\nc");
+        }
+
+        #[test]
+        fn change_with_typed_text() {
+            let mut editor = normal_editor("one two");
+            editor.handle_cmd(&Cmd::Change(Some(Move::Word(false))));
+            type_text(&mut editor, "three");
+            editor.switch_mode(Mode::Normal);
+            assert_eq!(editor.text_str().unwrap(), "three two");
+
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "one two");
+        }
+
+        #[test]
+        fn new_line_in_session() {
+            let mut editor = normal_editor("  a\nb");
+            editor.handle_cmd(&Cmd::NewLine(NewLine {
+                up: false,
+                switch_mode: true,
+            }));
+            type_text(&mut editor, "x");
+            editor.switch_mode(Mode::Normal);
+            assert_eq!(editor.text_str().unwrap(), "  a\n  x\nb");
+
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "  a\nb");
+        }
+
+        fn ctrl(editor: &mut Editor, keycode: Keycode) {
+            editor.event(Event::KeyDown {
+                timestamp: 0,
+                window_id: 0,
+                keycode: Some(keycode),
+                scancode: None,
+                keymod: Mod::LCTRLMOD,
+                repeat: false,
+            });
+        }
+
+        #[test]
+        fn ctrl_w() {
+            let mut editor = normal_editor("one\n");
+            editor.handle_cmd(&Cmd::SwitchMove(Move::Down));
+            editor.switch_mode(Mode::Insert);
+            type_text(&mut editor, "foo.bar(baz  ");
+
+            ctrl(&mut editor, Keycode::W);
+            assert_eq!(editor.text_str().unwrap(), "one\nfoo.bar(");
+            assert_eq!(editor.lines, vec![3, 8]);
+            ctrl(&mut editor, Keycode::W);
+            assert_eq!(editor.text_str().unwrap(), "one\nfoo.bar");
+            ctrl(&mut editor, Keycode::W);
+            ctrl(&mut editor, Keycode::W);
+            ctrl(&mut editor, Keycode::W);
+            assert_eq!(editor.text_str().unwrap(), "one\n");
+            assert_eq!(editor.lines, vec![3, 0]);
+
+            // Stops at the start of the line instead of joining lines
+            ctrl(&mut editor, Keycode::W);
+            assert_eq!(editor.text_str().unwrap(), "one\n");
+            assert_eq!((editor.line, editor.cursor), (1, 0));
+
+            editor.switch_mode(Mode::Normal);
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "one\n");
+            assert_eq!(editor.lines, vec![3, 0]);
+            assert!(editor.edits.is_empty());
+        }
+
+        #[test]
+        fn ctrl_u() {
+            let mut editor = normal_editor("keep");
+            editor.handle_cmd(&Cmd::SwitchMove(Move::LineEnd));
+            editor.switch_mode(Mode::Insert);
+            editor.cursor = 4;
+            type_text(&mut editor, " typed");
+
+            // Only the text typed in this session goes first, then the rest of the line
+            ctrl(&mut editor, Keycode::U);
+            assert_eq!(editor.text_str().unwrap(), "keep");
+            assert_eq!(editor.lines, vec![4]);
+            ctrl(&mut editor, Keycode::U);
+            assert_eq!(editor.text_str().unwrap(), "");
+            assert_eq!(editor.lines, vec![0]);
+
+            editor.switch_mode(Mode::Normal);
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "keep");
+            assert_eq!(editor.lines, vec![4]);
+        }
+
+        #[test]
+        fn user_command() {
+            let mut editor = normal_editor("a\nb");
+            editor.run_command("Pad").unwrap();
+            assert_eq!(editor.text_str().unwrap(), "\na\n\nb");
+            assert_eq!(editor.lines, vec![0, 1, 0, 1]);
+
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "a\nb");
+            assert!(editor.edits.is_empty());
+        }
+
+        #[test]
+        fn user_command_error_runs_nothing() {
+            let mut editor = normal_editor("a");
+            editor
+                .define_commands("command Broken = \"dd:Broken<cr>\"")
+                .unwrap();
+            assert_eq!(
+                editor.run_command("Broken"),
+                Err(CommandError::Recursive("Broken".into()))
+            );
+            assert_eq!(editor.text_str().unwrap(), "a");
+        }
+
+        #[test]
+        fn user_command_ex_cmds() {
+            let mut editor = normal_editor("a\nb\nc");
+            editor
+                .define_commands("command SaveTop = \":w<cr>ggdd:noh<cr>:x<cr>\"")
+                .unwrap();
+            editor.goto(2, 0);
+            // It stops at each one the window runs, before the keys after it
+            assert_eq!(editor.run_command("SaveTop"), Ok(EditorEvent::ExCmd));
+            assert_eq!(editor.take_ex_cmd(), Some(ExCmd::Write));
+            assert_eq!(editor.take_ex_cmd(), None);
+            assert_eq!((editor.line, editor.text_str().unwrap()), (2, "a\nb\nc"));
+
+            // The editor runs `:noh` itself
+            assert_eq!(editor.resume_command(), EditorEvent::ExCmd);
+            assert_eq!(editor.take_ex_cmd(), Some(ExCmd::WriteQuit));
+            assert_eq!((editor.line, editor.text_str().unwrap()), (0, "b\nc"));
+
+            assert_eq!(editor.resume_command(), EditorEvent::DrawText);
+            assert_eq!(editor.resume_command(), EditorEvent::Nothing);
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "a\nb\nc");
+        }
+
+        #[test]
+        fn user_command_cancel() {
+            let mut editor = normal_editor("a\nb");
+            editor
+                .define_commands("command Save = \"x:w<cr>dd\"")
+                .unwrap();
+            assert_eq!(editor.run_command("Save"), Ok(EditorEvent::ExCmd));
+            assert_eq!(editor.take_ex_cmd(), Some(ExCmd::Write));
+
+            // Saving failed, the line stays
+            editor.cancel_command();
+            assert_eq!(editor.resume_command(), EditorEvent::Nothing);
+            assert_eq!(editor.text_str().unwrap(), "\nb");
+            assert_eq!(editor.mode(), Mode::Normal);
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "a\nb");
+        }
+
+        #[test]
+        fn ctrl_u_after_new_line() {
+            let mut editor = normal_editor("ab");
+            editor.switch_mode(Mode::Insert);
+            editor.cursor = 2;
+            type_text(&mut editor, "c\nde");
+
+            ctrl(&mut editor, Keycode::U);
+            assert_eq!(editor.text_str().unwrap(), "abc\n");
+            assert_eq!(editor.lines, vec![3, 0]);
+
+            editor.switch_mode(Mode::Normal);
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "ab");
+            assert_eq!(editor.lines, vec![2]);
+        }
+    }
+
+    #[cfg(test)]
+    mod changed_lines {
+        use super::*;
+
+        fn changed(editor: &Editor) -> Vec<usize> {
+            editor.signs().lines(SignNamespace::Changed).collect()
+        }
+
+        #[test]
+        fn edits_mark_lines() {
+            let mut editor = Editor::with_text(Some("one\ntwo\nthree".into()));
+            assert!(changed(&editor).is_empty());
+
+            editor.line = 1;
+            editor.cursor = 3;
+            editor.insert("!");
+            assert_eq!(changed(&editor), vec![1]);
+
+            editor.cursor = 1;
+            editor.enter();
+            assert_eq!(changed(&editor), vec![1, 2]);
+
+            editor.mark_saved();
+            assert!(changed(&editor).is_empty());
+        }
+
+        #[test]
+        fn undo_to_save_point() {
+            let mut editor = Editor::with_text(Some("one\ntwo".into()));
+            editor.cursor = 3;
+            editor.insert("!");
+            editor.mark_saved();
+            assert!(!editor.is_modified());
+
+            editor.line = 1;
+            editor.cursor = 3;
+            editor.insert("?");
+            assert_eq!(changed(&editor), vec![1]);
+            assert!(editor.is_modified());
+
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "one!\ntwo");
+            assert!(changed(&editor).is_empty());
+            assert!(!editor.is_modified());
+
+            editor.redo();
+            assert_eq!(changed(&editor), vec![1]);
+
+            // Undoing past the save point changes the buffer again
+            editor.undo();
+            editor.undo();
+            assert_eq!(changed(&editor), vec![0]);
+            assert!(editor.is_modified());
+        }
+
+        #[test]
+        fn line_insertions_shift_marks() {
+            let mut editor = Editor::with_text(Some("a\nb\nc".into()));
+            editor.line = 2;
+            editor.cursor = 1;
+            editor.insert("!");
+            assert_eq!(changed(&editor), vec![2]);
+
+            editor.line = 0;
+            editor.cursor = 1;
+            editor.enter();
+            assert_eq!(editor.text_str().unwrap(), "a\n\nb\nc!");
+            assert_eq!(changed(&editor), vec![1, 3]);
+
+            editor.backspace();
+            assert_eq!(editor.text_str().unwrap(), "a\nb\nc!");
+            assert_eq!(changed(&editor), vec![0, 2]);
+        }
+
+        #[test]
+        fn save_file() {
+            let path = std::env::temp_dir().join("glyph_save_file.txt");
+            let mut editor = Editor::with_text(Some("a\nb".into()));
+            editor.insert("!");
+            assert!(editor.is_modified());
+
+            editor.save_file(&path).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), "!a\nb");
+            assert!(!editor.is_modified());
+            assert!(changed(&editor).is_empty());
+        }
+
+        #[test]
+        fn line_endings() {
+            assert_eq!(LineEnding::detect("a\r\nb\n"), LineEnding::CrLf);
+            assert_eq!(LineEnding::detect("a\rb"), LineEnding::Cr);
+            assert_eq!(LineEnding::detect("a\nb\r\n"), LineEnding::Lf);
+            assert_eq!(LineEnding::detect("a"), LineEnding::Lf);
+
+            let path = std::env::temp_dir().join("glyph_line_endings.txt");
+            let mut editor = Editor::with_text(Some("ab\r\nc\r\n".into()));
+            assert_eq!(editor.text_str().unwrap(), "ab\nc\n");
+            assert_eq!(editor.lines, vec![2, 1, 0]);
+            editor.goto(1, 1);
+            editor.enter();
+            editor.insert("d");
+            editor.backspace();
+            editor.backspace();
+            editor.save_file(&path).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), "ab\r\nc\r\n");
+
+            let mut editor = Editor::with_text(Some("a\rb".into()));
+            assert_eq!(editor.lines, vec![1, 1]);
+            editor.save_file(&path).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), "a\rb");
+
+            // A `\r` in the middle of a line stays
+            let editor = Editor::with_text(Some("a\nb\rc".into()));
+            assert_eq!(editor.text_str().unwrap(), "a\nb\rc");
+        }
+
+        #[test]
+        fn save() {
+            let dir = std::env::temp_dir().join("glyph_save");
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("file.txt");
+            fs::write(&path, "old").unwrap();
+
+            let mut editor = Editor::with_text(Some("new".into()));
+            editor.insert("!");
+            let err = editor.save().unwrap_err();
+            assert_eq!(err.to_string(), "No file name");
+            assert!(editor.is_modified());
+
+            editor.set_path(Some(&path));
+            editor.save().unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), "!new");
+            assert!(!editor.is_modified());
+            // Nothing is left next to it
+            assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+            // A failed write leaves the file alone
+            editor.insert("?");
+            editor.set_path(Some(&dir.join("missing").join("file.txt")));
+            assert!(editor.save().is_err());
+            assert!(editor.is_modified());
+            assert_eq!(fs::read_to_string(&path).unwrap(), "!new");
+        }
+
+        #[test]
+        fn revision() {
+            let mut editor = Editor::with_text(Some("a\nb".into()));
+            let other = Editor::with_text(Some("a\nb".into()));
+            assert_ne!(editor.revision(), other.revision());
+
+            // Only changing the text makes a new revision
+            let start = editor.revision();
+            editor.switch_mode(Mode::Normal);
+            editor.goto(1, 0);
+            assert_eq!(editor.revision(), start);
+
+            editor.switch_mode(Mode::Insert);
+            editor.insert("!");
+            let inserted = editor.revision();
+            assert_ne!(inserted, start);
+
+            editor.switch_mode(Mode::Normal);
+            editor.undo();
+            assert_ne!(editor.revision(), inserted);
+            assert_ne!(editor.revision(), start);
+        }
+    }
+
+    #[cfg(test)]
+    mod registers {
+        use std::cell::RefCell;
+
+        use super::*;
+
+        #[test]
+        fn clipboard() {
+            let clipboard = Rc::new(RefCell::new(None));
+            let mut editor = normal_editor("one two\nthree");
+            editor.set_clipboard(clipboard.clone());
+
+            editor.feed_keys("wvll");
+            editor.copy_selection();
+            assert_eq!(clipboard.borrow().as_deref(), Some("two"));
+            assert_eq!(editor.mode(), Mode::Normal);
+            editor.feed_keys("yy");
+            assert_eq!(clipboard.borrow().as_deref(), Some("one two\n"));
+
+            // Copied in another program
+            *clipboard.borrow_mut() = Some("a\nb".into());
+            editor.feed_keys("jA <c-v>!<esc>");
+            assert_eq!(editor.text_str().unwrap(), "one two\nthree a\nb!");
+            assert_eq!(editor.lines, vec![7, 7, 2]);
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "one two\nthree");
+
+            // Lines copied elsewhere paste as lines
+            *clipboard.borrow_mut() = Some("new\n".into());
+            editor.feed_keys("gg\"+P");
+            assert_eq!(editor.text_str().unwrap(), "new\none two\nthree");
+        }
+
+        #[test]
+        fn yank_delete_paste() {
+            let mut editor = normal_editor(
+                "keep
+gone
+end",
+            );
+            editor.feed_keys("yyjdd");
+            assert_eq!(
+                editor.text_str().unwrap(),
+                "keep
+end"
+            );
+
+            // The unnamed register has the delete, `"0` still has the yank
+            editor.feed_keys("\"0p");
+            assert_eq!(
+                editor.text_str().unwrap(),
+                "keep
+end
+keep"
+            );
+            assert_eq!((editor.line, editor.cursor), (2, 0));
+            editor.feed_keys("ggP");
+            assert_eq!(editor.text_str().unwrap(), "gone\nkeep\nend\nkeep");
+            assert_eq!(editor.lines, vec![4, 4, 3, 4]);
+
+            // Each paste is undone on its own
+            editor.undo();
+            assert_eq!(
+                editor.text_str().unwrap(),
+                "keep
+end
+keep"
+            );
+            editor.undo();
+            assert_eq!(
+                editor.text_str().unwrap(),
+                "keep
+end"
+            );
+        }
+
+        #[test]
+        fn small_delete() {
+            let mut editor = normal_editor(
+                "one two
+three",
+            );
+            editor.feed_keys("ywjddkdw");
+            assert_eq!(editor.text_str().unwrap(), "two");
+            assert_eq!(
+                editor.registers.get(Some('-')).map(|r| r.text.as_str()),
+                Some("one ")
+            );
+
+            // A small delete doesn't shift the numbered registers
+            editor.feed_keys("\"1p");
+            assert_eq!(editor.text_str().unwrap(), "two\nthree");
+            editor.feed_keys("k$\"0p");
+            assert_eq!(editor.text_str().unwrap(), "twoone \nthree");
+            assert_eq!((editor.line, editor.cursor), (0, 6));
+        }
+
+        #[test]
+        fn numbered_deletes() {
+            let mut editor = normal_editor("a\nb\nc\nd");
+            editor.feed_keys("dddddd");
+            assert_eq!(editor.text_str().unwrap(), "d");
+            editor.feed_keys("\"3p\"1P");
+            assert_eq!(editor.text_str().unwrap(), "d\nc\na");
+        }
+
+        #[test]
+        fn named_register() {
+            let mut editor = normal_editor("one two");
+            editor.feed_keys("\"adw");
+            assert_eq!(editor.text_str().unwrap(), "two");
+            // Deleting into a named register leaves `"-` alone
+            assert!(editor.registers.get(Some('-')).is_none());
+            editor.feed_keys("$\"ap");
+            assert_eq!(editor.text_str().unwrap(), "twoone ");
+        }
+    }
+
+    #[cfg(test)]
+    mod visual {
+        use super::*;
+
+        #[test]
+        fn select_around() {
+            let mut editor = normal_editor("foo.bar  baz\nqux\n");
+            editor.select_around(0, 5, SelectUnit::Word);
+            assert_eq!(editor.mode(), Mode::Visual);
+            assert_eq!(editor.selection(), Some((4, 6)));
+            // Whitespace and punctuation are words of their own
+            editor.select_around(0, 7, SelectUnit::Word);
+            assert_eq!(editor.selection(), Some((7, 8)));
+            editor.select_around(0, 3, SelectUnit::Word);
+            assert_eq!(editor.selection(), Some((3, 3)));
+
+            // Dragging goes by whole words either way
+            editor.select_around(0, 5, SelectUnit::Word);
+            editor.select_to_line_col(0, 10);
+            assert_eq!(editor.selection(), Some((4, 11)));
+            editor.select_to_line_col(0, 1);
+            assert_eq!(editor.selection(), Some((6, 0)));
+
+            editor.select_around(1, 1, SelectUnit::Line);
+            assert_eq!(editor.selection(), Some((13, 16)));
+            editor.select_to_line_col(0, 0);
+            assert_eq!(editor.selection(), Some((16, 0)));
+
+            // Leaving visual mode goes back to dragging by chars
+            editor.feed_keys("v");
+            editor.set_cursor_line_col(0, 0);
+            editor.select_to_line_col(0, 1);
+            assert_eq!(editor.selection(), Some((0, 1)));
+        }
+
+        #[test]
+        fn select_to_line_col() {
+            let mut editor = normal_editor("abc\ndef\n");
+            editor.set_cursor_line_col(0, 1);
+            // Not moving off of the cursor isn't a selection yet
+            editor.select_to_line_col(0, 1);
+            assert_eq!(editor.mode(), Mode::Normal);
+            assert_eq!(editor.selection(), None);
+
+            editor.select_to_line_col(1, 1);
+            assert_eq!(editor.mode(), Mode::Visual);
+            assert_eq!(editor.selection(), Some((1, 5)));
+            editor.select_to_line_col(0, 0);
+            assert_eq!(editor.selection(), Some((1, 0)));
+
+            editor.select_to_line_col(1, 1);
+            editor.feed_keys("d");
+            assert_eq!(editor.text_all().to_string(), "aef\n");
+            assert_eq!(editor.mode(), Mode::Normal);
+
+            // Insert mode is left
+            editor.feed_keys("ggi");
+            editor.select_to_line_col(0, 1);
+            assert_eq!(editor.mode(), Mode::Visual);
+            assert_eq!(editor.selection(), Some((0, 1)));
+        }
+
+        #[test]
+        fn mode_changed() {
+            let mut editor = normal_editor("abc");
+            assert_eq!(
+                editor.feed_keys("i"),
+                vec![
+                    EditorEvent::DrawCursor,
+                    EditorEvent::ModeChanged(Mode::Insert)
+                ]
+            );
+            assert_eq!(
+                editor.feed_keys("<esc>"),
+                vec![
+                    EditorEvent::DrawCursor,
+                    EditorEvent::ModeChanged(Mode::Normal)
+                ]
+            );
+            assert_eq!(editor.feed_keys("l"), vec![EditorEvent::DrawCursor]);
+            assert_eq!(
+                editor.feed_keys("v"),
+                vec![
+                    EditorEvent::DrawCursor,
+                    EditorEvent::ModeChanged(Mode::Visual)
+                ]
+            );
+            // Visual mode already sends more than one event
+            assert_eq!(
+                editor.feed_keys("d"),
+                vec![
+                    EditorEvent::DrawSelection,
+                    EditorEvent::DrawText,
+                    EditorEvent::ModeChanged(Mode::Normal)
+                ]
+            );
+        }
+
+        #[test]
+        fn indent() {
+            let mut editor = normal_editor(
+                "fn main() {
+let a = 1;
+
+  let b = 2;
+}",
+            );
+            // The selection only has to touch a line for all of it to shift
+            editor.feed_keys("jlvjj>");
+            assert_eq!(
+                editor.text_str().unwrap(),
+                "fn main() {
+  let a = 1;
+
+    let b = 2;
+}"
+            );
+            assert_eq!(editor.mode, Mode::Normal);
+            assert_eq!(editor.selection, None);
+            assert_eq!((editor.line, editor.cursor), (1, 1));
+
+            editor.feed_keys("vj<");
+            assert_eq!(
+                editor.text_str().unwrap(),
+                "fn main() {
+let a = 1;
+
+    let b = 2;
+}"
+            );
+
+            // Each shift is one undo step
+            editor.undo();
+            assert_eq!(
+                editor.text_str().unwrap(),
+                "fn main() {
+  let a = 1;
+
+    let b = 2;
+}"
+            );
+            editor.undo();
+            assert_eq!(
+                editor.text_str().unwrap(),
+                "fn main() {
+let a = 1;
+
+  let b = 2;
+}"
+            );
+            assert!(!editor.is_modified());
+        }
+
+        #[test]
+        fn yank() {
+            let mut editor = normal_editor("one two three");
+            editor.feed_keys("wvey");
+            assert_eq!(editor.text_str().unwrap(), "one two three");
+            assert_eq!(editor.mode, Mode::Normal);
+            assert_eq!(editor.selection, None);
+            assert_eq!((editor.line, editor.cursor), (0, 4));
+            assert_eq!(editor.registers.get(Some('0')).unwrap().text, "two");
+
+            // Selected backwards
+            editor.feed_keys("$vby");
+            assert_eq!(editor.registers.get(None).unwrap().text, "three");
+            assert_eq!((editor.line, editor.cursor), (0, 8));
+            editor.feed_keys("P");
+            assert_eq!(editor.text_str().unwrap(), "one two threethree");
+        }
+
+        #[test]
+        fn case() {
+            let mut editor = normal_editor("Hello World\nfoo Bar");
+            editor.feed_keys("wvjhhhgU");
+            assert_eq!(editor.text_str().unwrap(), "Hello WORLD\nFOO Bar");
+            assert_eq!((editor.line, editor.cursor), (0, 6));
+            assert_eq!(editor.lines, vec![11, 7]);
+
+            editor.feed_keys("0vll~");
+            assert_eq!(editor.text_str().unwrap(), "hELlo WORLD\nFOO Bar");
+            editor.feed_keys("v$gu");
+            assert_eq!(editor.text_str().unwrap(), "hello world\nFOO Bar");
+            assert_eq!(editor.mode, Mode::Normal);
+
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "hELlo WORLD\nFOO Bar");
+            editor.undo();
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "Hello World\nfoo Bar");
+        }
+    }
+
+    #[cfg(test)]
+    mod change_word {
+        use super::*;
+
+        #[test]
+        fn stops_at_word_end() {
+            let mut editor = normal_editor("foo bar");
+            editor.feed_keys("cwX<esc>");
+            assert_eq!(editor.text_str().unwrap(), "X bar");
+
+            // The last char of a word only changes that char
+            let mut editor = normal_editor("foo bar");
+            editor.feed_keys("llcwX<esc>");
+            assert_eq!(editor.text_str().unwrap(), "foX bar");
+
+            // The last word on a line doesn't take the newline
+            let mut editor = normal_editor("foo\n  bar");
+            editor.feed_keys("cwX<esc>");
+            assert_eq!(editor.text_str().unwrap(), "X\n  bar");
+
+            // Punctuation ends the word
+            let mut editor = normal_editor("foo.bar");
+            editor.feed_keys("cwX<esc>");
+            assert_eq!(editor.text_str().unwrap(), "X.bar");
+        }
+
+        #[test]
+        fn counted() {
+            let mut editor = normal_editor("one two three");
+            editor.feed_keys("c2wX<esc>");
+            assert_eq!(editor.text_str().unwrap(), "X three");
+        }
+
+        #[test]
+        fn on_blanks() {
+            // Same as `dw`
+            let mut editor = normal_editor("foo   bar");
+            editor.feed_keys("3lcwX<esc>");
+            assert_eq!(editor.text_str().unwrap(), "fooXbar");
+        }
+    }
+
+    #[cfg(test)]
+    mod counted_delete {
+        use super::*;
+
+        #[test]
+        fn delete_chars() {
+            let mut editor = normal_editor("abcdef\nxyz");
+            editor.feed_keys("lx");
+            assert_eq!(editor.text_str().unwrap(), "acdef\nxyz");
+            assert_eq!(editor.cursor, 1);
+
+            // Stops at the end of the line instead of joining the next one
+            editor.feed_keys("ll9x");
+            assert_eq!(editor.text_str().unwrap(), "acd\nxyz");
+            assert_eq!((editor.line, editor.cursor), (0, 2));
+            assert_eq!(editor.registers.get(None).unwrap().text, "ef");
+            assert_eq!(editor.lines, vec![3, 3]);
+
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "acdef\nxyz");
+
+            let mut editor = normal_editor("\nb");
+            editor.feed_keys("3x");
+            assert_eq!(editor.text_str().unwrap(), "\nb");
+        }
+
+        #[test]
+        fn delete_lines() {
+            let mut editor = normal_editor("one\ntwo\nthree\nfour\nx");
+            editor.feed_keys("$2dd");
+            assert_eq!(editor.text_str().unwrap(), "three\nfour\nx");
+            assert_eq!((editor.line, editor.cursor), (0, 2));
+            let register = editor.registers.get(None).unwrap();
+            assert_eq!(
+                (register.text.as_str(), register.linewise),
+                ("one\ntwo\n", true)
+            );
+
+            // One undo step
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "one\ntwo\nthree\nfour\nx");
+        }
+
+        #[test]
+        fn delete_last_lines() {
+            let mut editor = normal_editor("a\nlonger line\nthird line\nend");
+            editor.feed_keys("j$9dd");
+            assert_eq!(editor.text_str().unwrap(), "a");
+            assert_eq!((editor.line, editor.cursor), (0, 0));
+            assert_eq!(editor.lines, vec![1]);
+
+            let mut editor = normal_editor("a long line\nb\nc");
+            editor.feed_keys("Gdd");
+            assert_eq!((editor.line, editor.cursor), (1, 0));
+            editor.feed_keys("kdd");
+            assert_eq!(editor.text_str().unwrap(), "b");
+
+            let mut editor = normal_editor("a\nb");
+            editor.feed_keys("5dd");
+            assert_eq!(editor.text_str().unwrap(), "");
+            assert_eq!((editor.line, editor.cursor), (0, 0));
+            editor.feed_keys("dd");
+            assert_eq!(editor.lines, vec![0]);
+            // Deleting nothing isn't an undo step
+            editor.feed_keys("2ddu");
+            assert_eq!(editor.text_str().unwrap(), "a\nb");
+        }
+
+        #[test]
+        fn delete_backwards() {
+            let mut editor = normal_editor("one two three");
+            editor.feed_keys("$db");
+            assert_eq!(editor.text_str().unwrap(), "one two e");
+            assert_eq!(editor.cursor, 8);
+            editor.feed_keys("d0");
+            assert_eq!(editor.text_str().unwrap(), "e");
+            assert_eq!(editor.cursor, 0);
+        }
+    }
+
+    #[cfg(test)]
+    mod case {
+        use super::*;
+
+        #[test]
+        fn operators() {
+            let mut editor = normal_editor("Hello World\nfoo Bar\n\nBaz");
+            editor.feed_keys("wguw");
+            assert_eq!(editor.text_str().unwrap(), "Hello world\nfoo Bar\n\nBaz");
+            assert_eq!((editor.line, editor.cursor), (0, 6));
+
+            editor.feed_keys("jgUU");
+            assert_eq!(editor.text_str().unwrap(), "Hello world\nFOO BAR\n\nBaz");
+            assert_eq!((editor.line, editor.cursor), (1, 0));
+            editor.feed_keys("ggwgUgU");
+            assert_eq!(editor.text_str().unwrap(), "HELLO WORLD\nFOO BAR\n\nBaz");
+            assert_eq!((editor.line, editor.cursor), (0, 0));
+
+            editor.feed_keys("lgu}");
+            assert_eq!(editor.text_str().unwrap(), "hello world\nfoo bar\n\nBaz");
+            assert_eq!((editor.line, editor.cursor), (0, 0));
+            assert_eq!(editor.lines, vec![11, 7, 0, 3]);
+
+            // Each operator is one undo step
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "HELLO WORLD\nFOO BAR\n\nBaz");
+            editor.undo();
+            editor.undo();
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "Hello World\nfoo Bar\n\nBaz");
+        }
+
+        #[test]
+        fn multi_char_uppercase() {
+            let mut editor = normal_editor("straße x\nnext");
+            editor.feed_keys("gUU");
+            assert_eq!(editor.text_str().unwrap(), "STRASSE X\nnext");
+            assert_eq!(editor.lines, vec![9, 4]);
+            editor.feed_keys("jguu");
+            assert_eq!(editor.text_str().unwrap(), "STRASSE X\nnext");
+
+            editor.undo();
+            assert_eq!(editor.text_str().unwrap(), "straße x\nnext");
+            assert_eq!(editor.lines, vec![8, 4]);
+        }
+    }
+
+    #[cfg(test)]
+    mod comment {
+        use super::*;
+
+        fn rust_editor(text: &str) -> Editor {
+            let mut editor = normal_editor(text);
+            editor.set_language(Some(syntax::tree_sitter_rust::language()));
+            editor
+        }
+
+        #[test]
+        fn current_line() {
+            let mut editor = rust_editor("fn a() {\n    b();\n}\n");
+            editor.feed_keys("jgcc");
+            assert_eq!(editor.text_str().unwrap(), "fn a() {\n    // b();\n}\n");
+            assert_eq!((editor.line, editor.cursor), (1, 4));
+            editor.feed_keys("gcc");
+            assert_eq!(editor.text_str().unwrap(), "fn a() {\n    b();\n}\n");
+        }
+
+        #[test]
+        fn lines() {
+            let mut editor = rust_editor("a();\n\n  // b();\nc();\n");
+            // Any line that isn't commented comments all of them, blank
+            // lines are skipped
+            editor.feed_keys("gcG");
+            assert_eq!(
+                editor.text_str().unwrap(),
+                "// a();\n\n  // // b();\n// c();\n"
+            );
+            // One undo step
+            editor.feed_keys("u");
+            assert_eq!(editor.text_str().unwrap(), "a();\n\n  // b();\nc();\n");
+
+            // Visual mode uncomments the selection when it's all commented
+            editor.feed_keys("jjvgc");
+            assert_eq!(editor.text_str().unwrap(), "a();\n\n  b();\nc();\n");
+            assert_eq!(editor.mode(), Mode::Normal);
+        }
+
+        #[test]
+        fn no_line_comments() {
+            let mut editor = rust_editor("a\n");
+            editor.set_language(None);
+            editor.feed_keys("gcc");
+            assert_eq!(editor.text_str().unwrap(), "a\n");
+
+            let mut editor = rust_editor("a\n");
+            editor.set_language(Some(syntax::PYTHON_CFG.language));
+            editor.feed_keys("gcc");
+            assert_eq!(editor.text_str().unwrap(), "# a\n");
+        }
+    }
+
+    mod reindent {
+        use super::*;
+
+        #[test]
+        fn syntax_tree() {
+            let mut editor = normal_editor(
+                "fn main() {
+let a = foo(
+        1,
+  );
+
+ match a {
+_ => {}
+    }
+}",
+            );
+            editor.set_language(Some(syntax::tree_sitter_rust::language()));
+            editor.feed_keys("=G");
+            assert_eq!(
+                editor.text_str().unwrap(),
+                "fn main() {
+  let a = foo(
+    1,
+  );
+
+  match a {
+    _ => {}
+  }
+}"
+            );
+            assert_eq!((editor.line, editor.cursor), (0, 0));
+
+            // All of it is undone at once
+            editor.undo();
+            assert_eq!(
+                editor.text_str().unwrap().lines().nth(2),
+                Some("        1,")
+            );
+            assert!(!editor.is_modified());
+        }
+
+        #[test]
+        fn current_line() {
+            let mut editor = normal_editor("if (a) {\n      b();\n}");
+            editor.feed_keys("j$==");
+            assert_eq!(editor.text_str().unwrap(), "if (a) {\n  b();\n}");
+            assert_eq!((editor.line, editor.cursor), (1, 2));
+            assert_eq!(editor.lines, vec![8, 6, 1]);
+        }
+
+        #[test]
+        fn visual() {
+            let mut editor = normal_editor("[\n1,\n[\n2\n]\n]");
+            editor.feed_keys("jvjj=");
+            assert_eq!(editor.text_str().unwrap(), "[\n  1,\n  [\n    2\n]\n]");
+            assert_eq!(editor.mode, Mode::Normal);
+            assert_eq!(editor.selection, None);
+        }
+    }
+
+    #[cfg(test)]
+    mod auto_indent {
+        use super::*;
+
+        fn type_keys(text: &str, line: usize, col: usize, typed: &str) -> Editor {
+            let mut editor = normal_editor(text);
+            editor.goto(line, col);
+            editor.feed_keys(typed);
+            editor
+        }
+
+        #[test]
+        fn keeps_indent() {
+            let editor = type_keys("  \tfoobar", 0, 5, "a<cr>x");
+            assert_eq!(editor.text_str().unwrap(), "  \tfoo\n  \txbar");
+            assert_eq!((editor.line, editor.cursor), (1, 4));
+            assert_eq!(editor.lines, vec![6, 7]);
+
+            // Only the indentation before the cursor
+            let editor = type_keys("    foo", 0, 2, "i<cr>");
+            assert_eq!(editor.text_str().unwrap(), "  \n    foo");
+            assert_eq!((editor.line, editor.cursor), (1, 2));
+        }
+
+        #[test]
+        fn after_bracket() {
+            let editor = type_keys("  if a {", 0, 7, "a<cr>b");
+            assert_eq!(editor.text_str().unwrap(), "  if a {\n    b");
+            assert_eq!((editor.line, editor.cursor), (1, 5));
+
+            // Not a pair
+            let editor = type_keys("foo(a]", 0, 3, "a<cr>");
+            assert_eq!(editor.text_str().unwrap(), "foo(\n  a]");
+        }
+
+        #[test]
+        fn between_brackets() {
+            let mut editor = type_keys(" f() {}", 0, 5, "a<cr>");
+            assert_eq!(editor.text_str().unwrap(), " f() {\n   \n }");
+            assert_eq!((editor.line, editor.cursor), (1, 3));
+            assert_eq!(editor.lines, vec![6, 3, 2]);
+
+            editor.feed_keys("x");
+            assert_eq!(editor.text_str().unwrap(), " f() {\n   x\n }");
+
+            // The new lines are undone along with the text before them
+            editor.feed_keys("<esc>u");
+            assert_eq!(editor.text_str().unwrap(), " f() {\n   \n }");
+            editor.feed_keys("u");
+            assert_eq!(editor.text_str().unwrap(), " f() {}");
+        }
+
+        #[test]
+        fn open_below() {
+            let mut editor = normal_editor("  if a {\n  }");
+            editor.take_text_edits();
+            editor.feed_keys("o");
+            assert_eq!(editor.text_str().unwrap(), "  if a {\n    \n  }");
+            assert_eq!((editor.line, editor.cursor), (1, 4));
+            assert_eq!(editor.lines, vec![8, 4, 3]);
+
+            // The indentation is sent along with the new line
+            let edits = editor.take_text_edits();
+            assert_eq!(edits.len(), 1);
+            assert_eq!(edits[0].text, "\n    ");
+        }
+
+        #[test]
+        fn open_above() {
+            let mut editor = normal_editor("  if a {\n  }");
+            editor.feed_keys("jO");
+            assert_eq!(editor.text_str().unwrap(), "  if a {\n    \n  }");
+            assert_eq!((editor.line, editor.cursor), (1, 4));
+
+            let mut editor = normal_editor("a\n\tb");
+            editor.feed_keys("jOc");
+            assert_eq!(editor.text_str().unwrap(), "a\n\tc\n\tb");
         }
 
         #[test]
-        fn multiple_lines() {
-            let text = "line 1\nline 2";
-            assert_eq!(vec![6, 6], text_to_lines(text.chars()));
+        fn match_arm() {
+            let mut editor = normal_editor("  None =>");
+            editor.feed_keys("A<cr>0");
+            assert_eq!(editor.text_str().unwrap(), "  None =>\n    0");
         }
 
         #[test]
-        fn trailing_newline() {
-            let text = "line 1\n";
-            assert_eq!(vec![6, 0], text_to_lines(text.chars()));
+        fn tabs() {
+            let mut editor = normal_editor("a {\n}");
+            editor.set_indent(IndentSettings {
+                use_spaces: false,
+                width: 4,
+            });
+            editor.feed_keys("A<cr>b<tab>c<esc>");
+            assert_eq!(editor.text_str().unwrap(), "a {\n\tb\tc\n}");
+
+            editor.feed_keys("v>");
+            assert_eq!(editor.text_str().unwrap(), "a {\n\t\tb\tc\n}");
+            editor.feed_keys("v<v<v<");
+            assert_eq!(editor.text_str().unwrap(), "a {\nb\tc\n}");
+
+            let mut editor = normal_editor("    b");
+            editor.set_indent(IndentSettings {
+                use_spaces: true,
+                width: 3,
+            });
+            editor.feed_keys("v<");
+            assert_eq!(editor.text_str().unwrap(), " b");
+            editor.feed_keys("A<tab>");
+            assert_eq!(editor.text_str().unwrap(), " b   ");
         }
 
         #[test]
-        fn leading_newline() {
-            let text = "\nline 1\n";
-            assert_eq!(vec![0, 6, 0], text_to_lines(text.chars()));
+        fn dedent_closing() {
+            let mut editor = Editor::new();
+            editor.set_auto_pairs(false);
+            editor.feed_keys("fn a() {<cr>b<cr>}");
+            assert_eq!(editor.text_str().unwrap(), "fn a() {\n  b\n}");
+            assert_eq!(editor.lines, vec![8, 3, 1]);
+
+            // Only when it's the first char
+            editor.feed_keys("<cr>  x)");
+            assert_eq!(editor.text_str().unwrap(), "fn a() {\n  b\n}\n  x)");
+
+            // Undone with the rest of the line
+            editor.feed_keys("<esc>u");
+            assert_eq!(editor.text_str().unwrap(), "fn a() {\n  b\n}\n");
+            // The indent came with the new line, not the dedent
+            editor.feed_keys("u");
+            assert_eq!(editor.text_str().unwrap(), "fn a() {\n  b\n  ");
         }
     }
 
     #[cfg(test)]
-    mod movement {
+    mod auto_pairs {
         use super::*;
 
         #[test]
-        fn sync_lines() {
-            // Should not exceed line length
+        fn closes_pairs() {
             let mut editor = Editor::new();
-            editor.insert("1");
-            editor.insert("2");
-            editor.enter();
-            editor.insert("1");
-            editor.insert("2");
-            editor.insert("3");
-            editor.up(1);
+            editor.feed_keys("f(a, [\"b");
+            assert_eq!(editor.text_all(), "f(a, [\"b\"])");
+            assert_eq!(editor.cursor(), 8);
+
+            // Typed over instead of typed again
+            editor.feed_keys("\"]x)");
+            assert_eq!(editor.text_all(), "f(a, [\"b\"]x)");
+            assert_eq!(editor.cursor(), 12);
+
+            // Undone along with the rest of the insert
+            editor.feed_keys("<esc>u");
+            assert_eq!(editor.text_all(), "");
+            assert_eq!(editor.mode(), Mode::Normal);
+        }
+
+        #[test]
+        fn apostrophe() {
+            let mut editor = Editor::new();
+            editor.feed_keys("don't 'x");
+            assert_eq!(editor.text_str().unwrap(), "don't 'x'");
+        }
+
+        #[test]
+        fn backspace() {
+            let mut editor = Editor::new();
+            editor.feed_keys("a{<bs>");
+            assert_eq!(editor.text_str().unwrap(), "a");
 
+            // Only right after the pair was typed
+            editor.feed_keys("(b<bs><bs>");
+            assert_eq!(editor.text_str().unwrap(), "a)");
             assert_eq!(editor.cursor, 1);
         }
+
+        #[test]
+        fn disabled() {
+            let mut editor = Editor::new();
+            editor.set_auto_pairs(false);
+            editor.feed_keys("f(\"a\")<bs>");
+            assert_eq!(editor.text_str().unwrap(), "f(\"a\"");
+        }
     }
 
     #[cfg(test)]
-    mod edit {
+    mod fuzz {
         use super::*;
+        use std::panic::{self, AssertUnwindSafe};
+
+        const FIXTURES: &[&str] = &[
+            "",
+            "a",
+            "\n",
+            "one two\nthree",
+            "fn main() {\n    let x = 1;\n\n    x.foo(bar);\n}\n",
+            "\n\nword\n\n",
+        ];
+
+        const KEYS: &[&str] = &[
+            "h", "j", "k", "l", "w", "W", "b", "B", "e", "E", "0", "$", "{", "}", "g", "G", "f",
+            "F", "d", "c", "y", "u", "U", "r", "p", "P", "\"", ">", "<", "~", "=", "a", "A", "i",
+            "o", "O", "v", "1", "2", "9", "x", " ", ".", "<esc>", "<bs>", "<cr>", "<tab>", "<c-w>",
+            "<c-u>", "<left>", "<right>", "<up>", "<down>", "<home>", "<end>", "<del>", "<pgup>",
+            "<pgdn>",
+        ];
+
+        /// xorshift, good enough to pick keys and keeps the runs reproducible
+        struct Rng(u64);
+
+        impl Rng {
+            fn next(&mut self) -> usize {
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                self.0 as usize
+            }
+        }
 
-        #[cfg(test)]
-        mod delete_range {
-            use super::*;
-
-            #[test]
-            fn single_line() {
-                let mut editor = Editor::new();
-                editor.insert("1");
-                editor.enter();
-                editor.insert("1");
-                let start = editor.pos();
-                editor.insert("2");
-                editor.insert("3");
-                let end = editor.pos();
-                editor.enter();
-                editor.insert("1");
-                editor.up(1);
-                editor.cursor = 0;
+        fn run(text: &str, keys: &[&str]) {
+            let mut editor = normal_editor(text);
+            editor.set_page_lines(3);
+            for key in keys {
+                editor.feed_keys(key);
+                assert_eq!(editor.lines, text_to_lines(editor.text.chars()));
+                assert!(editor.line < editor.lines.len());
+                assert!(editor.cursor <= editor.lines[editor.line] as usize);
+            }
 
-                editor.delete_range(start..end);
-                assert_eq!(editor.text_str().unwrap(), "1\n1\n1");
-                assert_eq!(editor.lines, vec![1, 1, 1]);
+            // Undoing everything should get back to where we started
+            editor.switch_mode(Mode::Normal);
+            while !editor.edits.is_empty() {
+                editor.undo();
             }
+            assert_eq!(editor.text_str().unwrap(), text);
+        }
 
-            #[test]
-            fn single_line_full() {
-                let mut editor = Editor::new();
-                editor.insert("1");
-                editor.enter();
-                let start = editor.pos();
-                editor.insert("1");
-                editor.insert("2");
-                editor.insert("3");
-                let end = editor.pos();
-                editor.enter();
-                editor.insert("1");
-                editor.up(1);
-                editor.cursor = 0;
+        fn panics(text: &str, keys: &[&str]) -> bool {
+            panic::catch_unwind(AssertUnwindSafe(|| run(text, keys))).is_err()
+        }
 
-                editor.delete_range(start..end);
-                assert_eq!(editor.text_str().unwrap(), "1\n\n1");
-                assert_eq!(editor.lines, vec![1, 0, 1]);
+        /// Drop steps one at a time while the sequence still fails so failures
+        /// are reported with something readable
+        fn shrink<T: Clone>(mut steps: Vec<T>, fails: impl Fn(&[T]) -> bool) -> Vec<T> {
+            let mut i = steps.len();
+            while i > 0 {
+                i -= 1;
+                let mut shrunk = steps.clone();
+                shrunk.remove(i);
+                if fails(&shrunk) {
+                    steps = shrunk;
+                    i = i.min(steps.len());
+                }
             }
+            steps
+        }
 
-            #[test]
-            fn multi_line() {
-                let mut editor = Editor::new();
-                editor.insert("1");
-                editor.enter();
-                editor.insert("1");
-                editor.insert("2");
-                editor.insert("3");
-                editor.enter();
-                editor.insert("1");
-                editor.up(1);
-                let start = editor.pos();
-                editor.down(1);
-                let end = editor.pos();
-
-                editor.delete_range(start..end);
-                assert_eq!(editor.text_str().unwrap(), "1\n");
-                assert_eq!(editor.lines, vec![1]);
+        #[test]
+        fn random_keys() {
+            let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+            for fixture in FIXTURES {
+                for _ in 0..100 {
+                    let keys: Vec<&str> = (0..200).map(|_| KEYS[rng.next() % KEYS.len()]).collect();
+                    if panics(fixture, &keys) {
+                        panic!(
+                            "panicked on {:?} with keys {:?}",
+                            fixture,
+                            shrink(keys, |keys| panics(fixture, keys))
+                        );
+                    }
+                }
             }
+        }
 
-            #[test]
-            fn entire_text() {
-                let mut editor = Editor::new();
-                editor.insert("1");
-                editor.insert("2");
-                editor.insert("3");
-                editor.enter();
-                editor.insert("1");
-                editor.insert("2");
-                editor.insert("3");
-                editor.enter();
-                editor.insert("1");
-                editor.insert("2");
-                editor.insert("3");
+        // Keys typed in insert mode, with chars that take more than a byte
+        // in UTF-8 so the cursor has to count chars
+        const TYPED: &[&str] = &["a", "b", " ", "ö", "𝔸", "<cr>", "<bs>", "<del>"];
 
-                // move to start
-                editor.cursor = 0;
-                editor.line = 0;
-                let start = editor.pos();
-                editor.line = editor.lines.len() - 1;
-                let end = editor.pos();
+        #[derive(Clone, Debug)]
+        enum Op {
+            /// Type `keys`, entering insert mode with `a` or `i` first if needed
+            Type {
+                append: bool,
+                keys: Vec<&'static str>,
+            },
+            Escape,
+            Move(&'static str),
+            Goto(usize, usize),
+            /// `x` with a count
+            DeleteChars(usize),
+            /// `dd` with a count
+            DeleteLines(usize),
+            Undo,
+            Redo,
+        }
 
-                editor.delete_range(start..end);
-                assert_eq!(editor.text_str().unwrap(), "");
-                assert_eq!(editor.lines, Vec::<u32>::new());
+        impl Op {
+            fn random(rng: &mut Rng) -> Self {
+                match rng.next() % 12 {
+                    0..=3 => Op::Type {
+                        append: rng.next().is_multiple_of(2),
+                        keys: (0..1 + rng.next() % 8)
+                            .map(|_| TYPED[rng.next() % TYPED.len()])
+                            .collect(),
+                    },
+                    4 => Op::Escape,
+                    5 | 6 => Op::Move(["h", "j", "k", "l", "w", "b", "$"][rng.next() % 7]),
+                    7 => Op::Goto(rng.next() % 8, rng.next() % 12),
+                    8 => Op::DeleteChars(1 + rng.next() % 3),
+                    9 => Op::DeleteLines(1 + rng.next() % 2),
+                    10 => Op::Undo,
+                    _ => Op::Redo,
+                }
             }
         }
 
-        #[test]
-        fn delete_line_first() {
-            let mut editor = Editor::new();
-            editor.insert("1");
-            editor.enter();
-            editor.insert("1");
-            editor.enter();
-            editor.insert("1");
-            editor.up(2);
-            editor.delete_line(0);
-
-            assert_eq!(editor.lines, vec![1, 1]);
+        /// The text as a plain list of chars that the ops are applied to
+        /// directly, along with the undo history they should make
+        struct Model {
+            text: Vec<char>,
+            undos: Vec<Vec<char>>,
+            redos: Vec<Vec<char>>,
+            insert: bool,
+            // The text before the insert session, until its first change
+            session_start: Option<Vec<char>>,
         }
 
-        #[test]
-        fn delete_line_middle() {
-            let mut editor = Editor::new();
-            editor.insert("1");
-            editor.enter();
-            editor.insert("1");
-            editor.insert("1");
-            editor.insert("1");
-            editor.enter();
-            editor.insert("1");
-            editor.insert("2");
-            editor.up(1);
-            editor.delete_line(1);
+        impl Model {
+            fn line_start(&self, line: usize) -> usize {
+                if line == 0 {
+                    return 0;
+                }
+                self.text
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, ch)| **ch == '\n')
+                    .nth(line - 1)
+                    .map_or(self.text.len(), |(i, _)| i + 1)
+            }
 
-            assert_eq!(editor.lines, vec![1, 2]);
-        }
+            /// Chars in `line`, without the new line
+            fn line_len(&self, line: usize) -> usize {
+                self.text[self.line_start(line)..]
+                    .iter()
+                    .take_while(|ch| **ch != '\n')
+                    .count()
+            }
 
-        #[test]
-        fn delete_line_last() {
-            let mut editor = Editor::new();
-            editor.insert("1");
-            editor.enter();
-            editor.insert("1");
-            editor.enter();
-            editor.insert("1");
-            editor.insert("2");
-            editor.delete_line(2);
+            fn line_count(&self) -> usize {
+                self.text.iter().filter(|ch| **ch == '\n').count() + 1
+            }
 
-            assert_eq!(editor.lines, vec![1, 1]);
+            /// Record the change from `before` in the undo history, each line
+            /// typed in an insert session is one change
+            fn changed(&mut self, before: Vec<char>) {
+                if before == self.text {
+                    return;
+                }
+                if !self.insert {
+                    self.undos.push(before);
+                    self.redos.clear();
+                } else if let Some(start) = self.session_start.take() {
+                    self.undos.push(start);
+                    self.redos.clear();
+                }
+            }
+
+            fn type_key(&mut self, key: &str, line: usize, col: usize) {
+                let pos = self.line_start(line) + col;
+                match key {
+                    "<cr>" => {
+                        // The new line keeps the indentation before the cursor
+                        let start = self.line_start(line);
+                        let indent = self.text[start..pos]
+                            .iter()
+                            .take_while(|ch| **ch == ' ')
+                            .count();
+                        let mut new_line: Vec<char> = std::iter::once('\n')
+                            .chain(std::iter::repeat_n(' ', indent))
+                            .collect();
+                        // One level more after an opening bracket, and the
+                        // closing one goes on the line after that
+                        let before = self.text[start..pos].iter().rev().find(|ch| **ch != ' ');
+                        if let Some(close) = before.copied().and_then(closing_bracket) {
+                            new_line.extend(IndentSettings::default().unit().chars());
+                            if self.text.get(pos) == Some(&close) {
+                                new_line.push('\n');
+                                new_line.extend(std::iter::repeat_n(' ', indent));
+                            }
+                        }
+                        self.text.splice(pos..pos, new_line);
+                    }
+                    "<bs>" if pos > 0 => {
+                        self.text.remove(pos - 1);
+                    }
+                    "<del>" if pos < self.text.len() => {
+                        self.text.remove(pos);
+                    }
+                    "<bs>" | "<del>" => {}
+                    text => {
+                        self.text.splice(pos..pos, text.chars());
+                    }
+                }
+            }
+
+            /// Like `delete_lines`, the last line takes the new line before it
+            fn delete_lines(&mut self, first: usize, last: usize) {
+                let mut start = self.line_start(first);
+                let end = if last + 1 < self.line_count() {
+                    self.line_start(last + 1)
+                } else {
+                    self.text.len()
+                };
+                if last + 1 == self.line_count() && first > 0 {
+                    start -= 1;
+                }
+                self.text.drain(start..end);
+            }
         }
 
-        #[test]
-        fn backspace_beginning_in_between_line() {
-            let mut editor = Editor::new();
-            editor.insert("1");
-            editor.insert("2");
-            editor.insert("3");
-            editor.enter();
-            editor.insert("1");
-            editor.enter();
-            editor.insert("1");
-            editor.up(1);
-            editor.left(1);
+        fn normal_mode(editor: &mut Editor, model: &mut Model) {
+            if model.insert {
+                editor.feed_keys("<esc>");
+                model.insert = false;
+                model.session_start = None;
+            }
+        }
 
-            assert_eq!(editor.backspace(), EditorEvent::DrawText);
-            assert_eq!(editor.lines, vec![4, 1]);
+        fn step(editor: &mut Editor, model: &mut Model, op: &Op) {
+            match op {
+                Op::Type { append, keys } => {
+                    if !model.insert {
+                        editor.feed_keys(if *append { "a" } else { "i" });
+                        model.insert = true;
+                        model.session_start = Some(model.text.clone());
+                    }
+                    for key in keys {
+                        let before = model.text.clone();
+                        model.type_key(key, editor.line, editor.cursor);
+                        editor.feed_keys(key);
+                        model.changed(before);
+                        // A new line starts the next step of the session
+                        if *key == "<cr>" {
+                            model.session_start = Some(model.text.clone());
+                        }
+                    }
+                }
+                Op::Escape => normal_mode(editor, model),
+                Op::Move(key) => {
+                    normal_mode(editor, model);
+                    editor.feed_keys(key);
+                }
+                Op::Goto(line, col) => editor.goto(*line, *col),
+                Op::DeleteChars(count) => {
+                    normal_mode(editor, model);
+                    let before = model.text.clone();
+                    let len = model.line_len(editor.line);
+                    if editor.cursor < len {
+                        let start = model.line_start(editor.line) + editor.cursor;
+                        let end = start + (*count).min(len - editor.cursor);
+                        model.text.drain(start..end);
+                    }
+                    editor.feed_keys(&format!("{}x", count));
+                    model.changed(before);
+                }
+                Op::DeleteLines(count) => {
+                    normal_mode(editor, model);
+                    let before = model.text.clone();
+                    let last = (editor.line + count - 1).min(model.line_count() - 1);
+                    model.delete_lines(editor.line, last);
+                    match count {
+                        1 => editor.feed_keys("dd"),
+                        count => editor.feed_keys(&format!("{}dd", count)),
+                    };
+                    model.changed(before);
+                }
+                Op::Undo => {
+                    normal_mode(editor, model);
+                    if let Some(text) = model.undos.pop() {
+                        model.redos.push(mem::replace(&mut model.text, text));
+                    }
+                    editor.feed_keys("u");
+                }
+                Op::Redo => {
+                    normal_mode(editor, model);
+                    if let Some(text) = model.redos.pop() {
+                        model.undos.push(mem::replace(&mut model.text, text));
+                    }
+                    editor.feed_keys("r");
+                }
+            }
         }
 
-        #[test]
-        fn enter_in_between() {
-            let mut editor = Editor::new();
-            editor.insert("1");
-            editor.insert("2");
-            editor.insert("3");
-            editor.cursor = 2;
+        /// Apply `ops` to an editor and the model, checking after each one that
+        /// the rope matches the model, and that replaying the editor's text
+        /// edits on a copy of the starting text ends up with the same thing.
+        /// At the end undoing everything should give back the starting text
+        fn check_model(text: &str, ops: &[Op]) {
+            let mut editor = normal_editor(text);
+            let mut model = Model {
+                text: text.chars().collect(),
+                undos: Vec::new(),
+                redos: Vec::new(),
+                insert: false,
+                session_start: None,
+            };
+            let mut shadow: Vec<char> = text.chars().collect();
+
+            for (i, op) in ops.iter().enumerate() {
+                step(&mut editor, &mut model, op);
+
+                let rope: String = editor.text.chars().collect();
+                let expected: String = model.text.iter().collect();
+                assert_eq!(rope, expected, "text after op {} {:?}", i, op);
+                assert_eq!(editor.lines, text_to_lines(editor.text.chars()));
+                assert_eq!(editor.is_insert(), model.insert);
+
+                for edit in editor.take_text_edits() {
+                    shadow.splice(edit.start..edit.old_end, edit.text.chars());
+                    assert_eq!(edit.new_end, edit.start + edit.text.chars().count());
+                }
+                assert_eq!(
+                    shadow.iter().collect::<String>(),
+                    rope,
+                    "text edits after op {} {:?}",
+                    i,
+                    op
+                );
+            }
 
-            editor.enter();
-            assert_eq!(editor.lines, vec![2, 1]);
+            normal_mode(&mut editor, &mut model);
+            while !editor.edits.is_empty() {
+                editor.undo();
+            }
+            assert_eq!(editor.text_str().unwrap(), text);
         }
 
-        #[test]
-        fn enter_beginning() {
-            let mut editor = Editor::new();
-            editor.insert("1");
-            editor.insert("2");
-            editor.insert("3");
-            editor.cursor = 0;
+        fn fails(text: &str, ops: &[Op]) -> bool {
+            panic::catch_unwind(AssertUnwindSafe(|| check_model(text, ops))).is_err()
+        }
 
-            editor.enter();
-            assert_eq!(editor.lines, vec![0, 3]);
+        fn random_ops(seed: u64, runs: usize) {
+            let mut rng = Rng(seed);
+            for fixture in FIXTURES {
+                for _ in 0..runs {
+                    let ops: Vec<Op> = (0..100).map(|_| Op::random(&mut rng)).collect();
+                    if fails(fixture, &ops) {
+                        panic!(
+                            "failed on {:?} with seed {:#x} and ops {:?}",
+                            fixture,
+                            seed,
+                            shrink(ops, |ops| fails(fixture, ops))
+                        );
+                    }
+                }
+            }
         }
 
         #[test]
-        fn enter_end() {
-            let mut editor = Editor::new();
-            editor.insert("1");
-            editor.insert("2");
-            editor.insert("3");
-            editor.cursor = 3;
+        fn matches_model() {
+            random_ops(0x9e37_79b9_7f4a_7c15, 50);
+        }
 
-            editor.enter();
-            assert_eq!(editor.lines, vec![3, 0]);
+        /// Many more runs from a new seed each time, run with
+        /// `cargo test -- --ignored matches_model_long`. `GLYPH_FUZZ_SEED`
+        /// repeats a run from the seed it failed with
+        #[test]
+        #[ignore]
+        fn matches_model_long() {
+            let seed = std::env::var("GLYPH_FUZZ_SEED")
+                .ok()
+                .and_then(|seed| u64::from_str_radix(seed.trim_start_matches("0x"), 16).ok())
+                .unwrap_or_else(|| {
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap()
+                        .as_nanos() as u64
+                        | 1
+                });
+            random_ops(seed, 2000);
         }
     }
 }
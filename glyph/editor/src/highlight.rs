@@ -0,0 +1,541 @@
+use std::ops::Range;
+
+use lsp::SemanticTokenSpan;
+use ropey::RopeSlice;
+use syntax::{
+    tree_sitter::{Node, Parser, QueryCursor, Tree},
+    tree_sitter_highlight::HighlightConfiguration,
+    HIGHLIGHTS,
+};
+
+use crate::{Color, FontStyle, HighlightColors, TextEdit, ThemeType};
+
+/// Keeps a buffer's syntax tree and the color of each of its chars up to
+/// date. Edits are fed to tree-sitter so only the changed parts of the tree
+/// are reparsed, and only the lines whose highlighting could have changed are
+/// highlighted again. Without a configuration everything is the theme's
+/// foreground color
+pub struct SyntaxHighlighter<'highlight> {
+    highlight_colors: HighlightColors,
+    cfg: Option<&'highlight HighlightConfiguration>,
+    parser: Parser,
+    tree: Option<Tree>,
+    query_cursor: QueryCursor,
+    // The index of the highlight for each capture in `cfg.query`
+    capture_highlights: Vec<Option<u8>>,
+    colors: Vec<Color>,
+    // The font style of each char, from the same highlights as the colors
+    styles: Vec<FontStyle>,
+    // Colors from the language server's semantic tokens, these win over the
+    // tree-sitter ones. Empty until the server sends some
+    semantic: Vec<Option<Color>>,
+}
+
+impl<'highlight> SyntaxHighlighter<'highlight> {
+    pub fn new(theme: &ThemeType, cfg: Option<&'highlight HighlightConfiguration>) -> Self {
+        let mut parser = Parser::new();
+        if let Some(cfg) = cfg {
+            parser
+                .set_language(cfg.language)
+                .expect("Highlight configuration should have a valid language");
+        }
+
+        Self {
+            highlight_colors: HighlightColors::new(theme),
+            cfg,
+            parser,
+            tree: None,
+            query_cursor: QueryCursor::new(),
+            capture_highlights: cfg
+                .map(|cfg| capture_highlights(cfg.query.capture_names()))
+                .unwrap_or_default(),
+            colors: Vec::new(),
+            styles: Vec::new(),
+            semantic: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn config(&self) -> Option<&'highlight HighlightConfiguration> {
+        self.cfg
+    }
+
+    /// Highlight with `cfg` from now on, ex. when the buffer's language
+    /// changes. The tree belongs to the old grammar so it's dropped instead of
+    /// being reused, the next `update` parses and colors the whole text again
+    pub fn set_config(&mut self, cfg: Option<&'highlight HighlightConfiguration>) {
+        if let Some(cfg) = cfg {
+            self.parser
+                .set_language(cfg.language)
+                .expect("Highlight configuration should have a valid language");
+        }
+        self.cfg = cfg;
+        self.tree = None;
+        self.capture_highlights = cfg
+            .map(|cfg| capture_highlights(cfg.query.capture_names()))
+            .unwrap_or_default();
+    }
+
+    /// The tree-sitter color of each char in the text
+    #[inline]
+    pub fn colors(&self) -> &[Color] {
+        &self.colors
+    }
+
+    /// The color of the char at `idx`, from its semantic token if it has one
+    #[inline]
+    pub fn color(&self, idx: usize) -> Color {
+        match self.semantic.get(idx) {
+            Some(Some(color)) => *color,
+            _ => self.colors[idx],
+        }
+    }
+
+    /// The font style of the char at `idx`
+    #[inline]
+    pub fn style(&self, idx: usize) -> FontStyle {
+        self.styles[idx]
+    }
+
+    /// Color the chars of `tokens` over the tree-sitter colors. Tokens the
+    /// theme has no color for keep the tree-sitter ones, and tokens past the
+    /// end of `text` are left out, they're from before it was edited
+    pub fn set_semantic_tokens(
+        &mut self,
+        text: RopeSlice,
+        tokens: &[SemanticTokenSpan],
+        theme: &ThemeType,
+    ) {
+        self.semantic.clear();
+        self.semantic.resize(text.len_chars(), None);
+
+        let len_utf16 = text.len_utf16_cu();
+        for token in tokens {
+            let line = token.line as usize;
+            let Some(color) = theme.semantic_token_color(&token.token_type) else {
+                continue;
+            };
+            if line >= text.len_lines() {
+                continue;
+            }
+            // LSP positions count UTF-16 code units
+            let line_start = text.char_to_utf16_cu(text.line_to_char(line));
+            let start = (line_start + token.start as usize).min(len_utf16);
+            let end = (line_start + (token.start + token.len) as usize).min(len_utf16);
+            let start = text.utf16_cu_to_char(start);
+            let end = text.utf16_cu_to_char(end);
+            self.semantic[start..end].fill(Some(*color));
+        }
+    }
+
+    /// Bring the tree and colors up to date with `text`, `edits` are the
+    /// changes made to it since the last update
+    pub fn update(&mut self, text: RopeSlice, edits: &[TextEdit]) {
+        let mut tree = match self.tree.take() {
+            Some(tree) => tree,
+            None => {
+                self.tree = self.parse(text, None);
+                self.colors = vec![self.highlight_colors.fg(); text.len_chars()];
+                self.styles = vec![FontStyle::Normal; text.len_chars()];
+                self.highlight(text, 0..text.len_chars());
+                return;
+            }
+        };
+
+        // The edited chars in the new text
+        let mut dirty: Option<Range<usize>> = None;
+        for edit in edits {
+            tree.edit(&edit.input_edit);
+            self.colors.splice(
+                edit.start..edit.old_end,
+                std::iter::repeat_n(self.highlight_colors.fg(), edit.new_end - edit.start),
+            );
+            self.styles.splice(
+                edit.start..edit.old_end,
+                std::iter::repeat_n(FontStyle::Normal, edit.new_end - edit.start),
+            );
+            // Tokens stay on their text until the server sends new ones
+            if !self.semantic.is_empty() {
+                self.semantic.splice(
+                    edit.start..edit.old_end,
+                    std::iter::repeat_n(None, edit.new_end - edit.start),
+                );
+            }
+
+            dirty = Some(match dirty {
+                Some(range) => {
+                    let start = shift_pos(range.start, edit).min(edit.start);
+                    let end = shift_pos(range.end, edit).max(edit.new_end);
+                    start..end
+                }
+                None => edit.start..edit.new_end,
+            });
+        }
+
+        let new_tree = match self.parse(text, Some(&tree)) {
+            Some(new_tree) => new_tree,
+            None => return,
+        };
+
+        // Nodes that were changed by the edits can extend past them, ex. opening
+        // a string literal changes the rest of the line
+        let mut dirty_bytes =
+            dirty.map(|range| text.char_to_byte(range.start)..text.char_to_byte(range.end));
+        for changed in tree.changed_ranges(&new_tree) {
+            dirty_bytes = Some(match dirty_bytes {
+                Some(range) => range.start.min(changed.start_byte)..range.end.max(changed.end_byte),
+                None => changed.start_byte..changed.end_byte,
+            });
+        }
+        self.tree = Some(new_tree);
+
+        if let Some(bytes) = dirty_bytes {
+            // Redo whole lines, a change can affect the highlighting of the
+            // chars around it
+            let len = text.len_bytes();
+            let first_line = text.byte_to_line(bytes.start.min(len));
+            let last_line = text.byte_to_line(bytes.end.min(len));
+            let start = text.line_to_char(first_line);
+            let end = if last_line + 1 < text.len_lines() {
+                text.line_to_char(last_line + 1)
+            } else {
+                text.len_chars()
+            };
+            self.highlight(text, start..end);
+        }
+    }
+
+    fn parse(&mut self, text: RopeSlice, old_tree: Option<&Tree>) -> Option<Tree> {
+        self.cfg?;
+        let len = text.len_bytes();
+        self.parser.parse_with(
+            &mut |byte, _| {
+                if byte >= len {
+                    return &[] as &[u8];
+                }
+                let (chunk, chunk_byte, _, _) = text.chunk_at_byte(byte);
+                &chunk.as_bytes()[byte - chunk_byte..]
+            },
+            old_tree,
+        )
+    }
+
+    /// Recompute the colors of the chars in `range`
+    fn highlight(&mut self, text: RopeSlice, range: Range<usize>) {
+        self.colors[range.clone()].fill(self.highlight_colors.fg());
+        self.styles[range.clone()].fill(FontStyle::Normal);
+
+        let (tree, cfg) = match (&self.tree, self.cfg) {
+            (Some(tree), Some(cfg)) => (tree, cfg),
+            _ => return,
+        };
+
+        let start_byte = text.char_to_byte(range.start);
+        let end_byte = text.char_to_byte(range.end);
+        self.query_cursor.set_byte_range(start_byte..end_byte);
+
+        let node_text = |node: Node| {
+            let node_range = node.byte_range();
+            text.slice(text.byte_to_char(node_range.start)..text.byte_to_char(node_range.end))
+                .chunks()
+                .map(str::as_bytes)
+        };
+
+        // Captures come in order of where they start, so nested nodes come
+        // after their parents and take precedence. When a node has multiple
+        // captures the first one wins, same as tree-sitter-highlight
+        let mut last_node = None;
+        for (m, i) in self
+            .query_cursor
+            .captures(&cfg.query, tree.root_node(), node_text)
+        {
+            let capture = m.captures[i];
+            let node_range = capture.node.byte_range();
+            if last_node == Some(node_range.clone()) {
+                continue;
+            }
+            last_node = Some(node_range.clone());
+
+            // Captures without a highlight leave the color of the parent
+            let highlight = match self.capture_highlights[capture.index as usize] {
+                Some(highlight) => highlight,
+                None => continue,
+            };
+            let color = self.highlight_colors.get(highlight);
+            let style = self.highlight_colors.style(highlight);
+            let start = text.byte_to_char(node_range.start.max(start_byte));
+            let end = text.byte_to_char(node_range.end.min(end_byte));
+            if start < end {
+                self.colors[start..end].fill(color);
+                self.styles[start..end].fill(style);
+            }
+        }
+    }
+}
+
+/// Where a char index ends up after an edit
+#[inline]
+fn shift_pos(pos: usize, edit: &TextEdit) -> usize {
+    if pos <= edit.start {
+        pos
+    } else if pos >= edit.old_end {
+        pos - edit.old_end + edit.new_end
+    } else {
+        edit.new_end
+    }
+}
+
+/// Match capture names to highlights the same way
+/// `HighlightConfiguration::configure()` does, the longest highlight whose
+/// parts are all in the capture name wins
+fn capture_highlights(capture_names: &[String]) -> Vec<Option<u8>> {
+    capture_names
+        .iter()
+        .map(|capture_name| {
+            let capture_parts: Vec<&str> = capture_name.split('.').collect();
+
+            let mut best = None;
+            let mut best_len = 0;
+            for (i, name) in HIGHLIGHTS.iter().enumerate() {
+                let parts: Vec<&str> = name.split('.').collect();
+                if parts.iter().all(|part| capture_parts.contains(part)) && parts.len() > best_len {
+                    best = Some(i as u8);
+                    best_len = parts.len();
+                }
+            }
+            best
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{commands::key_events, Editor, GITHUB};
+    use syntax::Highlight;
+
+    #[test]
+    fn capture_names() {
+        let names = ["function.method", "punctuation.bracket", "nope"].map(String::from);
+        let highlights = capture_highlights(&names);
+        assert_eq!(
+            highlights[0].and_then(Highlight::from_u8),
+            Some(Highlight::Function)
+        );
+        assert_eq!(
+            highlights[1].and_then(Highlight::from_u8),
+            Some(Highlight::PunctuationBracket)
+        );
+        assert!(highlights[2].is_none());
+        assert_eq!(Highlight::PunctuationBracket.name(), "punctuation.bracket");
+        for (i, name) in HIGHLIGHTS.iter().enumerate() {
+            assert_eq!(Highlight::from_u8(i as u8).unwrap().name(), *name);
+        }
+    }
+
+    #[test]
+    fn incremental_matches_full() {
+        let mut editor = Editor::with_text(Some("fn main() {\n    let x = 1;\n}\n".into()));
+        let mut highlighter = SyntaxHighlighter::new(&**GITHUB, Some(&syntax::RUST_CFG));
+        let edits = editor.take_text_edits();
+        highlighter.update(editor.text_all(), &edits);
+
+        let keys = [
+            "<esc>",
+            "j",
+            "A",
+            " // done",
+            "<esc>",
+            "k",
+            "o",
+            "let s = \"open",
+            "<esc>",
+            "d",
+            "d",
+            "u",
+            "x",
+            "x",
+            "O",
+            "struct Foo;",
+            "<esc>",
+            "G",
+            "d",
+            "k",
+        ];
+        for keys in keys {
+            for event in key_events(keys) {
+                editor.event(event);
+            }
+            let edits = editor.take_text_edits();
+            highlighter.update(editor.text_all(), &edits);
+
+            let mut full = SyntaxHighlighter::new(&**GITHUB, Some(&syntax::RUST_CFG));
+            full.update(editor.text_all(), &[]);
+            assert_eq!(
+                highlighter.colors(),
+                full.colors(),
+                "after {:?}: {:?}",
+                keys,
+                editor.text_all().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn plain_text() {
+        let mut editor = Editor::with_text(Some("fn main() {}\n".into()));
+        let mut highlighter = SyntaxHighlighter::new(&**GITHUB, None);
+        let edits = editor.take_text_edits();
+        highlighter.update(editor.text_all(), &edits);
+
+        for event in key_events("ilet x = 1;") {
+            editor.event(event);
+        }
+        let edits = editor.take_text_edits();
+        highlighter.update(editor.text_all(), &edits);
+
+        let fg = *GITHUB.fg();
+        assert_eq!(highlighter.colors().len(), editor.text_all().len_chars());
+        assert!(highlighter.colors().iter().all(|color| *color == fg));
+    }
+
+    #[test]
+    fn python() {
+        let cfg = syntax::config_for_extension("py").map(|cfg| &**cfg);
+        let mut highlighter = SyntaxHighlighter::new(&**GITHUB, cfg);
+        highlighter.update(ropey::Rope::from_str("def f():\n    pass\n").slice(..), &[]);
+
+        let keyword = *GITHUB.highlight(Highlight::Keyword).unwrap();
+        assert_eq!(highlighter.colors()[..3], [keyword; 3]);
+    }
+
+    #[test]
+    fn json() {
+        let cfg = syntax::config_for_extension("json").map(|cfg| &**cfg);
+        let mut highlighter = SyntaxHighlighter::new(&**GITHUB, cfg);
+        highlighter.update(ropey::Rope::from_str("{\"a\": [1, null]}").slice(..), &[]);
+
+        let color = |highlight| *GITHUB.highlight(highlight).unwrap();
+        let colors = highlighter.colors();
+        assert_eq!(colors[0], color(Highlight::PunctuationBracket));
+        assert_eq!(colors[1..4], [color(Highlight::Property); 3]);
+        assert_eq!(colors[4], color(Highlight::PunctuationDelimiter));
+        assert_eq!(colors[7], color(Highlight::Constant));
+        assert_eq!(colors[10..14], [color(Highlight::Constant); 4]);
+    }
+
+    #[test]
+    fn markdown() {
+        let cfg = syntax::config_for_extension("md").map(|cfg| &**cfg);
+        let mut highlighter = SyntaxHighlighter::new(&**GITHUB, cfg);
+        highlighter.update(
+            ropey::Rope::from_str("# Title\n\n- `code`\n").slice(..),
+            &[],
+        );
+
+        let color = |highlight| *GITHUB.highlight(highlight).unwrap();
+        let colors = highlighter.colors();
+        assert_eq!(colors[0], color(Highlight::PunctuationSpecial));
+        assert_eq!(colors[2..7], [color(Highlight::Keyword); 5]);
+        assert_eq!(colors[9], color(Highlight::PunctuationSpecial));
+        assert_eq!(colors[11..17], [color(Highlight::String); 6]);
+    }
+
+    #[test]
+    fn switch_language() {
+        let mut editor = Editor::with_text(Some("def f():\n    pass\n".into()));
+        let mut highlighter = SyntaxHighlighter::new(&**GITHUB, Some(&syntax::RUST_CFG));
+        let edits = editor.take_text_edits();
+        highlighter.update(editor.text_all(), &edits);
+
+        let python = syntax::config_for_extension("py").map(|cfg| &**cfg);
+        highlighter.set_config(python);
+        // An edit made after the switch still gets a full parse with the new
+        // grammar instead of reusing the old tree
+        for event in key_events("<esc>Go# done") {
+            editor.event(event);
+        }
+        let edits = editor.take_text_edits();
+        highlighter.update(editor.text_all(), &edits);
+
+        let mut full = SyntaxHighlighter::new(&**GITHUB, python);
+        full.update(editor.text_all(), &[]);
+        assert_eq!(highlighter.colors(), full.colors());
+        let keyword = *GITHUB.highlight(Highlight::Keyword).unwrap();
+        assert_eq!(highlighter.colors()[..3], [keyword; 3]);
+
+        highlighter.set_config(None);
+        highlighter.update(editor.text_all(), &[]);
+        let fg = *GITHUB.fg();
+        assert!(highlighter.colors().iter().all(|color| *color == fg));
+    }
+
+    #[test]
+    fn semantic_tokens() {
+        let mut editor = Editor::with_text(Some("fn f(x: u8) {\n    x;\n}\n".into()));
+        let mut highlighter = SyntaxHighlighter::new(&**GITHUB, Some(&syntax::RUST_CFG));
+        let edits = editor.take_text_edits();
+        highlighter.update(editor.text_all(), &edits);
+
+        let span = |line, start, len, token_type: &str| SemanticTokenSpan {
+            line,
+            start,
+            len,
+            token_type: token_type.into(),
+        };
+        highlighter.set_semantic_tokens(
+            editor.text_all(),
+            &[
+                span(0, 5, 1, "enumMember"),
+                span(1, 4, 1, "enumMember"),
+                // The theme has no color for these
+                span(0, 3, 1, "nope"),
+                // From before the text was edited
+                span(9, 0, 4, "enumMember"),
+            ],
+            &**GITHUB,
+        );
+
+        let constant = *GITHUB.semantic_token_color("enumMember").unwrap();
+        assert_ne!(highlighter.colors()[5], constant);
+        assert_eq!(highlighter.color(5), constant);
+        assert_eq!(highlighter.color(18), constant);
+        // Tree-sitter colors everything else
+        assert_eq!(highlighter.color(0), highlighter.colors()[0]);
+        assert_eq!(highlighter.color(3), highlighter.colors()[3]);
+
+        // Edits move the tokens along with their text
+        for event in key_events("<esc>ggiab") {
+            editor.event(event);
+        }
+        let edits = editor.take_text_edits();
+        highlighter.update(editor.text_all(), &edits);
+        assert_eq!(highlighter.color(7), constant);
+        assert_eq!(highlighter.color(20), constant);
+        assert_eq!(highlighter.color(0), highlighter.colors()[0]);
+    }
+
+    #[test]
+    fn shift() {
+        let edit = |start, old_end, new_end| TextEdit {
+            start,
+            old_end,
+            new_end,
+            text: String::new(),
+            input_edit: syntax::tree_sitter::InputEdit {
+                start_byte: 0,
+                old_end_byte: 0,
+                new_end_byte: 0,
+                start_position: Default::default(),
+                old_end_position: Default::default(),
+                new_end_position: Default::default(),
+            },
+        };
+        // Insertion
+        assert_eq!(shift_pos(2, &edit(2, 2, 5)), 2);
+        assert_eq!(shift_pos(3, &edit(2, 2, 5)), 6);
+        // Removal
+        assert_eq!(shift_pos(3, &edit(2, 4, 2)), 2);
+        assert_eq!(shift_pos(6, &edit(2, 4, 2)), 4);
+    }
+}
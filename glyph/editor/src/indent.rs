@@ -0,0 +1,297 @@
+use std::{collections::HashMap, ops::RangeInclusive};
+
+use syntax::tree_sitter::{Node, Tree};
+
+/// Closing brackets, a line starting with one is indented like the line
+/// that opened it
+const CLOSING: [&str; 3] = ["}", ")", "]"];
+
+/// What a level of indentation is made of
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IndentSettings {
+    /// Indent with spaces instead of a tab
+    pub use_spaces: bool,
+    /// Columns in a level, tabs are drawn this wide too
+    pub width: u8,
+}
+
+impl Default for IndentSettings {
+    fn default() -> Self {
+        Self {
+            use_spaces: true,
+            width: 2,
+        }
+    }
+}
+
+impl IndentSettings {
+    /// A level of indentation, what `<tab>` inserts
+    pub fn unit(&self) -> String {
+        if self.use_spaces {
+            " ".repeat(self.width.max(1) as usize)
+        } else {
+            "\t".to_string()
+        }
+    }
+
+    /// How many chars at the start of `whitespace` make up a level, a tab or
+    /// up to `width` spaces
+    pub fn level_len(&self, whitespace: impl Iterator<Item = char>) -> usize {
+        let mut len = 0;
+        for c in whitespace.take(self.width.max(1) as usize) {
+            match c {
+                '\t' if len == 0 => return 1,
+                ' ' => len += 1,
+                _ => break,
+            }
+        }
+        len
+    }
+}
+
+/// The indent level of each line in `lines`, `None` for lines that should
+/// be left alone like ones inside a multi-line string or comment.
+///
+/// A line is indented one level more than the last line that starts a
+/// node around it, so `foo(bar(` only adds one level for the lines after it
+pub fn tree_indents(tree: &Tree, text: &str, lines: RangeInclusive<usize>) -> Vec<Option<usize>> {
+    let mut indents = TreeIndents {
+        tree,
+        text,
+        line_starts: line_starts(text),
+        levels: HashMap::new(),
+    };
+    lines
+        .map(|line| {
+            let node = indents.first_node(line)?;
+            // The line continues a token from an earlier one
+            if node.start_position().row < line {
+                return None;
+            }
+            Some(indents.level(line))
+        })
+        .collect()
+}
+
+struct TreeIndents<'a> {
+    tree: &'a Tree,
+    text: &'a str,
+    line_starts: Vec<usize>,
+    /// Levels of the lines computed so far
+    levels: HashMap<usize, usize>,
+}
+
+impl<'a> TreeIndents<'a> {
+    fn level(&mut self, line: usize) -> usize {
+        if let Some(level) = self.levels.get(&line) {
+            return *level;
+        }
+
+        let level = match self.first_node(line) {
+            None => 0,
+            Some(node) => match node.parent() {
+                Some(parent) if CLOSING.contains(&node.kind()) => {
+                    let row = parent.start_position().row;
+                    if row < line {
+                        self.level(row)
+                    } else {
+                        0
+                    }
+                }
+                _ => {
+                    let mut nearest = None;
+                    let mut ancestor = node.parent();
+                    while let Some(node) = ancestor {
+                        ancestor = node.parent();
+                        let row = node.start_position().row;
+                        // The root spans the whole file
+                        if ancestor.is_some() && row < line {
+                            nearest = nearest.max(Some(row));
+                        }
+                    }
+                    nearest.map_or(0, |row| self.level(row) + 1)
+                }
+            },
+        };
+
+        self.levels.insert(line, level);
+        level
+    }
+
+    /// The node of the first char on `line` that isn't whitespace
+    fn first_node(&self, line: usize) -> Option<Node<'a>> {
+        let start = *self.line_starts.get(line)?;
+        let first = start + first_non_blank(&self.text[start..])?;
+        self.tree
+            .root_node()
+            .descendant_for_byte_range(first, first + 1)
+    }
+}
+
+/// `tree_indents` for text without a grammar, lines are indented once for
+/// every earlier line with brackets that are still open. Blank lines are
+/// left alone
+pub fn bracket_indents(text: &str, lines: RangeInclusive<usize>) -> Vec<Option<usize>> {
+    // The line each open bracket is on
+    let mut open: Vec<usize> = Vec::new();
+    let mut indents = Vec::new();
+
+    for (row, line) in text.split('\n').enumerate() {
+        if row > *lines.end() {
+            break;
+        }
+        if row >= *lines.start() {
+            let closing = line
+                .chars()
+                .take_while(|c| matches!(c, '}' | ')' | ']') || c.is_whitespace())
+                .filter(|c| !c.is_whitespace())
+                .count();
+            let still_open = &open[..open.len().saturating_sub(closing)];
+            let mut rows = still_open.to_vec();
+            rows.dedup();
+            indents.push(first_non_blank(line).map(|_| rows.len()));
+        }
+
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if in_string => escaped = true,
+                '"' => in_string = !in_string,
+                _ if in_string => {}
+                '/' if chars.peek() == Some(&'/') => break,
+                '(' | '[' | '{' => open.push(row),
+                ')' | ']' | '}' => {
+                    open.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Lines past the end of the text
+    indents.resize(lines.count(), None);
+    indents
+}
+
+/// The bracket that closes `open`, `None` if it isn't an opening bracket
+pub fn closing_bracket(open: char) -> Option<char> {
+    match open {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        _ => None,
+    }
+}
+
+/// The byte each line starts at
+fn line_starts(text: &str) -> Vec<usize> {
+    let newlines = text.match_indices('\n').map(|(i, _)| i + 1);
+    std::iter::once(0).chain(newlines).collect()
+}
+
+/// The byte offset of the first char on the line that isn't whitespace,
+/// `None` for blank lines
+fn first_non_blank(line: &str) -> Option<usize> {
+    line.char_indices()
+        .take_while(|(_, c)| *c != '\n')
+        .find(|(_, c)| !c.is_whitespace())
+        .map(|(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::tree_sitter::Parser;
+
+    use super::*;
+
+    fn parse(text: &str) -> Tree {
+        let mut parser = Parser::new();
+        parser
+            .set_language(syntax::tree_sitter_rust::language())
+            .unwrap();
+        parser.parse(text, None).unwrap()
+    }
+
+    fn indents(text: &str) -> Vec<Option<usize>> {
+        let tree = parse(text);
+        let last = text.lines().count() - 1;
+        tree_indents(&tree, text, 0..=last)
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let text = "fn main() {
+if true {
+let a = 1;
+} else {
+loop {}
+}
+}";
+        assert_eq!(indents(text), [0, 1, 2, 1, 2, 1, 0].map(Some).to_vec());
+    }
+
+    #[test]
+    fn multi_line_expressions() {
+        let text = "fn main() {
+let a = foo(bar(
+1,
+2,
+));
+let b = a
+.len();
+}";
+        assert_eq!(indents(text), [0, 1, 2, 2, 1, 1, 2, 0].map(Some).to_vec());
+    }
+
+    #[test]
+    fn match_arms() {
+        let text = "fn f(x: u8) -> u8 {
+match x {
+0 => 1,
+1 => {
+2
+}
+_ =>
+3,
+}
+}";
+        assert_eq!(
+            indents(text),
+            [0, 1, 2, 2, 3, 2, 2, 3, 1, 0].map(Some).to_vec()
+        );
+    }
+
+    #[test]
+    fn strings_and_comments() {
+        let text = "fn main() {
+let s = \"a
+  b\";
+/*
+ * c
+ */
+
+}";
+        assert_eq!(
+            indents(text),
+            vec![Some(0), Some(1), None, Some(1), None, None, None, Some(0)]
+        );
+    }
+
+    #[test]
+    fn brackets() {
+        let text = "{
+a: [1, (2,
+3)],
+\"}\" // }
+}
+)";
+        assert_eq!(
+            bracket_indents(text, 0..=5),
+            [0, 1, 2, 1, 0, 0].map(Some).to_vec()
+        );
+        assert_eq!(bracket_indents(text, 4..=6), vec![Some(0), Some(0), None]);
+    }
+}
@@ -1,20 +1,42 @@
 #![feature(option_result_unwrap_unchecked)]
 
+use std::fmt;
+
 use once_cell::sync::Lazy;
 
 pub use atlas::*;
+pub use buffer::*;
+pub use commands::*;
+pub use config::*;
 pub use constants::*;
 pub use editor::*;
 pub use gl_program::*;
+pub use highlight::*;
+pub use indent::*;
+pub use paths::*;
+pub use popup::*;
+pub use registers::*;
+pub use signs::*;
 pub use theme::*;
+pub use walk::*;
 pub use window::*;
 
 mod atlas;
+mod buffer;
+mod commands;
+mod config;
 mod constants;
 mod editor;
 mod gl_program;
+mod highlight;
+mod indent;
+mod paths;
+mod popup;
+mod registers;
+mod signs;
 mod theme;
 mod vim;
+mod walk;
 mod window;
 #[derive(Debug)]
 
@@ -32,6 +54,13 @@ pub enum EditorEvent {
     DrawCursor,
     DrawSelection,
     Multiple,
+    /// Open the file from [`Editor::take_goto_file`]
+    GotoFile,
+    /// Run the `:` command from [`Editor::take_ex_cmd`]
+    ExCmd,
+    /// Switched to the mode, sent with what else the key did in
+    /// [`EditorEvent::Multiple`]
+    ModeChanged(Mode),
 }
 
 pub enum MoveWordKind {
@@ -40,6 +69,7 @@ pub enum MoveWordKind {
     End,
 }
 
+#[derive(Clone, Copy)]
 pub enum WindowFrameKind {
     Draw,
     Scroll,
@@ -57,6 +87,34 @@ pub const ERROR_RED: Color = Color {
     a: 255,
 };
 
+pub const WARNING_YELLOW: Color = Color {
+    r: 229,
+    g: 181,
+    b: 43,
+    a: 255,
+};
+
+pub const INFO_BLUE: Color = Color {
+    r: 55,
+    g: 148,
+    b: 255,
+    a: 255,
+};
+
+pub const HINT_GRAY: Color = Color {
+    r: 128,
+    g: 128,
+    b: 128,
+    a: 255,
+};
+
+pub const CHANGED_GRAY: Color = Color {
+    r: 160,
+    g: 160,
+    b: 160,
+    a: 255,
+};
+
 pub const HIGHLIGHT_BLUE: Color = Color {
     r: 15,
     g: 191,
@@ -65,7 +123,7 @@ pub const HIGHLIGHT_BLUE: Color = Color {
 };
 
 #[repr(C)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -83,30 +141,232 @@ impl Color {
         ]
     }
 
-    fn from_hex(hex: &str) -> Self {
-        let [r, g, b, a] = Color::hex_to_rgba(hex);
-        Self { r, g, b, a }
+    /// Mix with white, `amount` is from 0 (unchanged) to 1 (white)
+    pub fn lighten(&self, amount: f32) -> Self {
+        let mix = |c: u8| c + ((255 - c) as f32 * amount).round() as u8;
+        Self {
+            r: mix(self.r),
+            g: mix(self.g),
+            b: mix(self.b),
+            a: self.a,
+        }
     }
 
-    fn hex_to_rgba(hex: &str) -> [u8; 4] {
-        let mut rgba = [0, 0, 0, 255];
-        let hex = hex.trim_start_matches('#');
-        for (i, c) in hex
+    /// Mix with black, `amount` is from 0 (unchanged) to 1 (black)
+    pub fn darken(&self, amount: f32) -> Self {
+        let mix = |c: u8| c - (c as f32 * amount).round() as u8;
+        Self {
+            r: mix(self.r),
+            g: mix(self.g),
+            b: mix(self.b),
+            a: self.a,
+        }
+    }
+
+    /// WCAG relative luminance, from 0 (black) to 1 (white). Alpha is ignored
+    pub fn luminance(&self) -> f32 {
+        let linear = |c: u8| {
+            let c = c as f32 / 255.0;
+            if c <= 0.03928 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        0.2126 * linear(self.r) + 0.7152 * linear(self.g) + 0.0722 * linear(self.b)
+    }
+
+    /// WCAG contrast ratio, from 1 (no contrast) to 21 (black on white)
+    pub fn contrast(&self, other: &Color) -> f32 {
+        let (a, b) = (self.luminance(), other.luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    /// The closest color to this one with a contrast of at least `min`
+    /// against `bg`. It's lightened if white stands out more from `bg` than
+    /// black does and darkened otherwise, all the way to white or black if
+    /// `min` can't be reached before that
+    pub fn with_contrast(&self, bg: &Color, min: f32) -> Self {
+        if self.contrast(bg) >= min {
+            return *self;
+        }
+
+        let white = Color::from_hex("#fff").unwrap();
+        let black = Color::from_hex("#000").unwrap();
+        let adjust = |amount| {
+            if white.contrast(bg) >= black.contrast(bg) {
+                self.lighten(amount)
+            } else {
+                self.darken(amount)
+            }
+        };
+
+        // Contrast only grows with `amount`, so binary search the smallest
+        // one that's enough
+        let (mut lo, mut hi) = (0.0, 1.0);
+        for _ in 0..16 {
+            let mid = (lo + hi) / 2.0;
+            if adjust(mid).contrast(bg) >= min {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        adjust(hi)
+    }
+
+    /// The same color with its alpha set to `alpha` (0 to 1)
+    pub fn with_alpha(&self, alpha: f32) -> Self {
+        Self {
+            a: (255.0 * alpha).round() as u8,
+            ..*self
+        }
+    }
+
+    /// Parse `#rgb`, `#rrggbb` or `#rrggbbaa`, the `#` is optional
+    pub fn from_hex(hex: &str) -> Result<Self, ColorParseError> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        let digits = hex
             .chars()
-            .step_by(2)
-            .zip(hex.chars().skip(1).step_by(2))
-            .enumerate()
-        {
-            let c = c.0.to_digit(16).unwrap() << 4 | c.1.to_digit(16).unwrap();
-            rgba[i] = c as u8;
+            .map(|c| match c.to_digit(16) {
+                Some(digit) => Ok(digit as u8),
+                None => Err(ColorParseError::Digit(c)),
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+
+        let channel = |i: usize| digits[i] << 4 | digits[i + 1];
+        let [r, g, b, a] = match digits.len() {
+            // `#abc` is short for `#aabbcc`
+            3 => [digits[0] * 17, digits[1] * 17, digits[2] * 17, 255],
+            6 => [channel(0), channel(2), channel(4), 255],
+            8 => [channel(0), channel(2), channel(4), channel(6)],
+            len => return Err(ColorParseError::Length(len)),
+        };
+        Ok(Self { r, g, b, a })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ColorParseError {
+    /// The amount of hex digits, only 3, 6 and 8 are colors
+    Length(usize),
+    /// A char that isn't a hex digit
+    Digit(char),
+}
+
+impl fmt::Display for ColorParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorParseError::Length(len) => {
+                write!(f, "expected 3, 6 or 8 hex digits but there are {}", len)
+            }
+            ColorParseError::Digit(c) => write!(f, "{:?} is not a hex digit", c),
         }
-        rgba
     }
 }
 
-pub type ThemeType = Lazy<Box<dyn Theme + Send + Sync>>;
+pub type ThemeType = dyn Theme + Send + Sync;
+
+pub static TOKYO_NIGHT_STORM: Lazy<Box<ThemeType>> = Lazy::new(|| Box::new(TokyoNightStorm::new()));
+
+pub static GITHUB: Lazy<Box<ThemeType>> = Lazy::new(|| Box::new(GithubDark::new()));
+
+pub static GRUVBOX_DARK: Lazy<Box<ThemeType>> = Lazy::new(|| Box::new(GruvboxDark::new()));
+
+pub static DRACULA: Lazy<Box<ThemeType>> = Lazy::new(|| Box::new(Dracula::new()));
+
+pub static HIGH_CONTRAST: Lazy<Box<ThemeType>> = Lazy::new(|| Box::new(HighContrast::new()));
+
+/// The theme in `theme.toml` in the config directory, `None` if there isn't
+/// one or it couldn't be loaded
+pub static CONFIG_THEME: Lazy<Option<Box<ThemeType>>> = Lazy::new(load_config_theme);
+
+/// The theme windows use when they aren't given one, the config directory's
+/// theme file or GitHub Dark
+pub fn default_theme() -> &'static ThemeType {
+    CONFIG_THEME.as_deref().unwrap_or(&**GITHUB)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(hex: &str) -> Result<[u8; 4], ColorParseError> {
+        Color::from_hex(hex).map(|c| [c.r, c.g, c.b, c.a])
+    }
+
+    #[test]
+    fn from_hex() {
+        assert_eq!(rgba("#0d1117"), Ok([13, 17, 23, 255]));
+        assert_eq!(rgba("A5D6FF"), Ok([165, 214, 255, 255]));
+        assert_eq!(rgba("#fff"), Ok([255, 255, 255, 255]));
+        assert_eq!(rgba("#a1c"), Ok([170, 17, 204, 255]));
+        assert_eq!(rgba("#0f0f0f80"), Ok([15, 15, 15, 128]));
+    }
+
+    #[test]
+    fn from_hex_invalid() {
+        assert_eq!(rgba(""), Err(ColorParseError::Length(0)));
+        assert_eq!(rgba("#ff"), Err(ColorParseError::Length(2)));
+        assert_eq!(rgba("#fffff"), Err(ColorParseError::Length(5)));
+        assert_eq!(rgba("#fffffffff"), Err(ColorParseError::Length(9)));
+        assert_eq!(rgba("#ggg"), Err(ColorParseError::Digit('g')));
+        assert_eq!(rgba("##fff"), Err(ColorParseError::Digit('#')));
+    }
+
+    fn contrast(fg: &str, bg: &str) -> f32 {
+        Color::from_hex(fg)
+            .unwrap()
+            .contrast(&Color::from_hex(bg).unwrap())
+    }
+
+    #[test]
+    fn luminance() {
+        assert_eq!(Color::from_hex("#000").unwrap().luminance(), 0.0);
+        assert!((Color::from_hex("#fff").unwrap().luminance() - 1.0).abs() < 1e-4);
+        assert!((Color::from_hex("#808080").unwrap().luminance() - 0.2159).abs() < 1e-4);
+    }
+
+    #[test]
+    fn contrast_ratio() {
+        assert!((contrast("#000", "#fff") - 21.0).abs() < 1e-3);
+        assert!((contrast("#fff", "#000") - 21.0).abs() < 1e-3);
+        assert!((contrast("#abcdef", "#abcdef") - 1.0).abs() < 1e-6);
+        // The classic pair on either side of the WCAG AA threshold of 4.5
+        assert!((contrast("#777777", "#fff") - 4.48).abs() < 0.01);
+        assert!((contrast("#767676", "#fff") - 4.54).abs() < 0.01);
+        // GitHub Dark's comments
+        assert!((contrast("#8b949e", "#0d1117") - 6.15).abs() < 0.01);
+    }
+
+    #[test]
+    fn with_contrast() {
+        let dark_bg = Color::from_hex("#0d1117").unwrap();
+        let light_bg = Color::from_hex("#fff").unwrap();
+        let gray = Color::from_hex("#4d5566").unwrap();
+
+        let lighter = gray.with_contrast(&dark_bg, 4.5);
+        assert!(lighter.contrast(&dark_bg) >= 4.5);
+        assert!(lighter.contrast(&dark_bg) < 4.6);
+        assert!(lighter.luminance() > gray.luminance());
+        assert_eq!(lighter.a, gray.a);
+
+        let darker = gray.with_contrast(&light_bg, 10.0);
+        assert!(darker.contrast(&light_bg) >= 10.0);
+        assert!(darker.luminance() < gray.luminance());
 
-pub static TOKYO_NIGHT_STORM: Lazy<Box<dyn Theme + Send + Sync>> =
-    Lazy::new(|| Box::new(TokyoNightStorm::new()));
+        // Already enough
+        let fg = Color::from_hex("#c9d1d9").unwrap();
+        assert_eq!(rgba_of(fg.with_contrast(&dark_bg, 4.5)), rgba_of(fg));
 
-pub static GITHUB: Lazy<Box<dyn Theme + Send + Sync>> = Lazy::new(|| Box::new(GithubDark::new()));
+        // Can't be reached, so it goes as far as it can
+        assert_eq!(
+            rgba_of(gray.with_contrast(&dark_bg, 30.0)),
+            [255, 255, 255, 255]
+        );
+    }
+
+    fn rgba_of(c: Color) -> [u8; 4] {
+        [c.r, c.g, c.b, c.a]
+    }
+}
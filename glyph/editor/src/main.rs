@@ -2,11 +2,18 @@ use core::time;
 use std::{
     ffi::CStr,
     fs,
+    path::{Path, PathBuf},
+    rc::Rc,
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use glyph::{EventResult, Window, WindowFrameKind, GITHUB, SCREEN_HEIGHT, SCREEN_WIDTH};
+use glyph::{
+    default_theme, editor_config_path, low_contrast, ContrastMode, EditorConfig, EventResult,
+    FileTheme, ThemeType, Window, WindowFrameKind, DRACULA, GITHUB, GRUVBOX_DARK, HIGH_CONTRAST,
+    SCREEN_HEIGHT, SCREEN_WIDTH, TOKYO_NIGHT_STORM,
+};
 use lsp::Client;
+use sdl2::event::{Event, WindowEvent};
 
 fn main() {
     #[cfg(debug_assertions)]
@@ -14,8 +21,135 @@ fn main() {
     #[cfg(not(debug_assertions))]
     let filepath_idx = 1;
 
-    let initial_text = std::env::args()
-        .nth(filepath_idx)
+    let mut args: Vec<String> = std::env::args().collect();
+    let file_theme: FileTheme;
+    let theme: Option<&ThemeType> = match take_option(&mut args, "--theme").as_deref() {
+        None => None,
+        Some("github") => Some(&**GITHUB),
+        Some("tokyo-night-storm") => Some(&**TOKYO_NIGHT_STORM),
+        Some("gruvbox") => Some(&**GRUVBOX_DARK),
+        Some("dracula") => Some(&**DRACULA),
+        Some("high-contrast") => Some(&**HIGH_CONTRAST),
+        Some(path) if Path::new(path).is_file() => match FileTheme::from_file(Path::new(path)) {
+            Ok(theme) => {
+                file_theme = theme;
+                Some(&file_theme)
+            }
+            Err(e) => {
+                eprintln!("Failed to load theme {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        Some(name) => {
+            eprintln!(
+                "Unknown theme \"{}\", the themes are github, tokyo-night-storm, gruvbox, dracula and high-contrast, or a path to a theme file",
+                name
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let min_contrast =
+        take_option(&mut args, "--min-contrast").map(|ratio| match ratio.parse::<f32>() {
+            Ok(ratio) if ratio >= 1.0 => ratio,
+            _ => {
+                eprintln!("--min-contrast should be a contrast ratio from 1 to 21, like 4.5");
+                std::process::exit(1);
+            }
+        });
+    let contrast_mode = match take_option(&mut args, "--contrast-mode").as_deref() {
+        None | Some("warn") => ContrastMode::Warn,
+        Some("adjust") => ContrastMode::Adjust,
+        Some(mode) => {
+            eprintln!(
+                "Unknown contrast mode \"{}\", the modes are warn and adjust",
+                mode
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let adjusted_theme: FileTheme;
+    let mut contrast_warning = None;
+    let theme = match min_contrast {
+        None => theme,
+        Some(min) => {
+            let theme = theme.unwrap_or_else(|| default_theme());
+            match contrast_mode {
+                ContrastMode::Warn => {
+                    let low = low_contrast(theme, min);
+                    if !low.is_empty() {
+                        contrast_warning =
+                            Some(format!("Contrast below {}: {}", min, low.join(", ")));
+                    }
+                    Some(theme)
+                }
+                ContrastMode::Adjust => {
+                    let mut theme = FileTheme::from_theme(theme);
+                    let adjusted = theme.enforce_contrast(min);
+                    if !adjusted.is_empty() {
+                        eprintln!("Adjusted contrast of {}", adjusted.join(", "));
+                    }
+                    adjusted_theme = theme;
+                    Some(&adjusted_theme as &ThemeType)
+                }
+            }
+        }
+    };
+
+    let mut config = match EditorConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            let path = editor_config_path().unwrap_or_default();
+            eprintln!("Failed to load {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    if take_flag(&mut args, "--no-cursorline") {
+        config.cursor_line = false;
+    }
+    if take_flag(&mut args, "--wrap") {
+        config.wrap = true;
+    }
+    if take_flag(&mut args, "--tabs") {
+        config.tabs = true;
+    }
+    if take_flag(&mut args, "--no-auto-pairs") {
+        config.auto_pairs = false;
+    }
+    if let Some(width) = take_option(&mut args, "--tab-width") {
+        config.tab_width = match width.parse::<u8>() {
+            Ok(width) if width > 0 => width,
+            _ => {
+                eprintln!("--tab-width should be a number of columns, like 4");
+                std::process::exit(1);
+            }
+        };
+    }
+
+    if let Some(path) = take_option(&mut args, "--font") {
+        config.font_path = PathBuf::from(path);
+    }
+    if let Some(size) = take_option(&mut args, "--font-size") {
+        config.font_size = match size.parse::<u32>() {
+            Ok(size) if size > 0 => size,
+            _ => {
+                eprintln!("--font-size should be a size in pixels, like 48");
+                std::process::exit(1);
+            }
+        };
+    }
+    config.font_path = match config.font().resolve() {
+        Some(path) => path,
+        None => {
+            eprintln!("Can't find the font {}", config.font_path.display());
+            std::process::exit(1);
+        }
+    };
+
+    let filepath = args.get(filepath_idx).map(PathBuf::from);
+    let initial_text = filepath
+        .as_ref()
         .map(|path| fs::read_to_string(path).unwrap());
 
     let sdl_ctx = sdl2::init().unwrap();
@@ -61,7 +195,17 @@ fn main() {
         "/Users/zackradisic/Desktop/Code/lsp-test-workspace",
     );
 
-    let mut editor_window = Window::new(initial_text, &GITHUB, &lsp_client);
+    let mut editor_window = Window::new(
+        initial_text,
+        filepath.as_deref(),
+        theme,
+        &config,
+        &lsp_client,
+    );
+    if let Some(warning) = contrast_warning {
+        editor_window.set_message(warning);
+    }
+    editor_window.set_clipboard(Rc::new(video_subsystem.clipboard()));
     editor_window.render_text();
     window.gl_swap_window();
 
@@ -94,6 +238,16 @@ fn main() {
         let mut draw = false;
         let mut scroll = false;
         for event in event_pump.poll_iter() {
+            if let Event::Window {
+                win_event: WindowEvent::SizeChanged(..),
+                ..
+            } = event
+            {
+                // The drawable can be bigger than the window on high DPI
+                // screens
+                let (width, height) = window.drawable_size();
+                unsafe { gl::Viewport(0, 0, width as i32, height as i32) };
+            }
             match editor_window.event(event, timer.ticks()) {
                 EventResult::Quit => break 'running,
                 EventResult::Draw | EventResult::Nothing => {
@@ -106,9 +260,12 @@ fn main() {
         }
 
         editor_window.queue_diagnostics();
+        draw |= editor_window.update_server_message(timer.ticks());
+        draw |= editor_window.update_semantic_tokens();
+        draw |= editor_window.update_inlay_hints(timer.ticks());
 
         frames += 1;
-        if draw {
+        if draw || editor_window.is_stale() {
             editor_window.frame(WindowFrameKind::Draw, timer.ticks());
             window.gl_swap_window();
         } else if scroll {
@@ -143,3 +300,23 @@ fn main() {
         // std::thread::sleep(time::Duration::from_millis(1000));
     }
 }
+
+/// Remove the flag `name` from `args`, returns true if it was there
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| arg != name);
+    args.len() != len
+}
+
+/// Remove `name value` or `name=value` from `args` and return the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let idx = args
+        .iter()
+        .position(|arg| arg == name || arg.starts_with(&format!("{}=", name)))?;
+    let arg = args.remove(idx);
+    match arg.strip_prefix(&format!("{}=", name)) {
+        Some(value) => Some(value.to_string()),
+        None if idx < args.len() => Some(args.remove(idx)),
+        None => None,
+    }
+}
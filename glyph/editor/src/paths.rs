@@ -0,0 +1,215 @@
+use std::path::{Path, PathBuf};
+
+/// A file path written in some text, with the `path:line:col` position after
+/// it if there is one. The position is 1-based, same as it's written
+#[derive(Debug, PartialEq)]
+pub struct FileLocation {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub col: Option<usize>,
+}
+
+/// Chars that can be in a path when it isn't quoted, like Vim's `isfname`
+/// plus `:` for the position suffix
+#[inline]
+fn is_path_char(c: char) -> bool {
+    c.is_alphanumeric() || "/\\.-_~+@%=#$,:".contains(c)
+}
+
+/// The path around the char at `col` in `line`. Paths in quotes can have
+/// spaces, otherwise the path is the run of path chars the cursor is on.
+/// Punctuation that ends a sentence or list is left out
+pub fn path_at(line: &str, col: usize) -> Option<FileLocation> {
+    let chars: Vec<char> = line.chars().collect();
+    if col >= chars.len() {
+        return None;
+    }
+
+    let token: String = match quoted_range(&chars, col) {
+        Some((start, end)) => chars[start..end].iter().collect(),
+        None => {
+            if !is_path_char(chars[col]) {
+                return None;
+            }
+            let start = chars[..col]
+                .iter()
+                .rposition(|c| !is_path_char(*c))
+                .map_or(0, |i| i + 1);
+            let end = chars[col..]
+                .iter()
+                .position(|c| !is_path_char(*c))
+                .map_or(chars.len(), |i| col + i);
+            chars[start..end].iter().collect()
+        }
+    };
+
+    let token = token
+        .trim()
+        .trim_end_matches(['.', ',', ':', ';', '!', '?']);
+    let (path, line, col) = split_position(token);
+    // Ellipses and lone slashes aren't worth opening
+    if !path.chars().any(char::is_alphanumeric) {
+        return None;
+    }
+
+    Some(FileLocation {
+        path: PathBuf::from(path),
+        line,
+        col,
+    })
+}
+
+/// The chars between the quotes around `col`, if it's inside a pair of them
+fn quoted_range(chars: &[char], col: usize) -> Option<(usize, usize)> {
+    for quote in ['"', '\'', '`'] {
+        if chars[col] == quote {
+            continue;
+        }
+        let before = chars[..col].iter().filter(|c| **c == quote).count();
+        if before % 2 == 0 {
+            continue;
+        }
+        let start = chars[..col].iter().rposition(|c| *c == quote)? + 1;
+        if let Some(len) = chars[col..].iter().position(|c| *c == quote) {
+            return Some((start, col + len));
+        }
+    }
+    None
+}
+
+/// Split `path:line:col` or `path:line` into its parts
+fn split_position(token: &str) -> (&str, Option<usize>, Option<usize>) {
+    let number = |s: &str| s.parse::<usize>().ok();
+    let mut parts = token.rsplitn(3, ':');
+    let last = parts.next().unwrap_or_default();
+    let middle = parts.next();
+    let first = parts.next();
+
+    match (first, middle.and_then(number), number(last)) {
+        (Some(path), Some(line), Some(col)) => (path, Some(line), Some(col)),
+        (_, _, Some(line)) if middle.is_some() => {
+            (&token[..token.len() - last.len() - 1], Some(line), None)
+        }
+        _ => (token, None, None),
+    }
+}
+
+/// Find `path` on disk, relative paths are tried against each of `dirs`
+/// in order
+pub fn resolve_path(path: &Path, dirs: &[&Path]) -> Option<PathBuf> {
+    if path.is_absolute() {
+        return path.exists().then(|| path.to_path_buf());
+    }
+    dirs.iter()
+        .map(|dir| dir.join(path))
+        .find(|path| path.exists())
+}
+
+/// `path` relative to `dir` if it's inside it, for showing to the user
+pub fn display_path(path: &Path, dir: &Path) -> String {
+    path.strip_prefix(dir).unwrap_or(path).display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(line: &str, col: usize) -> Option<(String, Option<usize>, Option<usize>)> {
+        path_at(line, col).map(|loc| (loc.path.display().to_string(), loc.line, loc.col))
+    }
+
+    #[test]
+    fn quoted() {
+        let line = "#include \"my dir/util.h\"";
+        assert_eq!(path(line, 13), Some(("my dir/util.h".into(), None, None)));
+        assert_eq!(
+            path("import x from '../lib/x.js';", 17),
+            Some(("../lib/x.js".into(), None, None))
+        );
+        assert_eq!(
+            path("#include <stdio.h>", 11),
+            Some(("stdio.h".into(), None, None))
+        );
+        // Between two strings isn't in one
+        assert_eq!(
+            path("\"a\" + src/lib.rs + \"b\"", 8),
+            Some(("src/lib.rs".into(), None, None))
+        );
+    }
+
+    #[test]
+    fn trailing_punctuation() {
+        assert_eq!(
+            path("See src/editor.rs.", 6),
+            Some(("src/editor.rs".into(), None, None))
+        );
+        assert_eq!(
+            path("(../Cargo.toml), then", 3),
+            Some(("../Cargo.toml".into(), None, None))
+        );
+        assert_eq!(
+            path("done: a.txt, b", 7),
+            Some(("a.txt".into(), None, None))
+        );
+    }
+
+    #[test]
+    fn position_suffix() {
+        assert_eq!(
+            path("/abs/path.txt:42", 0),
+            Some(("/abs/path.txt".into(), Some(42), None))
+        );
+        assert_eq!(
+            path("error at src/main.rs:10:5: oops", 12),
+            Some(("src/main.rs".into(), Some(10), Some(5)))
+        );
+        // Not a position
+        assert_eq!(path("C:foo.txt", 4), Some(("C:foo.txt".into(), None, None)));
+    }
+
+    #[test]
+    fn nothing_under_cursor() {
+        assert_eq!(path("a  b", 1), None);
+        assert_eq!(path("...", 1), None);
+        assert_eq!(path("abc", 3), None);
+    }
+
+    #[test]
+    fn resolve() {
+        let dir = std::env::temp_dir().join("glyph_resolve_path");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/file.txt"), "").unwrap();
+
+        let sub = dir.join("sub");
+        assert_eq!(
+            resolve_path(Path::new("file.txt"), &[&dir, &sub]),
+            Some(sub.join("file.txt"))
+        );
+        assert_eq!(
+            resolve_path(Path::new("sub/file.txt"), &[&dir, &sub]),
+            Some(dir.join("sub/file.txt"))
+        );
+        assert_eq!(resolve_path(Path::new("nope.txt"), &[&dir, &sub]), None);
+        assert_eq!(
+            resolve_path(&sub.join("file.txt"), &[]),
+            Some(sub.join("file.txt"))
+        );
+    }
+
+    #[test]
+    fn display() {
+        let dir = Path::new("/home/me/project");
+        assert_eq!(
+            display_path(Path::new("/home/me/project/src/main.rs"), dir),
+            "src/main.rs"
+        );
+        assert_eq!(
+            display_path(Path::new("/home/me/other/lib.rs"), dir),
+            "/home/me/other/lib.rs"
+        );
+        assert_eq!(
+            display_path(Path::new("/home/me/projectile/a.rs"), dir),
+            "/home/me/projectile/a.rs"
+        );
+    }
+}
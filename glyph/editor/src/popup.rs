@@ -0,0 +1,261 @@
+use std::path::{Path, PathBuf};
+
+use lsp::{Diagnostic, DiagnosticSeverity, NumberOrString};
+
+use crate::display_path;
+
+/// A place a popup can jump to, 0-based like the editor's position
+#[derive(Clone, Debug, PartialEq)]
+pub struct PopupLocation {
+    pub path: PathBuf,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// A row of a popup, wrapped to fit. Rows listing a related location have the
+/// index of that location
+#[derive(Clone, Debug, PartialEq)]
+pub struct PopupLine {
+    pub text: String,
+    pub location: Option<usize>,
+}
+
+/// The contents of the popup showing a diagnostic
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiagnosticPopup {
+    pub lines: Vec<PopupLine>,
+    pub locations: Vec<PopupLocation>,
+    // The location `Enter` jumps to
+    pub selected: usize,
+}
+
+impl DiagnosticPopup {
+    /// Lay out `diag` in rows at most `width` chars wide. The severity, code
+    /// and source go on the first row when the server sent any of them, then
+    /// the message keeps its own line breaks, then the related locations are
+    /// numbered as `file:line: message` with paths shown relative to `dir`
+    pub fn new(diag: &Diagnostic, width: usize, dir: &Path) -> Self {
+        let mut lines = Vec::new();
+        let mut push = |text: &str, location: Option<usize>| {
+            for row in text.lines().flat_map(|line| wrap(line, width)) {
+                lines.push(PopupLine {
+                    text: row,
+                    location,
+                });
+            }
+        };
+
+        let header = header(diag);
+        if !header.is_empty() {
+            push(&header, None);
+        }
+        push(&diag.message, None);
+
+        let mut locations = Vec::new();
+        for (i, info) in diag.related_information.iter().flatten().enumerate() {
+            // Anything that isn't a file still gets listed, opening it fails
+            // with an error in the status line
+            let path = info
+                .location
+                .uri
+                .to_file_path()
+                .unwrap_or_else(|_| PathBuf::from(info.location.uri.path()));
+            let start = info.location.range.start;
+            push(
+                &format!(
+                    "{}. {}:{}: {}",
+                    i + 1,
+                    display_path(&path, dir),
+                    start.line + 1,
+                    info.message
+                ),
+                Some(i),
+            );
+            locations.push(PopupLocation {
+                path,
+                line: start.line as usize,
+                col: start.character as usize,
+            });
+        }
+
+        Self {
+            lines,
+            locations,
+            selected: 0,
+        }
+    }
+
+    /// Move the selection `amount` locations, stopping at the first and last
+    pub fn select(&mut self, amount: isize) {
+        if self.locations.is_empty() {
+            return;
+        }
+        self.selected = self
+            .selected
+            .saturating_add_signed(amount)
+            .min(self.locations.len() - 1);
+    }
+}
+
+/// Like rustc's `error[E0382] (rustc)`, empty if there's nothing to show
+fn header(diag: &Diagnostic) -> String {
+    let mut header = match diag.severity {
+        Some(DiagnosticSeverity::ERROR) => "error",
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) => "info",
+        Some(DiagnosticSeverity::HINT) => "hint",
+        _ => "",
+    }
+    .to_string();
+    match &diag.code {
+        Some(NumberOrString::Number(code)) => header.push_str(&format!("[{}]", code)),
+        Some(NumberOrString::String(code)) => header.push_str(&format!("[{}]", code)),
+        None => {}
+    }
+    if let Some(source) = &diag.source {
+        if !header.is_empty() {
+            header.push(' ');
+        }
+        header.push_str(&format!("({})", source));
+    }
+    header
+}
+
+/// Break `line` into rows of at most `width` chars, at the last space that
+/// fits or mid-word if there isn't one. The indentation of the line is kept
+/// on its first row
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let indent = line.len() - line.trim_start().len();
+    let mut rows = Vec::new();
+    let mut rest = line;
+    loop {
+        let Some((end, _)) = rest.char_indices().nth(width) else {
+            rows.push(rest.to_string());
+            return rows;
+        };
+        let min = if rows.is_empty() { indent } else { 0 };
+        let space = if rest[end..].starts_with(' ') {
+            Some(end)
+        } else {
+            rest[..end].rfind(' ')
+        };
+        match space.filter(|space| *space > min) {
+            Some(space) => {
+                rows.push(rest[..space].to_string());
+                rest = &rest[space + 1..];
+            }
+            None => {
+                rows.push(rest[..end].to_string());
+                rest = &rest[end..];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp::{DiagnosticRelatedInformation, Location, Position, Range, Url};
+
+    use super::*;
+
+    fn related(path: &str, line: u32, message: &str) -> DiagnosticRelatedInformation {
+        let pos = Position::new(line, 4);
+        DiagnosticRelatedInformation {
+            location: Location::new(Url::from_file_path(path).unwrap(), Range::new(pos, pos)),
+            message: message.into(),
+        }
+    }
+
+    fn texts(popup: &DiagnosticPopup) -> Vec<&str> {
+        popup.lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn wraps_to_width() {
+        assert_eq!(wrap("one two three", 7), vec!["one two", "three"]);
+        assert_eq!(wrap("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("  indented words", 10), vec!["  indented", "words"]);
+        assert_eq!(wrap("short", 80), vec!["short"]);
+        assert_eq!(wrap("", 80), vec![""]);
+    }
+
+    #[test]
+    fn message_only() {
+        let diag = Diagnostic {
+            message: "first\n\n  second".into(),
+            ..Default::default()
+        };
+        let popup = DiagnosticPopup::new(&diag, 80, Path::new("/"));
+        assert_eq!(texts(&popup), vec!["first", "", "  second"]);
+        assert!(popup.locations.is_empty());
+        assert!(popup.lines.iter().all(|line| line.location.is_none()));
+    }
+
+    #[test]
+    fn one_related() {
+        let diag = Diagnostic {
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: Some(NumberOrString::String("E0382".into())),
+            source: Some("rustc".into()),
+            message: "borrow of moved value: `x`".into(),
+            related_information: Some(vec![related("/project/src/main.rs", 2, "value moved here")]),
+            ..Default::default()
+        };
+        let popup = DiagnosticPopup::new(&diag, 80, Path::new("/project"));
+        assert_eq!(
+            texts(&popup),
+            vec![
+                "error[E0382] (rustc)",
+                "borrow of moved value: `x`",
+                "1. src/main.rs:3: value moved here",
+            ]
+        );
+        assert_eq!(popup.lines[2].location, Some(0));
+        assert_eq!(
+            popup.locations,
+            vec![PopupLocation {
+                path: "/project/src/main.rs".into(),
+                line: 2,
+                col: 4,
+            }]
+        );
+    }
+
+    #[test]
+    fn many_related() {
+        let diag = Diagnostic {
+            source: Some("rustc".into()),
+            message: "mismatched types".into(),
+            related_information: Some(vec![
+                related("/project/a.rs", 0, "expected due to this"),
+                related("/other/b.rs", 9, "found this type here"),
+            ]),
+            ..Default::default()
+        };
+        let mut popup = DiagnosticPopup::new(&diag, 20, Path::new("/project"));
+        assert_eq!(
+            texts(&popup),
+            vec![
+                "(rustc)",
+                "mismatched types",
+                "1. a.rs:1: expected",
+                "due to this",
+                "2. /other/b.rs:10:",
+                "found this type here",
+            ]
+        );
+        let locations: Vec<_> = popup.lines.iter().map(|line| line.location).collect();
+        assert_eq!(
+            locations,
+            vec![None, None, Some(0), Some(0), Some(1), Some(1)]
+        );
+
+        popup.select(1);
+        assert_eq!(popup.selected, 1);
+        popup.select(1);
+        assert_eq!(popup.selected, 1);
+        popup.select(-5);
+        assert_eq!(popup.selected, 0);
+    }
+}
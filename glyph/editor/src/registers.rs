@@ -0,0 +1,234 @@
+use std::{collections::HashMap, fmt, rc::Rc};
+
+/// Text that was yanked or deleted
+#[derive(Clone, Debug, PartialEq)]
+pub struct Register {
+    pub text: String,
+    /// Whole lines, these are pasted on their own lines instead of
+    /// into the middle of one. The text ends with a new line character
+    pub linewise: bool,
+}
+
+/// The system clipboard
+pub trait Clipboard {
+    fn get(&self) -> Option<String>;
+    fn set(&self, text: &str);
+}
+
+impl fmt::Debug for dyn Clipboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Clipboard")
+    }
+}
+
+/// Vim's registers, only the ones that hold yanked and deleted text:
+///
+/// - `"0` has the last yank, it's copied to the clipboard too
+/// - `"+` is the clipboard
+/// - `"1` has the last delete of a line or more, older ones are shifted
+///   down to `"9`
+/// - `"-` has the last delete within a line
+/// - `"a` to `"z` are only written when they're named, uppercase names
+///   append to them
+///
+/// The unnamed register `""` is whichever one was written last, it's what
+/// pastes without a register name use
+#[derive(Default, Debug)]
+pub struct Registers {
+    registers: HashMap<char, Register>,
+    unnamed: Option<char>,
+    clipboard: Option<Rc<dyn Clipboard>>,
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `name` can come after `"`
+    #[inline]
+    pub fn is_valid(name: char) -> bool {
+        matches!(name, '"' | '-' | '+' | '0'..='9' | 'a'..='z' | 'A'..='Z')
+    }
+
+    pub fn set_clipboard(&mut self, clipboard: Rc<dyn Clipboard>) {
+        self.clipboard = Some(clipboard);
+    }
+
+    /// The register `name` refers to, `None` is the unnamed register
+    pub fn get(&self, name: Option<char>) -> Option<&Register> {
+        let name = match name {
+            None | Some('"') => self.unnamed?,
+            Some(name) => name.to_ascii_lowercase(),
+        };
+        self.registers.get(&name)
+    }
+
+    /// The register to paste from `name`, `"+` is read from the clipboard.
+    /// Text copied with a new line at the end is pasted as lines
+    pub fn paste(&mut self, name: Option<char>) -> Option<Register> {
+        if name == Some('+') {
+            if let Some(text) = self
+                .clipboard
+                .as_ref()
+                .and_then(|clipboard| clipboard.get())
+            {
+                // Lines are kept ending in `\n`, whatever the clipboard has
+                let text = text.replace("\r\n", "\n");
+                let register = Register {
+                    linewise: text.ends_with('\n'),
+                    text,
+                };
+                self.registers.insert('+', register);
+            }
+        }
+        self.get(name).cloned()
+    }
+
+    pub fn yank(&mut self, name: Option<char>, register: Register) {
+        match name {
+            None | Some('"') => {
+                self.copy(&register.text);
+                self.set('0', register)
+            }
+            Some(name) => self.set(name, register),
+        }
+    }
+
+    pub fn delete(&mut self, name: Option<char>, register: Register) {
+        match name {
+            None | Some('"') if register.linewise || register.text.contains('\n') => {
+                for i in (1..9).rev() {
+                    let digit = |i| char::from_digit(i, 10).unwrap();
+                    if let Some(older) = self.registers.remove(&digit(i)) {
+                        self.registers.insert(digit(i + 1), older);
+                    }
+                }
+                self.set('1', register);
+            }
+            None | Some('"') => self.set('-', register),
+            Some(name) => self.set(name, register),
+        }
+    }
+
+    fn set(&mut self, name: char, register: Register) {
+        if name == '+' {
+            self.copy(&register.text);
+        }
+        let lower = name.to_ascii_lowercase();
+        match self.registers.get_mut(&lower) {
+            Some(existing) if name.is_ascii_uppercase() => {
+                if register.linewise && !existing.text.ends_with('\n') {
+                    existing.text.push('\n');
+                }
+                existing.text.push_str(&register.text);
+                existing.linewise |= register.linewise;
+            }
+            _ => {
+                self.registers.insert(lower, register);
+            }
+        }
+        self.unnamed = Some(lower);
+    }
+
+    fn copy(&self, text: &str) {
+        if let Some(clipboard) = &self.clipboard {
+            clipboard.set(text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    // For tests here and in the editor
+    impl Clipboard for RefCell<Option<String>> {
+        fn get(&self) -> Option<String> {
+            self.borrow().clone()
+        }
+
+        fn set(&self, text: &str) {
+            *self.borrow_mut() = Some(text.to_string());
+        }
+    }
+
+    fn chars(text: &str) -> Register {
+        Register {
+            text: text.into(),
+            linewise: false,
+        }
+    }
+
+    fn lines(text: &str) -> Register {
+        Register {
+            text: text.into(),
+            linewise: true,
+        }
+    }
+
+    #[test]
+    fn yank_survives_deletes() {
+        let mut registers = Registers::new();
+        registers.yank(None, chars("yanked"));
+        registers.delete(None, lines("deleted\n"));
+        registers.delete(None, chars("small"));
+
+        assert_eq!(registers.get(None), Some(&chars("small")));
+        assert_eq!(registers.get(Some('0')), Some(&chars("yanked")));
+        assert_eq!(registers.get(Some('1')), Some(&lines("deleted\n")));
+        assert_eq!(registers.get(Some('-')), Some(&chars("small")));
+    }
+
+    #[test]
+    fn numbered_shift() {
+        let mut registers = Registers::new();
+        for i in 0..10 {
+            registers.delete(None, lines(&format!("{}\n", i)));
+        }
+        assert_eq!(registers.get(Some('1')), Some(&lines("9\n")));
+        assert_eq!(registers.get(Some('9')), Some(&lines("1\n")));
+
+        // Deletes that span lines are numbered even when they're not linewise
+        registers.delete(None, chars("a\nb"));
+        assert_eq!(registers.get(Some('1')), Some(&chars("a\nb")));
+        assert_eq!(registers.get(Some('2')), Some(&lines("9\n")));
+        assert_eq!(registers.get(Some('-')), None);
+    }
+
+    #[test]
+    fn named() {
+        let mut registers = Registers::new();
+        registers.delete(Some('a'), lines("one\n"));
+        registers.yank(Some('A'), chars("two"));
+        assert_eq!(registers.get(Some('a')), Some(&lines("one\ntwo")));
+        assert_eq!(registers.get(None), registers.get(Some('a')));
+
+        // Naming a register keeps the others as they were
+        assert_eq!(registers.get(Some('0')), None);
+        assert_eq!(registers.get(Some('1')), None);
+    }
+
+    #[test]
+    fn clipboard() {
+        let clipboard = Rc::new(RefCell::new(None));
+        let mut registers = Registers::new();
+        registers.set_clipboard(clipboard.clone());
+
+        // Yanks are copied, deletes aren't
+        registers.yank(None, lines("yanked\n"));
+        registers.delete(None, chars("deleted"));
+        assert_eq!(clipboard.get().as_deref(), Some("yanked\n"));
+        registers.yank(Some('a'), chars("named"));
+        assert_eq!(clipboard.get().as_deref(), Some("yanked\n"));
+        registers.delete(Some('+'), chars("cut"));
+        assert_eq!(clipboard.get().as_deref(), Some("cut"));
+
+        // `"+` pastes whatever the clipboard has now
+        clipboard.set("from\nelsewhere\n");
+        assert_eq!(registers.paste(Some('+')), Some(lines("from\nelsewhere\n")));
+        assert_eq!(registers.paste(Some('0')), Some(lines("yanked\n")));
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::RangeInclusive,
+};
+
+/// Each producer of gutter marks owns a namespace so it can replace
+/// its own signs without clobbering the others.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SignNamespace {
+    /// Lines modified since the buffer was last saved
+    Changed,
+}
+
+#[derive(Default, Debug)]
+pub struct Signs {
+    namespaces: BTreeMap<SignNamespace, BTreeSet<usize>>,
+}
+
+impl Signs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mark(&mut self, ns: SignNamespace, lines: RangeInclusive<usize>) {
+        self.namespaces.entry(ns).or_default().extend(lines);
+    }
+
+    pub fn clear(&mut self, ns: SignNamespace) {
+        self.namespaces.remove(&ns);
+    }
+
+    #[inline]
+    pub fn contains(&self, ns: SignNamespace, line: usize) -> bool {
+        self.namespaces
+            .get(&ns)
+            .is_some_and(|lines| lines.contains(&line))
+    }
+
+    pub fn lines(&self, ns: SignNamespace) -> impl Iterator<Item = usize> + '_ {
+        self.namespaces.get(&ns).into_iter().flatten().copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SignNamespace, usize)> + '_ {
+        self.namespaces
+            .iter()
+            .flat_map(|(ns, lines)| lines.iter().map(move |line| (*ns, *line)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.namespaces.values().all(|lines| lines.is_empty())
+    }
+
+    /// Keep signs attached to their lines when lines are added or removed.
+    ///
+    /// A positive `delta` means `delta` lines were inserted at `line`, so every
+    /// sign at or after it moves down. A negative `delta` means the lines
+    /// `line..line + |delta|` were removed, their signs are dropped and the ones
+    /// after them move up.
+    pub fn shift(&mut self, line: usize, delta: isize) {
+        if delta == 0 {
+            return;
+        }
+
+        for lines in self.namespaces.values_mut() {
+            let shifted = lines.split_off(&line);
+            for l in shifted {
+                if delta > 0 {
+                    lines.insert(l + delta as usize);
+                } else {
+                    let removed = delta.unsigned_abs();
+                    if l >= line + removed {
+                        lines.insert(l - removed);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shift_insert() {
+        let mut signs = Signs::new();
+        signs.mark(SignNamespace::Changed, 1..=1);
+        signs.mark(SignNamespace::Changed, 4..=4);
+        signs.shift(2, 2);
+
+        assert_eq!(
+            signs.lines(SignNamespace::Changed).collect::<Vec<_>>(),
+            vec![1, 6]
+        );
+    }
+
+    #[test]
+    fn shift_remove() {
+        let mut signs = Signs::new();
+        signs.mark(SignNamespace::Changed, 1..=5);
+        signs.shift(2, -2);
+
+        assert_eq!(
+            signs.lines(SignNamespace::Changed).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
+}
@@ -1,11 +1,120 @@
-use syntax::Highlight;
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
 
-use crate::Color;
+use lsp::DiagnosticSeverity;
+use serde::Deserialize;
+use syntax::{Highlight, HIGHLIGHTS};
+
+use crate::{
+    config_dir, Color, ColorParseError, FontStyle, ThemeType, ERROR_RED, HINT_GRAY, INFO_BLUE,
+    WARNING_YELLOW,
+};
 
 pub trait Theme {
     fn bg(&self) -> &Color;
     fn fg(&self) -> &Color;
     fn highlight(&self, highlight: Highlight) -> Option<&Color>;
+    /// Drawn over the selected text, so it should be see-through
+    fn selection_bg(&self) -> &Color;
+    fn cursor(&self) -> &Color;
+    fn line_number(&self) -> &Color;
+
+    /// Background of the line the cursor is on
+    fn cursor_line(&self) -> Color {
+        self.bg().lighten(0.05)
+    }
+
+    /// Background of the status line, drawn over `bg`
+    fn statusline_bg(&self) -> Color {
+        Color {
+            a: 20,
+            ..*self.fg()
+        }
+    }
+
+    /// The file name and position in the status line
+    fn statusline_fg(&self) -> Color {
+        *self.fg()
+    }
+
+    /// Vertical lines marking each indentation level
+    fn indent_guide(&self) -> Color {
+        self.fg().with_alpha(0.15)
+    }
+
+    /// Inlay hints from the language server, faded comments
+    fn inlay_hint(&self) -> Color {
+        self.highlight(Highlight::Comment)
+            .unwrap_or_else(|| self.fg())
+            .with_alpha(0.7)
+    }
+
+    /// Behind every match of the search, see-through like the selection
+    fn search_match(&self) -> Color {
+        WARNING_YELLOW.with_alpha(0.25)
+    }
+
+    /// Behind the search match the cursor is on
+    fn current_search_match(&self) -> Color {
+        WARNING_YELLOW.with_alpha(0.5)
+    }
+
+    /// The face of the font a highlight is drawn with, comments are italic
+    /// and types bold
+    fn font_style(&self, highlight: Highlight) -> FontStyle {
+        match highlight {
+            Highlight::Comment => FontStyle::Italic,
+            Highlight::Type | Highlight::TypeBuiltin => FontStyle::Bold,
+            _ => FontStyle::Normal,
+        }
+    }
+
+    /// Underline of a diagnostic, servers that leave out the severity get
+    /// the error color
+    fn diagnostic_color(&self, severity: DiagnosticSeverity) -> &Color {
+        match severity {
+            DiagnosticSeverity::WARNING => &WARNING_YELLOW,
+            DiagnosticSeverity::INFORMATION => &INFO_BLUE,
+            DiagnosticSeverity::HINT => &HINT_GRAY,
+            _ => &ERROR_RED,
+        }
+    }
+
+    /// Color of an LSP semantic token type, by default the color of the
+    /// closest tree-sitter highlight
+    fn semantic_token_color(&self, token_type: &str) -> Option<&Color> {
+        let highlight = match token_type {
+            "type" | "class" | "enum" | "interface" | "struct" | "typeParameter" => Highlight::Type,
+            "namespace" => {
+                return self
+                    .highlight(Highlight::Namespace)
+                    .or_else(|| self.highlight(Highlight::Type))
+            }
+            "number" => {
+                return self
+                    .highlight(Highlight::Number)
+                    .or_else(|| self.highlight(Highlight::Constant))
+            }
+            "parameter" => {
+                return self
+                    .highlight(Highlight::VariableParameter)
+                    .or_else(|| self.highlight(Highlight::Param))
+            }
+            "variable" => Highlight::Variable,
+            "property" => Highlight::Property,
+            "enumMember" => Highlight::Constant,
+            "function" | "method" | "macro" => Highlight::Function,
+            "keyword" => Highlight::Keyword,
+            "comment" => Highlight::Comment,
+            "string" => Highlight::String,
+            "operator" => Highlight::Operator,
+            _ => return None,
+        };
+        self.highlight(highlight)
+    }
 }
 
 macro_rules! define_theme {
@@ -21,7 +130,7 @@ macro_rules! define_theme {
             pub fn new() -> Self {
                 Self {
                     $(
-                        $color_name: Color::from_hex($hex),
+                        $color_name: Color::from_hex($hex).unwrap(),
                     )*
                 }
             }
@@ -50,7 +159,13 @@ define_theme!(
     (green1 "#73daca"),
     (comment "#565f89"),
     (magenta "#bb9af7"),
-    (yellow "#e0af68")
+    (yellow "#e0af68"),
+    (selection_bg "#3d59a166"),
+    (fg_gutter "#3b4261"),
+    (bg_dark "#1f2335"),
+    (error "#db4b4b"),
+    (info "#0db9d7"),
+    (hint "#1abc9c")
 );
 
 impl Theme for TokyoNightStorm {
@@ -64,17 +179,55 @@ impl Theme for TokyoNightStorm {
         &self.fg
     }
 
+    #[inline]
+    fn selection_bg(&self) -> &Color {
+        &self.selection_bg
+    }
+
+    #[inline]
+    fn cursor(&self) -> &Color {
+        &self.fg
+    }
+
+    #[inline]
+    fn line_number(&self) -> &Color {
+        &self.fg_gutter
+    }
+
+    #[inline]
+    fn statusline_bg(&self) -> Color {
+        self.bg_dark
+    }
+
+    #[inline]
+    fn statusline_fg(&self) -> Color {
+        self.fg_dark
+    }
+
+    #[inline]
+    fn diagnostic_color(&self, severity: DiagnosticSeverity) -> &Color {
+        match severity {
+            DiagnosticSeverity::WARNING => &self.yellow,
+            DiagnosticSeverity::INFORMATION => &self.info,
+            DiagnosticSeverity::HINT => &self.hint,
+            _ => &self.error,
+        }
+    }
+
     #[inline]
     fn highlight(&self, highlight: Highlight) -> Option<&Color> {
         match highlight {
             Highlight::Attribute => None,
             Highlight::Constant => Some(&self.orange),
             Highlight::Constructor => Some(&self.fg_dark),
+            Highlight::Embedded => Some(&self.fg),
             Highlight::Comment => Some(&self.comment),
             Highlight::FunctionBuiltin => None,
             Highlight::Function => Some(&self.blue),
             Highlight::Keyword => Some(&self.magenta),
             Highlight::Label => Some(&self.blue),
+            Highlight::Namespace => Some(&self.cyan),
+            Highlight::Number => Some(&self.orange),
             Highlight::Operator => Some(&self.blue5),
             Highlight::Property => None, /* Some(&self.green1) */
             Highlight::Param => Some(&self.yellow),
@@ -105,7 +258,13 @@ define_theme!(
     (func "#d2a8ff"),
     (func_param "#c9d1d9"),
     (variable "#FFA657"),
-    (keyword "#ff7b72")
+    (keyword "#ff7b72"),
+    (selection_bg "#388bfd66"),
+    (line_number "#6e7681"),
+    (error "#f85149"),
+    (warning "#d29922"),
+    (info "#58a6ff"),
+    (statusline_bg "#161b22")
 );
 
 impl Theme for GithubDark {
@@ -119,17 +278,50 @@ impl Theme for GithubDark {
         &self.fg
     }
 
+    #[inline]
+    fn selection_bg(&self) -> &Color {
+        &self.selection_bg
+    }
+
+    #[inline]
+    fn cursor(&self) -> &Color {
+        &self.fg
+    }
+
+    #[inline]
+    fn line_number(&self) -> &Color {
+        &self.line_number
+    }
+
+    #[inline]
+    fn statusline_bg(&self) -> Color {
+        self.statusline_bg
+    }
+
+    #[inline]
+    fn diagnostic_color(&self, severity: DiagnosticSeverity) -> &Color {
+        match severity {
+            DiagnosticSeverity::WARNING => &self.warning,
+            DiagnosticSeverity::INFORMATION => &self.info,
+            DiagnosticSeverity::HINT => &self.comment,
+            _ => &self.error,
+        }
+    }
+
     #[inline]
     fn highlight(&self, highlight: Highlight) -> Option<&Color> {
         match highlight {
             Highlight::Attribute => None,
             Highlight::Constant => Some(&self.constant),
             Highlight::Constructor => Some(&self.fg),
+            Highlight::Embedded => None,
             Highlight::Comment => Some(&self.comment),
             Highlight::FunctionBuiltin => None,
             Highlight::Function => Some(&self.func),
             Highlight::Keyword => Some(&self.keyword),
             // Highlight::Label => Some(&self.blue),
+            Highlight::Namespace => None,
+            Highlight::Number => Some(&self.constant),
             Highlight::Operator => Some(&self.keyword),
             Highlight::Property => Some(&self.fg),
             Highlight::Punctuation => None,
@@ -148,3 +340,913 @@ impl Theme for GithubDark {
         }
     }
 }
+
+define_theme!(
+    GruvboxDark,
+    (bg "#282828"),
+    (fg "#ebdbb2"),
+    (fg_dark "#a89984"),
+    (gray "#928374"),
+    (red "#fb4934"),
+    (green "#b8bb26"),
+    (yellow "#fabd2f"),
+    (blue "#83a598"),
+    (purple "#d3869b"),
+    (aqua "#8ec07c"),
+    (orange "#fe8019"),
+    (selection_bg "#83a59855"),
+    (bg4 "#7c6f64")
+);
+
+impl Theme for GruvboxDark {
+    #[inline]
+    fn bg(&self) -> &Color {
+        &self.bg
+    }
+
+    #[inline]
+    fn fg(&self) -> &Color {
+        &self.fg
+    }
+
+    #[inline]
+    fn selection_bg(&self) -> &Color {
+        &self.selection_bg
+    }
+
+    #[inline]
+    fn cursor(&self) -> &Color {
+        &self.fg
+    }
+
+    #[inline]
+    fn line_number(&self) -> &Color {
+        &self.bg4
+    }
+
+    #[inline]
+    fn highlight(&self, highlight: Highlight) -> Option<&Color> {
+        match highlight {
+            Highlight::Attribute => Some(&self.aqua),
+            Highlight::Constant => Some(&self.purple),
+            Highlight::Constructor => Some(&self.yellow),
+            Highlight::Embedded => None,
+            Highlight::Comment => Some(&self.gray),
+            Highlight::FunctionBuiltin => Some(&self.yellow),
+            Highlight::Function => Some(&self.green),
+            Highlight::Keyword => Some(&self.red),
+            Highlight::Label => Some(&self.aqua),
+            Highlight::Namespace => Some(&self.aqua),
+            Highlight::Number => Some(&self.purple),
+            Highlight::Operator => Some(&self.fg),
+            Highlight::Property => Some(&self.blue),
+            Highlight::Param => Some(&self.blue),
+            Highlight::Punctuation => None,
+            Highlight::PunctuationBracket => Some(&self.fg_dark),
+            Highlight::PunctuationDelimiter => Some(&self.fg_dark),
+            Highlight::PunctuationSpecial => Some(&self.orange),
+            Highlight::String => Some(&self.green),
+            Highlight::StringSpecial => Some(&self.orange),
+            Highlight::Tag => Some(&self.aqua),
+            Highlight::Type => Some(&self.yellow),
+            Highlight::TypeBuiltin => Some(&self.yellow),
+            Highlight::Variable => None,
+            Highlight::VariableBuiltin => Some(&self.orange),
+            Highlight::VariableParameter => Some(&self.blue),
+        }
+    }
+}
+
+define_theme!(
+    Dracula,
+    (bg "#282a36"),
+    (fg "#f8f8f2"),
+    (comment "#6272a4"),
+    (cyan "#8be9fd"),
+    (green "#50fa7b"),
+    (orange "#ffb86c"),
+    (pink "#ff79c6"),
+    (purple "#bd93f9"),
+    (yellow "#f1fa8c"),
+    (selection "#44475a99")
+);
+
+impl Theme for Dracula {
+    #[inline]
+    fn bg(&self) -> &Color {
+        &self.bg
+    }
+
+    #[inline]
+    fn fg(&self) -> &Color {
+        &self.fg
+    }
+
+    #[inline]
+    fn selection_bg(&self) -> &Color {
+        &self.selection
+    }
+
+    #[inline]
+    fn cursor(&self) -> &Color {
+        &self.fg
+    }
+
+    #[inline]
+    fn line_number(&self) -> &Color {
+        &self.comment
+    }
+
+    #[inline]
+    fn highlight(&self, highlight: Highlight) -> Option<&Color> {
+        match highlight {
+            Highlight::Attribute => Some(&self.green),
+            Highlight::Constant => Some(&self.purple),
+            Highlight::Constructor => Some(&self.cyan),
+            Highlight::Embedded => None,
+            Highlight::Comment => Some(&self.comment),
+            Highlight::FunctionBuiltin => Some(&self.cyan),
+            Highlight::Function => Some(&self.cyan),
+            Highlight::Keyword => Some(&self.pink),
+            Highlight::Label => Some(&self.purple),
+            Highlight::Namespace => Some(&self.fg),
+            Highlight::Number => Some(&self.purple),
+            Highlight::Operator => Some(&self.pink),
+            Highlight::Property => Some(&self.fg),
+            Highlight::Param => Some(&self.orange),
+            Highlight::Punctuation => Some(&self.fg),
+            Highlight::PunctuationBracket => Some(&self.fg),
+            Highlight::PunctuationDelimiter => Some(&self.fg),
+            Highlight::PunctuationSpecial => Some(&self.pink),
+            Highlight::String => Some(&self.green),
+            Highlight::StringSpecial => Some(&self.yellow),
+            Highlight::Tag => Some(&self.pink),
+            Highlight::Type => Some(&self.orange),
+            Highlight::TypeBuiltin => Some(&self.orange),
+            Highlight::Variable => Some(&self.fg),
+            Highlight::VariableBuiltin => Some(&self.purple),
+            Highlight::VariableParameter => Some(&self.orange),
+        }
+    }
+}
+
+define_theme!(
+    HighContrast,
+    (bg "#000000"),
+    (fg "#ffffff"),
+    (comment "#c0c0c0"),
+    (punctuation "#d7d7d7"),
+    (keyword "#ffd75f"),
+    (string "#87ff87"),
+    (func "#5fd7ff"),
+    (constant "#ff87ff"),
+    (ty "#ffaf5f"),
+    (cursor "#ffff00"),
+    (selection_bg "#3a7bd599"),
+    (line_number "#a8a8a8")
+);
+
+impl Theme for HighContrast {
+    #[inline]
+    fn bg(&self) -> &Color {
+        &self.bg
+    }
+
+    #[inline]
+    fn fg(&self) -> &Color {
+        &self.fg
+    }
+
+    #[inline]
+    fn selection_bg(&self) -> &Color {
+        &self.selection_bg
+    }
+
+    #[inline]
+    fn cursor(&self) -> &Color {
+        &self.cursor
+    }
+
+    #[inline]
+    fn line_number(&self) -> &Color {
+        &self.line_number
+    }
+
+    #[inline]
+    fn highlight(&self, highlight: Highlight) -> Option<&Color> {
+        match highlight {
+            Highlight::Attribute => Some(&self.keyword),
+            Highlight::Constant => Some(&self.constant),
+            Highlight::Constructor => Some(&self.ty),
+            Highlight::Embedded => None,
+            Highlight::Comment => Some(&self.comment),
+            Highlight::FunctionBuiltin => Some(&self.func),
+            Highlight::Function => Some(&self.func),
+            Highlight::Keyword => Some(&self.keyword),
+            Highlight::Label => Some(&self.constant),
+            Highlight::Namespace => Some(&self.ty),
+            Highlight::Number => Some(&self.constant),
+            Highlight::Operator => Some(&self.keyword),
+            Highlight::Property => Some(&self.fg),
+            Highlight::Param => Some(&self.fg),
+            Highlight::Punctuation => Some(&self.punctuation),
+            Highlight::PunctuationBracket => Some(&self.punctuation),
+            Highlight::PunctuationDelimiter => Some(&self.punctuation),
+            Highlight::PunctuationSpecial => Some(&self.keyword),
+            Highlight::String => Some(&self.string),
+            Highlight::StringSpecial => Some(&self.constant),
+            Highlight::Tag => Some(&self.keyword),
+            Highlight::Type => Some(&self.ty),
+            Highlight::TypeBuiltin => Some(&self.ty),
+            Highlight::Variable => Some(&self.fg),
+            Highlight::VariableBuiltin => Some(&self.constant),
+            Highlight::VariableParameter => Some(&self.fg),
+        }
+    }
+}
+
+/// The color of every highlight of a theme, resolved once so highlighting
+/// doesn't go through the trait for each capture. Highlights the theme doesn't
+/// have fall back to their parent, `punctuation.bracket` to `punctuation`,
+/// and then to `fg`
+#[derive(Clone, Copy, Debug)]
+pub struct HighlightColors {
+    fg: Color,
+    colors: [Color; HIGHLIGHTS.len()],
+    styles: [FontStyle; HIGHLIGHTS.len()],
+}
+
+impl HighlightColors {
+    pub fn new(theme: &ThemeType) -> Self {
+        let fg = *theme.fg();
+        let mut colors = [fg; HIGHLIGHTS.len()];
+        for (color, mut name) in colors.iter_mut().zip(HIGHLIGHTS.iter().copied()) {
+            loop {
+                let highlight = HIGHLIGHTS
+                    .iter()
+                    .position(|highlight| *highlight == name)
+                    .and_then(|idx| Highlight::from_u8(idx as u8));
+                if let Some(theme_color) =
+                    highlight.and_then(|highlight| theme.highlight(highlight))
+                {
+                    *color = *theme_color;
+                    break;
+                }
+                match name.rfind('.') {
+                    Some(dot) => name = &name[..dot],
+                    None => break,
+                }
+            }
+        }
+        let mut styles = [FontStyle::Normal; HIGHLIGHTS.len()];
+        for (idx, style) in styles.iter_mut().enumerate() {
+            if let Some(highlight) = Highlight::from_u8(idx as u8) {
+                *style = theme.font_style(highlight);
+            }
+        }
+        Self { fg, colors, styles }
+    }
+
+    #[inline]
+    pub fn fg(&self) -> Color {
+        self.fg
+    }
+
+    /// The color of the highlight with the index `highlight` in `HIGHLIGHTS`
+    #[inline]
+    pub fn get(&self, highlight: u8) -> Color {
+        self.colors[highlight as usize]
+    }
+
+    /// The font style of the highlight with the index `highlight`
+    #[inline]
+    pub fn style(&self, highlight: u8) -> FontStyle {
+        self.styles[highlight as usize]
+    }
+}
+
+/// What to do with the colors of a theme that have less contrast against its
+/// background than the minimum
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContrastMode {
+    /// Keep them and tell the user which ones they are
+    Warn,
+    /// Lighten or darken them until they have enough
+    Adjust,
+}
+
+/// The names of `fg` and the highlights of `theme` that have a contrast ratio
+/// against its background below `min`, 4.5 is the WCAG minimum for text
+pub fn low_contrast(theme: &ThemeType, min: f32) -> Vec<&'static str> {
+    let bg = theme.bg();
+    let mut low = Vec::new();
+    if theme.fg().contrast(bg) < min {
+        low.push("fg");
+    }
+    for (idx, name) in HIGHLIGHTS.iter().enumerate() {
+        let highlight = Highlight::from_u8(idx as u8).unwrap();
+        match theme.highlight(highlight) {
+            Some(color) if color.contrast(bg) < min => low.push(*name),
+            _ => (),
+        }
+    }
+    low
+}
+
+#[derive(Debug)]
+pub enum ThemeError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    /// A theme file entry that isn't `fg`, `bg`, one of `HIGHLIGHTS` or an
+    /// older name for them
+    UnknownHighlight(String),
+    Missing(&'static str),
+    Color {
+        name: &'static str,
+        hex: String,
+        error: ColorParseError,
+    },
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThemeError::Io(e) => write!(f, "{}", e),
+            ThemeError::Toml(e) => write!(f, "{}", e),
+            ThemeError::Json(e) => write!(f, "{}", e),
+            ThemeError::UnknownHighlight(name) => write!(f, "unknown highlight \"{}\"", name),
+            ThemeError::Missing(name) => write!(f, "missing \"{}\"", name),
+            ThemeError::Color { name, hex, error } => write!(f, "{} = {:?}: {}", name, hex, error),
+        }
+    }
+}
+
+/// Names theme files used before highlights were given by their own names,
+/// each colors the highlights after it unless the file gives them a color
+const LEGACY_NAMES: &[(&str, &[&str])] = &[
+    ("fg_dark", &["punctuation.bracket"]),
+    ("func", &["function"]),
+    ("func_param", &["param"]),
+];
+
+/// A theme file entry, dotted TOML keys like `punctuation.bracket` come in
+/// as tables
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FileThemeEntry {
+    Color(String),
+    Table(HashMap<String, FileThemeEntry>),
+}
+
+/// A theme loaded at runtime from a TOML or JSON file giving a color to `fg`,
+/// `bg` and highlights by their name in `HIGHLIGHTS`:
+///
+/// ```toml
+/// bg = "#282a36"
+/// fg = "#f8f8f2"
+/// keyword = "#ff79c6"
+/// "punctuation.bracket" = "#f8f8f2"
+/// ```
+///
+/// Highlights that aren't in the file are drawn with `fg`. `selection_bg`,
+/// `cursor` and `line_number` can be given too. Files made for the older
+/// fixed set of colors still load, see `LEGACY_NAMES`
+pub struct FileTheme {
+    bg: Color,
+    fg: Color,
+    selection_bg: Color,
+    cursor: Color,
+    line_number: Color,
+    colors: HashMap<Highlight, Color>,
+}
+
+impl FileTheme {
+    fn new(entries: HashMap<String, FileThemeEntry>) -> Result<Self, ThemeError> {
+        let mut flat = Vec::new();
+        flatten_entries("", entries, &mut flat);
+
+        let mut bg = None;
+        let mut fg = None;
+        let mut selection_bg = None;
+        let mut cursor = None;
+        let mut line_number = None;
+        let mut colors = HashMap::new();
+        let mut legacy = Vec::new();
+        for (name, hex) in flat {
+            match name.as_str() {
+                "bg" => bg = Some(parse_color("bg", &hex)?),
+                "fg" => fg = Some(parse_color("fg", &hex)?),
+                "selection_bg" => selection_bg = Some(parse_color("selection_bg", &hex)?),
+                "cursor" => cursor = Some(parse_color("cursor", &hex)?),
+                "line_number" => line_number = Some(parse_color("line_number", &hex)?),
+                _ => {
+                    if let Some((legacy_name, names)) =
+                        LEGACY_NAMES.iter().find(|(legacy, _)| *legacy == name)
+                    {
+                        let color = parse_color(legacy_name, &hex)?;
+                        legacy.extend(
+                            names
+                                .iter()
+                                .filter_map(|name| highlight_named(name))
+                                .map(|highlight| (highlight, color)),
+                        );
+                        continue;
+                    }
+                    let highlight =
+                        highlight_named(&name).ok_or(ThemeError::UnknownHighlight(name))?;
+                    colors.insert(highlight, parse_color(highlight.name(), &hex)?);
+                }
+            }
+        }
+        for (highlight, color) in legacy {
+            colors.entry(highlight).or_insert(color);
+        }
+
+        let fg = fg.ok_or(ThemeError::Missing("fg"))?;
+        Ok(Self {
+            bg: bg.ok_or(ThemeError::Missing("bg"))?,
+            fg,
+            selection_bg: selection_bg.unwrap_or_else(|| default_selection(&fg)),
+            cursor: cursor.unwrap_or(fg),
+            line_number: line_number.unwrap_or_else(|| fg.with_alpha(0.5)),
+            colors,
+        })
+    }
+
+    /// A copy of the colors of any theme, to change them with
+    /// `enforce_contrast`
+    pub fn from_theme(theme: &ThemeType) -> Self {
+        let highlight = |idx: usize| Highlight::from_u8(idx as u8).unwrap();
+        let colors = (0..HIGHLIGHTS.len())
+            .filter_map(|idx| Some((highlight(idx), *theme.highlight(highlight(idx))?)))
+            .collect();
+
+        Self {
+            bg: *theme.bg(),
+            fg: *theme.fg(),
+            selection_bg: *theme.selection_bg(),
+            cursor: *theme.cursor(),
+            line_number: *theme.line_number(),
+            colors,
+        }
+    }
+
+    /// Adjust `fg` and the highlights with a contrast ratio below `min` with
+    /// `Color::with_contrast`, returning the names of the ones that changed
+    pub fn enforce_contrast(&mut self, min: f32) -> Vec<&'static str> {
+        let low = low_contrast(self, min);
+        let bg = self.bg;
+        self.fg = self.fg.with_contrast(&bg, min);
+        for color in self.colors.values_mut() {
+            *color = color.with_contrast(&bg, min);
+        }
+        low
+    }
+
+    pub fn from_toml(src: &str) -> Result<Self, ThemeError> {
+        Self::new(toml::from_str(src).map_err(ThemeError::Toml)?)
+    }
+
+    pub fn from_json(src: &str) -> Result<Self, ThemeError> {
+        Self::new(serde_json::from_str(src).map_err(ThemeError::Json)?)
+    }
+
+    /// Load a `.json` file as JSON and anything else as TOML
+    pub fn from_file(path: &Path) -> Result<Self, ThemeError> {
+        let src = fs::read_to_string(path).map_err(ThemeError::Io)?;
+        match path.extension() {
+            Some(ext) if ext == "json" => Self::from_json(&src),
+            _ => Self::from_toml(&src),
+        }
+    }
+}
+
+impl Theme for FileTheme {
+    #[inline]
+    fn bg(&self) -> &Color {
+        &self.bg
+    }
+
+    #[inline]
+    fn fg(&self) -> &Color {
+        &self.fg
+    }
+
+    #[inline]
+    fn selection_bg(&self) -> &Color {
+        &self.selection_bg
+    }
+
+    #[inline]
+    fn cursor(&self) -> &Color {
+        &self.cursor
+    }
+
+    #[inline]
+    fn line_number(&self) -> &Color {
+        &self.line_number
+    }
+
+    #[inline]
+    fn highlight(&self, highlight: Highlight) -> Option<&Color> {
+        self.colors.get(&highlight)
+    }
+}
+
+/// The highlight called `name` in `HIGHLIGHTS`, the same conversion
+/// `make_highlights!` generates
+fn highlight_named(name: &str) -> Option<Highlight> {
+    let idx = HIGHLIGHTS.iter().position(|highlight| *highlight == name)?;
+    Highlight::from_u8(idx as u8)
+}
+
+/// Join the keys of nested tables with `.` into `out`
+fn flatten_entries(
+    prefix: &str,
+    entries: HashMap<String, FileThemeEntry>,
+    out: &mut Vec<(String, String)>,
+) {
+    for (key, entry) in entries {
+        let name = if prefix.is_empty() {
+            key
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match entry {
+            FileThemeEntry::Color(hex) => out.push((name, hex)),
+            FileThemeEntry::Table(entries) => flatten_entries(&name, entries, out),
+        }
+    }
+}
+
+/// The selection color of theme files that don't have one
+fn default_selection(fg: &Color) -> Color {
+    fg.with_alpha(0.2)
+}
+
+fn parse_color(name: &'static str, hex: &str) -> Result<Color, ThemeError> {
+    Color::from_hex(hex).map_err(|error| ThemeError::Color {
+        name,
+        hex: hex.to_string(),
+        error,
+    })
+}
+
+/// Where the theme file goes, `$XDG_CONFIG_HOME/glyph/theme.toml` or
+/// `~/.config/glyph/theme.toml`
+pub fn theme_config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("theme.toml"))
+}
+
+impl dyn Theme {
+    /// Load a theme file, see [`FileTheme`] for what it looks like
+    pub fn from_file(path: &Path) -> Result<Box<ThemeType>, ThemeError> {
+        Ok(Box::new(FileTheme::from_file(path)?))
+    }
+}
+
+pub(crate) fn load_config_theme() -> Option<Box<ThemeType>> {
+    let path = theme_config_path().filter(|path| path.exists())?;
+    match <dyn Theme>::from_file(&path) {
+        Ok(theme) => Some(theme),
+        Err(e) => {
+            eprintln!("Failed to load theme {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r##"
+bg = "#0d1117"
+fg = "#c9d1d9"
+comment = "#8b949e"
+keyword = "#ff7b72"
+"##;
+
+    // The colors `GithubDark` is made of, by the names theme files used to
+    // have
+    const LEGACY_CONFIG: &str = r##"
+bg = "#0d1117"
+fg = "#c9d1d9"
+fg_dark = "#4d5566"
+comment = "#8b949e"
+constant = "#79c0ff"
+string = "#a5d6ff"
+func = "#d2a8ff"
+func_param = "#c9d1d9"
+variable = "#ffa657"
+keyword = "#ff7b72"
+"##;
+
+    #[test]
+    fn from_toml() {
+        let theme = FileTheme::from_toml(LEGACY_CONFIG).unwrap();
+        let github = GithubDark::new();
+        assert_eq!(theme.bg().floats(), github.bg().floats());
+        assert_eq!(theme.fg().floats(), github.fg().floats());
+        let color = |theme: &dyn Theme, highlight| theme.highlight(highlight).map(Color::floats);
+        for highlight in [
+            Highlight::Keyword,
+            Highlight::String,
+            Highlight::Comment,
+            Highlight::Constant,
+            Highlight::Function,
+            Highlight::Variable,
+        ] {
+            assert_eq!(color(&theme, highlight), color(&github, highlight));
+        }
+        assert_eq!(
+            color(&theme, Highlight::PunctuationBracket),
+            Some(Color::from_hex("#4d5566").unwrap().floats())
+        );
+
+        // The highlight's own name wins over the older one
+        let config = format!("{}\"punctuation.bracket\" = \"#ffffff\"", LEGACY_CONFIG);
+        let theme = FileTheme::from_toml(&config).unwrap();
+        assert_eq!(
+            color(&theme, Highlight::PunctuationBracket),
+            Some(Color::from_hex("#ffffff").unwrap().floats())
+        );
+    }
+
+    #[test]
+    fn from_file() {
+        let path = std::env::temp_dir().join("glyph_theme_from_file.toml");
+        fs::write(&path, LEGACY_CONFIG).unwrap();
+        let theme = <dyn Theme>::from_file(&path).unwrap();
+        assert_eq!(theme.bg().floats(), GithubDark::new().bg().floats());
+
+        assert!(matches!(
+            <dyn Theme>::from_file(&path.with_extension("missing")),
+            Err(ThemeError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn invalid() {
+        let bad_hex = LEGACY_CONFIG.replace("\"#ff7b72\"", "\"#ff7b7\"");
+        assert!(matches!(
+            FileTheme::from_toml(&bad_hex),
+            Err(ThemeError::Color {
+                name: "keyword",
+                ..
+            })
+        ));
+        let bad_legacy = LEGACY_CONFIG.replace("\"#d2a8ff\"", "\"#d2a8f\"");
+        assert!(matches!(
+            FileTheme::from_toml(&bad_legacy),
+            Err(ThemeError::Color { name: "func", .. })
+        ));
+
+        let missing = LEGACY_CONFIG.replace("fg = \"#c9d1d9\"", "");
+        assert!(matches!(
+            FileTheme::from_toml(&missing),
+            Err(ThemeError::Missing("fg"))
+        ));
+        assert!(matches!(
+            FileTheme::from_toml("bg = "),
+            Err(ThemeError::Toml(_))
+        ));
+    }
+
+    #[test]
+    fn gruvbox() {
+        let theme = GruvboxDark::new();
+        let hex = |hex| Some(Color::from_hex(hex).unwrap().floats());
+        let color = |highlight| theme.highlight(highlight).map(Color::floats);
+        assert_eq!(Some(theme.bg().floats()), hex("#282828"));
+        assert_eq!(color(Highlight::Keyword), hex("#fb4934"));
+        assert_eq!(color(Highlight::Function), hex("#b8bb26"));
+        assert_eq!(color(Highlight::String), hex("#b8bb26"));
+        assert_eq!(color(Highlight::Type), hex("#fabd2f"));
+        assert_eq!(color(Highlight::Comment), hex("#928374"));
+        assert_eq!(color(Highlight::Constant), hex("#d3869b"));
+    }
+
+    #[test]
+    fn diagnostic_colors() {
+        let severities = [
+            DiagnosticSeverity::ERROR,
+            DiagnosticSeverity::WARNING,
+            DiagnosticSeverity::INFORMATION,
+            DiagnosticSeverity::HINT,
+        ];
+        let themes: [&ThemeType; 3] = [
+            &TokyoNightStorm::new(),
+            &GithubDark::new(),
+            &FileTheme::from_toml(CONFIG).unwrap(),
+        ];
+        for theme in themes {
+            let colors: Vec<_> = severities
+                .iter()
+                .map(|severity| *theme.diagnostic_color(*severity))
+                .collect();
+            for (i, color) in colors.iter().enumerate() {
+                assert!(!colors[i + 1..].contains(color));
+            }
+        }
+        assert_eq!(
+            *FileTheme::from_toml(CONFIG)
+                .unwrap()
+                .diagnostic_color(DiagnosticSeverity::ERROR),
+            ERROR_RED
+        );
+    }
+
+    #[test]
+    fn ui_colors() {
+        let hex = |hex| Color::from_hex(hex).unwrap().floats();
+        let theme = FileTheme::from_toml(CONFIG).unwrap();
+        assert_eq!(theme.cursor().floats(), theme.fg().floats());
+        assert_eq!(
+            theme.line_number().floats(),
+            theme.fg().with_alpha(0.5).floats()
+        );
+        assert_eq!(theme.selection_bg().floats()[3], 0.2);
+
+        let config = format!(
+            "{}selection_bg = \"#388bfd66\"\ncursor = \"#58a6ff\"\nline_number = \"#888\"",
+            CONFIG
+        );
+        let theme = FileTheme::from_toml(&config).unwrap();
+        assert_eq!(theme.selection_bg().floats(), hex("#388bfd66"));
+        assert_eq!(theme.cursor().floats(), hex("#58a6ff"));
+        assert_eq!(theme.line_number().floats(), hex("#888"));
+    }
+
+    #[test]
+    fn file_theme() {
+        let hex = |hex| Some(Color::from_hex(hex).unwrap().floats());
+        let toml = r##"
+bg = "#282a36"
+fg = "#f8f8f2"
+keyword = "#ff79c6"
+"punctuation.bracket" = "#bd93f9"
+function.builtin = "#50fa7b"
+"##;
+        let json = r##"{
+    "bg": "#282a36",
+    "fg": "#f8f8f2",
+    "keyword": "#ff79c6",
+    "punctuation.bracket": "#bd93f9",
+    "function": { "builtin": "#50fa7b" }
+}"##;
+
+        for theme in [FileTheme::from_toml(toml), FileTheme::from_json(json)] {
+            let theme = theme.unwrap();
+            let color = |highlight| theme.highlight(highlight).map(Color::floats);
+            assert_eq!(Some(theme.bg().floats()), hex("#282a36"));
+            assert_eq!(Some(theme.fg().floats()), hex("#f8f8f2"));
+            assert_eq!(color(Highlight::Keyword), hex("#ff79c6"));
+            assert_eq!(color(Highlight::PunctuationBracket), hex("#bd93f9"));
+            assert_eq!(color(Highlight::FunctionBuiltin), hex("#50fa7b"));
+            assert_eq!(color(Highlight::Function), None);
+        }
+
+        let path = std::env::temp_dir().join("glyph_file_theme.json");
+        fs::write(&path, json).unwrap();
+        assert!(FileTheme::from_file(&path).is_ok());
+        assert!(matches!(
+            FileTheme::from_file(&path.with_extension("toml")),
+            Err(ThemeError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn file_theme_invalid() {
+        let theme = |src: &str| FileTheme::from_toml(&format!("bg = \"#000\"\n{}", src));
+        assert!(theme("fg = \"#fff\"").is_ok());
+        assert!(matches!(
+            theme("fg = \"#fff\"\nkeywords = \"#fff\""),
+            Err(ThemeError::UnknownHighlight(name)) if name == "keywords"
+        ));
+        assert!(matches!(
+            theme("fg = \"#fff\"\n\"type.builtin\" = \"#ff\""),
+            Err(ThemeError::Color {
+                name: "type.builtin",
+                ..
+            })
+        ));
+        assert!(matches!(
+            theme("keyword = \"#fff\""),
+            Err(ThemeError::Missing("fg"))
+        ));
+        assert!(matches!(
+            FileTheme::from_json("[]"),
+            Err(ThemeError::Json(_))
+        ));
+    }
+
+    #[test]
+    fn high_contrast() {
+        // WCAG AAA for every color text is drawn with
+        let theme = HighContrast::new();
+        assert!(low_contrast(&theme, 7.0).is_empty());
+        assert!(theme.line_number().contrast(theme.bg()) >= 7.0);
+    }
+
+    #[test]
+    fn low_contrast_highlights() {
+        let github = GithubDark::new();
+        assert_eq!(low_contrast(&github, 4.5), vec!["punctuation.bracket"]);
+        assert!(low_contrast(&github, 1.0).is_empty());
+
+        let theme = FileTheme::from_toml(
+            "bg = \"#fff\"\nfg = \"#777\"\ncomment = \"#767676\"\nstring = \"#ffff00\"",
+        )
+        .unwrap();
+        assert_eq!(low_contrast(&theme, 4.5), vec!["fg", "string"]);
+    }
+
+    #[test]
+    fn enforce_contrast() {
+        let mut theme = FileTheme::from_theme(&GithubDark::new());
+        let unchanged = *theme.highlight(Highlight::Keyword).unwrap();
+
+        assert_eq!(theme.enforce_contrast(4.5), vec!["punctuation.bracket"]);
+        assert!(low_contrast(&theme, 4.5).is_empty());
+        let bracket = theme.highlight(Highlight::PunctuationBracket).unwrap();
+        assert!(bracket.contrast(theme.bg()) >= 4.5);
+        assert_eq!(
+            theme.highlight(Highlight::Keyword).unwrap().floats(),
+            unchanged.floats()
+        );
+        // Highlights GitHub Dark leaves to `fg` still are
+        assert!(theme.highlight(Highlight::Tag).is_none());
+    }
+
+    #[test]
+    fn highlight_colors() {
+        let themes: [Box<ThemeType>; 5] = [
+            Box::new(TokyoNightStorm::new()),
+            Box::new(GithubDark::new()),
+            Box::new(GruvboxDark::new()),
+            Box::new(Dracula::new()),
+            Box::new(HighContrast::new()),
+        ];
+        for theme in &themes {
+            let colors = HighlightColors::new(&**theme);
+            assert_eq!(colors.fg(), *theme.fg());
+            for (idx, name) in HIGHLIGHTS.iter().enumerate() {
+                if let Some(color) = theme.highlight(Highlight::from_u8(idx as u8).unwrap()) {
+                    assert_eq!(colors.get(idx as u8), *color, "{}", name);
+                }
+            }
+        }
+
+        // GitHub Dark has no `function.builtin` or `attribute`
+        let github = GithubDark::new();
+        let colors = HighlightColors::new(&github);
+        let index = |name| HIGHLIGHTS.iter().position(|h| *h == name).unwrap() as u8;
+        assert_eq!(
+            colors.get(index("function.builtin")),
+            *github.highlight(Highlight::Function).unwrap()
+        );
+        assert_eq!(colors.get(index("attribute")), *github.fg());
+
+        let tokyo = TokyoNightStorm::new();
+        assert_eq!(tokyo.highlight(Highlight::Number), Some(&tokyo.orange));
+        assert_eq!(tokyo.highlight(Highlight::Namespace), Some(&tokyo.cyan));
+        assert_eq!(tokyo.highlight(Highlight::Embedded), Some(&tokyo.fg));
+    }
+
+    #[test]
+    fn highlight_styles() {
+        let colors = HighlightColors::new(&GithubDark::new());
+        let index = |name| HIGHLIGHTS.iter().position(|h| *h == name).unwrap() as u8;
+        assert_eq!(colors.style(index("comment")), FontStyle::Italic);
+        assert_eq!(colors.style(index("type")), FontStyle::Bold);
+        assert_eq!(colors.style(index("keyword")), FontStyle::Normal);
+    }
+
+    #[test]
+    fn highlight_colors_file_theme() {
+        let theme = FileTheme::from_toml(
+            r##"
+bg = "#000000"
+fg = "#ffffff"
+punctuation = "#888888"
+"punctuation.special" = "#ff0000"
+type = "#00ff00"
+"##,
+        )
+        .unwrap();
+        let colors = HighlightColors::new(&theme);
+        let color = |name| {
+            let idx = HIGHLIGHTS.iter().position(|h| *h == name).unwrap();
+            colors.get(idx as u8)
+        };
+        let hex = |hex| Color::from_hex(hex).unwrap();
+
+        assert_eq!(color("punctuation"), hex("#888888"));
+        assert_eq!(color("punctuation.bracket"), hex("#888888"));
+        assert_eq!(color("punctuation.special"), hex("#ff0000"));
+        assert_eq!(color("type.builtin"), hex("#00ff00"));
+        assert_eq!(color("keyword"), hex("#ffffff"));
+    }
+}
@@ -1,6 +1,6 @@
 use sdl2::{event::Event, keyboard::Keycode};
 
-use crate::Mode;
+use crate::{Mode, Registers};
 
 #[derive(Debug, PartialEq)]
 pub enum Cmd {
@@ -11,13 +11,44 @@ pub enum Cmd {
     /// None is only valid in visual mode, means to apply
     /// to the selection
     Delete(Option<Move>),
+    /// `x`, delete the char under the cursor
+    DeleteChar,
     Change(Option<Move>),
     Yank(Option<Move>),
+    /// `>`, shift lines right by one indent
+    Indent(Option<Move>),
+    /// `<`, shift lines left by one indent
+    Dedent(Option<Move>),
+    /// `gu`, `gU` or `~`
+    Case(Case, Option<Move>),
+    /// `=`, fix the indentation of lines
+    Reindent(Option<Move>),
+    /// `gc`, comment out lines or uncomment them
+    Comment(Option<Move>),
+    /// `p` or `P`
+    Paste {
+        before: bool,
+    },
+    /// Run `cmd` with the register `name`, ex. `"ayy`
+    Register {
+        name: char,
+        cmd: Box<Cmd>,
+    },
 
     Move(Move),
     SwitchMove(Move),
     SwitchMode(Mode),
     NewLine(NewLine),
+    /// `gf`, open the file whose path is under the cursor
+    GotoFile,
+    /// A command typed after `:` and run with `Enter`
+    Ex(ExCmd),
+    /// `/` or `?` with `reverse`, an empty pattern searches for the last
+    /// one again
+    Search {
+        pattern: String,
+        reverse: bool,
+    },
     Undo,
     Redo,
 }
@@ -31,6 +62,87 @@ impl Cmd {
             _ => false,
         }
     }
+
+    /// `j` or `k`, with or without a count
+    #[inline]
+    pub fn is_vertical_movement(&self) -> bool {
+        match self {
+            Cmd::Move(Move::Up | Move::Down) => true,
+            Cmd::Repeat { cmd, .. } => cmd.is_vertical_movement(),
+            _ => false,
+        }
+    }
+
+    /// `gj` or `gk`, with or without a count
+    #[inline]
+    pub fn is_display_movement(&self) -> bool {
+        match self {
+            Cmd::Move(Move::DisplayUp | Move::DisplayDown) => true,
+            Cmd::Repeat { cmd, .. } => cmd.is_display_movement(),
+            _ => false,
+        }
+    }
+}
+
+/// Commands from the `:` command line
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExCmd {
+    /// `:w`
+    Write,
+    /// `:q`, `:q!` quits without saving
+    Quit { force: bool },
+    /// `:wq` or `:x`
+    WriteQuit,
+    /// `:42`, go to that line. Lines start at 1
+    Line(usize),
+    /// `:$`
+    LastLine,
+    /// `:+5` or `:-3`, that many lines down or up
+    RelativeLine(isize),
+    /// `:set wrap`, with the option after `set`
+    Set(String),
+    /// `:noh`, stop highlighting the search until the next one
+    NoHighlight,
+    /// Anything else, what was typed
+    Unknown(String),
+}
+
+impl ExCmd {
+    /// Parse the text typed after `:`, `None` if it's blank
+    pub fn parse(line: &str) -> Option<ExCmd> {
+        let line = line.trim();
+        let cmd = match line {
+            "" => return None,
+            "w" | "write" => ExCmd::Write,
+            "q" | "quit" => ExCmd::Quit { force: false },
+            "q!" | "quit!" => ExCmd::Quit { force: true },
+            "wq" | "x" => ExCmd::WriteQuit,
+            "noh" | "nohlsearch" => ExCmd::NoHighlight,
+            "$" => ExCmd::LastLine,
+            "+" => ExCmd::RelativeLine(1),
+            "-" => ExCmd::RelativeLine(-1),
+            _ if line.starts_with("set ") || line.starts_with("se ") => {
+                let (_, option) = line.split_once(' ').unwrap_or_default();
+                ExCmd::Set(option.trim().to_string())
+            }
+            _ if line.starts_with('+') || line.starts_with('-') => match line.parse::<isize>() {
+                Ok(n) => ExCmd::RelativeLine(n),
+                Err(_) => ExCmd::Unknown(line.to_string()),
+            },
+            _ => match line.parse::<usize>() {
+                Ok(line) => ExCmd::Line(line),
+                Err(_) => ExCmd::Unknown(line.to_string()),
+            },
+        };
+        Some(cmd)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Case {
+    Lower,
+    Upper,
+    Toggle,
 }
 
 #[derive(Debug, PartialEq)]
@@ -46,6 +158,10 @@ pub enum Move {
     Right,
     Up,
     Down,
+    // Move by rows on the screen instead of lines, these differ when
+    // lines are wrapped
+    DisplayUp,
+    DisplayDown,
     LineStart,
     LineEnd,
     // Bool is true if find in reverse
@@ -57,6 +173,9 @@ pub enum Move {
     Word(bool),
     BeginningWord(bool),
     EndWord(bool),
+    // `n`, or `N` with true, the next match of the search in the direction
+    // it was made
+    NextMatch(bool),
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -64,8 +183,17 @@ pub enum Token {
     Start,
     End,
     Delete,
+    DeleteChar,
     Change,
     Yank,
+    Indent,
+    Dedent,
+    Case(Case),
+    Reindent,
+    Comment,
+    Paste(bool),
+    Register(char),
+    GotoFile,
     Find,
     FindReverse,
     Left,
@@ -74,6 +202,8 @@ pub enum Token {
     Undo,
     Redo,
     Down,
+    DisplayUp,
+    DisplayDown,
     LineStart,
     LineEnd,
     ParagraphBegin,
@@ -83,6 +213,7 @@ pub enum Token {
     Word(bool),
     BeginningWord(bool),
     EndWord(bool),
+    NextMatch(bool),
 }
 
 #[derive(Debug, PartialEq)]
@@ -95,9 +226,10 @@ enum FailAction {
 type Result<T> = core::result::Result<T, FailAction>;
 
 fn digits_to_num(digits: Vec<u16>) -> u16 {
-    let mut num = 0;
+    let mut num: u16 = 0;
     for digit in digits {
-        num = num * 10 + digit as u16;
+        // Clamp huge counts instead of overflowing
+        num = num.saturating_mul(10).saturating_add(digit);
     }
     num
 }
@@ -106,8 +238,13 @@ pub struct Vim {
     cmd_stack: Vec<Token>,
     parsing_find: bool,
     parsing_start: bool,
+    parsing_register: bool,
     parse_idx: usize,
     mode: Mode,
+    // What's typed after `:`, `/` or `?`, `None` when the command line
+    // isn't open. `command_prompt` is which of those opened it
+    command_line: Option<String>,
+    command_prompt: char,
 }
 
 impl Vim {
@@ -116,12 +253,19 @@ impl Vim {
             cmd_stack: Vec::new(),
             parsing_find: false,
             parsing_start: false,
+            parsing_register: false,
             parse_idx: 0,
             mode: Mode::Normal,
+            command_line: None,
+            command_prompt: ':',
         }
     }
 
     pub fn event(&mut self, event: Event) -> Option<Cmd> {
+        if self.command_line.is_some() {
+            return self.command_line_event(event);
+        }
+
         match event {
             Event::KeyDown {
                 keycode: Some(key), ..
@@ -137,15 +281,45 @@ impl Vim {
                         _ => {} /* self.cmd_stack.push(Token::LineStart) */
                     };
                 }
-                _ => {}
+                Keycode::Left | Keycode::Right | Keycode::Up | Keycode::Down
+                    if self.parsing_find || self.parsing_start || self.parsing_register =>
+                {
+                    self.reset();
+                }
+                Keycode::Left => self.cmd_stack.push(Token::Left),
+                Keycode::Right => self.cmd_stack.push(Token::Right),
+                Keycode::Up => self.cmd_stack.push(Token::Up),
+                Keycode::Down => self.cmd_stack.push(Token::Down),
+                // Handled by the `TextInput` event that follows
+                key if is_text_key(key) => {}
+                _ => self.reset(),
             },
             Event::TextInput { text, .. } => {
                 if self.parsing_start {
-                    if text.as_str() == "g" {
-                        self.cmd_stack.push(Token::Start);
-                        self.parsing_start = false;
-                    } else {
-                        self.reset();
+                    let token = match text.as_str() {
+                        "g" => Some(Token::Start),
+                        "j" => Some(Token::DisplayDown),
+                        "k" => Some(Token::DisplayUp),
+                        "f" => Some(Token::GotoFile),
+                        "u" => Some(Token::Case(Case::Lower)),
+                        "U" => Some(Token::Case(Case::Upper)),
+                        "c" => Some(Token::Comment),
+                        _ => None,
+                    };
+                    match token {
+                        Some(token) => {
+                            self.cmd_stack.push(token);
+                            self.parsing_start = false;
+                        }
+                        None => self.reset(),
+                    }
+                } else if self.parsing_register {
+                    match text.chars().next() {
+                        Some(name) if Registers::is_valid(name) => {
+                            self.cmd_stack.push(Token::Register(name));
+                            self.parsing_register = false;
+                        }
+                        _ => self.reset(),
                     }
                 } else if self.parsing_find {
                     self.cmd_stack
@@ -153,6 +327,11 @@ impl Vim {
                     self.parsing_find = false;
                 } else {
                     match text.as_str() {
+                        ":" | "/" | "?" if matches!(self.mode, Mode::Normal) => {
+                            self.reset();
+                            self.command_line = Some(String::new());
+                            self.command_prompt = text.chars().next().unwrap();
+                        }
                         // Visual mode
                         "v" => {
                             self.reset();
@@ -165,10 +344,31 @@ impl Vim {
                         "l" => self.cmd_stack.push(Token::Right),
                         // Ops
                         "d" => self.cmd_stack.push(Token::Delete),
+                        "x" => self.cmd_stack.push(Token::DeleteChar),
+                        // `gcc` is `gcgc`
+                        "c" if self.cmd_stack.last() == Some(&Token::Comment) => {
+                            self.cmd_stack.push(Token::Comment)
+                        }
                         "c" => self.cmd_stack.push(Token::Change),
                         "y" => self.cmd_stack.push(Token::Yank),
+                        ">" => self.cmd_stack.push(Token::Indent),
+                        "<" => self.cmd_stack.push(Token::Dedent),
+                        "~" => self.cmd_stack.push(Token::Case(Case::Toggle)),
+                        "=" => self.cmd_stack.push(Token::Reindent),
+                        // `guu` and `gUU` are `gugu` and `gUgU`
+                        "u" if self.cmd_stack.last() == Some(&Token::Case(Case::Lower)) => {
+                            self.cmd_stack.push(Token::Case(Case::Lower))
+                        }
+                        "U" if self.cmd_stack.last() == Some(&Token::Case(Case::Upper)) => {
+                            self.cmd_stack.push(Token::Case(Case::Upper))
+                        }
                         "u" => self.cmd_stack.push(Token::Undo),
                         "r" => self.cmd_stack.push(Token::Redo),
+                        "p" => self.cmd_stack.push(Token::Paste(false)),
+                        "P" => self.cmd_stack.push(Token::Paste(true)),
+                        "\"" => {
+                            self.parsing_register = true;
+                        }
                         // Movement
                         "F" => {
                             self.cmd_stack.push(Token::FindReverse);
@@ -217,6 +417,8 @@ impl Vim {
                         "b" => self.cmd_stack.push(Token::BeginningWord(false)),
                         "E" => self.cmd_stack.push(Token::EndWord(true)),
                         "e" => self.cmd_stack.push(Token::EndWord(false)),
+                        "n" => self.cmd_stack.push(Token::NextMatch(false)),
+                        "N" => self.cmd_stack.push(Token::NextMatch(true)),
                         r => {
                             let c = r.chars().next().unwrap();
                             if c.is_numeric() {
@@ -248,7 +450,7 @@ impl Vim {
             _ => {}
         };
 
-        if self.cmd_stack.is_empty() || self.parsing_start {
+        if self.cmd_stack.is_empty() || self.parsing_start || self.parsing_register {
             return None;
         }
 
@@ -270,6 +472,154 @@ impl Vim {
     }
 }
 
+impl Vim {
+    /// Keys while the command line is open, `Enter` parses and runs it
+    fn command_line_event(&mut self, event: Event) -> Option<Cmd> {
+        let line = self.command_line.as_mut()?;
+        match event {
+            Event::KeyDown {
+                keycode: Some(Keycode::Return | Keycode::KpEnter),
+                ..
+            } => {
+                let line = self.command_line.take()?;
+                return match self.command_prompt {
+                    ':' => ExCmd::parse(&line).map(Cmd::Ex),
+                    prompt => Some(Cmd::Search {
+                        pattern: line,
+                        reverse: prompt == '?',
+                    }),
+                };
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => self.command_line = None,
+            // Deleting past the prompt closes the command line like Vim
+            Event::KeyDown {
+                keycode: Some(Keycode::Backspace),
+                ..
+            } => {
+                if line.pop().is_none() {
+                    self.command_line = None;
+                }
+            }
+            Event::TextInput { text, .. } => line.push_str(&text),
+            _ => {}
+        }
+        None
+    }
+
+    /// The text typed after the prompt, `None` when the command line isn't
+    /// open
+    #[inline]
+    pub fn command_line(&self) -> Option<&str> {
+        self.command_line.as_deref()
+    }
+
+    /// `:`, `/` or `?`, what the command line was opened with
+    #[inline]
+    pub fn command_prompt(&self) -> char {
+        self.command_prompt
+    }
+}
+
+/// Keys that type text or only modify other keys, so the pending command
+/// shouldn't be thrown away when they're pressed
+fn is_text_key(key: Keycode) -> bool {
+    // The keycodes of printable keys are their ASCII char
+    (0x20..0x7f).contains(&(key as i32))
+        || matches!(
+            key,
+            Keycode::Kp1
+                | Keycode::Kp2
+                | Keycode::Kp3
+                | Keycode::Kp4
+                | Keycode::Kp5
+                | Keycode::Kp6
+                | Keycode::Kp7
+                | Keycode::Kp8
+                | Keycode::Kp9
+                | Keycode::KpPeriod
+                | Keycode::KpDivide
+                | Keycode::KpMultiply
+                | Keycode::KpMinus
+                | Keycode::KpPlus
+                | Keycode::LShift
+                | Keycode::RShift
+                | Keycode::LCtrl
+                | Keycode::RCtrl
+                | Keycode::LAlt
+                | Keycode::RAlt
+                | Keycode::LGui
+                | Keycode::RGui
+                | Keycode::CapsLock
+                | Keycode::Mode
+        )
+}
+
+impl Vim {
+    /// The keys of the command typed so far, like `2d` while waiting for a
+    /// movement. Empty when there isn't one
+    pub fn pending_display(&self) -> String {
+        let mut keys: String = self.cmd_stack.iter().map(token_keys).collect();
+        if self.parsing_start {
+            keys.push('g');
+        }
+        if self.parsing_register {
+            keys.push('"');
+        }
+        keys
+    }
+}
+
+/// The keys typed for `token`
+fn token_keys(token: &Token) -> String {
+    let keys = match token {
+        Token::Start => "gg",
+        Token::End => "G",
+        Token::Delete => "d",
+        Token::DeleteChar => "x",
+        Token::Change => "c",
+        Token::Yank => "y",
+        Token::Indent => ">",
+        Token::Dedent => "<",
+        Token::Case(Case::Lower) => "gu",
+        Token::Case(Case::Upper) => "gU",
+        Token::Case(Case::Toggle) => "~",
+        Token::Reindent => "=",
+        Token::Comment => "gc",
+        Token::Paste(false) => "p",
+        Token::Paste(true) => "P",
+        Token::Register(name) => return format!("\"{}", name),
+        Token::GotoFile => "gf",
+        Token::Find => "f",
+        Token::FindReverse => "F",
+        Token::Left => "h",
+        Token::Right => "l",
+        Token::Up => "k",
+        Token::Down => "j",
+        Token::Undo => "u",
+        Token::Redo => "r",
+        Token::DisplayUp => "gk",
+        Token::DisplayDown => "gj",
+        Token::LineStart => "0",
+        Token::LineEnd => "$",
+        Token::ParagraphBegin => "{",
+        Token::ParagraphEnd => "}",
+        Token::Number(count) => return count.to_string(),
+        Token::Char(c) => return c.to_string(),
+        Token::Word(true) => "W",
+        Token::Word(false) => "w",
+        Token::BeginningWord(true) => "B",
+        Token::BeginningWord(false) => "b",
+        Token::EndWord(true) => "E",
+        Token::EndWord(false) => "e",
+        Token::NextMatch(true) => "N",
+        Token::NextMatch(false) => "n",
+    };
+    keys.to_string()
+}
+
 // Parsing
 impl Vim {
     fn parse_cmd(&mut self) -> Result<Cmd> {
@@ -280,25 +630,46 @@ impl Vim {
         }
     }
 
-    /// Delete/Chank/Yank and movements are only valid in visual mode
+    /// Delete/Change/Yank, indenting, changing case and movements are only
+    /// valid in visual mode
     fn parse_cmd_visual_mode(&mut self) -> Result<Cmd> {
         match self.next().cloned() {
             None => Err(FailAction::Continue),
-            Some(Token::Delete) => Ok(Cmd::Delete(None)),
+            // `x` on a selection is the same as `d`
+            Some(Token::Delete | Token::DeleteChar) => Ok(Cmd::Delete(None)),
             Some(Token::Change) => Ok(Cmd::Change(None)),
             Some(Token::Yank) => Ok(Cmd::Yank(None)),
+            Some(Token::Indent) => Ok(Cmd::Indent(None)),
+            Some(Token::Dedent) => Ok(Cmd::Dedent(None)),
+            Some(Token::Case(case)) => Ok(Cmd::Case(case, None)),
+            Some(Token::Reindent) => Ok(Cmd::Reindent(None)),
+            Some(Token::Comment) => Ok(Cmd::Comment(None)),
+            Some(Token::Register(name)) => match self.parse_cmd()? {
+                cmd @ (Cmd::Delete(None) | Cmd::Change(None) | Cmd::Yank(None)) => {
+                    Ok(Cmd::Register {
+                        name,
+                        cmd: Box::new(cmd),
+                    })
+                }
+                _ => Err(FailAction::Reset),
+            },
             Some(Token::Number(count)) => {
                 match self.parse_cmd()? {
                     Cmd::Delete(None) => Ok(Cmd::Delete(None)),
                     Cmd::Change(None) => Ok(Cmd::Change(None)),
                     Cmd::Yank(None) => Ok(Cmd::Yank(None)),
+                    cmd @ (Cmd::Indent(None)
+                    | Cmd::Dedent(None)
+                    | Cmd::Case(_, None)
+                    | Cmd::Reindent(None)
+                    | Cmd::Comment(None)) => Ok(cmd),
                     Cmd::Move(m) => Ok(Cmd::Repeat {
                         count,
                         cmd: Box::new(Cmd::Move(m)),
                     }),
                     _ => {
-                        // Only delete/yank/change or movements are valid repeated
-                        // cmds in visual mode
+                        // Only operators on the selection or movements are valid
+                        // repeated cmds in visual mode
                         Err(FailAction::Reset)
                     }
                 }
@@ -316,8 +687,21 @@ impl Vim {
             Some(Token::Undo) => Ok(Cmd::Undo),
             Some(Token::Redo) => Ok(Cmd::Redo),
             Some(Token::Delete) => self.parse_op(Token::Delete).map(Cmd::Delete),
+            Some(Token::DeleteChar) => Ok(Cmd::DeleteChar),
             Some(Token::Change) => self.parse_op(Token::Change).map(Cmd::Change),
             Some(Token::Yank) => self.parse_op(Token::Yank).map(Cmd::Yank),
+            Some(Token::Reindent) => self.parse_op(Token::Reindent).map(Cmd::Reindent),
+            Some(Token::Comment) => self.parse_op(Token::Comment).map(Cmd::Comment),
+            // `~` only changes the selection
+            Some(Token::Case(case @ (Case::Lower | Case::Upper))) => self
+                .parse_op(Token::Case(case))
+                .map(|mv| Cmd::Case(case, mv)),
+            Some(Token::Paste(before)) => Ok(Cmd::Paste { before }),
+            Some(Token::GotoFile) => Ok(Cmd::GotoFile),
+            Some(Token::Register(name)) => self.parse_cmd().map(|cmd| Cmd::Register {
+                name,
+                cmd: Box::new(cmd),
+            }),
             Some(Token::Number(count)) => self.parse_cmd().map(|cmd| Cmd::Repeat {
                 count,
                 cmd: Box::new(cmd),
@@ -345,6 +729,8 @@ impl Vim {
             None => Err(FailAction::Continue),
             Some(Token::Up) => Ok(Move::Up),
             Some(Token::Down) => Ok(Move::Down),
+            Some(Token::DisplayUp) => Ok(Move::DisplayUp),
+            Some(Token::DisplayDown) => Ok(Move::DisplayDown),
             Some(Token::Left) => Ok(Move::Left),
             Some(Token::Right) => Ok(Move::Right),
             Some(Token::LineEnd) => Ok(Move::LineEnd),
@@ -358,6 +744,7 @@ impl Vim {
                 Ok(Move::BeginningWord(skip_punctuation))
             }
             Some(Token::EndWord(skip_punctuation)) => Ok(Move::EndWord(skip_punctuation)),
+            Some(Token::NextMatch(back)) => Ok(Move::NextMatch(back)),
             Some(Token::Find) => match self.next() {
                 Some(Token::Char(char)) => Ok(Move::Find(*char, false)),
                 Some(_) => Err(FailAction::Reset),
@@ -380,6 +767,7 @@ impl Vim {
     fn reset(&mut self) {
         self.parsing_start = false;
         self.parsing_find = false;
+        self.parsing_register = false;
         self.parse_idx = 0;
         self.cmd_stack.clear();
     }
@@ -448,14 +836,14 @@ mod tests {
         #[test]
         fn basic_ops() {
             let mut vim = Vim::new();
-            let basic = vec![Keycode::H, Keycode::J, Keycode::K, Keycode::L];
+            let basic = vec!["h", "j", "k", "l"];
             let basic_moves = vec![Move::Left, Move::Down, Move::Up, Move::Right];
             let basic_input = vec!["d", "c", "y"];
 
             for (i, input) in basic_input.into_iter().enumerate() {
                 assert_eq!(vim.event(text_input(input)), None);
                 assert_eq!(
-                    vim.event(keydown(basic[i])),
+                    vim.event(text_input(basic[i])),
                     Some(match input {
                         "d" => Cmd::Delete(Some(basic_moves[i].clone())),
                         "c" => Cmd::Change(Some(basic_moves[i].clone())),
@@ -471,7 +859,7 @@ mod tests {
         fn repeated_ops() {
             let mut vim = Vim::new();
             let counts = vec![3, 4, 2];
-            let basic = vec![Keycode::H, Keycode::J, Keycode::K, Keycode::L];
+            let basic = vec!["h", "j", "k", "l"];
             let basic_moves = vec![Move::Left, Move::Down, Move::Up, Move::Right];
             let basic_input = vec!["d", "c", "y"];
 
@@ -487,11 +875,200 @@ mod tests {
                         _ => unreachable!(),
                     }),
                 };
-                assert_eq!(vim.event(keydown(basic[i])), Some(repeated));
+                assert_eq!(vim.event(text_input(basic[i])), Some(repeated));
                 is_reset(&mut vim);
             }
         }
 
+        #[test]
+        fn arrow_ops() {
+            let mut vim = Vim::new();
+            assert_eq!(vim.event(text_input("d")), None);
+            assert_eq!(
+                vim.event(keydown(Keycode::Down)),
+                Some(Cmd::Delete(Some(Move::Down)))
+            );
+            is_reset(&mut vim);
+
+            assert_eq!(vim.event(text_input("3")), None);
+            assert_eq!(vim.event(text_input("c")), None);
+            assert_eq!(
+                vim.event(keydown(Keycode::Left)),
+                Some(Cmd::Repeat {
+                    count: 3,
+                    cmd: Box::new(Cmd::Change(Some(Move::Left)))
+                })
+            );
+            is_reset(&mut vim);
+        }
+
+        #[test]
+        fn registers() {
+            let mut vim = Vim::new();
+            assert_eq!(vim.event(text_input("\"")), None);
+            assert_eq!(vim.event(text_input("0")), None);
+            assert_eq!(
+                vim.event(text_input("p")),
+                Some(Cmd::Register {
+                    name: '0',
+                    cmd: Box::new(Cmd::Paste { before: false })
+                })
+            );
+            is_reset(&mut vim);
+
+            assert_eq!(vim.event(text_input("\"")), None);
+            assert_eq!(vim.event(text_input("a")), None);
+            assert_eq!(vim.event(text_input("2")), None);
+            assert_eq!(vim.event(text_input("y")), None);
+            assert_eq!(
+                vim.event(text_input("y")),
+                Some(Cmd::Register {
+                    name: 'a',
+                    cmd: Box::new(Cmd::Repeat {
+                        count: 2,
+                        cmd: Box::new(Cmd::Yank(None))
+                    })
+                })
+            );
+            is_reset(&mut vim);
+
+            // Not a register
+            assert_eq!(vim.event(text_input("\"")), None);
+            assert_eq!(vim.event(text_input("!")), None);
+            is_reset(&mut vim);
+            assert!(!vim.parsing_register);
+            assert_eq!(
+                vim.event(text_input("P")),
+                Some(Cmd::Paste { before: true })
+            );
+        }
+
+        #[test]
+        fn reindent() {
+            let mut vim = Vim::new();
+            assert_eq!(vim.event(text_input("=")), None);
+            assert_eq!(vim.event(text_input("=")), Some(Cmd::Reindent(None)));
+            assert_eq!(vim.event(text_input("=")), None);
+            assert_eq!(
+                vim.event(text_input("}")),
+                Some(Cmd::Reindent(Some(Move::ParagraphEnd)))
+            );
+            is_reset(&mut vim);
+        }
+
+        #[test]
+        fn comment() {
+            let mut vim = Vim::new();
+            assert_eq!(vim.event(text_input("g")), None);
+            assert_eq!(vim.event(text_input("c")), None);
+            assert_eq!(vim.pending_display(), "gc");
+            assert_eq!(vim.event(text_input("c")), Some(Cmd::Comment(None)));
+            is_reset(&mut vim);
+            assert_eq!(vim.event(text_input("g")), None);
+            assert_eq!(vim.event(text_input("c")), None);
+            assert_eq!(
+                vim.event(text_input("j")),
+                Some(Cmd::Comment(Some(Move::Down)))
+            );
+            is_reset(&mut vim);
+        }
+
+        #[test]
+        fn delete_char() {
+            let mut vim = Vim::new();
+            assert_eq!(vim.event(text_input("x")), Some(Cmd::DeleteChar));
+            assert_eq!(vim.event(text_input("5")), None);
+            assert_eq!(
+                vim.event(text_input("x")),
+                Some(Cmd::Repeat {
+                    count: 5,
+                    cmd: Box::new(Cmd::DeleteChar)
+                })
+            );
+
+            vim.set_mode(Mode::Visual);
+            assert_eq!(vim.event(text_input("x")), Some(Cmd::Delete(None)));
+            is_reset(&mut vim);
+        }
+
+        #[test]
+        fn case_ops() {
+            let mut vim = Vim::new();
+            for key in ["g", "u"] {
+                assert_eq!(vim.event(text_input(key)), None);
+            }
+            assert_eq!(
+                vim.event(text_input("w")),
+                Some(Cmd::Case(Case::Lower, Some(Move::Word(false))))
+            );
+            for key in ["g", "U"] {
+                assert_eq!(vim.event(text_input(key)), None);
+            }
+            assert_eq!(
+                vim.event(text_input("U")),
+                Some(Cmd::Case(Case::Upper, None))
+            );
+            for key in ["g", "U", "g"] {
+                assert_eq!(vim.event(text_input(key)), None);
+            }
+            assert_eq!(
+                vim.event(text_input("U")),
+                Some(Cmd::Case(Case::Upper, None))
+            );
+            for key in ["g", "u"] {
+                assert_eq!(vim.event(text_input(key)), None);
+            }
+            assert_eq!(
+                vim.event(text_input("u")),
+                Some(Cmd::Case(Case::Lower, None))
+            );
+            for key in ["g", "u"] {
+                assert_eq!(vim.event(text_input(key)), None);
+            }
+            assert_eq!(
+                vim.event(text_input("}")),
+                Some(Cmd::Case(Case::Lower, Some(Move::ParagraphEnd)))
+            );
+            is_reset(&mut vim);
+
+            // `u` is still undo and `~` isn't an operator
+            assert_eq!(vim.event(text_input("u")), Some(Cmd::Undo));
+            assert_eq!(vim.event(text_input("~")), None);
+            is_reset(&mut vim);
+        }
+
+        #[test]
+        fn visual_ops() {
+            let mut vim = Vim::new();
+            vim.set_mode(Mode::Visual);
+            assert_eq!(vim.event(text_input(">")), Some(Cmd::Indent(None)));
+            assert_eq!(vim.event(text_input("<")), Some(Cmd::Dedent(None)));
+            assert_eq!(
+                vim.event(text_input("~")),
+                Some(Cmd::Case(Case::Toggle, None))
+            );
+            assert_eq!(vim.event(text_input("g")), None);
+            assert_eq!(
+                vim.event(text_input("u")),
+                Some(Cmd::Case(Case::Lower, None))
+            );
+            assert_eq!(vim.event(text_input("g")), None);
+            assert_eq!(
+                vim.event(text_input("U")),
+                Some(Cmd::Case(Case::Upper, None))
+            );
+            is_reset(&mut vim);
+
+            assert_eq!(vim.event(text_input("=")), Some(Cmd::Reindent(None)));
+            assert_eq!(vim.event(text_input("g")), None);
+            assert_eq!(vim.event(text_input("c")), Some(Cmd::Comment(None)));
+
+            // Only parsed on a selection
+            vim.set_mode(Mode::Normal);
+            assert_eq!(vim.event(text_input(">")), None);
+            is_reset(&mut vim);
+        }
+
         #[test]
         fn complex() {
             let mut vim = Vim::new();
@@ -512,6 +1089,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn pending_display() {
+        let mut vim = Vim::new();
+        assert_eq!(vim.pending_display(), "");
+
+        let mut pending = Vec::new();
+        for key in ["2", "\"", "a", "d", "1", "2", "f"] {
+            assert_eq!(vim.event(text_input(key)), None);
+            pending.push(vim.pending_display());
+        }
+        assert_eq!(
+            pending,
+            ["2", "2\"", "2\"a", "2\"ad", "2\"ad1", "2\"ad12", "2\"ad12f"]
+        );
+
+        // Done once the command is
+        assert!(vim.event(text_input("x")).is_some());
+        assert_eq!(vim.pending_display(), "");
+
+        for key in ["g", "U"] {
+            vim.event(text_input(key));
+        }
+        assert_eq!(vim.pending_display(), "gU");
+        vim.event(keydown(Keycode::Escape));
+        assert_eq!(vim.pending_display(), "");
+
+        vim.event(text_input("g"));
+        assert_eq!(vim.pending_display(), "g");
+        vim.event(text_input("u"));
+        vim.event(text_input("u"));
+        assert_eq!(vim.pending_display(), "");
+    }
+
     #[cfg(test)]
     mod movement {
         use super::*;
@@ -519,13 +1129,13 @@ mod tests {
         #[test]
         fn basic_movement() {
             let mut vim = Vim::new();
-            assert_eq!(vim.event(keydown(Keycode::H)), Some(Cmd::Move(Move::Left)));
+            assert_eq!(vim.event(text_input("h")), Some(Cmd::Move(Move::Left)));
             is_reset(&mut vim);
-            assert_eq!(vim.event(keydown(Keycode::K)), Some(Cmd::Move(Move::Up)));
+            assert_eq!(vim.event(text_input("k")), Some(Cmd::Move(Move::Up)));
             is_reset(&mut vim);
-            assert_eq!(vim.event(keydown(Keycode::J)), Some(Cmd::Move(Move::Down)));
+            assert_eq!(vim.event(text_input("j")), Some(Cmd::Move(Move::Down)));
             is_reset(&mut vim);
-            assert_eq!(vim.event(keydown(Keycode::L)), Some(Cmd::Move(Move::Right)));
+            assert_eq!(vim.event(text_input("l")), Some(Cmd::Move(Move::Right)));
             is_reset(&mut vim);
 
             assert_eq!(vim.event(text_input("0")), Some(Cmd::Move(Move::LineStart)));
@@ -548,7 +1158,7 @@ mod tests {
             let mut vim = Vim::new();
             assert_eq!(vim.event(text_input("2")), None);
             assert_eq!(
-                vim.event(keydown(Keycode::K)),
+                vim.event(text_input("k")),
                 Some(Cmd::Repeat {
                     count: 2,
                     cmd: Box::new(Cmd::Move(Move::Up))
@@ -567,5 +1177,240 @@ mod tests {
             );
             is_reset(&mut vim);
         }
+
+        #[test]
+        fn arrow_movement() {
+            let mut vim = Vim::new();
+            assert_eq!(
+                vim.event(keydown(Keycode::Left)),
+                Some(Cmd::Move(Move::Left))
+            );
+            is_reset(&mut vim);
+            assert_eq!(vim.event(keydown(Keycode::Up)), Some(Cmd::Move(Move::Up)));
+            is_reset(&mut vim);
+            assert_eq!(
+                vim.event(keydown(Keycode::Down)),
+                Some(Cmd::Move(Move::Down))
+            );
+            is_reset(&mut vim);
+            assert_eq!(
+                vim.event(keydown(Keycode::Right)),
+                Some(Cmd::Move(Move::Right))
+            );
+            is_reset(&mut vim);
+
+            assert_eq!(vim.event(text_input("2")), None);
+            assert_eq!(
+                vim.event(keydown(Keycode::Up)),
+                Some(Cmd::Repeat {
+                    count: 2,
+                    cmd: Box::new(Cmd::Move(Move::Up))
+                })
+            );
+            is_reset(&mut vim);
+        }
+
+        #[test]
+        fn stray_keys_reset() {
+            let mut vim = Vim::new();
+            assert_eq!(vim.event(text_input("d")), None);
+            assert_eq!(vim.event(keydown(Keycode::Home)), None);
+            is_reset(&mut vim);
+            assert_eq!(vim.event(text_input("j")), Some(Cmd::Move(Move::Down)));
+
+            // An arrow can't be the char to find
+            assert_eq!(vim.event(text_input("f")), None);
+            assert_eq!(vim.event(keydown(Keycode::Right)), None);
+            is_reset(&mut vim);
+
+            // Keys that are followed by their text keep the command going
+            assert_eq!(vim.event(text_input("d")), None);
+            assert_eq!(vim.event(keydown(Keycode::LShift)), None);
+            assert_eq!(vim.event(keydown(Keycode::G)), None);
+            assert_eq!(
+                vim.event(text_input("G")),
+                Some(Cmd::Delete(Some(Move::End)))
+            );
+        }
+
+        #[test]
+        fn display_movement() {
+            let mut vim = Vim::new();
+            assert_eq!(vim.event(text_input("g")), None);
+            assert_eq!(
+                vim.event(text_input("j")),
+                Some(Cmd::Move(Move::DisplayDown))
+            );
+            is_reset(&mut vim);
+
+            assert_eq!(vim.event(text_input("3")), None);
+            assert_eq!(vim.event(text_input("g")), None);
+            assert_eq!(
+                vim.event(text_input("k")),
+                Some(Cmd::Repeat {
+                    count: 3,
+                    cmd: Box::new(Cmd::Move(Move::DisplayUp))
+                })
+            );
+            is_reset(&mut vim);
+        }
+
+        #[test]
+        fn goto_file() {
+            let mut vim = Vim::new();
+            assert_eq!(vim.event(text_input("g")), None);
+            assert_eq!(vim.event(text_input("f")), Some(Cmd::GotoFile));
+            is_reset(&mut vim);
+
+            // Not a movement
+            assert_eq!(vim.event(text_input("d")), None);
+            assert_eq!(vim.event(text_input("g")), None);
+            assert_eq!(vim.event(text_input("f")), None);
+            is_reset(&mut vim);
+        }
+
+        #[test]
+        fn huge_count() {
+            let mut vim = Vim::new();
+            for _ in 0..6 {
+                assert_eq!(vim.event(text_input("9")), None);
+            }
+            assert_eq!(
+                vim.event(text_input("j")),
+                Some(Cmd::Repeat {
+                    count: u16::MAX,
+                    cmd: Box::new(Cmd::Move(Move::Down))
+                })
+            );
+        }
+    }
+
+    #[cfg(test)]
+    mod command_line {
+        use super::*;
+
+        fn run(vim: &mut Vim, line: &str) -> Option<Cmd> {
+            assert_eq!(vim.event(text_input(":")), None);
+            for c in line.chars() {
+                assert_eq!(vim.event(text_input(&c.to_string())), None);
+            }
+            assert_eq!(vim.command_line(), Some(line));
+            let cmd = vim.event(keydown(Keycode::Return));
+            assert_eq!(vim.command_line(), None);
+            cmd
+        }
+
+        #[test]
+        fn commands() {
+            let mut vim = Vim::new();
+            assert_eq!(run(&mut vim, "w"), Some(Cmd::Ex(ExCmd::Write)));
+            assert_eq!(
+                run(&mut vim, "q"),
+                Some(Cmd::Ex(ExCmd::Quit { force: false }))
+            );
+            assert_eq!(
+                run(&mut vim, "q!"),
+                Some(Cmd::Ex(ExCmd::Quit { force: true }))
+            );
+            assert_eq!(run(&mut vim, "wq"), Some(Cmd::Ex(ExCmd::WriteQuit)));
+            assert_eq!(run(&mut vim, "42"), Some(Cmd::Ex(ExCmd::Line(42))));
+            assert_eq!(run(&mut vim, "$"), Some(Cmd::Ex(ExCmd::LastLine)));
+            assert_eq!(run(&mut vim, "+5"), Some(Cmd::Ex(ExCmd::RelativeLine(5))));
+            assert_eq!(run(&mut vim, "-3"), Some(Cmd::Ex(ExCmd::RelativeLine(-3))));
+            assert_eq!(run(&mut vim, "-"), Some(Cmd::Ex(ExCmd::RelativeLine(-1))));
+            assert_eq!(
+                run(&mut vim, "+x"),
+                Some(Cmd::Ex(ExCmd::Unknown("+x".into())))
+            );
+            assert_eq!(
+                run(&mut vim, "set nowrap"),
+                Some(Cmd::Ex(ExCmd::Set("nowrap".into())))
+            );
+            assert_eq!(
+                run(&mut vim, "se  wrap!"),
+                Some(Cmd::Ex(ExCmd::Set("wrap!".into())))
+            );
+            assert_eq!(
+                run(&mut vim, "nope"),
+                Some(Cmd::Ex(ExCmd::Unknown("nope".into())))
+            );
+            assert_eq!(run(&mut vim, ""), None);
+
+            // Keys that are commands in normal mode are typed
+            assert_eq!(
+                run(&mut vim, "dd"),
+                Some(Cmd::Ex(ExCmd::Unknown("dd".into())))
+            );
+            is_reset(&mut vim);
+        }
+
+        #[test]
+        fn edit_and_cancel() {
+            let mut vim = Vim::new();
+            assert_eq!(vim.event(text_input(":")), None);
+            assert_eq!(vim.event(text_input("x")), None);
+            assert_eq!(vim.event(text_input("w")), None);
+            assert_eq!(vim.event(keydown(Keycode::Backspace)), None);
+            assert_eq!(vim.command_line(), Some("x"));
+            assert_eq!(
+                vim.event(keydown(Keycode::Return)),
+                Some(Cmd::Ex(ExCmd::WriteQuit))
+            );
+
+            assert_eq!(vim.event(text_input(":")), None);
+            assert_eq!(vim.event(text_input("w")), None);
+            assert_eq!(vim.event(keydown(Keycode::Escape)), None);
+            assert_eq!(vim.command_line(), None);
+            assert_eq!(vim.event(text_input("j")), Some(Cmd::Move(Move::Down)));
+
+            // Backspace on the empty command line closes it
+            assert_eq!(vim.event(text_input(":")), None);
+            assert_eq!(vim.event(keydown(Keycode::Backspace)), None);
+            assert_eq!(vim.command_line(), None);
+
+            // A pending command is thrown away
+            assert_eq!(vim.event(text_input("d")), None);
+            assert_eq!(vim.event(text_input(":")), None);
+            is_reset(&mut vim);
+            assert_eq!(vim.command_line(), Some(""));
+        }
+
+        #[test]
+        fn only_normal_mode() {
+            let mut vim = Vim::new();
+            vim.set_mode(Mode::Visual);
+            assert_eq!(vim.event(text_input(":")), None);
+            assert_eq!(vim.command_line(), None);
+        }
+
+        #[test]
+        fn search() {
+            let mut vim = Vim::new();
+            for (prompt, reverse) in [("/", false), ("?", true)] {
+                assert_eq!(vim.event(text_input(prompt)), None);
+                assert_eq!(vim.command_prompt(), prompt.chars().next().unwrap());
+                for c in "fn x".chars() {
+                    assert_eq!(vim.event(text_input(&c.to_string())), None);
+                }
+                assert_eq!(vim.command_line(), Some("fn x"));
+                assert_eq!(
+                    vim.event(keydown(Keycode::Return)),
+                    Some(Cmd::Search {
+                        pattern: "fn x".into(),
+                        reverse
+                    })
+                );
+            }
+            assert_eq!(
+                vim.event(text_input("n")),
+                Some(Cmd::Move(Move::NextMatch(false)))
+            );
+            assert_eq!(
+                vim.event(text_input("N")),
+                Some(Cmd::Move(Move::NextMatch(true)))
+            );
+            assert_eq!(run(&mut vim, "noh"), Some(Cmd::Ex(ExCmd::NoHighlight)));
+            is_reset(&mut vim);
+        }
     }
 }
@@ -0,0 +1,283 @@
+use std::{
+    collections::HashSet,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, TryRecvError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+#[derive(Clone, Debug)]
+pub struct WalkOptions {
+    /// Stop after finding this many files
+    pub max_results: usize,
+    /// How many directories deep to go, files in the root are at depth 0
+    pub max_depth: usize,
+    /// Names of directories that aren't entered, wherever they are
+    pub skip_dirs: Vec<String>,
+    /// How many paths are sent at a time
+    pub batch_size: usize,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            max_results: 100_000,
+            max_depth: 32,
+            skip_dirs: vec![".git".into(), "target".into(), "node_modules".into()],
+            batch_size: 512,
+        }
+    }
+}
+
+/// Files under a directory, found on a background thread and sent in
+/// batches so a picker can show them before the walk is done. The walk
+/// stops when it's cancelled or dropped
+pub struct Walk {
+    batches: Receiver<Vec<PathBuf>>,
+    cancelled: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Walk {
+    pub fn start(root: PathBuf, options: WalkOptions) -> Self {
+        let (tx, batches) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let cancelled = cancelled.clone();
+            thread::spawn(move || {
+                walk(&root, &options, &cancelled, |batch| tx.send(batch).is_ok());
+            })
+        };
+
+        Self {
+            batches,
+            cancelled,
+            thread: Some(thread),
+        }
+    }
+
+    /// The paths found since this was last called, relative to the root.
+    /// `None` once the walk is over and every batch has been taken
+    pub fn take_paths(&self) -> Option<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        loop {
+            match self.batches.try_recv() {
+                Ok(batch) => paths.extend(batch),
+                Err(TryRecvError::Empty) => return Some(paths),
+                Err(TryRecvError::Disconnected) if paths.is_empty() => return None,
+                Err(TryRecvError::Disconnected) => return Some(paths),
+            }
+        }
+    }
+
+    /// Stop walking and wait for the thread to exit, it checks between
+    /// directory entries so this doesn't wait long
+    pub fn cancel(mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Walk {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Walk the directories under `root` and call `send` with batches of the
+/// files in them. Returning false from `send` stops the walk
+fn walk(
+    root: &Path,
+    options: &WalkOptions,
+    cancelled: &AtomicBool,
+    mut send: impl FnMut(Vec<PathBuf>) -> bool,
+) {
+    let mut visited = HashSet::new();
+    if let Ok(meta) = fs::metadata(root) {
+        visited.insert((meta.dev(), meta.ino()));
+    }
+
+    let mut dirs = vec![(root.to_path_buf(), 0)];
+    let mut batch = Vec::new();
+    let mut found = 0;
+
+    'walk: while let Some((dir, depth)) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            if cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+
+            let path = entry.path();
+            // Symlinks are followed, directories are only walked once so a
+            // link to a parent doesn't loop forever
+            let meta = match fs::metadata(&path) {
+                Ok(meta) => meta,
+                Err(_) => continue,
+            };
+
+            if meta.is_dir() {
+                let name = entry.file_name();
+                let skipped = options
+                    .skip_dirs
+                    .iter()
+                    .any(|skip| name.to_str() == Some(skip));
+                if skipped || depth >= options.max_depth {
+                    continue;
+                }
+                if !visited.insert((meta.dev(), meta.ino())) {
+                    continue;
+                }
+                dirs.push((path, depth + 1));
+            } else {
+                let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+                batch.push(relative);
+                found += 1;
+
+                if batch.len() >= options.batch_size && !send(std::mem::take(&mut batch)) {
+                    return;
+                }
+                if found >= options.max_results {
+                    break 'walk;
+                }
+            }
+        }
+    }
+
+    if !batch.is_empty() {
+        send(batch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::symlink, time::Duration};
+
+    use super::*;
+
+    /// ```text
+    /// root/
+    ///   a.txt
+    ///   src/
+    ///     b.rs
+    ///     nested/
+    ///       c.rs
+    ///       loop -> ../..
+    ///   target/
+    ///     skipped.o
+    /// ```
+    fn tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join("a.txt"), "").unwrap();
+        fs::write(root.join("src/b.rs"), "").unwrap();
+        fs::write(root.join("src/nested/c.rs"), "").unwrap();
+        fs::write(root.join("target/skipped.o"), "").unwrap();
+        symlink("../..", root.join("src/nested/loop")).unwrap();
+        root
+    }
+
+    fn walk_all(root: &Path, options: &WalkOptions) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        walk(root, options, &AtomicBool::new(false), |batch| {
+            paths.extend(batch);
+            true
+        });
+        paths.sort();
+        paths
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn symlink_cycle() {
+        let root = tree("glyph_walk_cycle");
+        assert_eq!(
+            walk_all(&root, &WalkOptions::default()),
+            paths(&["a.txt", "src/b.rs", "src/nested/c.rs"])
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let root = tree("glyph_walk_limits");
+        let options = WalkOptions {
+            max_depth: 1,
+            skip_dirs: Vec::new(),
+            ..WalkOptions::default()
+        };
+        assert_eq!(
+            walk_all(&root, &options),
+            paths(&["a.txt", "src/b.rs", "target/skipped.o"])
+        );
+
+        let options = WalkOptions {
+            max_results: 2,
+            batch_size: 1,
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk_all(&root, &options).len(), 2);
+    }
+
+    #[test]
+    fn cancel() {
+        let root = std::env::temp_dir().join("glyph_walk_cancel");
+        let _ = fs::remove_dir_all(&root);
+        for i in 0..50 {
+            let dir = root.join(i.to_string());
+            fs::create_dir_all(&dir).unwrap();
+            for j in 0..10 {
+                fs::write(dir.join(j.to_string()), "").unwrap();
+            }
+        }
+        let options = WalkOptions {
+            batch_size: 1,
+            ..WalkOptions::default()
+        };
+
+        // Nothing else is sent once the picker is closed
+        let cancelled = AtomicBool::new(false);
+        let mut batches = 0;
+        walk(&root, &options, &cancelled, |_| {
+            batches += 1;
+            cancelled.store(true, Ordering::Relaxed);
+            true
+        });
+        assert_eq!(batches, 1);
+
+        let walk = Walk::start(root.clone(), options);
+        while matches!(walk.take_paths(), Some(paths) if paths.is_empty()) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            walk.cancel();
+            tx.send(()).unwrap();
+        });
+        assert!(rx.recv_timeout(Duration::from_secs(1)).is_ok());
+
+        // Walking every file works
+        let walk = Walk::start(root, WalkOptions::default());
+        let mut count = 0;
+        while let Some(paths) = walk.take_paths() {
+            count += paths.len();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(count, 500);
+    }
+}
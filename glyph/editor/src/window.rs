@@ -1,23 +1,38 @@
 use std::{
-    ffi::{c_void, CString},
-    mem,
+    cell::RefCell,
+    collections::BTreeMap,
+    ffi::{c_void, CString, OsStr},
+    fs, io, mem,
+    ops::Range,
+    path::{Path, PathBuf},
     ptr::null,
+    rc::Rc,
     sync::{Arc, RwLock},
 };
 
 use gl::types::{GLint, GLsizeiptr, GLuint, GLvoid};
-use lsp::{Client, Diagnostics, LspSender};
-use once_cell::sync::Lazy;
+use lsp::{
+    Client, Diagnostic, DiagnosticSeverity, Diagnostics, LspSender, MessageType, Position,
+    ServerMessage,
+};
+use ropey::RopeSlice;
 use sdl2::{
-    event::Event,
+    clipboard::ClipboardUtil,
+    event::{Event, WindowEvent},
     keyboard::{Keycode, Mod},
+    mouse::MouseButton,
 };
-use syntax::tree_sitter_highlight::{HighlightConfiguration, HighlightEvent, Highlighter};
-use syntax::Highlight;
+use syntax::{tree_sitter_highlight::HighlightConfiguration, Highlight};
+use unicode_width::UnicodeWidthChar;
 
 use crate::{
-    atlas::Atlas, Color, Editor, EditorEvent, EventResult, GLProgram, Shader, ThemeType,
-    WindowFrameKind, ERROR_RED, SCREEN_HEIGHT, SCREEN_WIDTH,
+    atlas::{Atlas, AtlasSet, FontStyle, Glyph},
+    default_theme, display_path, resolve_path,
+    vim::ExCmd,
+    BufferId, Buffers, Clipboard, Color, CommandError, DiagnosticPopup, Editor, EditorConfig,
+    EditorEvent, EventResult, GLProgram, IndentSettings, LineEnding, Mode, NoWrap, SelectUnit,
+    Shader, SignNamespace, SyntaxHighlighter, ThemeType, WindowFrameKind, WordWrap, CHANGED_GRAY,
+    ERROR_RED, SCREEN_HEIGHT, SCREEN_WIDTH, WRAP_INDENT,
 };
 
 #[repr(C)]
@@ -36,129 +51,702 @@ struct Point3 {
     z: f32,
 }
 
-impl Point3 {
+impl Default for Point3 {
+    fn default() -> Self {
+        Self {
+            x: f32::MAX,
+            y: Default::default(),
+            z: Default::default(),
+        }
+    }
+}
+
+/// Vertices of text and their colors, with the texture each run of them is
+/// drawn from. A new run starts where a glyph is on another atlas page
+#[derive(Default)]
+struct TextVertices {
+    coords: Vec<Point>,
+    colors: Vec<Color>,
+    runs: Vec<(GLuint, Range<usize>)>,
+}
+
+// Padding between the edges of a pane and its text, scaled like the text
+const PADDING_X: f32 = 8.0;
+const PADDING_Y: f32 = 50.0;
+
+// The width of the cursor in insert mode, in pixels like the padding
+const CURSOR_BAR_WIDTH: f32 = 2.0;
+
+// How long a message from the language server stays in the status line
+const SERVER_MESSAGE_MS: u32 = 5000;
+
+// Inlay hints are asked for again once typing stops for this long
+const HINTS_IDLE_MS: u32 = 300;
+
+// Popups wrap their text at this many columns, or the pane's width if it's less
+const POPUP_MAX_COLS: usize = 80;
+
+// The font size of inlay hints, as a share of the text's
+const HINT_FONT_SCALE: f32 = 0.8;
+
+// How much zooming in or out changes the font size, and how far it goes
+const FONT_SIZE_STEP: u32 = 4;
+const MIN_FONT_SIZE: u32 = 8;
+const MAX_FONT_SIZE: u32 = 128;
+
+/// The font the text is drawn in
+#[derive(Clone, Debug)]
+pub struct FontOptions {
+    /// Relative paths are from the executable's directory or the ones above
+    /// it, see `resolve`
+    pub path: PathBuf,
+    /// How tall the glyphs are rasterized, in pixels
+    pub size: u32,
+}
+
+impl Default for FontOptions {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("fonts/FiraCode.ttf"),
+            size: 48,
+        }
+    }
+}
+
+impl FontOptions {
+    /// Where the font file is, `None` if it can't be found. The working
+    /// directory isn't searched so it doesn't matter where glyph is run from
+    pub fn resolve(&self) -> Option<PathBuf> {
+        let exe = std::env::current_exe().ok();
+        // `target/debug` when run with cargo, so the directories above it too
+        let dirs: Vec<&Path> = exe.iter().flat_map(|exe| exe.ancestors().skip(1)).collect();
+        resolve_path(&self.path, &dirs)
+    }
+
+    /// The text's and inlay hints' atlases
+    fn atlases(&self, text_tex: GLint, hint_tex: GLint) -> Result<(AtlasSet, Atlas), String> {
+        let hint_size = (self.size as f32 * HINT_FONT_SCALE) as u32;
+        Ok((
+            AtlasSet::new(&self.path, self.size, text_tex)?,
+            Atlas::new(&self.path, hint_size.max(1), hint_tex, FontStyle::Normal)?,
+        ))
+    }
+}
+
+/// The area of the screen a pane draws in, in normalized device coordinates
+/// with (x, y) being the top left corner
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl Viewport {
+    pub const FULL: Viewport = Viewport {
+        x: -1.0,
+        y: 1.0,
+        w: 2.0,
+        h: 2.0,
+    };
+
+    fn split(&self, kind: SplitKind) -> (Viewport, Viewport) {
+        match kind {
+            SplitKind::Horizontal => {
+                let h = self.h / 2.0;
+                (
+                    Viewport { h, ..*self },
+                    Viewport {
+                        y: self.y - h,
+                        h,
+                        ..*self
+                    },
+                )
+            }
+            SplitKind::Vertical => {
+                let w = self.w / 2.0;
+                (
+                    Viewport { w, ..*self },
+                    Viewport {
+                        x: self.x + w,
+                        w,
+                        ..*self
+                    },
+                )
+            }
+        }
+    }
+
+    /// Lines of text that fit above the status line, on a window
+    /// `screen_height` pixels high
+    fn lines(&self, line_height: f32, screen_height: u32) -> usize {
+        let h = screen_height as f32 * self.h - line_height;
+        (h / line_height).max(0.0) as usize
+    }
+
     #[inline]
-    fn is_null(&self) -> bool {
-        self.x == f32::MAX
+    fn right(&self) -> f32 {
+        self.x + self.w
     }
 
     #[inline]
-    fn null() -> Point3 {
-        Point3 {
-            x: f32::MAX,
-            y: f32::MAX,
-            z: f32::MAX,
+    fn bottom(&self) -> f32 {
+        self.y - self.h
+    }
+
+    /// Whether the point `(x, y)` is inside, the top and left edges are
+    fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.right() && y <= self.y && y > self.bottom()
+    }
+
+    /// Whether `other` sits right next to this viewport in the given direction
+    fn is_neighbour(&self, other: &Viewport, direction: Direction) -> bool {
+        const EPSILON: f32 = 0.0001;
+        let overlaps_x = other.x < self.right() && other.right() > self.x;
+        let overlaps_y = other.bottom() < self.y && other.y > self.bottom();
+        match direction {
+            Direction::Left => (other.right() - self.x).abs() < EPSILON && overlaps_y,
+            Direction::Right => (other.x - self.right()).abs() < EPSILON && overlaps_y,
+            Direction::Up => (other.bottom() - self.y).abs() < EPSILON && overlaps_x,
+            Direction::Down => (other.y - self.bottom()).abs() < EPSILON && overlaps_x,
         }
     }
 }
 
-impl Default for Point3 {
-    fn default() -> Self {
+/// Horizontal splits stack panes on top of each other, vertical
+/// splits put them side by side. Same as Vim's `:split` and `:vsplit`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitKind {
+    Horizontal,
+    Vertical,
+}
+
+/// What the gutter shows next to each line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineNumberMode {
+    None,
+    Absolute,
+    /// The distance from the cursor line, which is 0
+    Relative,
+    /// The distance from the cursor line, which has its own number. Vim's
+    /// `number` and `relativenumber` together
+    Hybrid,
+}
+
+impl LineNumberMode {
+    /// The number shown next to `line` with the cursor on `cursor_line`,
+    /// both 0-based
+    fn label(self, line: usize, cursor_line: usize) -> Option<usize> {
+        match self {
+            LineNumberMode::None => None,
+            LineNumberMode::Absolute => Some(line + 1),
+            LineNumberMode::Hybrid if line == cursor_line => Some(line + 1),
+            LineNumberMode::Relative | LineNumberMode::Hybrid => Some(line.abs_diff(cursor_line)),
+        }
+    }
+}
+
+/// Where the cursor was before opening another file, `Ctrl-O` goes back to it
+struct Jump {
+    path: PathBuf,
+    line: usize,
+    col: usize,
+}
+
+/// A buffer's editor and highlighting. Every pane showing the buffer shares
+/// them, so there's one text, path and language server version for it
+#[derive(Clone)]
+struct Document<'highlight> {
+    editor: Rc<RefCell<Editor>>,
+    highlighter: Rc<RefCell<SyntaxHighlighter<'highlight>>>,
+}
+
+impl<'highlight> Document<'highlight> {
+    fn new(editor: Editor, highlighter: SyntaxHighlighter<'highlight>) -> Self {
         Self {
-            x: f32::MAX,
-            y: Default::default(),
-            z: Default::default(),
+            editor: Rc::new(RefCell::new(editor)),
+            highlighter: Rc::new(RefCell::new(highlighter)),
         }
     }
 }
 
-const SX: f32 = 0.8 / SCREEN_WIDTH as f32;
-const SY: f32 = 0.8 / SCREEN_HEIGHT as f32;
+/// A buffer a pane showed before switching to another one, kept so switching
+/// back has the same text, undo history and scroll
+struct HiddenBuffer<'highlight> {
+    buffer: BufferId,
+    editor: Rc<RefCell<Editor>>,
+    highlighter: Rc<RefCell<SyntaxHighlighter<'highlight>>>,
+    y_offset: f32,
+    x_offset: f32,
+}
 
-const START_X: f32 = -1f32 + 8f32 * SX;
-const START_Y: f32 = 1f32 - 50f32 * SY;
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Direction {
+    Left,
+    Down,
+    Up,
+    Right,
+}
 
 pub struct Window<'theme, 'highlight> {
     // Graphics
-    atlas: Atlas,
+    atlases: AtlasSet,
+    // What the atlases were rasterized from, for new panes and zooming
+    font: FontOptions,
     text_shader: TextShaderProgram,
     cursor_shader: CursorShaderProgram,
+    cursor_line_shader: CursorLineShaderProgram,
+    indent_guides_shader: IndentGuidesShaderProgram,
     highlight_shader: HighlightShaderProgram,
     diagnostic_shader: DiagnosticShaderProgram,
-    editor: Editor,
-    text_coords: Vec<Point>,
-    text_colors: Vec<Color>,
+    sign_shader: DiagnosticShaderProgram,
+    line_number_shader: TextShaderProgram,
+    status_shader: StatusLineShaderProgram,
+    // Inlay hints are drawn smaller than the text
+    hint_atlas: Atlas,
+    hint_shader: TextShaderProgram,
+    // Shared with the other panes showing the buffer
+    editor: Rc<RefCell<Editor>>,
+    text_vertices: TextVertices,
+    // The lines `text_vertices` has, the ones on screen and a page either
+    // side of them. Their chars are the only ones loaded into the atlases
+    queued_lines: Range<usize>,
     cursor_coords: [Point3; 6],
+    cursor_line_coords: [Point3; 6],
+    indent_guide_coords: Vec<Point3>,
     highlight_coords: Vec<Point3>,
+    // A rectangle for each row of each search match in the queued lines.
+    // `search_matches` has the chars of each match and its vertices,
+    // `current_match` the vertices of the one under the cursor. `search_key`
+    // is the revision, pattern and lines they were found for
+    search_coords: Vec<Point3>,
+    search_matches: Vec<(Range<usize>, Range<usize>)>,
+    current_match: Range<usize>,
+    search_key: Option<(u64, String, Range<usize>)>,
     diagnostics_coords: Vec<Point3>,
     diagnostics_colors: Vec<Color>,
+    signs_coords: Vec<Point3>,
+    signs_colors: Vec<Color>,
+    line_number_mode: LineNumberMode,
+    // Whether the line the cursor is on gets a background, like Vim's
+    // `cursorline`
+    cursor_line: bool,
+    line_number_vertices: TextVertices,
+    status_coords: Vec<Point3>,
+    status_colors: Vec<Color>,
+    status_text_vertices: TextVertices,
+    popup_coords: Vec<Point3>,
+    popup_colors: Vec<Color>,
+    popup_text_vertices: TextVertices,
+    hint_vertices: TextVertices,
     y_offset: f32,
     x_offset: f32,
     text_height: f32,
     text_width: f32,
+    // Lines longer than this many columns are wrapped onto more rows
+    wrap_col: Option<u32>,
+    // Wrap at the right edge of the pane, `wrap_col` follows its width
+    wrap: bool,
+    // The row each line starts at, with the total rows at the end. Only
+    // kept while wrapping, otherwise lines and rows are the same
+    line_rows: Vec<usize>,
     last_stroke: u32, // Time since last stroke in ms
 
     // Syntax highlighting
     theme: &'theme ThemeType,
-    highlighter: Highlighter,
-    highlight_cfg: &'highlight Lazy<HighlightConfiguration>,
+    highlighter: Rc<RefCell<SyntaxHighlighter<'highlight>>>,
     text_changed: bool,
     cursor_changed: bool,
+    // The editor's revision and cursor the queued geometry was built from
+    drawn_revision: u64,
+    drawn_cursor: (usize, usize),
+    // The revisions of the text and hint atlases, the texture coordinates
+    // of every glyph move when one grows
+    drawn_atlas: (u64, u64),
+
+    // The buffer this pane shows, shared with the other panes along with the
+    // document of every open buffer
+    buffers: Rc<RefCell<Buffers>>,
+    documents: Rc<RefCell<BTreeMap<BufferId, Document<'highlight>>>>,
+    buffer: BufferId,
+    // The other buffers opened in this pane
+    hidden: Vec<HiddenBuffer<'highlight>>,
+    // The buffer shown before this one, for `Ctrl-6`
+    alternate: Option<BufferId>,
+    jumps: Vec<Jump>,
+    // Shown in the status line until the next key
+    message: Option<String>,
+    // Takes the keys while it's open, see `popup_event`
+    popup: Option<DiagnosticPopup>,
+    // Given to the editor of every buffer
+    clipboard: Option<Rc<dyn Clipboard>>,
+    // The user commands from `define_commands`, for the editors of buffers
+    // opened later
+    commands: String,
 
     // LSP
     diagnostics: Arc<RwLock<Diagnostics>>,
     lsp_send: LspSender,
     last_clock: u64,
+    // The clock of the semantic tokens the highlighter has
+    semantic_clock: u64,
+    // The clock of the inlay hints last queued
+    hint_clock: u64,
+    // The text changed since the inlay hints were asked for
+    hints_stale: bool,
+    server_messages: Arc<RwLock<Vec<ServerMessage>>>,
+    seen_server_messages: usize,
+    // The latest server message and the ticks it came in at, shown in the
+    // status line for `SERVER_MESSAGE_MS`
+    server_message: Option<(ServerMessage, u32)>,
+
+    // Splits, a window with children only lays them out and forwards events
+    // to the focused one
+    viewport: Viewport,
+    start_x: f32,
+    start_y: f32,
+    // The size of the whole window in pixels, and how much a pixel of the
+    // font is in normalized device coordinates
+    screen_width: u32,
+    screen_height: u32,
+    sx: f32,
+    sy: f32,
+    focused: bool,
+    children: Option<
+        Box<(
+            Window<'theme, 'highlight>,
+            Window<'theme, 'highlight>,
+            SplitKind,
+        )>,
+    >,
+    // Waiting for the key after `Ctrl-W`
+    window_cmd_pending: bool,
 }
 
 impl<'theme, 'highlight> Window<'theme, 'highlight> {
+    /// `theme` takes precedence over the theme file in the config directory,
+    /// without either the theme is GitHub Dark. `config.font_path` is used
+    /// as it is, it should already be resolved
     pub fn new(
         initial_text: Option<String>,
-        theme: &'theme ThemeType,
+        path: Option<&Path>,
+        theme: Option<&'theme ThemeType>,
+        config: &EditorConfig,
         lsp_client: &Client,
     ) -> Self {
-        let font_path = "./fonts/FiraCode.ttf";
+        let theme = theme.unwrap_or_else(|| default_theme());
+        let highlight_cfg = highlight_config(path);
+
+        let mut buffers = Buffers::new();
+        let buffer = buffers.open(path);
+
+        let mut editor = Editor::with_text(initial_text);
+        editor.set_path(path);
+        editor.configure_lsp(lsp_client.sender());
+        editor.set_language(highlight_cfg.map(|cfg| cfg.language));
+        let document = Document::new(editor, SyntaxHighlighter::new(theme, highlight_cfg));
+        let documents = BTreeMap::from([(buffer, document.clone())]);
+
+        let mut window = Self::pane(
+            document,
+            theme,
+            config.font(),
+            Rc::new(RefCell::new(buffers)),
+            Rc::new(RefCell::new(documents)),
+            buffer,
+            lsp_client.diagnostics().clone(),
+            lsp_client.sender().clone(),
+        );
+        window.set_cursor_line(config.cursor_line);
+        window.set_indent(config.indent());
+        window.set_auto_pairs(config.auto_pairs);
+        if config.wrap {
+            window.set_wrap(true);
+        }
+        if let Err(err) = window.define_commands(&config.commands) {
+            window.message = Some(err.to_string());
+        }
+        window.watch_server_messages(lsp_client.messages().clone());
+        window.open_document();
+        window.request_semantic_tokens();
+        window.request_inlay_hints();
+        window
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn pane(
+        document: Document<'highlight>,
+        theme: &'theme ThemeType,
+        font: FontOptions,
+        buffers: Rc<RefCell<Buffers>>,
+        documents: Rc<RefCell<BTreeMap<BufferId, Document<'highlight>>>>,
+        buffer: BufferId,
+        diagnostics: Arc<RwLock<Diagnostics>>,
+        lsp_send: LspSender,
+    ) -> Self {
+        let viewport = Viewport::FULL;
 
         let text_shader = TextShaderProgram::default();
-        let atlas = Atlas::new(font_path, 48, text_shader.uniform_tex).unwrap();
         let cursor_shader = CursorShaderProgram::default();
+        let cursor_line_shader = CursorLineShaderProgram::default();
+        let indent_guides_shader = IndentGuidesShaderProgram::default();
         let highlight_shader = HighlightShaderProgram::default();
         let diagnostic_shader = DiagnosticShaderProgram::default();
+        let sign_shader = DiagnosticShaderProgram::default();
+        let line_number_shader = TextShaderProgram::default();
+        let status_shader = StatusLineShaderProgram::default();
+        let hint_shader = TextShaderProgram::default();
+        let (atlases, hint_atlas) = font
+            .atlases(text_shader.uniform_tex, hint_shader.uniform_tex)
+            .unwrap();
 
-        let highlighter = Highlighter::new();
-
-        let mut editor = Editor::with_text(initial_text);
-        editor.configure_lsp(lsp_client);
+        document
+            .editor
+            .borrow_mut()
+            .set_page_lines(viewport.lines(atlases.normal.max_h, SCREEN_HEIGHT));
+        let (sx, sy) = text_scale(SCREEN_WIDTH, SCREEN_HEIGHT);
 
         Self {
-            atlas,
+            atlases,
+            font,
             text_shader,
             cursor_shader,
+            cursor_line_shader,
+            indent_guides_shader,
             highlight_shader,
             diagnostic_shader,
-            editor,
-            text_coords: Vec::new(),
-            text_colors: Vec::new(),
+            sign_shader,
+            line_number_shader,
+            status_shader,
+            hint_atlas,
+            hint_shader,
+            editor: document.editor,
+            text_vertices: TextVertices::default(),
+            queued_lines: 0..0,
             cursor_coords: Default::default(),
+            cursor_line_coords: Default::default(),
+            indent_guide_coords: Vec::new(),
             highlight_coords: Default::default(),
+            search_coords: Vec::new(),
+            search_matches: Vec::new(),
+            current_match: 0..0,
+            search_key: None,
             diagnostics_coords: Default::default(),
             diagnostics_colors: Vec::new(),
+            signs_coords: Vec::new(),
+            signs_colors: Vec::new(),
+            line_number_mode: LineNumberMode::Absolute,
+            cursor_line: true,
+            line_number_vertices: TextVertices::default(),
+            status_coords: Vec::new(),
+            status_colors: Vec::new(),
+            status_text_vertices: TextVertices::default(),
+            popup_coords: Vec::new(),
+            popup_colors: Vec::new(),
+            popup_text_vertices: TextVertices::default(),
+            hint_vertices: TextVertices::default(),
             y_offset: 0.0,
             x_offset: 0.0,
             text_height: 0.0,
             text_width: 0.0,
+            wrap_col: None,
+            wrap: false,
+            line_rows: Vec::new(),
             last_stroke: 0,
 
             theme,
-            highlighter,
-            highlight_cfg: &syntax::RUST_CFG,
+            highlighter: document.highlighter,
             text_changed: false,
             cursor_changed: false,
+            drawn_revision: u64::MAX,
+            drawn_atlas: (0, 0),
+            drawn_cursor: (usize::MAX, usize::MAX),
 
-            diagnostics: lsp_client.diagnostics().clone(),
-            lsp_send: lsp_client.sender().clone(),
+            buffers,
+            documents,
+            buffer,
+            hidden: Vec::new(),
+            alternate: None,
+            jumps: Vec::new(),
+            message: None,
+            popup: None,
+            clipboard: None,
+            commands: String::new(),
+
+            diagnostics,
+            lsp_send,
             last_clock: 0,
+            semantic_clock: 0,
+            hint_clock: 0,
+            hints_stale: false,
+            server_messages: Default::default(),
+            seen_server_messages: 0,
+            server_message: None,
+
+            viewport,
+            start_x: viewport.x + PADDING_X * sx,
+            start_y: viewport.y - PADDING_Y * sy,
+            screen_width: SCREEN_WIDTH,
+            screen_height: SCREEN_HEIGHT,
+            sx,
+            sy,
+            focused: true,
+            children: None,
+            window_cmd_pending: false,
         }
     }
 
     pub fn event(&mut self, event: Event, time: u32) -> EventResult {
         match event {
             Event::Quit { .. } => EventResult::Quit,
+            // Copy in visual mode, quit everywhere else
+            Event::KeyDown {
+                keycode: Some(Keycode::C),
+                keymod,
+                ..
+            } if keymod == Mod::LCTRLMOD
+                && self.focused_pane().editor.borrow().mode() == Mode::Visual =>
+            {
+                let event = self.focused_pane().editor.borrow_mut().copy_selection();
+                self.focused_pane().handle_editor_event(event, time)
+            }
+            Event::Window {
+                win_event: WindowEvent::SizeChanged(width, height),
+                ..
+            } => {
+                self.resize(width as u32, height as u32);
+                EventResult::Draw
+            }
+            // Nothing changed but what was drawn is gone, uncovering the
+            // window or restoring it shouldn't wait for a key to repaint
+            Event::Window {
+                win_event:
+                    WindowEvent::Exposed
+                    | WindowEvent::Resized(..)
+                    | WindowEvent::Restored
+                    | WindowEvent::Shown,
+                ..
+            } => EventResult::Draw,
             Event::KeyDown {
                 keycode: Some(Keycode::C),
                 keymod,
                 ..
             } if keymod == Mod::LCTRLMOD => EventResult::Quit,
+            Event::KeyDown {
+                keycode: Some(Keycode::S),
+                keymod,
+                ..
+            } if keymod == Mod::LCTRLMOD => {
+                let pane = self.focused_pane();
+                if let Err(err) = pane.save() {
+                    pane.message = Some(err);
+                }
+                EventResult::Draw
+            }
+            // `Ctrl-+` is `Ctrl-=` with shift on most keyboards
+            Event::KeyDown {
+                keycode: Some(keycode @ (Keycode::Equals | Keycode::Plus | Keycode::KpPlus)),
+                keymod,
+                ..
+            }
+            | Event::KeyDown {
+                keycode: Some(keycode @ (Keycode::Minus | Keycode::KpMinus)),
+                keymod,
+                ..
+            } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                let step = match keycode {
+                    Keycode::Minus | Keycode::KpMinus => -1,
+                    _ => 1,
+                };
+                let size = zoomed_font_size(self.font.size, step);
+                if size != self.font.size {
+                    self.set_font_size(size);
+                }
+                EventResult::Draw
+            }
+            _ if self.focused_pane().popup.is_some() => self.focused_pane().popup_event(event),
+            Event::MouseButtonDown {
+                mouse_btn: MouseButton::Left,
+                clicks,
+                x,
+                y,
+                ..
+            } => {
+                let unit = match clicks {
+                    0 | 1 => SelectUnit::Char,
+                    2 => SelectUnit::Word,
+                    _ => SelectUnit::Line,
+                };
+                self.click(x, y, unit, time)
+            }
+            // The selection stays after the button is let go
+            Event::MouseMotion {
+                mousestate, x, y, ..
+            } if mousestate.left() => self.drag(x, y, time),
+            // Insert mode uses `Ctrl-W` to delete words
+            Event::KeyDown {
+                keycode: Some(Keycode::W),
+                keymod,
+                ..
+            } if keymod == Mod::LCTRLMOD && !self.focused_pane().editor.borrow().is_insert() => {
+                self.window_cmd_pending = true;
+                EventResult::Nothing
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::O),
+                keymod,
+                ..
+            } if keymod == Mod::LCTRLMOD && !self.focused_pane().editor.borrow().is_insert() => {
+                self.focused_pane().jump_back();
+                EventResult::Draw
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::Num6),
+                keymod,
+                ..
+            } if keymod == Mod::LCTRLMOD && !self.focused_pane().editor.borrow().is_insert() => {
+                self.focused_pane().alternate_buffer();
+                EventResult::Draw
+            }
+            _ if self.window_cmd_pending => self.window_cmd(event),
+            _ => self.focused_pane().pane_event(event, time),
+        }
+    }
+
+    /// Handle the key after `Ctrl-W`, like Vim these switch focus with h/j/k/l
+    /// and split the focused pane with s/v. `d` shows the diagnostic under the
+    /// cursor like Neovim
+    fn window_cmd(&mut self, event: Event) -> EventResult {
+        let text = match event {
+            Event::TextInput { text, .. } => text,
+            Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => {
+                self.window_cmd_pending = false;
+                return EventResult::Nothing;
+            }
+            // The text input for the key follows its key down
+            _ => return EventResult::Nothing,
+        };
+        self.window_cmd_pending = false;
+
+        match text.as_str() {
+            "h" => self.focus(Direction::Left),
+            "j" => self.focus(Direction::Down),
+            "k" => self.focus(Direction::Up),
+            "l" => self.focus(Direction::Right),
+            "s" => self.focused_pane().split_horizontal(),
+            "v" => self.focused_pane().split_vertical(),
+            "d" => self.focused_pane().open_diagnostic_popup(),
+            _ => return EventResult::Nothing,
+        }
+        EventResult::Draw
+    }
+
+    fn pane_event(&mut self, event: Event, time: u32) -> EventResult {
+        match event {
             Event::MouseWheel { x, y, .. } => {
                 if x.abs() > y.abs() {
                     self.scroll_x(x as f32 * -4.0);
@@ -166,147 +754,1323 @@ impl<'theme, 'highlight> Window<'theme, 'highlight> {
                     self.scroll_y(y as f32);
                 }
                 self.queue_cursor();
+                self.queue_indent_guides();
                 EventResult::Scroll
             }
             _ => {
-                let evt = self.editor.event(event);
+                if matches!(event, Event::KeyDown { .. } | Event::TextInput { .. }) {
+                    self.message = None;
+                }
+                // `gj` and paging go by this pane's rows, not the ones of
+                // the last pane that had the shared editor
+                self.install_wrap();
+                self.editor.borrow_mut().set_page_lines(
+                    self.viewport
+                        .lines(self.atlases.normal.max_h, self.screen_height),
+                );
+                let evt = self.editor.borrow_mut().event(event);
                 self.handle_editor_event(evt, time)
             }
         }
     }
-}
 
-// This impl contains utilities
-impl<'theme, 'highlight> Window<'theme, 'highlight> {
-    fn scroll_y(&mut self, mut amount: f32) {
-        let pix_amount = amount * self.atlas.max_h;
-        amount *= -1.0;
-        match pix_amount > 0.0 {
-            // Scrolling up
-            true => {
-                if self.y_offset + pix_amount >= 0.0 {
-                    self.y_offset = 0.0;
-                } else {
-                    self.y_offset += pix_amount;
-                    self.editor.incr_line(amount as i32)
+    /// Keys while the popup is open. A number jumps to that related location,
+    /// `j`/`k` select one for `Enter` to jump to, any other key closes it
+    fn popup_event(&mut self, event: Event) -> EventResult {
+        let Some(popup) = &mut self.popup else {
+            return EventResult::Nothing;
+        };
+        let jump = match event {
+            Event::KeyDown {
+                keycode: Some(Keycode::Return),
+                ..
+            } => Some(popup.selected),
+            Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => None,
+            Event::TextInput { text, .. } => match text.as_str() {
+                "j" => {
+                    popup.select(1);
+                    return EventResult::Draw;
                 }
-            }
-            // Scrolling down
-            false => {
-                if -1.0 * (self.y_offset + pix_amount) >= self.text_height {
-                    self.y_offset = self.text_height * -1.0;
-                    let len = self.editor.lines().len();
-                    if len == 0 {
-                        self.editor.incr_line(0)
-                    } else {
-                        self.editor.set_line(len - 1);
-                    }
-                } else {
-                    self.y_offset += pix_amount;
-                    self.editor.incr_line(amount as i32)
+                "k" => {
+                    popup.select(-1);
+                    return EventResult::Draw;
                 }
-            }
+                _ => text.parse::<usize>().ok().and_then(|n| n.checked_sub(1)),
+            },
+            // The text input for the key follows its key down
+            _ => return EventResult::Nothing,
+        };
+
+        let location = jump.and_then(|i| popup.locations.get(i).cloned());
+        self.popup = None;
+        if let Some(location) = location {
+            self.open_file(&location.path, location.line, location.col, false);
         }
+        EventResult::Draw
     }
 
-    fn scroll_x(&mut self, amount: f32) {
-        match amount > 0.0 {
-            true => {
-                if self.x_offset + amount >= 0.0 {
-                    self.x_offset = 0.0;
-                } else {
-                    self.x_offset += amount;
-                }
-            }
-            false => {
-                if -1.0 * (self.x_offset + amount) >= self.text_width {
-                    self.x_offset = self.text_width * -1.0;
-                } else {
-                    self.x_offset += amount;
-                }
-            }
-        }
+    /// `Ctrl-W d`, open a popup with the whole message of the diagnostic under
+    /// the cursor and the locations related to it
+    fn open_diagnostic_popup(&mut self) {
+        let diag = {
+            let diagnostics = self.diagnostics.read().unwrap();
+            let buffers = self.buffers.borrow();
+            diagnostic_at(
+                buffer_diagnostics(&diagnostics, &buffers, self.buffer),
+                self.editor.borrow().line(),
+                self.editor.borrow().cursor(),
+            )
+            .cloned()
+        };
+        let Some(diag) = diag else {
+            self.message = Some("No diagnostic under cursor".into());
+            return;
+        };
+
+        let column_w = self.atlases.normal.max_w * self.sx;
+        let cols =
+            ((self.viewport.right() - self.start_x - PADDING_X * self.sx) / column_w) as usize;
+        // Leave a column of padding on each side
+        let width = cols.saturating_sub(2).min(POPUP_MAX_COLS);
+        let dir = std::env::current_dir().unwrap_or_default();
+        self.popup = Some(DiagnosticPopup::new(&diag, width, &dir));
     }
 }
 
-// This impl contains graphics functions
+// This impl contains opening files
 impl<'theme, 'highlight> Window<'theme, 'highlight> {
-    #[inline]
-    fn handle_editor_event(&mut self, evt: EditorEvent, time: u32) -> EventResult {
-        match evt {
-            EditorEvent::DrawText => {
-                self.text_changed = true;
-                self.last_stroke = time;
-                self.render_text();
-                EventResult::Draw
+    /// `gf`, relative paths are looked for next to the buffer's file then in
+    /// the directory glyph was started in
+    fn goto_file(&mut self) {
+        let Some(location) = self.editor.borrow_mut().take_goto_file() else {
+            self.message = Some("No file name under cursor".into());
+            return;
+        };
+
+        let buffer_dir = self
+            .buffers
+            .borrow()
+            .path(self.buffer)
+            .and_then(Path::parent)
+            .map(Path::to_path_buf);
+        let root = std::env::current_dir().ok();
+        let dirs: Vec<&Path> = buffer_dir
+            .iter()
+            .chain(root.iter())
+            .map(|dir| dir.as_path())
+            .collect();
+
+        match resolve_path(&location.path, &dirs) {
+            Some(path) => {
+                // The position is 1-based like compiler output
+                let line = location.line.unwrap_or(1).saturating_sub(1);
+                let col = location.col.unwrap_or(1).saturating_sub(1);
+                self.open_file(&path, line, col, false);
             }
-            EditorEvent::DrawCursor => {
-                self.cursor_changed = true;
-                self.adjust_scroll();
-                self.queue_cursor();
-                EventResult::Draw
+            None => self.message = Some(format!("Can't find file \"{}\"", location.path.display())),
+        }
+    }
+
+    /// Go back to where the cursor was before the last file was opened
+    fn jump_back(&mut self) {
+        let Some(jump) = self.jumps.pop() else {
+            return;
+        };
+        let len = self.jumps.len();
+        if self.open_file(&jump.path, jump.line, jump.col, false) {
+            // Going back isn't a jump of its own
+            self.jumps.truncate(len);
+        } else {
+            self.jumps.push(jump);
+        }
+    }
+
+    /// Show the file at `path` in this pane with the cursor on `line` at `col`.
+    /// A file that doesn't exist is only created with `create`, same as `:e`,
+    /// otherwise it's an error instead of an empty buffer. Returns false if the
+    /// file wasn't opened, the reason is shown in the status line
+    pub fn open_file(&mut self, path: &Path, line: usize, col: usize, create: bool) -> bool {
+        // Files that are already open switch to their buffer instead of being
+        // read again, and a buffer open in another pane is shown with its
+        // text as it is there
+        let open = self.buffers.borrow().find_path(path);
+        if open == Some(self.buffer) || open.is_some_and(|buffer| self.is_hidden(buffer)) {
+            self.push_jump();
+            if let Some(buffer) = open {
+                self.switch_buffer(buffer);
             }
-            EditorEvent::DrawSelection => {
-                self.queue_selection(START_X, START_Y, SX, SY);
-                EventResult::Draw
+            self.editor.borrow_mut().goto(line, col);
+            self.handle_editor_event(EditorEvent::DrawCursor, self.last_stroke);
+            return true;
+        }
+        let shared =
+            open.and_then(|buffer| Some((buffer, self.documents.borrow().get(&buffer)?.clone())));
+        if let Some((buffer, document)) = shared {
+            self.push_jump();
+            self.show_buffer(HiddenBuffer {
+                buffer,
+                editor: document.editor,
+                highlighter: document.highlighter,
+                y_offset: 0.0,
+                x_offset: 0.0,
+            });
+            self.editor.borrow_mut().goto(line, col);
+            self.handle_editor_event(EditorEvent::DrawCursor, self.last_stroke);
+            return true;
+        }
+
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && create => String::new(),
+            Err(err) => {
+                self.message = Some(format!("Can't open \"{}\": {}", path.display(), err));
+                return false;
             }
-            EditorEvent::Multiple => {
-                let evts = self.editor.take_multiple_event_data();
-                let mut draw = false;
+        };
 
-                for evt in evts.into_iter() {
-                    if matches!(self.handle_editor_event(evt, time), EventResult::Draw) {
-                        draw = true;
-                    }
-                }
+        self.push_jump();
+        let buffer = self.buffers.borrow_mut().open(Some(path));
+        let highlight_cfg = highlight_config(Some(path));
 
-                if draw {
-                    EventResult::Draw
-                } else {
-                    EventResult::Nothing
-                }
-            }
+        let mut editor = Editor::with_text(Some(text));
+        editor.set_path(Some(path));
+        editor.configure_lsp(&self.lsp_send);
+        editor.set_indent(self.editor.borrow().indent());
+        editor.set_auto_pairs(self.editor.borrow().auto_pairs());
+        editor
+            .define_commands(&self.commands)
+            .expect("The commands were defined once already");
+        if let Some(clipboard) = &self.clipboard {
+            editor.set_clipboard(clipboard.clone());
+        }
+        editor.set_language(highlight_cfg.map(|cfg| cfg.language));
+        editor.set_mode(Mode::Normal);
+        editor.goto(line, col);
 
-            _ => EventResult::Nothing,
+        let document = Document::new(editor, SyntaxHighlighter::new(self.theme, highlight_cfg));
+        self.documents.borrow_mut().insert(buffer, document.clone());
+        self.show_buffer(HiddenBuffer {
+            buffer,
+            editor: document.editor,
+            highlighter: document.highlighter,
+            y_offset: 0.0,
+            x_offset: 0.0,
+        });
+        true
+    }
+
+    /// `:bn`, show the next buffer opened in this pane, after the last one
+    /// comes the first
+    pub fn next_buffer(&mut self) {
+        self.focused_pane().cycle_buffer(true)
+    }
+
+    /// `:bp`, show the previous buffer opened in this pane
+    pub fn prev_buffer(&mut self) {
+        self.focused_pane().cycle_buffer(false)
+    }
+
+    fn cycle_buffer(&mut self, forward: bool) {
+        let mut buffers: Vec<BufferId> = self.hidden.iter().map(|hidden| hidden.buffer).collect();
+        buffers.push(self.buffer);
+        let buffer = cycle_buffers(&buffers, self.buffer, forward);
+        self.switch_buffer(buffer);
+    }
+
+    /// `Ctrl-6`, go back to the buffer shown before this one
+    fn alternate_buffer(&mut self) {
+        match self.alternate.filter(|buffer| self.is_hidden(*buffer)) {
+            Some(buffer) => self.switch_buffer(buffer),
+            None => self.message = Some("No alternate file".into()),
         }
     }
 
-    pub fn render_text(&mut self) {
-        self.adjust_scroll();
-        self.queue_cursor();
-        let colors = self.queue_highlights();
-        self.queue_text(colors, -1f32 + 8f32 * SX, 1f32 - 50f32 * SY, SX, SY);
-        self.queue_selection(-1f32 + 8f32 * SX, 1f32 - 50f32 * SY, SX, SY)
+    fn is_hidden(&self, buffer: BufferId) -> bool {
+        self.hidden.iter().any(|hidden| hidden.buffer == buffer)
     }
 
-    pub fn queue_cursor(&mut self) {
-        let w = self.atlas.max_w * SX;
-        let real_h = self.atlas.max_h * SY;
-        let h = (self.atlas.max_h/*+ 5f32*/) * SY;
+    /// Show `buffer` if it's one of the hidden ones
+    fn switch_buffer(&mut self, buffer: BufferId) {
+        if let Some(i) = self
+            .hidden
+            .iter()
+            .position(|hidden| hidden.buffer == buffer)
+        {
+            let hidden = self.hidden.remove(i);
+            self.show_buffer(hidden);
+        }
+    }
 
-        let x = (-1f32 + 8f32 * SX)
-            + (self.editor.cursor() as f32 * (w/*+ self.atlas.glyphs[35].advance_x * SX*/));
-        let y = ((1f32 - 50f32 * SY) + real_h) - (self.editor.line() as f32 * real_h);
+    /// Hide the current buffer and show `next` in its place, with the view it
+    /// had when it was hidden
+    fn show_buffer(&mut self, mut next: HiddenBuffer<'highlight>) {
+        mem::swap(&mut self.editor, &mut next.editor);
+        mem::swap(&mut self.highlighter, &mut next.highlighter);
+        mem::swap(&mut self.y_offset, &mut next.y_offset);
+        mem::swap(&mut self.x_offset, &mut next.x_offset);
+        next.buffer = mem::replace(&mut self.buffer, next.buffer);
+        self.alternate = Some(next.buffer);
+        self.hidden.push(next);
+        self.open_document();
 
-        self.cursor_coords = [
-            // // bottom left
-            Point3 {
-                x,
-                y: y - h,
-                z: 0.0,
-            },
-            // top left
-            Point3 { x, y, z: 0.0 },
-            // top right
-            Point3 {
-                x: x + w,
-                y,
-                z: 0.0,
-            },
-            // bottom right
-            Point3 {
-                x: x + w,
-                y: y - h,
+        self.editor.borrow_mut().set_page_lines(
+            self.viewport
+                .lines(self.atlases.normal.max_h, self.screen_height),
+        );
+        // Queued again for the new buffer on the next frame
+        self.last_clock = 0;
+        self.hint_vertices.clear();
+        self.install_wrap();
+        self.render_text();
+        self.request_semantic_tokens();
+        self.request_inlay_hints();
+    }
+
+    /// What `<tab>` inserts and how wide tabs are drawn, in every pane and
+    /// buffer
+    pub fn set_indent(&mut self, indent: IndentSettings) {
+        if let Some(children) = &mut self.children {
+            children.0.set_indent(indent);
+            children.1.set_indent(indent);
+            return;
+        }
+        self.editor.borrow_mut().set_indent(indent);
+        for hidden in self.hidden.iter_mut() {
+            hidden.editor.borrow_mut().set_indent(indent);
+        }
+    }
+
+    /// Add user commands to every pane and buffer, and the ones opened after.
+    /// See [`UserCommands`] for the syntax
+    pub fn define_commands(&mut self, src: &str) -> Result<(), CommandError> {
+        if let Some(children) = &mut self.children {
+            children.0.define_commands(src)?;
+            return children.1.define_commands(src);
+        }
+        self.editor.borrow_mut().define_commands(src)?;
+        for hidden in self.hidden.iter_mut() {
+            hidden.editor.borrow_mut().define_commands(src)?;
+        }
+        self.commands.push_str(src);
+        self.commands.push('\n');
+        Ok(())
+    }
+
+    /// Whether typing an opening bracket or quote types the closing one too,
+    /// in every pane and buffer
+    pub fn set_auto_pairs(&mut self, enabled: bool) {
+        if let Some(children) = &mut self.children {
+            children.0.set_auto_pairs(enabled);
+            children.1.set_auto_pairs(enabled);
+            return;
+        }
+        self.editor.borrow_mut().set_auto_pairs(enabled);
+        for hidden in self.hidden.iter_mut() {
+            hidden.editor.borrow_mut().set_auto_pairs(enabled);
+        }
+    }
+
+    /// Copy yanks to the system clipboard and paste from it with `"+`, in
+    /// every pane and buffer
+    pub fn set_clipboard(&mut self, clipboard: Rc<dyn Clipboard>) {
+        if let Some(children) = &mut self.children {
+            children.0.set_clipboard(clipboard.clone());
+            children.1.set_clipboard(clipboard);
+            return;
+        }
+        self.editor.borrow_mut().set_clipboard(clipboard.clone());
+        for hidden in self.hidden.iter_mut() {
+            hidden.editor.borrow_mut().set_clipboard(clipboard.clone());
+        }
+        self.clipboard = Some(clipboard);
+    }
+
+    /// `Ctrl-S` or `:w`, write the buffer to its file. The error is the
+    /// message to show if it couldn't be written
+    fn save(&mut self) -> Result<(), String> {
+        let mut editor = self.editor.borrow_mut();
+        let result = editor.save().map_err(|err| match editor.path() {
+            Some(path) => format!("Can't write \"{}\": {}", path.display(), err),
+            None => err.to_string(),
+        });
+        drop(editor);
+        // Saving clears the changed lines
+        self.queue_signs();
+        result
+    }
+
+    /// Run the `:` command the editor stopped at. A user command goes on
+    /// after each of its `:` commands, up to the next one, and stops for good
+    /// at the first one that fails
+    fn ex_cmd(&mut self, time: u32) -> EventResult {
+        let mut result = EventResult::Nothing;
+        loop {
+            let Some(cmd) = self.editor.borrow_mut().take_ex_cmd() else {
+                break;
+            };
+            match self.run_ex_cmd(cmd, time) {
+                Ok(EventResult::Nothing) => {}
+                Ok(EventResult::Quit) => return EventResult::Quit,
+                Ok(evt) => result = evt,
+                Err(err) => {
+                    self.message = Some(err);
+                    self.editor.borrow_mut().cancel_command();
+                    // It may have changed the text before it stopped
+                    return self.handle_editor_event(EditorEvent::DrawText, time);
+                }
+            }
+            let evt = self.editor.borrow_mut().resume_command();
+            match evt {
+                EditorEvent::ExCmd | EditorEvent::Nothing => {}
+                evt => result = self.handle_editor_event(evt, time),
+            }
+        }
+        result
+    }
+
+    /// The buffer with changes `:q` would lose, this pane's first and then
+    /// the first opened of the ones in other panes or hidden
+    fn unsaved_buffer(&self) -> Option<BufferId> {
+        let documents = self.documents.borrow();
+        let modified: Vec<(BufferId, bool)> = documents
+            .iter()
+            .map(|(buffer, document)| (*buffer, document.editor.borrow().is_modified()))
+            .collect();
+        first_modified(&modified, self.buffer)
+    }
+
+    /// The path of `buffer` relative to the working directory, like the
+    /// status line shows it
+    fn buffer_name(&self, buffer: BufferId) -> String {
+        match self.buffers.borrow().path(buffer) {
+            Some(path) => match std::env::current_dir() {
+                Ok(cwd) => display_path(path, &cwd),
+                Err(_) => path.display().to_string(),
+            },
+            None => "[No Name]".to_string(),
+        }
+    }
+
+    /// Run a `:` command, names that aren't built in are user commands. The
+    /// error is the message to show
+    fn run_ex_cmd(&mut self, cmd: ExCmd, time: u32) -> Result<EventResult, String> {
+        match cmd {
+            ExCmd::Write => self.save().map(|_| EventResult::Draw),
+            ExCmd::Quit { force: false } => match self.unsaved_buffer() {
+                Some(buffer) if buffer == self.buffer => {
+                    Err("No write since last change (add ! to override)".into())
+                }
+                Some(buffer) => Err(format!(
+                    "No write since last change for buffer \"{}\"",
+                    self.buffer_name(buffer)
+                )),
+                None => Ok(EventResult::Quit),
+            },
+            ExCmd::Quit { force: true } => Ok(EventResult::Quit),
+            ExCmd::WriteQuit => self.save().map(|_| EventResult::Quit),
+            // The editor handles these itself
+            ExCmd::Line(_) | ExCmd::LastLine | ExCmd::RelativeLine(_) | ExCmd::NoHighlight => {
+                Ok(EventResult::Nothing)
+            }
+            ExCmd::Set(option) => self.set_option(&option).map(|_| EventResult::Draw),
+            ExCmd::Unknown(name) => {
+                let evt = self.editor.borrow_mut().run_command(&name);
+                match evt {
+                    Ok(evt) => Ok(self.handle_editor_event(evt, time)),
+                    Err(err) => Err(err.to_string()),
+                }
+            }
+        }
+    }
+
+    /// Remember where the cursor is for `Ctrl-O` to come back to
+    fn push_jump(&mut self) {
+        if let Some(current) = self.buffers.borrow().path(self.buffer) {
+            self.jumps.push(Jump {
+                path: current.to_path_buf(),
+                line: self.editor.borrow().line(),
+                col: self.editor.borrow().cursor(),
+            });
+        }
+    }
+}
+
+// This impl contains splits
+impl<'theme, 'highlight> Window<'theme, 'highlight> {
+    /// Split the focused pane into one on top of the other
+    pub fn split_horizontal(&mut self) {
+        self.split(SplitKind::Horizontal)
+    }
+
+    /// Split the focused pane into two side by side
+    pub fn split_vertical(&mut self) {
+        self.split(SplitKind::Vertical)
+    }
+
+    fn split(&mut self, kind: SplitKind) {
+        if let Some(children) = &mut self.children {
+            let (first, second, _) = &mut **children;
+            if first.has_focus() {
+                first.split(kind)
+            } else {
+                second.split(kind)
+            }
+            return;
+        }
+
+        // The first child takes over this pane, the second one shows the same
+        // buffer. They share its editor, so edits in either are in both and
+        // the file and language server see one text
+        let (first_viewport, second_viewport) = self.viewport.split(kind);
+        let document = Document {
+            editor: self.editor.clone(),
+            highlighter: self.highlighter.clone(),
+        };
+        let mut second = Self::pane(
+            document.clone(),
+            self.theme,
+            self.font.clone(),
+            self.buffers.clone(),
+            self.documents.clone(),
+            self.buffer,
+            self.diagnostics.clone(),
+            self.lsp_send.clone(),
+        );
+        second.set_screen_size(self.screen_width, self.screen_height);
+        second.set_viewport(second_viewport);
+        second.line_number_mode = self.line_number_mode;
+        second.cursor_line = self.cursor_line;
+        second.wrap = self.wrap;
+        second.wrap_col = self.wrap_col;
+        second.y_offset = self.y_offset;
+        second.x_offset = self.x_offset;
+        second.commands = self.commands.clone();
+        second.clipboard = self.clipboard.clone();
+        let mut first = Self::pane(
+            document,
+            self.theme,
+            self.font.clone(),
+            self.buffers.clone(),
+            self.documents.clone(),
+            self.buffer,
+            self.diagnostics.clone(),
+            self.lsp_send.clone(),
+        );
+        let viewport = self.viewport;
+        mem::swap(self, &mut first);
+        self.set_screen_size(first.screen_width, first.screen_height);
+        self.clipboard = first.clipboard.clone();
+        self.set_viewport(viewport);
+
+        second.watch_server_messages(first.server_messages.clone());
+
+        first.set_viewport(first_viewport);
+        first.focused = false;
+        second.focused = true;
+        first.render_text();
+        second.render_text();
+
+        self.focused = false;
+        self.children = Some(Box::new((first, second, kind)));
+    }
+
+    /// The window was resized to `width` by `height` pixels, the panes keep
+    /// their share of it and the text keeps its size
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if let Some(children) = &mut self.children {
+            children.0.resize(width, height);
+            children.1.resize(width, height);
+        }
+        self.set_screen_size(width, height);
+        self.set_viewport(self.viewport);
+        if self.children.is_none() {
+            // Diagnostics are queued again at the new scale
+            self.last_clock = 0;
+            self.render_text();
+        }
+    }
+
+    /// Rasterize the font again at `size` pixels in every pane, like when
+    /// zooming in. The size stays the same if the font can't be loaded
+    pub fn set_font_size(&mut self, size: u32) {
+        self.font.size = size;
+        if let Some(children) = &mut self.children {
+            children.0.set_font_size(size);
+            children.1.set_font_size(size);
+            return;
+        }
+
+        match self
+            .font
+            .atlases(self.text_shader.uniform_tex, self.hint_shader.uniform_tex)
+        {
+            Ok((atlases, hint_atlas)) => {
+                self.atlases = atlases;
+                self.hint_atlas = hint_atlas;
+            }
+            Err(err) => {
+                self.set_message(format!("Failed to load the font: {}", err));
+                return;
+            }
+        }
+        // The gutter and page are measured in glyphs
+        self.set_viewport(self.viewport);
+        self.render_text();
+    }
+
+    fn set_screen_size(&mut self, width: u32, height: u32) {
+        self.screen_width = width.max(1);
+        self.screen_height = height.max(1);
+        (self.sx, self.sy) = text_scale(self.screen_width, self.screen_height);
+    }
+
+    fn set_viewport(&mut self, viewport: Viewport) {
+        self.viewport = viewport;
+        self.start_x = viewport.x + PADDING_X * self.sx + self.gutter_width();
+        self.start_y = viewport.y - PADDING_Y * self.sy;
+        self.editor
+            .borrow_mut()
+            .set_page_lines(viewport.lines(self.atlases.normal.max_h, self.screen_height));
+    }
+
+    fn has_focus(&self) -> bool {
+        match &self.children {
+            Some(children) => children.0.has_focus() || children.1.has_focus(),
+            None => self.focused,
+        }
+    }
+
+    fn focused_pane(&mut self) -> &mut Self {
+        if self.children.is_none() {
+            return self;
+        }
+        let (first, second, _) = &mut **self.children.as_mut().unwrap();
+        if first.has_focus() {
+            first.focused_pane()
+        } else {
+            second.focused_pane()
+        }
+    }
+
+    fn panes<'a>(&'a mut self, panes: &mut Vec<&'a mut Self>) {
+        if self.children.is_none() {
+            panes.push(self);
+            return;
+        }
+        let (first, second, _) = &mut **self.children.as_mut().unwrap();
+        first.panes(panes);
+        second.panes(panes);
+    }
+
+    /// Move focus to the closest pane next to the focused one
+    fn focus(&mut self, direction: Direction) {
+        let mut panes = Vec::new();
+        self.panes(&mut panes);
+        let Some(current) = panes.iter().position(|pane| pane.focused) else {
+            return;
+        };
+        let viewport = panes[current].viewport;
+        let cursor_y = panes[current].cursor_y();
+
+        // Prefer the neighbour level with the cursor, same as Vim
+        let target = panes
+            .iter()
+            .enumerate()
+            .filter(|(_, pane)| viewport.is_neighbour(&pane.viewport, direction))
+            .min_by(|(_, a), (_, b)| {
+                let distance = |v: &Viewport| match direction {
+                    Direction::Left | Direction::Right => {
+                        (cursor_y.clamp(v.bottom(), v.y) - cursor_y).abs()
+                    }
+                    Direction::Up | Direction::Down => (v.x - viewport.x).abs(),
+                };
+                distance(&a.viewport).total_cmp(&distance(&b.viewport))
+            })
+            .map(|(i, _)| i);
+
+        if let Some(target) = target {
+            panes[current].focused = false;
+            panes[target].focused = true;
+        }
+    }
+
+    /// Focus the pane under the mouse at `(x, y)` in the window and put its
+    /// cursor on the char that was clicked, or select the word or line
+    /// around it for double and triple clicks
+    fn click(&mut self, x: i32, y: i32, unit: SelectUnit, time: u32) -> EventResult {
+        let (x, y) = self.mouse_to_screen(x, y);
+        let mut panes = Vec::new();
+        self.panes(&mut panes);
+        let Some(target) = panes.iter().position(|pane| pane.viewport.contains(x, y)) else {
+            return EventResult::Nothing;
+        };
+        for pane in panes.iter_mut() {
+            pane.focused = false;
+        }
+        let pane = &mut panes[target];
+        pane.focused = true;
+        match unit {
+            SelectUnit::Char => pane.move_cursor_to(x, y, time),
+            unit => pane.select_around(x, y, unit, time),
+        }
+    }
+
+    /// Select from where the mouse was pressed to `(x, y)` in the window, in
+    /// the pane that was clicked
+    fn drag(&mut self, x: i32, y: i32, time: u32) -> EventResult {
+        let (x, y) = self.mouse_to_screen(x, y);
+        self.focused_pane().select_to(x, y, time)
+    }
+
+    /// From pixels in the window to the same -1 to 1 space as the viewports
+    fn mouse_to_screen(&self, x: i32, y: i32) -> (f32, f32) {
+        (
+            x as f32 / self.screen_width as f32 * 2.0 - 1.0,
+            1.0 - y as f32 / self.screen_height as f32 * 2.0,
+        )
+    }
+
+    /// The line and column of the char at `(x, y)` on the screen
+    fn logical_at(&self, x: f32, y: f32) -> (usize, usize) {
+        let h = self.atlases.normal.max_h * self.sy;
+        // Back to where the text is before it's scrolled
+        let x = x - self.x_offset * self.sx;
+        let y = y + self.y_offset * self.sy;
+        let row = ((self.start_y + h - y) / h).max(0.0) as usize;
+        self.screen_to_logical(row, x)
+    }
+
+    /// Move the cursor to the char at `(x, y)` on the screen. Clicking
+    /// leaves visual mode like Vim
+    fn move_cursor_to(&mut self, x: f32, y: f32, time: u32) -> EventResult {
+        let (line, col) = self.logical_at(x, y);
+
+        if self.editor.borrow().mode() == Mode::Visual {
+            self.editor.borrow_mut().set_mode(Mode::Normal);
+            self.queue_selection();
+        }
+        self.editor.borrow_mut().set_cursor_line_col(line, col);
+        self.handle_editor_event(EditorEvent::DrawCursor, time)
+    }
+
+    /// Extend the selection to the char at `(x, y)` on the screen, visual
+    /// mode starts once the mouse leaves the char it was pressed on
+    fn select_to(&mut self, x: f32, y: f32, time: u32) -> EventResult {
+        let (line, col) = self.logical_at(x, y);
+        self.editor.borrow_mut().select_to_line_col(line, col);
+        self.queue_selection();
+        self.handle_editor_event(EditorEvent::DrawCursor, time)
+    }
+
+    /// Select the `unit` around the char at `(x, y)` on the screen
+    fn select_around(&mut self, x: f32, y: f32, unit: SelectUnit, time: u32) -> EventResult {
+        let (line, col) = self.logical_at(x, y);
+        self.editor.borrow_mut().select_around(line, col, unit);
+        self.queue_selection();
+        self.handle_editor_event(EditorEvent::DrawCursor, time)
+    }
+
+    /// Position of the cursor on the screen
+    fn cursor_y(&self) -> f32 {
+        self.cursor_coords[1].y + self.y_offset * self.sy
+    }
+}
+
+// This impl contains utilities
+impl<'theme, 'highlight> Window<'theme, 'highlight> {
+    fn scroll_y(&mut self, mut amount: f32) {
+        let pix_amount = amount * self.atlases.normal.max_h;
+        amount *= -1.0;
+        let (y_offset, clamped) = clamp_scroll(self.y_offset, pix_amount, self.text_height);
+        self.y_offset = y_offset;
+        match (clamped, pix_amount > 0.0) {
+            (false, _) => self.editor.borrow_mut().incr_line(amount as i32),
+            // Hit the top
+            (true, true) => {}
+            // Hit the bottom
+            (true, false) => {
+                let len = self.editor.borrow().lines().len();
+                if len == 0 {
+                    self.editor.borrow_mut().incr_line(0)
+                } else {
+                    self.editor.borrow_mut().set_line(len - 1);
+                }
+            }
+        }
+    }
+
+    fn scroll_x(&mut self, amount: f32) {
+        self.x_offset = clamp_scroll(self.x_offset, amount, self.text_width).0;
+    }
+}
+
+// This impl contains graphics functions
+impl<'theme, 'highlight> Window<'theme, 'highlight> {
+    #[inline]
+    fn handle_editor_event(&mut self, evt: EditorEvent, time: u32) -> EventResult {
+        match evt {
+            EditorEvent::DrawText => {
+                self.text_changed = true;
+                self.last_stroke = time;
+                self.render_text();
+                self.hints_stale = true;
+                EventResult::Draw
+            }
+            EditorEvent::DrawCursor => {
+                self.cursor_changed = true;
+                self.adjust_scroll();
+                self.queue_cursor();
+                self.queue_cursor_line();
+                self.queue_indent_guides();
+                self.queue_search_highlights(false);
+                EventResult::Draw
+            }
+            EditorEvent::DrawSelection => {
+                self.queue_selection();
+                EventResult::Draw
+            }
+            EditorEvent::GotoFile => {
+                self.goto_file();
+                EventResult::Draw
+            }
+            EditorEvent::ExCmd => self.ex_cmd(time),
+            // The cursor only blinks in insert mode, start it off visible
+            EditorEvent::ModeChanged(_) => {
+                self.last_stroke = time;
+                self.cursor_changed = true;
+                self.queue_cursor();
+                EventResult::Draw
+            }
+            EditorEvent::Multiple => {
+                let evts = self.editor.borrow_mut().take_multiple_event_data();
+                let mut draw = false;
+
+                for evt in evts.into_iter() {
+                    if matches!(self.handle_editor_event(evt, time), EventResult::Draw) {
+                        draw = true;
+                    }
+                }
+
+                if draw {
+                    EventResult::Draw
+                } else {
+                    EventResult::Nothing
+                }
+            }
+
+            _ => EventResult::Nothing,
+        }
+    }
+
+    pub fn render_text(&mut self) {
+        if let Some(children) = &mut self.children {
+            children.0.render_text();
+            children.1.render_text();
+            return;
+        }
+
+        // The gutter grows with the number of digits in the last line number
+        self.start_x = self.viewport.x + PADDING_X * self.sx + self.gutter_width();
+        if self.wrap {
+            let col = Some(self.fit_cols());
+            if col != self.wrap_col {
+                self.wrap_col = col;
+                self.install_wrap();
+            }
+        }
+        self.update_line_rows();
+        (self.text_width, self.text_height) = text_bounds(
+            self.editor.borrow().lines(),
+            self.atlases.normal.max_w,
+            self.atlases.normal.max_h,
+        );
+        if let Some(rows) = self.line_rows.last() {
+            self.text_height = *rows as f32 * self.atlases.normal.max_h;
+        }
+        self.adjust_scroll();
+        self.queue_cursor();
+        self.queue_cursor_line();
+        // Glyphs loaded from here on are kept until the next time
+        self.atlases.begin_pass();
+        self.hint_atlas.begin_pass();
+        // The other panes showing the buffer share the highlighter, whichever
+        // renders first after an edit updates it and the language server
+        let edits = self.editor.borrow_mut().take_text_edits();
+        self.highlighter
+            .borrow_mut()
+            .update(self.editor.borrow().text_all(), &edits);
+        if !edits.is_empty() {
+            self.sync_document();
+        }
+        self.queue_text(self.start_x, self.start_y, self.sx, self.sy);
+        self.queue_search_highlights(true);
+        self.queue_selection();
+        self.queue_signs();
+        self.queue_line_numbers();
+        self.queue_indent_guides();
+        self.drawn_revision = self.editor.borrow().revision();
+        self.drawn_atlas = self.atlas_revisions();
+    }
+
+    fn atlas_revisions(&self) -> (u64, u64) {
+        (self.atlases.revision(), self.hint_atlas.revision())
+    }
+
+    /// Whether the editor changed since the geometry was last queued, like
+    /// when it's changed without going through `event`
+    pub fn is_stale(&self) -> bool {
+        match &self.children {
+            Some(children) => children.0.is_stale() || children.1.is_stale(),
+            None => {
+                self.drawn_revision != self.editor.borrow().revision()
+                    || self.drawn_atlas != self.atlas_revisions()
+                    || self.scrolled_past_text()
+                    || self.drawn_cursor
+                        != (self.editor.borrow().line(), self.editor.borrow().cursor())
+            }
+        }
+    }
+
+    /// Queue whatever is out of date with the editor again, returns true if
+    /// anything was
+    fn refresh(&mut self) -> bool {
+        if self.drawn_revision != self.editor.borrow().revision()
+            || self.drawn_atlas != self.atlas_revisions()
+            || self.scrolled_past_text()
+        {
+            self.render_text();
+        } else if self.drawn_cursor != (self.editor.borrow().line(), self.editor.borrow().cursor())
+        {
+            self.adjust_scroll();
+            self.queue_cursor();
+            self.queue_cursor_line();
+            self.queue_selection();
+            self.queue_indent_guides();
+            // Relative numbers count from the cursor's line, and its number
+            // stands out from the rest
+            if self.drawn_cursor.0 != self.editor.borrow().line() {
+                self.queue_line_numbers();
+            }
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Change what the gutter shows, `LineNumberMode::None` hides it
+    pub fn set_line_number_mode(&mut self, mode: LineNumberMode) {
+        if let Some(children) = &mut self.children {
+            children.0.set_line_number_mode(mode);
+            children.1.set_line_number_mode(mode);
+            return;
+        }
+
+        self.line_number_mode = mode;
+        self.render_text();
+    }
+
+    /// Turn the background of the cursor's line on or off
+    pub fn set_cursor_line(&mut self, cursor_line: bool) {
+        if let Some(children) = &mut self.children {
+            children.0.set_cursor_line(cursor_line);
+            children.1.set_cursor_line(cursor_line);
+            return;
+        }
+
+        self.cursor_line = cursor_line;
+    }
+
+    /// How much room the line numbers take up left of the text, the widest
+    /// number and a column to separate it from the text
+    fn gutter_width(&self) -> f32 {
+        if self.line_number_mode == LineNumberMode::None {
+            return 0.0;
+        }
+        let digits = self.editor.borrow().lines().len().to_string().len();
+        (digits + 1) as f32 * self.atlases.normal.max_w * self.sx
+    }
+
+    /// Wrap lines longer than `wrap_col` columns, or let them run off the
+    /// edge of the pane with `None`
+    pub fn set_wrap_col(&mut self, wrap_col: Option<u32>) {
+        if let Some(children) = &mut self.children {
+            children.0.set_wrap_col(wrap_col);
+            children.1.set_wrap_col(wrap_col);
+            return;
+        }
+
+        self.wrap = false;
+        self.wrap_col = wrap_col;
+        self.install_wrap();
+        self.render_text();
+    }
+
+    /// Wrap lines at the right edge of the pane, the column follows it when
+    /// the pane or the font is resized
+    pub fn set_wrap(&mut self, wrap: bool) {
+        if let Some(children) = &mut self.children {
+            children.0.set_wrap(wrap);
+            children.1.set_wrap(wrap);
+            return;
+        }
+
+        self.wrap = wrap;
+        // Measured when the text is drawn
+        self.wrap_col = None;
+        if wrap {
+            self.x_offset = 0.0;
+        }
+        self.install_wrap();
+        self.render_text();
+    }
+
+    /// Give the editor the rows `wrap_col` splits lines into. The editor is
+    /// shared with the other panes showing the buffer, so this is done again
+    /// before each event this pane handles
+    fn install_wrap(&mut self) {
+        match self.wrap_col {
+            Some(col) => self
+                .editor
+                .borrow_mut()
+                .set_display_rows(WordWrap::new(col as usize)),
+            None => self.editor.borrow_mut().set_display_rows(NoWrap),
+        }
+    }
+
+    /// The columns the rows of `line` start at in this pane, which may wrap
+    /// differently from the other panes showing the buffer
+    fn row_starts(&self, line: usize) -> Vec<usize> {
+        let editor = self.editor.borrow();
+        match self.wrap_col {
+            Some(col) => editor.row_starts_with(line, &WordWrap::new(col as usize)),
+            None => editor.row_starts_with(line, &NoWrap),
+        }
+    }
+
+    /// The columns of text that fit between the gutter and the right edge
+    fn fit_cols(&self) -> u32 {
+        let column_w = self.atlases.normal.max_w * self.sx;
+        ((self.viewport.right() - self.start_x - PADDING_X * self.sx) / column_w).max(1.0) as u32
+    }
+
+    /// `:set {option}`, only `wrap` for now. Flags are turned off with a
+    /// `no` in front and toggled with a `!` after
+    fn set_option(&mut self, option: &str) -> Result<(), String> {
+        if let Some(wrap) = flag_value(option, "wrap", self.wrap_col.is_some()) {
+            self.set_wrap(wrap);
+            return Ok(());
+        }
+        Err(format!("Unknown option: {}", option))
+    }
+
+    fn update_line_rows(&mut self) {
+        self.line_rows.clear();
+        if self.wrap_col.is_none() {
+            return;
+        }
+
+        let mut rows = 0;
+        for line in 0..self.editor.borrow().lines().len() {
+            self.line_rows.push(rows);
+            rows += self.row_starts(line).len();
+        }
+        self.line_rows.push(rows);
+    }
+
+    /// The row on the screen and the column in that row of the char at `col`
+    /// in `line`
+    fn screen_pos(&self, line: usize, col: usize) -> (usize, usize) {
+        let first_row = match self.line_rows.get(line) {
+            Some(row) if self.wrap_col.is_some() => *row,
+            _ => return (line, col),
+        };
+
+        let (row, col) = wrapped_pos(&self.row_starts(line), col);
+        (first_row + row, col)
+    }
+
+    /// The line and char at `x` on screen row `row`, before scrolling. Rows
+    /// past the end of the text are on the last line, and `x` past the end
+    /// of a row is on its last char
+    fn screen_to_logical(&self, row: usize, x: f32) -> (usize, usize) {
+        let last = self.editor.borrow().lines().len() - 1;
+        let (line, starts, row) = match self.wrap_col {
+            Some(_) if !self.line_rows.is_empty() => {
+                let line = self
+                    .line_rows
+                    .iter()
+                    .rposition(|start| *start <= row)
+                    .unwrap_or(0)
+                    .min(last);
+                let starts = self.row_starts(line);
+                let row = (row - self.line_rows[line]).min(starts.len() - 1);
+                (line, starts, row)
+            }
+            _ => (row.min(last), vec![0], 0),
+        };
+
+        // The last char of a wrapped row is the furthest it can go, the one
+        // after it is on the next row
+        let end = match starts.get(row + 1) {
+            Some(next) => next - 1,
+            None => self.editor.borrow().lines()[line] as usize,
+        };
+        let x = x - self.start_x;
+        let col = (starts[row]..end)
+            .find(|col| self.advance_width_up_to(line, col + 1) > x)
+            .unwrap_or(end);
+        (line, col)
+    }
+
+    /// The top left corner of the char at `col` in `line`, before scrolling
+    fn logical_to_screen(&self, line: usize, col: usize) -> (f32, f32) {
+        let (row, _) = self.screen_pos(line, col);
+        let h = self.atlases.normal.max_h * self.sy;
+        (
+            self.start_x + self.advance_width_up_to(line, col),
+            (self.start_y + h) - (row as f32 * h),
+        )
+    }
+
+    /// How far right of the start of its row the char at `col` in `line` is,
+    /// the advances of the glyphs before it on the row. Rows continuing a
+    /// wrapped line start after their indent
+    fn advance_width_up_to(&self, line: usize, col: usize) -> f32 {
+        let editor = self.editor.borrow();
+        let text = editor.text_all();
+        if line >= text.len_lines() {
+            return 0.0;
+        }
+        let chars = text.line(line);
+        let col = col.min(chars.len_chars());
+        let (row_start, indent) = match self.wrap_col {
+            Some(_) if line < self.editor.borrow().lines().len() => {
+                let starts = self.row_starts(line);
+                match starts.iter().rposition(|start| *start <= col).unwrap_or(0) {
+                    0 => (0, 0.0),
+                    row => (
+                        starts[row],
+                        WRAP_INDENT as f32 * self.atlases.normal.max_w * self.sx,
+                    ),
+                }
+            }
+            _ => (0, 0.0),
+        };
+        chars
+            .slice(row_start.min(col)..col)
+            .chars()
+            .fold(indent, |x, ch| match ch {
+                '\t' => x + self.tab_advance(x),
+                ch => x + self.char_advance(ch),
+            })
+    }
+
+    /// A bar before the char in insert mode. Otherwise a block as wide as the
+    /// char it's on, or a space past the end of the line. `x` is how far
+    /// right of the start of the row the cursor is
+    fn cursor_width(&self, x: f32) -> f32 {
+        let editor = self.editor.borrow();
+        match editor
+            .text_all()
+            .line(editor.line())
+            .get_char(editor.cursor())
+        {
+            _ if editor.is_insert() => CURSOR_BAR_WIDTH * self.sx,
+            Some('\t') => self.tab_advance(x),
+            Some(ch) if ch != '\n' => self.char_advance(ch),
+            _ => self.char_advance(' '),
+        }
+    }
+
+    /// How far the text moves right after `ch`, the same as `queue_text`.
+    /// Tabs are `tab_advance` instead
+    fn char_advance(&self, ch: char) -> f32 {
+        match self.atlases.normal.glyph(ch) {
+            Some(glyph) => glyph.advance_x * self.sx,
+            None if ch.is_control() => 0.0,
+            // Not drawn yet, it will be about as wide as the columns it takes
+            None => self.atlases.normal.max_w * self.sx * ch.width().unwrap_or(1) as f32,
+        }
+    }
+
+    /// How far a tab `x` right of the start of its line moves the text, to
+    /// the next tab stop. Tab stops are as far apart as a level of indent
+    fn tab_advance(&self, x: f32) -> f32 {
+        let stop = self.editor.borrow().indent().width as f32 * self.atlases.normal.max_w * self.sx;
+        next_tab_stop(x, stop) - x
+    }
+
+    /// A rectangle for each row the chars in `start..end` cover, as (left,
+    /// right, top). Line breaks don't get one
+    fn span_rows(&self, start: usize, end: usize) -> Vec<(f32, f32, f32)> {
+        let editor = self.editor.borrow();
+        let text = editor.text_all();
+        let end = end.min(text.len_chars());
+        let mut rows: Vec<(f32, f32, f32)> = Vec::new();
+        let mut i = start;
+        while i < end {
+            let line = text.char_to_line(i);
+            let line_start = text.line_to_char(line);
+            let line_end = line_start + text.line(line).len_chars();
+            let starts = match self.wrap_col {
+                Some(_) => self.row_starts(line),
+                None => vec![0],
+            };
+            // Only the first char and the ones starting a row are laid out
+            // from the start of their row, the rest follow the char before
+            let first = i - line_start;
+            let (mut x, mut y) = self.logical_to_screen(line, first);
+            for (col, ch) in (first..).zip(text.slice(i..end.min(line_end)).chars()) {
+                if ch == '\n' {
+                    continue;
+                }
+                if col != first && starts.contains(&col) {
+                    (x, y) = self.logical_to_screen(line, col);
+                }
+                let w = match ch {
+                    '\t' => self.tab_advance(x - self.start_x),
+                    ch => self.char_advance(ch),
+                };
+                match rows.last_mut() {
+                    Some((_, right, top)) if *top == y && *right == x => *right = x + w,
+                    _ => rows.push((x, x + w, y)),
+                }
+                x += w;
+            }
+            i = line_end;
+        }
+        rows
+    }
+
+    /// How many rows `line` takes up
+    fn line_row_count(&self, line: usize) -> usize {
+        match (self.line_rows.get(line), self.line_rows.get(line + 1)) {
+            (Some(start), Some(end)) => end - start,
+            _ => 1,
+        }
+    }
+
+    /// Queue a rectangle across the pane behind the line the cursor is on
+    pub fn queue_cursor_line(&mut self) {
+        let h = self.atlases.normal.max_h * self.sy;
+        let (_, top) =
+            self.logical_to_screen(self.editor.borrow().line(), self.editor.borrow().cursor());
+        let bot = top - h;
+        let left = self.viewport.x;
+        let right = self.viewport.right();
+
+        self.cursor_line_coords = [
+            Point3 {
+                x: left,
+                y: top,
+                z: 0.0,
+            },
+            Point3 {
+                x: left,
+                y: bot,
+                z: 0.0,
+            },
+            Point3 {
+                x: right,
+                y: bot,
+                z: 0.0,
+            },
+            Point3 {
+                x: left,
+                y: top,
+                z: 0.0,
+            },
+            Point3 {
+                x: right,
+                y: top,
+                z: 0.0,
+            },
+            Point3 {
+                x: right,
+                y: bot,
+                z: 0.0,
+            },
+        ];
+    }
+
+    /// The lines with a row on the screen
+    fn visible_lines(&self) -> Range<usize> {
+        let first_row = (-self.y_offset / self.atlases.normal.max_h).max(0.0) as usize;
+        let first = if self.line_rows.is_empty() {
+            first_row
+        } else {
+            self.line_rows.partition_point(|row| *row <= first_row) - 1
+        };
+        let last = (first
+            + self
+                .viewport
+                .lines(self.atlases.normal.max_h, self.screen_height)
+            + 2)
+        .min(self.editor.borrow().text_all().len_lines());
+        first.min(last)..last
+    }
+
+    /// Whether scrolling showed lines the text wasn't queued for
+    fn scrolled_past_text(&self) -> bool {
+        let visible = self.visible_lines();
+        visible.start < self.queued_lines.start || visible.end > self.queued_lines.end
+    }
+
+    /// Queue a line segment for each indentation level of the visible lines
+    pub fn queue_indent_guides(&mut self) {
+        let h = self.atlases.normal.max_h * self.sy;
+        let tab_width = self.editor.borrow().indent().width.max(1) as usize;
+        let w = tab_width as f32 * self.atlases.normal.max_w * self.sx;
+        let editor = self.editor.borrow();
+        let text = editor.text_all();
+        let lines = self.visible_lines();
+        let first = lines.start;
+
+        self.indent_guide_coords.clear();
+        for (i, level) in indent_levels(text, lines, tab_width)
+            .into_iter()
+            .enumerate()
+        {
+            let (_, top) = self.logical_to_screen(first + i, 0);
+            let bot = top - h * self.line_row_count(first + i) as f32;
+            for l in 0..level {
+                let x = self.start_x + l as f32 * w;
+                self.indent_guide_coords.push(Point3 { x, y: top, z: 0.0 });
+                self.indent_guide_coords.push(Point3 { x, y: bot, z: 0.0 });
+            }
+        }
+    }
+
+    pub fn queue_cursor(&mut self) {
+        let h = (self.atlases.normal.max_h/*+ 5f32*/) * self.sy;
+
+        let (x, y) =
+            self.logical_to_screen(self.editor.borrow().line(), self.editor.borrow().cursor());
+        let w = self.cursor_width(x - self.start_x);
+        self.drawn_cursor = (self.editor.borrow().line(), self.editor.borrow().cursor());
+
+        self.cursor_coords = [
+            // // bottom left
+            Point3 {
+                x,
+                y: y - h,
+                z: 0.0,
+            },
+            // top left
+            Point3 { x, y, z: 0.0 },
+            // top right
+            Point3 {
+                x: x + w,
+                y,
+                z: 0.0,
+            },
+            // bottom right
+            Point3 {
+                x: x + w,
+                y: y - h,
                 z: 0.0,
             },
             // top right,
@@ -325,70 +2089,201 @@ impl<'theme, 'highlight> Window<'theme, 'highlight> {
     }
 
     pub fn frame(&mut self, kind: WindowFrameKind, ticks_ms: u32) {
-        let draw = matches!(kind, WindowFrameKind::Draw);
+        match &mut self.children {
+            Some(children) => {
+                children.0.frame(kind, ticks_ms);
+                children.1.frame(kind, ticks_ms);
+            }
+            None => {
+                // Keep everything (text scrolled past the edges included) inside the pane
+                let mut gl_viewport = [0; 4];
+                unsafe {
+                    gl::GetIntegerv(gl::VIEWPORT, gl_viewport.as_mut_ptr());
+                    let [vx, vy, vw, vh] = gl_viewport.map(|v| v as f32);
+                    let left = vx + (self.viewport.x + 1.0) / 2.0 * vw;
+                    let bottom = vy + (self.viewport.bottom() + 1.0) / 2.0 * vh;
+                    gl::Enable(gl::SCISSOR_TEST);
+                    gl::Scissor(
+                        left.round() as i32,
+                        bottom.round() as i32,
+                        (self.viewport.w / 2.0 * vw).round() as i32,
+                        (self.viewport.h / 2.0 * vh).round() as i32,
+                    );
+                }
+                self.frame_pane(kind, ticks_ms);
+                unsafe {
+                    gl::Disable(gl::SCISSOR_TEST);
+                }
+            }
+        }
+    }
+
+    fn frame_pane(&mut self, kind: WindowFrameKind, ticks_ms: u32) {
+        // The buffers have to be uploaded again if anything was out of date
+        let draw = self.refresh() || matches!(kind, WindowFrameKind::Draw);
+
+        // Draw cursor line, it goes first so it's behind everything else
+        if self.cursor_line {
+            self.cursor_line_shader.set_used();
+            let [r, g, b, a] = self.theme.cursor_line().floats();
+            unsafe {
+                gl::VertexAttrib1f(
+                    self.cursor_line_shader.attrib_ytranslate,
+                    self.y_offset * self.sy,
+                );
+                gl::Uniform4f(self.cursor_line_shader.uniform_color, r, g, b, a);
+
+                gl::BindBuffer(gl::ARRAY_BUFFER, self.cursor_line_shader.vbo);
+                if draw {
+                    gl::BufferData(
+                        gl::ARRAY_BUFFER,
+                        (self.cursor_line_coords.len() * mem::size_of::<Point3>()) as isize,
+                        self.cursor_line_coords.as_ptr() as *const c_void,
+                        gl::DYNAMIC_DRAW,
+                    );
+                }
+                gl::VertexAttribPointer(
+                    self.cursor_line_shader.attrib_apos,
+                    3,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    mem::size_of::<Point3>() as i32,
+                    null(),
+                );
+                gl::EnableVertexAttribArray(self.cursor_line_shader.attrib_apos);
+                gl::DrawArrays(gl::TRIANGLES, 0, self.cursor_line_coords.len() as i32);
+                gl::DisableVertexAttribArray(self.cursor_line_shader.attrib_apos);
+            }
+        }
+
+        // Draw indent guides
+        if !self.indent_guide_coords.is_empty() {
+            self.indent_guides_shader.set_used();
+            let [r, g, b, a] = self.theme.indent_guide().floats();
+            unsafe {
+                gl::VertexAttrib1f(
+                    self.indent_guides_shader.attrib_ytranslate,
+                    self.y_offset * self.sy,
+                );
+                gl::VertexAttrib1f(
+                    self.indent_guides_shader.attrib_xtranslate,
+                    self.x_offset * self.sx,
+                );
+                gl::Uniform4f(self.indent_guides_shader.uniform_color, r, g, b, a);
+
+                gl::BindBuffer(gl::ARRAY_BUFFER, self.indent_guides_shader.vbo);
+                if draw {
+                    gl::BufferData(
+                        gl::ARRAY_BUFFER,
+                        (self.indent_guide_coords.len() * mem::size_of::<Point3>()) as isize,
+                        self.indent_guide_coords.as_ptr() as *const c_void,
+                        gl::DYNAMIC_DRAW,
+                    );
+                }
+                gl::VertexAttribPointer(
+                    self.indent_guides_shader.attrib_apos,
+                    3,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    mem::size_of::<Point3>() as i32,
+                    null(),
+                );
+                gl::EnableVertexAttribArray(self.indent_guides_shader.attrib_apos);
+                gl::LineWidth(1.0);
+                gl::DrawArrays(gl::LINES, 0, self.indent_guide_coords.len() as i32);
+                gl::DisableVertexAttribArray(self.indent_guides_shader.attrib_apos);
+            }
+        }
+
         self.text_shader.set_used();
 
         // Draw text
         unsafe {
             // TODO: X and Y translation can be global (make it a uniform)
-            gl::VertexAttrib1f(self.text_shader.attrib_ytranslate, SY * self.y_offset);
-            gl::VertexAttrib1f(self.text_shader.attrib_xtranslate, self.x_offset * SX);
+            gl::VertexAttrib1f(self.text_shader.attrib_ytranslate, self.sy * self.y_offset);
+            gl::VertexAttrib1f(self.text_shader.attrib_xtranslate, self.x_offset * self.sx);
 
-            // Use the texture containing the atlas
-            gl::BindTexture(gl::TEXTURE_2D, self.atlas.tex);
             gl::Uniform1i(self.text_shader.uniform_tex, 0);
+        }
+        // Each run from the texture of the atlas page its glyphs are on
+        self.text_vertices.draw(&self.text_shader, draw);
 
-            // Set up the VBO for our vertex data
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.text_shader.vbo);
-            gl::VertexAttribPointer(
-                self.text_shader.attrib_coord,
-                4,
-                gl::FLOAT,
-                gl::FALSE,
-                0,
-                null(),
-            );
-            gl::EnableVertexAttribArray(self.text_shader.attrib_coord);
-            if draw {
-                gl::BufferData(
-                    gl::ARRAY_BUFFER,
-                    (self.text_coords.len() * mem::size_of::<Point>()) as GLsizeiptr,
-                    self.text_coords.as_ptr() as *const GLvoid,
-                    gl::DYNAMIC_DRAW,
-                );
+        // Draw inlay hints, they scroll with the text but use their own atlas
+        {
+            let text_shader = &self.hint_shader;
+            text_shader.set_used();
+            unsafe {
+                gl::VertexAttrib1f(text_shader.attrib_ytranslate, self.y_offset * self.sy);
+                gl::VertexAttrib1f(text_shader.attrib_xtranslate, self.x_offset * self.sx);
+
+                gl::Uniform1i(text_shader.uniform_tex, 0);
             }
+            self.hint_vertices.draw(text_shader, draw);
+        }
 
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.text_shader.vbo_color);
-            gl::VertexAttribPointer(
-                self.text_shader.attrib_v_color,
-                4,
-                gl::UNSIGNED_BYTE,
-                gl::TRUE,
-                0,
-                null(),
-            );
-            gl::EnableVertexAttribArray(self.text_shader.attrib_v_color);
-            if draw {
-                gl::BufferData(
-                    gl::ARRAY_BUFFER,
-                    (self.text_colors.len() * mem::size_of::<Color>()) as GLsizeiptr,
-                    self.text_colors.as_ptr() as *const GLvoid,
-                    gl::DYNAMIC_DRAW,
+        // Draw search matches, the current one stronger than the rest
+        if !self.search_coords.is_empty() {
+            self.highlight_shader.set_used();
+            let current = self.current_match.clone();
+            let [r, g, b, a] = self.theme.search_match().floats();
+            unsafe {
+                gl::VertexAttrib1f(
+                    self.highlight_shader.attrib_ytranslate,
+                    self.y_offset * self.sy,
                 );
-            }
+                gl::VertexAttrib1f(
+                    self.highlight_shader.attrib_xtranslate,
+                    self.x_offset * self.sx,
+                );
+                gl::Uniform4f(self.highlight_shader.uniform_color, r, g, b, a);
+
+                gl::BindBuffer(gl::ARRAY_BUFFER, self.highlight_shader.search_vbo);
+                if draw {
+                    gl::BufferData(
+                        gl::ARRAY_BUFFER,
+                        mem::size_of_val(self.search_coords.as_slice()) as GLsizeiptr,
+                        self.search_coords.as_ptr() as *const c_void,
+                        gl::DYNAMIC_DRAW,
+                    );
+                }
+                gl::VertexAttribPointer(
+                    self.highlight_shader.attrib_apos,
+                    3,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    mem::size_of::<Point3>() as i32,
+                    null(),
+                );
+                gl::EnableVertexAttribArray(0);
 
-            gl::DrawArrays(gl::TRIANGLES, 0, self.text_coords.len() as i32);
-            gl::DisableVertexAttribArray(self.text_shader.attrib_v_color);
-            gl::DisableVertexAttribArray(self.text_shader.attrib_coord);
+                gl::DrawArrays(gl::TRIANGLES, 0, current.start as i32);
+                gl::DrawArrays(
+                    gl::TRIANGLES,
+                    current.end as i32,
+                    (self.search_coords.len() - current.end) as i32,
+                );
+                let [r, g, b, a] = self.theme.current_search_match().floats();
+                gl::Uniform4f(self.highlight_shader.uniform_color, r, g, b, a);
+                gl::DrawArrays(gl::TRIANGLES, current.start as i32, current.len() as i32);
+                gl::DisableVertexAttribArray(0);
+            }
         }
 
         // Draw highlight
         {
             self.highlight_shader.set_used();
             let attrib_ptr = self.highlight_shader.attrib_apos;
+            let [r, g, b, a] = self.theme.selection_bg().floats();
             unsafe {
-                gl::VertexAttrib1f(self.highlight_shader.attrib_ytranslate, self.y_offset * SY);
-                gl::VertexAttrib1f(self.highlight_shader.attrib_xtranslate, self.x_offset * SX);
+                gl::VertexAttrib1f(
+                    self.highlight_shader.attrib_ytranslate,
+                    self.y_offset * self.sy,
+                );
+                gl::VertexAttrib1f(
+                    self.highlight_shader.attrib_xtranslate,
+                    self.x_offset * self.sx,
+                );
+                gl::Uniform4f(self.highlight_shader.uniform_color, r, g, b, a);
 
                 gl::BindBuffer(gl::ARRAY_BUFFER, self.highlight_shader.vbo);
                 if draw {
@@ -417,8 +2312,14 @@ impl<'theme, 'highlight> Window<'theme, 'highlight> {
         {
             self.diagnostic_shader.set_used();
             unsafe {
-                gl::VertexAttrib1f(self.diagnostic_shader.attrib_ytranslate, self.y_offset * SY);
-                gl::VertexAttrib1f(self.diagnostic_shader.attrib_xtranslate, self.x_offset * SX);
+                gl::VertexAttrib1f(
+                    self.diagnostic_shader.attrib_ytranslate,
+                    self.y_offset * self.sy,
+                );
+                gl::VertexAttrib1f(
+                    self.diagnostic_shader.attrib_xtranslate,
+                    self.x_offset * self.sx,
+                );
 
                 // Coords
                 gl::BindBuffer(gl::ARRAY_BUFFER, self.diagnostic_shader.vbo);
@@ -465,506 +2366,1396 @@ impl<'theme, 'highlight> Window<'theme, 'highlight> {
             }
         }
 
-        // Draw cursor
-        {
-            self.cursor_shader.set_used();
-            unsafe {
-                gl::VertexAttrib1f(self.cursor_shader.attrib_ytranslate, self.y_offset * SY);
-                gl::VertexAttrib1f(self.cursor_shader.attrib_xtranslate, self.x_offset * SX);
-                gl::Uniform1f(
-                    self.cursor_shader.uniform_laststroke,
-                    self.last_stroke as f32 / 1000.0,
-                );
-                gl::Uniform1i(
-                    self.cursor_shader.uniform_is_blinking,
-                    if self.editor.is_insert() { 1 } else { 0 },
-                );
-                gl::Uniform1f(self.cursor_shader.uniform_time, ticks_ms as f32 / 1000.0);
+        // Draw signs, they live in the gutter so they only scroll vertically
+        {
+            self.sign_shader.set_used();
+            unsafe {
+                gl::VertexAttrib1f(self.sign_shader.attrib_ytranslate, self.y_offset * self.sy);
+                gl::VertexAttrib1f(self.sign_shader.attrib_xtranslate, 0.0);
+
+                // Coords
+                gl::BindBuffer(gl::ARRAY_BUFFER, self.sign_shader.vbo);
+                if draw {
+                    gl::BufferData(
+                        gl::ARRAY_BUFFER,
+                        (self.signs_coords.len() * mem::size_of::<Point3>()) as isize,
+                        self.signs_coords.as_ptr() as *const c_void,
+                        gl::DYNAMIC_DRAW,
+                    );
+                }
+                gl::VertexAttribPointer(
+                    self.sign_shader.attrib_apos,
+                    3,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    mem::size_of::<Point3>() as i32,
+                    null(),
+                );
+                // Color
+                gl::BindBuffer(gl::ARRAY_BUFFER, self.sign_shader.vbo_color);
+                if draw {
+                    gl::BufferData(
+                        gl::ARRAY_BUFFER,
+                        (self.signs_colors.len() * mem::size_of::<Color>()) as isize,
+                        self.signs_colors.as_ptr() as *const c_void,
+                        gl::DYNAMIC_DRAW,
+                    );
+                }
+                gl::VertexAttribPointer(
+                    self.sign_shader.attrib_color,
+                    4,
+                    gl::UNSIGNED_BYTE,
+                    gl::TRUE,
+                    0,
+                    null(),
+                );
+
+                gl::EnableVertexAttribArray(self.sign_shader.attrib_apos);
+                gl::EnableVertexAttribArray(self.sign_shader.attrib_color);
+                gl::DrawArrays(gl::TRIANGLES, 0, self.signs_coords.len() as i32);
+                gl::DisableVertexAttribArray(self.sign_shader.attrib_apos);
+                gl::DisableVertexAttribArray(self.sign_shader.attrib_color);
+            }
+        }
+
+        // Draw line numbers, like the signs they only scroll vertically
+        {
+            let text_shader = &self.line_number_shader;
+            text_shader.set_used();
+            unsafe {
+                gl::VertexAttrib1f(text_shader.attrib_ytranslate, self.y_offset * self.sy);
+                gl::VertexAttrib1f(text_shader.attrib_xtranslate, 0.0);
+
+                gl::Uniform1i(text_shader.uniform_tex, 0);
+            }
+            self.line_number_vertices.draw(text_shader, draw);
+        }
+
+        // Draw cursor, only the focused pane has one
+        if self.focused {
+            self.cursor_shader.set_used();
+            let [r, g, b, a] = self.theme.cursor().floats();
+            unsafe {
+                gl::VertexAttrib1f(
+                    self.cursor_shader.attrib_ytranslate,
+                    self.y_offset * self.sy,
+                );
+                gl::VertexAttrib1f(
+                    self.cursor_shader.attrib_xtranslate,
+                    self.x_offset * self.sx,
+                );
+                gl::Uniform1f(
+                    self.cursor_shader.uniform_laststroke,
+                    self.last_stroke as f32 / 1000.0,
+                );
+                gl::Uniform1i(
+                    self.cursor_shader.uniform_is_blinking,
+                    if self.editor.borrow().is_insert() {
+                        1
+                    } else {
+                        0
+                    },
+                );
+                gl::Uniform1f(self.cursor_shader.uniform_time, ticks_ms as f32 / 1000.0);
+                gl::Uniform4f(self.cursor_shader.uniform_color, r, g, b, a);
+            }
+
+            let attrib_ptr = self.cursor_shader.attrib_apos;
+            unsafe {
+                gl::BindBuffer(gl::ARRAY_BUFFER, self.cursor_shader.vbo);
+
+                // gl::BlendFunc(gl::SRC_ALPHA, gl::ONE);
+                // gl::BlendEquation(gl::FUNC_SUBTRACT);
+
+                if draw {
+                    gl::BufferData(
+                        gl::ARRAY_BUFFER,
+                        (self.cursor_coords.len() * mem::size_of::<Point3>()) as isize,
+                        self.cursor_coords.as_ptr() as *const c_void,
+                        gl::DYNAMIC_DRAW,
+                    );
+                }
+
+                gl::VertexAttribPointer(
+                    attrib_ptr,
+                    3,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    3 * mem::size_of::<f32>() as i32,
+                    null(),
+                );
+                gl::EnableVertexAttribArray(0);
+                gl::DrawArrays(gl::TRIANGLES, 0, 6);
+                gl::DisableVertexAttribArray(0);
+
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+                gl::BlendEquation(gl::FUNC_ADD);
+            }
+        }
+
+        // Popups go over the text but under the status line
+        if self.popup.is_some() {
+            self.queue_popup();
+            self.draw_overlay(
+                &self.popup_coords,
+                &self.popup_colors,
+                &self.popup_text_vertices,
+            );
+        }
+
+        // Draw status line, it's cheap so recompute it every frame. It's pinned
+        // to the bottom of the window so it ignores the scroll offsets
+        self.queue_status_line(ticks_ms);
+        self.draw_overlay(
+            &self.status_coords,
+            &self.status_colors,
+            &self.status_text_vertices,
+        );
+    }
+
+    /// Draw rectangles then text on top of them, in screen coordinates that
+    /// ignore the scroll offsets
+    fn draw_overlay(&self, coords: &[Point3], colors: &[Color], text: &TextVertices) {
+        self.status_shader.set_used();
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.status_shader.vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                mem::size_of_val(coords) as isize,
+                coords.as_ptr() as *const c_void,
+                gl::DYNAMIC_DRAW,
+            );
+            gl::VertexAttribPointer(
+                self.status_shader.attrib_apos,
+                3,
+                gl::FLOAT,
+                gl::FALSE,
+                mem::size_of::<Point3>() as i32,
+                null(),
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.status_shader.vbo_color);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                mem::size_of_val(colors) as isize,
+                colors.as_ptr() as *const c_void,
+                gl::DYNAMIC_DRAW,
+            );
+            gl::VertexAttribPointer(
+                self.status_shader.attrib_color,
+                4,
+                gl::UNSIGNED_BYTE,
+                gl::TRUE,
+                0,
+                null(),
+            );
+
+            gl::EnableVertexAttribArray(self.status_shader.attrib_apos);
+            gl::EnableVertexAttribArray(self.status_shader.attrib_color);
+            gl::DrawArrays(gl::TRIANGLES, 0, coords.len() as i32);
+            gl::DisableVertexAttribArray(self.status_shader.attrib_apos);
+            gl::DisableVertexAttribArray(self.status_shader.attrib_color);
+        }
+
+        let text_shader = &self.status_shader.text;
+        text_shader.set_used();
+        unsafe {
+            gl::VertexAttrib1f(text_shader.attrib_ytranslate, 0.0);
+            gl::VertexAttrib1f(text_shader.attrib_xtranslate, 0.0);
+
+            gl::Uniform1i(text_shader.uniform_tex, 0);
+        }
+        text.draw(text_shader, true);
+    }
+
+    fn queue_status_line(&mut self, ticks_ms: u32) {
+        let h = self.atlases.normal.max_h * self.sy;
+        let left = self.viewport.x;
+        let right = self.viewport.right();
+        let bot = self.viewport.bottom();
+        let top = bot + h;
+
+        // Opaque background so text scrolled underneath doesn't show through,
+        // then the status line's own, which can be see-through
+        let bg = *self.theme.bg();
+        let tint = Color {
+            a: 20,
+            ..*self.theme.fg()
+        };
+        self.status_coords.clear();
+        self.status_colors.clear();
+        for color in [bg, self.theme.statusline_bg()] {
+            self.status_coords.extend([
+                Point3 {
+                    x: left,
+                    y: top,
+                    z: 0.0,
+                },
+                Point3 {
+                    x: left,
+                    y: bot,
+                    z: 0.0,
+                },
+                Point3 {
+                    x: right,
+                    y: bot,
+                    z: 0.0,
+                },
+                Point3 {
+                    x: left,
+                    y: top,
+                    z: 0.0,
+                },
+                Point3 {
+                    x: right,
+                    y: top,
+                    z: 0.0,
+                },
+                Point3 {
+                    x: right,
+                    y: bot,
+                    z: 0.0,
+                },
+            ]);
+            self.status_colors.extend([color; 6]);
+        }
+
+        // Separate this pane from the one to its right
+        if right < 1.0 {
+            let x = right - 2f32 * self.sx;
+            let top = self.viewport.y;
+            self.status_coords.extend([
+                Point3 { x, y: top, z: 0.0 },
+                Point3 { x, y: bot, z: 0.0 },
+                Point3 {
+                    x: right,
+                    y: bot,
+                    z: 0.0,
+                },
+                Point3 { x, y: top, z: 0.0 },
+                Point3 {
+                    x: right,
+                    y: top,
+                    z: 0.0,
+                },
+                Point3 {
+                    x: right,
+                    y: bot,
+                    z: 0.0,
+                },
+            ]);
+            self.status_colors.extend([tint; 6]);
+        }
+
+        let (mode, mode_color) = match self.editor.borrow().mode() {
+            Mode::Normal => ("[NORMAL]", self.theme.highlight(Highlight::Function)),
+            Mode::Insert => ("[INSERT]", self.theme.highlight(Highlight::String)),
+            Mode::Visual => ("[VISUAL]", self.theme.highlight(Highlight::Keyword)),
+        };
+        let mode_color = *mode_color.unwrap_or_else(|| self.theme.fg());
+
+        let (errors, warnings) = {
+            let diagnostics = self.diagnostics.read().unwrap();
+            let buffers = self.buffers.borrow();
+            buffer_diagnostics(&diagnostics, &buffers, self.buffer)
+                .iter()
+                .fold((0, 0), |(errors, warnings), diag| match diag.severity {
+                    Some(DiagnosticSeverity::ERROR) => (errors + 1, warnings),
+                    Some(DiagnosticSeverity::WARNING) => (errors, warnings + 1),
+                    _ => (errors, warnings),
+                })
+        };
+        let position = format!(
+            "Ln {} Col {}  {}%  E:{} W:{}",
+            self.editor.borrow().line() + 1,
+            self.editor.borrow().cursor() + 1,
+            percent_through(
+                self.editor.borrow().line(),
+                self.editor.borrow().lines().len()
+            ),
+            errors,
+            warnings
+        );
+
+        self.status_text_vertices.clear();
+
+        // Leave room for descenders below the baseline
+        let y = bot + h * 0.25;
+        let left = self.viewport.x + PADDING_X * self.sx;
+        self.queue_status_text(mode, left, y, mode_color);
+        let mut name = self.buffer_name(self.buffer);
+        if self.editor.borrow().is_modified() {
+            name.push_str(" [+]");
+        }
+        match self.editor.borrow().line_ending() {
+            LineEnding::Lf => {}
+            LineEnding::CrLf => name.push_str(" [crlf]"),
+            LineEnding::Cr => name.push_str(" [cr]"),
+        }
+        let fg = self.theme.statusline_fg();
+        let column_w = self.atlases.normal.max_w * self.sx;
+        let x = left + (mode.len() + 1) as f32 * column_w;
+        self.queue_status_text(&name, x, y, fg);
+
+        let x = x + (name.chars().count() + 2) as f32 * column_w;
+        let command_line = {
+            let editor = self.editor.borrow();
+            let line = editor.command_line();
+            line.map(|line| format!("{}{}", editor.command_prompt(), line))
+        };
+        if let Some(line) = command_line {
+            self.queue_status_text(&line, x, y, fg);
+        } else if let Some(message) = self.message.clone() {
+            self.queue_status_text(&message, x, y, ERROR_RED);
+        } else if let Some((message, received)) = self.server_message.clone() {
+            if ticks_ms.saturating_sub(received) < SERVER_MESSAGE_MS {
+                let color = match message.typ {
+                    MessageType::ERROR => ERROR_RED,
+                    _ => fg,
+                };
+                // Only the first line fits
+                let text = message.message.lines().next().unwrap_or_default();
+                self.queue_status_text(text, x, y, color);
             }
+        }
 
-            let attrib_ptr = self.cursor_shader.attrib_apos;
-            unsafe {
-                gl::BindBuffer(gl::ARRAY_BUFFER, self.cursor_shader.vbo);
+        let width: f32 = position.chars().map(|c| self.char_advance(c)).sum();
+        let position_x = right - PADDING_X * self.sx - width;
+        self.queue_status_text(&position, position_x, y, fg);
 
-                // gl::BlendFunc(gl::SRC_ALPHA, gl::ONE);
-                // gl::BlendEquation(gl::FUNC_SUBTRACT);
+        // Left of the position, same place as Vim's `showcmd`
+        let pending = self.editor.borrow().pending_keys();
+        if !pending.is_empty() {
+            let x = position_x - (pending.chars().count() + 2) as f32 * column_w;
+            self.queue_status_text(&pending, x, y, fg);
+        }
+    }
 
-                if draw {
-                    gl::BufferData(
-                        gl::ARRAY_BUFFER,
-                        (self.cursor_coords.len() * mem::size_of::<Point3>()) as isize,
-                        self.cursor_coords.as_ptr() as *const c_void,
-                        gl::DYNAMIC_DRAW,
-                    );
-                }
+    fn queue_status_text(&mut self, text: &str, x: f32, y: f32, color: Color) {
+        queue_text_run(
+            &mut self.atlases.normal,
+            &mut self.status_text_vertices,
+            text,
+            color,
+            (x, y),
+            (self.sx, self.sy),
+        );
+    }
 
-                gl::VertexAttribPointer(
-                    attrib_ptr,
-                    3,
-                    gl::FLOAT,
-                    gl::FALSE,
-                    3 * mem::size_of::<f32>() as i32,
-                    null(),
-                );
-                gl::EnableVertexAttribArray(0);
-                gl::DrawArrays(gl::TRIANGLES, 0, 6);
-                gl::DisableVertexAttribArray(0);
+    /// Queue the popup's box below the cursor's line, or above it when it
+    /// doesn't fit between the cursor and the status line
+    fn queue_popup(&mut self) {
+        let Some(popup) = &self.popup else {
+            return;
+        };
+        let w = self.atlases.normal.max_w * self.sx;
+        let h = self.atlases.normal.max_h * self.sy;
+        let cols = popup
+            .lines
+            .iter()
+            .map(|line| line.text.chars().count())
+            .max()
+            .unwrap_or(0);
+        let width = (cols + 2) as f32 * w;
+        let height = popup.lines.len() as f32 * h;
 
-                gl::Enable(gl::BLEND);
-                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-                gl::BlendEquation(gl::FUNC_ADD);
+        let cursor_top = self.cursor_y();
+        // The status line is one row tall
+        let top = if cursor_top - h - height >= self.viewport.bottom() + h {
+            cursor_top - h
+        } else {
+            (cursor_top + height).min(self.viewport.y)
+        };
+        let left = self.start_x;
+        let right = (left + width).min(self.viewport.right());
+
+        let bg = *self.theme.bg();
+        let tint = Color {
+            a: 30,
+            ..*self.theme.fg()
+        };
+        self.popup_coords.clear();
+        self.popup_colors.clear();
+        for color in [bg, tint] {
+            push_rect(&mut self.popup_coords, left, top, right, top - height);
+            self.popup_colors.extend([color; 6]);
+        }
+        let selected = popup
+            .lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.location == Some(popup.selected));
+        for (i, _) in selected {
+            let row_top = top - i as f32 * h;
+            push_rect(&mut self.popup_coords, left, row_top, right, row_top - h);
+            self.popup_colors.extend([tint; 6]);
+        }
+
+        self.popup_text_vertices.clear();
+        let fg = *self.theme.fg();
+        for (i, line) in popup.lines.iter().enumerate() {
+            // Leave room for descenders below the baseline
+            let y = top - (i + 1) as f32 * h + h * 0.25;
+            queue_text_run(
+                &mut self.atlases.normal,
+                &mut self.popup_text_vertices,
+                &line.text,
+                fg,
+                (left + w, y),
+                (self.sx, self.sy),
+            );
+        }
+    }
+
+    /// Show the messages the language server sends from now on
+    fn watch_server_messages(&mut self, messages: Arc<RwLock<Vec<ServerMessage>>>) {
+        self.seen_server_messages = messages.read().unwrap().len();
+        self.server_messages = messages;
+    }
+
+    /// Pick up messages the language server sent since the last call, returns
+    /// true if the status line has to be drawn again because one came in or
+    /// the shown one timed out
+    pub fn update_server_message(&mut self, ticks_ms: u32) -> bool {
+        if let Some(children) = &mut self.children {
+            let first = children.0.update_server_message(ticks_ms);
+            let second = children.1.update_server_message(ticks_ms);
+            return first || second;
+        }
+
+        let messages = self.server_messages.read().unwrap();
+        if messages.len() > self.seen_server_messages {
+            self.seen_server_messages = messages.len();
+            self.server_message = messages.last().cloned().map(|msg| (msg, ticks_ms));
+            return true;
+        }
+        drop(messages);
+
+        match &self.server_message {
+            Some((_, received)) if ticks_ms.saturating_sub(*received) >= SERVER_MESSAGE_MS => {
+                self.server_message = None;
+                true
             }
+            _ => false,
         }
     }
 
-    pub fn queue_diagnostics(&mut self) {
-        let d = self.diagnostics.read().unwrap();
-        if self.last_clock != d.clock {
-            let mut coords: Vec<Point3> = Vec::new();
-            let mut colors: Vec<Color> = Vec::new();
+    /// Open this pane's buffer with the language server, which reads it from
+    /// the editor instead of the file from then on. Buffers stay open while
+    /// they're hidden
+    fn open_document(&self) {
+        let buffers = self.buffers.borrow();
+        let language_id = buffers
+            .path(self.buffer)
+            .and_then(Path::extension)
+            .and_then(OsStr::to_str)
+            .and_then(syntax::language_id);
+        if let (Some(url), Some(language_id)) = (buffers.url(self.buffer), language_id) {
+            self.lsp_send.open_document(
+                url.clone(),
+                language_id,
+                self.editor.borrow().text_all().to_string(),
+            );
+        }
+    }
 
-            let mut col = 0;
-            for diag in &d.diagnostics {
-                let max_w = self.atlas.max_w * SX;
-                let max_h = self.atlas.max_h;
+    /// Send the text to the language server after it was edited
+    fn sync_document(&self) {
+        if let Some(url) = self.buffers.borrow().url(self.buffer) {
+            self.lsp_send
+                .change_document(url, self.editor.borrow().text_all().to_string());
+        }
+    }
 
-                let mut x = START_X;
-                let mut y = START_Y;
+    fn request_semantic_tokens(&self) {
+        if let Some(url) = self.buffers.borrow().url(self.buffer) {
+            self.lsp_send.request_semantic_tokens(url.clone());
+        }
+    }
 
-                let mut top_left: Point3 = Point3::null();
-                let mut bot_left: Point3 = Point3::null();
+    /// Color the text with the semantic tokens the language server sent since
+    /// the last call, returns true if they're for this pane's buffer and the
+    /// text has to be drawn again
+    pub fn update_semantic_tokens(&mut self) -> bool {
+        if let Some(children) = &mut self.children {
+            let first = children.0.update_semantic_tokens();
+            let second = children.1.update_semantic_tokens();
+            return first || second;
+        }
 
-                let lsp::Range {
-                    start: start_pos,
-                    end: end_pos,
-                } = diag.range;
-                let start = self.editor.line_idx(start_pos.line as usize);
-                let end = self
-                    .editor
-                    .line_char_idx(end_pos.line as usize, end_pos.character as usize);
+        let tokens = self.lsp_send.semantic_tokens().clone();
+        let tokens = tokens.read().unwrap();
+        if tokens.clock == self.semantic_clock {
+            return false;
+        }
+        self.semantic_clock = tokens.clock;
+        let uri_buffer = tokens
+            .uri
+            .as_ref()
+            .and_then(|uri| self.buffers.borrow().find_url(uri));
+        if uri_buffer != Some(self.buffer) {
+            return false;
+        }
 
-                let within_range = |i: usize| -> bool {
-                    (i + start) >= (start + start_pos.character as usize) && (i + start) < end
-                };
+        self.highlighter.borrow_mut().set_semantic_tokens(
+            self.editor.borrow().text_all(),
+            &tokens.tokens,
+            self.theme,
+        );
+        self.queue_text(self.start_x, self.start_y, self.sx, self.sy);
+        true
+    }
 
-                for (i, ch) in self.editor.text(start..(end + 1)).chars().enumerate() {
-                    let c = ch as usize;
-
-                    // Calculate the vertex and texture coordinates
-                    let x2 = x + (col as f32 * max_w);
-                    // let x2 = x + max_w;
-                    let y2 = -y;
-                    let width = self.atlas.glyphs[c].bitmap_w * SX;
-                    let height = self.atlas.glyphs[c].bitmap_h * SY;
-
-                    // Skip glyphs that have no pixels
-                    if (width == 0.0 || height == 0.0) && !within_range(i) {
-                        match ch as u8 {
-                            32 => {
-                                col += 1;
-                            }
-                            // Tab
-                            9 => {
-                                x += self.atlas.max_w * SY * 4f32;
-                                col += 4;
-                            }
-                            // New line
-                            10 => {
-                                y -= max_h;
-                                x = START_X;
-                                if !top_left.is_null() {
-                                    let bot_right = Point3 {
-                                        x: x2,
-                                        y: -y2 + max_h,
-                                        z: 0.0,
-                                    };
-                                    let top_right = Point3 {
-                                        x: x2,
-                                        y: -y2,
-                                        z: 0.0,
-                                    };
-                                    // First triangle
-                                    coords.push(top_left.clone());
-                                    coords.push(bot_left.clone());
-                                    coords.push(bot_right.clone());
-                                    // Second triangle
-                                    coords.push(top_left.clone());
-                                    coords.push(top_right);
-                                    coords.push(bot_right);
-                                    colors.push(ERROR_RED);
-                                    colors.push(ERROR_RED);
-                                    colors.push(ERROR_RED);
-                                    colors.push(ERROR_RED);
-                                    colors.push(ERROR_RED);
-                                    colors.push(ERROR_RED);
-
-                                    top_left = Point3::null();
-                                    bot_left = Point3::null();
-                                }
-                                col = 0;
-                            }
-                            _ => {}
-                        }
-                        continue;
-                    }
+    /// Ask for the inlay hints of the lines around the ones on screen, a page
+    /// above and below so scrolling a bit doesn't leave them out
+    fn request_inlay_hints(&self) {
+        let Some(url) = self.buffers.borrow().url(self.buffer).cloned() else {
+            return;
+        };
+        let page = self
+            .viewport
+            .lines(self.atlases.normal.max_h, self.screen_height);
+        let first_row = (-self.y_offset / self.atlases.normal.max_h).max(0.0) as usize;
+        // Lines never start after their row, so this is at or above the screen
+        let first = first_row.saturating_sub(page);
+        let last = (first_row + page * 2).min(self.editor.borrow().lines().len());
+        self.lsp_send.request_inlay_hints(
+            url,
+            lsp::Range::new(
+                Position::new(first as u32, 0),
+                Position::new(last as u32, 0),
+            ),
+        );
+    }
 
-                    if top_left.is_null() && within_range(i) {
-                        top_left = Point3 {
-                            x: x2,
-                            y: -y2,
-                            z: 0.0,
-                        };
-                        bot_left = Point3 {
-                            x: x2,
-                            y: -y2 + max_h,
-                            z: 0.0,
-                        };
-                    } else if !top_left.is_null() && !within_range(i) {
-                        let bot_right = Point3 {
-                            x: x2,
-                            y: -y2 + max_h,
-                            z: 0.0,
-                        };
-                        let top_right = Point3 {
-                            x: x2,
-                            y: -y2,
-                            z: 0.0,
-                        };
-                        // First triangle
-                        coords.push(top_left.clone());
-                        coords.push(bot_left.clone());
-                        coords.push(bot_right.clone());
-                        // Second triangle
-                        coords.push(top_left.clone());
-                        coords.push(top_right);
-                        coords.push(bot_right);
-                        colors.push(ERROR_RED);
-                        colors.push(ERROR_RED);
-                        colors.push(ERROR_RED);
-                        colors.push(ERROR_RED);
-                        colors.push(ERROR_RED);
-                        colors.push(ERROR_RED);
-                        break;
-                    } else if i + start >= end {
-                        println!("BREKING");
-                        if !top_left.is_null() {
-                            let bot_right = Point3 {
-                                x: x2,
-                                y: -y2 + max_h,
-                                z: 0.0,
-                            };
-                            let top_right = Point3 {
-                                x: x2,
-                                y: -y2,
-                                z: 0.0,
-                            };
-                            // First triangle
-                            coords.push(top_left.clone());
-                            coords.push(bot_left.clone());
-                            coords.push(bot_right.clone());
-                            // Second triangle
-                            coords.push(top_left.clone());
-                            coords.push(top_right);
-                            coords.push(bot_right);
-                            colors.push(ERROR_RED);
-                            colors.push(ERROR_RED);
-                            colors.push(ERROR_RED);
-                            colors.push(ERROR_RED);
-                            colors.push(ERROR_RED);
-                            colors.push(ERROR_RED);
-                        }
-                        break;
-                    }
-                    col += 1;
-                }
-            }
+    /// Queue the inlay hints the language server sent since the last call,
+    /// returns true if they're for this pane's buffer and have to be drawn.
+    /// Hints for edited text are asked for here, after `HINTS_IDLE_MS` without
+    /// a keystroke
+    pub fn update_inlay_hints(&mut self, ticks_ms: u32) -> bool {
+        if let Some(children) = &mut self.children {
+            let first = children.0.update_inlay_hints(ticks_ms);
+            let second = children.1.update_inlay_hints(ticks_ms);
+            return first || second;
+        }
 
-            self.diagnostics_coords = coords;
-            self.diagnostics_colors = colors;
-            self.last_clock = d.clock;
+        if self.hints_stale && ticks_ms.saturating_sub(self.last_stroke) >= HINTS_IDLE_MS {
+            self.hints_stale = false;
+            self.request_inlay_hints();
+        }
+
+        let clock = self.lsp_send.inlay_hints().read().unwrap().clock;
+        if clock == self.hint_clock {
+            return false;
         }
+        self.hint_clock = clock;
+        self.queue_inlay_hints()
     }
 
-    fn queue_selection(&mut self, mut x: f32, mut y: f32, sx: f32, sy: f32) {
-        if self.editor.selection().is_none() {
-            self.highlight_coords.clear();
+    pub fn queue_diagnostics(&mut self) {
+        if let Some(children) = &mut self.children {
+            children.0.queue_diagnostics();
+            children.1.queue_diagnostics();
             return;
         }
 
-        let mut hl_coords: Vec<Point3> = Vec::new();
+        let d = self.diagnostics.read().unwrap();
+        // Only this buffer's diagnostics changing queues them again
+        let clock = buffer_diagnostics_clock(&d, &self.buffers.borrow(), self.buffer);
+        if self.last_clock != clock {
+            let mut coords: Vec<Point3> = Vec::new();
+            let mut colors: Vec<Color> = Vec::new();
 
-        let starting_x = x;
-        let max_w = self.atlas.max_w * sx;
-        let max_h = self.atlas.max_h * sy;
-
-        let mut top_left: Point3 = Point3::null();
-        let mut bot_left: Point3 = Point3::null();
-
-        let mut col: u32 = 0;
-        for (i, ch) in self.editor.text_all().chars().enumerate() {
-            let c = ch as usize;
-
-            // Calculate the vertex and texture coordinates
-            let x2 = x + (col as f32 * max_w);
-            // let x2 = x + max_w;
-            let y2 = -y;
-            let width = self.atlas.glyphs[c].bitmap_w * sx;
-            let height = self.atlas.glyphs[c].bitmap_h * sy;
-
-            // Skip glyphs that have no pixels
-            if (width == 0.0 || height == 0.0) && !self.editor.past_selection(i as u32) {
-                match ch as u8 {
-                    32 => {
-                        col += 1;
+            let h = self.atlases.normal.max_h * self.sy;
+            let editor = self.editor.borrow();
+            let text = editor.text_all();
+            let buffers = self.buffers.borrow();
+            for diag in buffer_diagnostics(&d, &buffers, self.buffer) {
+                let color = *self
+                    .theme
+                    .diagnostic_color(diag.severity.unwrap_or(DiagnosticSeverity::ERROR));
+                // The text can have changed since the server looked at it
+                let char_idx = |pos: Position| {
+                    let line = pos.line as usize;
+                    if line >= text.len_lines() {
+                        return text.len_chars();
                     }
-                    // Tab
-                    9 => {
-                        x += self.atlas.max_w * sy * 4f32;
-                        col += 4;
-                    }
-                    // New line
-                    10 => {
-                        y -= max_h;
-                        x = starting_x;
-                        if !top_left.is_null() {
-                            let bot_right = Point3 {
-                                x: x2,
-                                y: -y2 + max_h,
-                                z: 0.0,
-                            };
-                            let top_right = Point3 {
-                                x: x2,
-                                y: -y2,
-                                z: 0.0,
-                            };
-                            // First triangle
-                            hl_coords.push(top_left.clone());
-                            hl_coords.push(bot_left.clone());
-                            hl_coords.push(bot_right.clone());
-                            // Second triangle
-                            hl_coords.push(top_left.clone());
-                            hl_coords.push(top_right);
-                            hl_coords.push(bot_right);
-
-                            top_left = Point3::null();
-                            bot_left = Point3::null();
-                        }
-                        col = 0;
-                    }
-                    _ => {}
+                    let line_len = text.line(line).len_chars();
+                    text.line_to_char(line) + line_len.min(pos.character as usize)
+                };
+                let start = char_idx(diag.range.start);
+                let end = char_idx(diag.range.end);
+                for (left, right, top) in self.span_rows(start, end) {
+                    push_rect(&mut coords, left, top, right, top - h);
+                    colors.extend([color; 6]);
                 }
-                continue;
             }
 
-            if top_left.is_null() && self.editor.within_selection(i as u32) {
-                top_left = Point3 {
-                    x: x2,
-                    y: -y2,
-                    z: 0.0,
-                };
-                bot_left = Point3 {
-                    x: x2,
-                    y: -y2 + max_h,
+            self.diagnostics_coords = coords;
+            self.diagnostics_colors = colors;
+            self.last_clock = clock;
+        }
+    }
+
+    /// Queue a thin bar in the gutter for every line that has a sign
+    fn queue_signs(&mut self) {
+        let mut coords: Vec<Point3> = Vec::new();
+        let mut colors: Vec<Color> = Vec::new();
+
+        let real_h = self.atlases.normal.max_h * self.sy;
+        let x = self.viewport.x;
+        let w = 3f32 * self.sx;
+
+        // The gutter's background goes first so the signs are drawn over it,
+        // it hides text scrolled sideways under the line numbers
+        if self.line_number_mode != LineNumberMode::None {
+            let right = self.start_x - PADDING_X * self.sx;
+            let top = self.start_y + real_h;
+            let bot = self.start_y
+                - self
+                    .line_rows
+                    .last()
+                    .map_or(self.editor.borrow().lines().len(), |rows| *rows)
+                    as f32
+                    * real_h;
+            coords.extend([
+                Point3 { x, y: top, z: 0.0 },
+                Point3 { x, y: bot, z: 0.0 },
+                Point3 {
+                    x: right,
+                    y: bot,
                     z: 0.0,
-                };
-            } else if !top_left.is_null() && !self.editor.within_selection(i as u32) {
-                let bot_right = Point3 {
-                    x: x2,
-                    y: -y2 + max_h,
+                },
+                Point3 { x, y: top, z: 0.0 },
+                Point3 {
+                    x: right,
+                    y: top,
                     z: 0.0,
-                };
-                let top_right = Point3 {
-                    x: x2,
-                    y: -y2,
+                },
+                Point3 {
+                    x: right,
+                    y: bot,
                     z: 0.0,
-                };
-                // First triangle
-                hl_coords.push(top_left.clone());
-                hl_coords.push(bot_left.clone());
-                hl_coords.push(bot_right.clone());
-                // Second triangle
-                hl_coords.push(top_left.clone());
-                hl_coords.push(top_right);
-                hl_coords.push(bot_right);
-                break;
-            } else if self.editor.past_selection(i as u32) {
-                break;
+                },
+            ]);
+            colors.extend([*self.theme.bg(); 6]);
+        }
+
+        for (ns, line) in self.editor.borrow().signs().iter() {
+            let color = match ns {
+                SignNamespace::Changed => CHANGED_GRAY,
+            };
+            let y = (self.start_y + real_h) - (line as f32 * real_h);
+
+            // First triangle
+            coords.push(Point3 { x, y, z: 0.0 });
+            coords.push(Point3 {
+                x,
+                y: y - real_h,
+                z: 0.0,
+            });
+            coords.push(Point3 {
+                x: x + w,
+                y: y - real_h,
+                z: 0.0,
+            });
+            // Second triangle
+            coords.push(Point3 { x, y, z: 0.0 });
+            coords.push(Point3 {
+                x: x + w,
+                y,
+                z: 0.0,
+            });
+            coords.push(Point3 {
+                x: x + w,
+                y: y - real_h,
+                z: 0.0,
+            });
+            colors.extend([color; 6]);
+        }
+
+        self.signs_coords = coords;
+        self.signs_colors = colors;
+    }
+
+    /// Queue the number of each line `queue_text` queued right aligned in
+    /// the gutter, next to the first row of the line when it's wrapped
+    fn queue_line_numbers(&mut self) {
+        self.line_number_vertices.clear();
+
+        let cursor_line = self.editor.borrow().line();
+        let w = self.atlases.normal.max_w * self.sx;
+        let h = self.atlases.normal.max_h * self.sy;
+        let right = self.start_x - w;
+        for line in self.queued_lines.clone() {
+            let number = match self.line_number_mode.label(line, cursor_line) {
+                Some(number) => number.to_string(),
+                None => return,
+            };
+            let color = if line == cursor_line {
+                *self.theme.fg()
+            } else {
+                *self.theme.line_number()
+            };
+            let (_, top) = self.logical_to_screen(line, 0);
+            let mut x = right - number.len() as f32 * w;
+            self.atlases.normal.load(number.chars());
+            for ch in number.chars() {
+                if let Some(glyph) = self.atlases.normal.glyph(ch) {
+                    self.line_number_vertices.push_glyph(
+                        &self.atlases.normal,
+                        glyph,
+                        (x, top - h),
+                        (self.sx, self.sy),
+                        color,
+                    );
+                }
+                x += w;
+            }
+        }
+    }
+
+    fn queue_selection(&mut self) {
+        let (start, end) = match self.editor.borrow().selection() {
+            Some((a, b)) => (a.min(b) as usize, a.max(b) as usize),
+            None => {
+                self.highlight_coords.clear();
+                return;
+            }
+        };
+
+        let h = self.atlases.normal.max_h * self.sy;
+        let rows = self.span_rows(start, end + 1);
+
+        self.highlight_coords.clear();
+        for (left, right, top) in rows {
+            push_rect(&mut self.highlight_coords, left, top, right, top - h);
+        }
+    }
+
+    /// Queue a rectangle behind each row of every search match in the lines
+    /// `queue_text` queued. The matches are only found again when the text,
+    /// the pattern, the lines or the layout (`relayout`) changed, otherwise
+    /// this just finds the current one
+    fn queue_search_highlights(&mut self, relayout: bool) {
+        let key = self.editor.borrow().search_pattern().map(|pattern| {
+            (
+                self.editor.borrow().revision(),
+                pattern.to_string(),
+                self.queued_lines.clone(),
+            )
+        });
+        if key.is_none() || relayout || key != self.search_key {
+            let h = self.atlases.normal.max_h * self.sy;
+            self.search_coords.clear();
+            self.search_matches.clear();
+            for chars in self
+                .editor
+                .borrow()
+                .search_matches(self.queued_lines.clone())
+            {
+                let start = self.search_coords.len();
+                // Matches across wrapped rows get a rectangle for each
+                for (left, right, top) in self.span_rows(chars.start, chars.end) {
+                    push_rect(&mut self.search_coords, left, top, right, top - h);
+                }
+                self.search_matches
+                    .push((chars, start..self.search_coords.len()));
             }
-            col += 1;
+            self.search_key = key;
         }
 
-        self.highlight_coords = hl_coords;
+        let editor = self.editor.borrow();
+        let pos = editor.line_char_idx(editor.line(), editor.cursor());
+        self.current_match = self
+            .search_matches
+            .iter()
+            .find(|(chars, _)| chars.contains(&pos))
+            .map_or(0..0, |(_, vertices)| vertices.clone());
     }
 
-    fn queue_text(&mut self, colors: Vec<&Color>, mut x: f32, mut y: f32, sx: f32, sy: f32) {
-        let text = self.editor.text_all();
+    /// Queue the lines on screen and a page either side of them, so
+    /// scrolling a bit doesn't have to queue them again. Only their chars
+    /// are loaded into the atlases
+    fn queue_text(&mut self, mut x: f32, mut y: f32, sx: f32, sy: f32) {
+        let visible = self.visible_lines();
+        let editor = self.editor.borrow();
+        let text = editor.text_all();
+        let lines = visible.start.saturating_sub(visible.len())
+            ..(visible.end + visible.len()).min(text.len_lines());
+        let chars = text.line_to_char(lines.start)..text.line_to_char(lines.end);
+        let slice = text.slice(chars.clone());
         let starting_x = x;
+        y -= self.screen_pos(lines.start, 0).0 as f32 * self.atlases.normal.max_h * sy;
+        self.atlases.normal.load(slice.chars());
+        for (i, ch) in chars.clone().zip(slice.chars()) {
+            let style = self.highlighter.borrow().style(i);
+            if style != FontStyle::Normal {
+                self.atlases.get_mut(style).load([ch]);
+            }
+        }
 
         // TODO: Cache this
-        let mut coords: Vec<Point> = Vec::with_capacity(6 * text.len_chars());
-        let mut colors_vertex: Vec<Color> = Vec::with_capacity(coords.capacity());
+        // The vertices and their colors for each style, they're drawn with
+        // different textures
+        let mut styles: [TextVertices; 4] = Default::default();
 
-        let mut text_height = 0.0;
-        let mut line_width = 0.0;
+        // Where the rows of the current line start when wrapping
+        let row_starts = |line: usize| match self.wrap_col {
+            Some(_) if line < self.editor.borrow().lines().len() => self.row_starts(line),
+            _ => vec![0],
+        };
+        let mut line = lines.start;
+        let mut col = 0;
+        let mut starts = row_starts(line);
+        let mut next_row = 1;
 
-        for (i, ch) in text.chars().enumerate() {
-            let c = ch as usize;
+        for (i, ch) in chars.zip(slice.chars()) {
+            // The text moves on as far as the regular face says so the other
+            // styles stay on the same columns
+            let advance = self.atlases.normal.glyph(ch).cloned().unwrap_or_default();
+            let style = self.highlighter.borrow().style(i);
+            let atlas = self.atlases.get(style);
+            let glyph = atlas.glyph(ch).unwrap_or(&advance);
 
-            // Calculate the vertex and texture coordinates
-            let x2 = x + self.atlas.glyphs[c].bitmap_l * sx;
-            let y2 = -y - self.atlas.glyphs[c].bitmap_t * sy;
-            let width = self.atlas.glyphs[c].bitmap_w * sx;
-            let height = self.atlas.glyphs[c].bitmap_h * sy;
+            if ch != '\n' {
+                if starts.get(next_row) == Some(&col) {
+                    y -= self.atlases.normal.max_h * sy;
+                    x = starting_x + WRAP_INDENT as f32 * self.atlases.normal.max_w * sx;
+                    next_row += 1;
+                }
+                col += 1;
+            }
+            let origin = (x, y);
 
             // Advance the cursor to the start of the next character
-            x += self.atlas.glyphs[c].advance_x * sx;
-            y += self.atlas.glyphs[c].advance_y * sy;
-
-            line_width += self.atlas.glyphs[c].advance_x;
+            x += advance.advance_x * sx;
+            y += advance.advance_y * sy;
 
-            // Skip glyphs that have no pixels
-            if width == 0.0 || height == 0.0 {
-                match ch as u8 {
-                    // Tab
-                    9 => {
-                        x += self.atlas.max_w * sy * 4f32;
-                    }
-                    // New line
-                    10 => {
-                        y -= self.atlas.max_h * sy;
-                        text_height += self.atlas.max_h;
-                        self.text_height = self.text_height.max(text_height);
-                        line_width = 0.0;
-                        x = starting_x;
-                    }
-                    _ => {}
+            match ch {
+                '\t' => {
+                    x += self.tab_advance(x - starting_x);
                 }
+                '\n' => {
+                    y -= self.atlases.normal.max_h * sy;
+                    x = starting_x;
+                    line += 1;
+                    col = 0;
+                    starts = row_starts(line);
+                    next_row = 1;
+                }
+                _ => {
+                    styles[style as usize].push_glyph(
+                        atlas,
+                        glyph,
+                        origin,
+                        (sx, sy),
+                        self.highlighter.borrow().color(i),
+                    );
+                }
+            }
+        }
+
+        let [mut vertices, rest @ ..] = styles;
+        for other in rest {
+            vertices.append(other);
+        }
+        self.text_vertices = vertices;
+        self.queued_lines = lines;
+        drop(editor);
+        self.queue_inlay_hints();
+    }
+
+    /// Queue each inlay hint right after the char it's at. It's a pass of its
+    /// own so the text isn't moved over for it, the hint is drawn on top of
+    /// what follows. Returns false if the hints aren't for this buffer
+    fn queue_inlay_hints(&mut self) -> bool {
+        let hints = self.lsp_send.inlay_hints().clone();
+        let hints = hints.read().unwrap();
+        let uri_buffer = hints
+            .uri
+            .as_ref()
+            .and_then(|uri| self.buffers.borrow().find_url(uri));
+        // Another pane's hints keep this one's on screen
+        if uri_buffer != Some(self.buffer) {
+            return false;
+        }
+        self.hint_vertices.clear();
+
+        let editor = self.editor.borrow();
+        let text = editor.text_all();
+        let h = self.atlases.normal.max_h * self.sy;
+        let color = self.theme.inlay_hint();
+        for hint in hints.hints.iter() {
+            let Some((line, col)) = char_position(text, hint.position) else {
                 continue;
+            };
+            let (x, top) = self.logical_to_screen(line, col);
+            queue_text_run(
+                &mut self.hint_atlas,
+                &mut self.hint_vertices,
+                &hint.text(),
+                color,
+                (x, top - h),
+                (self.sx, self.sy),
+            );
+        }
+        true
+    }
+
+    fn adjust_scroll(&mut self) {
+        let (row, _) = self.screen_pos(self.editor.borrow().line(), self.editor.borrow().cursor());
+        let oy = self.row_y_offset(row);
+        // The same height `Viewport::lines` fits lines in, so it doesn't
+        // depend on the display's pixel density. Subtract the status line so
+        // the cursor never scrolls behind it
+        let scrolled_h = self.screen_height as f32 * self.viewport.h - self.atlases.normal.max_h
+            + (self.y_offset * -1.0);
+
+        if oy >= scrolled_h || oy < self.y_offset * -1.0 {
+            self.y_offset = oy * -1.0;
+        }
+        self.adjust_scroll_x();
+    }
+
+    /// Scroll sideways so the cursor is on screen, wrapped text always is
+    fn adjust_scroll_x(&mut self) {
+        if self.wrap || self.wrap_col.is_some() {
+            return;
+        }
+        let x =
+            self.advance_width_up_to(self.editor.borrow().line(), self.editor.borrow().cursor());
+        let w = self.cursor_width(x);
+        let visible = self.viewport.right() - self.start_x - PADDING_X * self.sx;
+        self.x_offset = scroll_into_view(self.x_offset * self.sx, x, x + w, visible) / self.sx;
+    }
+}
+
+// This impl contains small utilities
+impl<'theme, 'highlight> Window<'theme, 'highlight> {
+    pub fn theme(&self) -> &ThemeType {
+        self.theme
+    }
+
+    /// Show `message` in the status bar until the next key press
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    // Get the y offset (scroll pos) for the given row
+    #[inline]
+    fn row_y_offset(&self, row: usize) -> f32 {
+        (self.atlases.normal.max_h as f32 * row as f32) - self.start_y
+    }
+}
+
+/// The highlighting for the file at `path`, files without a grammar are
+/// shown as plain text
+fn highlight_config(path: Option<&Path>) -> Option<&'static HighlightConfiguration> {
+    path.and_then(Path::extension)
+        .and_then(OsStr::to_str)
+        .and_then(syntax::config_for_extension)
+        .map(|cfg| &**cfg)
+}
+
+/// The diagnostics that belong to `buffer`
+fn buffer_diagnostics<'a>(
+    diagnostics: &'a Diagnostics,
+    buffers: &Buffers,
+    buffer: BufferId,
+) -> &'a [Diagnostic] {
+    match buffers.url(buffer) {
+        Some(url) => diagnostics.get(url),
+        None => &[],
+    }
+}
+
+/// The clock of the last diagnostics published for `buffer`'s file
+fn buffer_diagnostics_clock(diagnostics: &Diagnostics, buffers: &Buffers, buffer: BufferId) -> u64 {
+    buffers
+        .url(buffer)
+        .map_or(1, |url| diagnostics.file_clock(url))
+}
+
+/// The diagnostic the cursor is in, or the first one on the cursor's line when
+/// it's in none of them
+fn diagnostic_at(diagnostics: &[Diagnostic], line: usize, col: usize) -> Option<&Diagnostic> {
+    let pos = (line as u32, col as u32);
+    let on_line = diagnostics
+        .iter()
+        .filter(|diag| diag.range.start.line <= pos.0 && pos.0 <= diag.range.end.line);
+    let start = |diag: &Diagnostic| (diag.range.start.line, diag.range.start.character);
+    let end = |diag: &Diagnostic| (diag.range.end.line, diag.range.end.character);
+    on_line
+        .clone()
+        .find(|diag| start(diag) <= pos && pos <= end(diag))
+        .or_else(|| on_line.clone().next())
+}
+
+/// The line and char column of an LSP position, whose column counts UTF-16
+/// code units. Columns past the end of the line are moved back to it, `None`
+/// if the line isn't in `text`
+fn char_position(text: RopeSlice, pos: Position) -> Option<(usize, usize)> {
+    let line = pos.line as usize;
+    if line >= text.len_lines() {
+        return None;
+    }
+    let line_text = text.line(line);
+    let mut len = line_text.len_chars();
+    while len > 0 && matches!(line_text.char(len - 1), '\n' | '\r') {
+        len -= 1;
+    }
+    let character = (pos.character as usize).min(line_text.char_to_utf16_cu(len));
+    Some((line, line_text.utf16_cu_to_char(character)))
+}
+
+/// The buffer after `current` in `buffers` in the order they were opened, or
+/// before it if not `forward`. Wraps around at either end
+fn cycle_buffers(buffers: &[BufferId], current: BufferId, forward: bool) -> BufferId {
+    let mut buffers = buffers.to_vec();
+    buffers.sort();
+    let Some(i) = buffers.iter().position(|buffer| *buffer == current) else {
+        return current;
+    };
+    let len = buffers.len();
+    if forward {
+        buffers[(i + 1) % len]
+    } else {
+        buffers[(i + len - 1) % len]
+    }
+}
+
+/// The buffer out of `buffers` with unsaved changes that's reported first,
+/// `current` if it has them and otherwise the first opened one
+fn first_modified(buffers: &[(BufferId, bool)], current: BufferId) -> Option<BufferId> {
+    if buffers.contains(&(current, true)) {
+        return Some(current);
+    }
+    buffers
+        .iter()
+        .filter(|(_, modified)| *modified)
+        .map(|(buffer, _)| *buffer)
+        .min()
+}
+
+/// The value `:set {option}` gives the flag `name` that's `value` now, `None`
+/// if the option isn't for that flag
+fn flag_value(option: &str, name: &str, value: bool) -> Option<bool> {
+    if option == name {
+        Some(true)
+    } else if option.strip_prefix("no") == Some(name) {
+        Some(false)
+    } else if option.strip_suffix('!') == Some(name) || option.strip_prefix("inv") == Some(name) {
+        Some(!value)
+    } else {
+        None
+    }
+}
+
+/// The row of a wrapped line `col` is on, and its column in that row
+fn wrapped_pos(row_starts: &[usize], col: usize) -> (usize, usize) {
+    let row = row_starts
+        .iter()
+        .rposition(|start| *start <= col)
+        .unwrap_or(0);
+    let indent = if row > 0 { WRAP_INDENT } else { 0 };
+    (row, col - row_starts[row] + indent)
+}
+
+/// The width and height of the text in pixels, from the length of each line
+fn text_bounds(lines: &[u32], max_w: f32, max_h: f32) -> (f32, f32) {
+    let max_cols = lines.iter().copied().max().unwrap_or(0);
+    (max_cols as f32 * max_w, lines.len() as f32 * max_h)
+}
+
+/// Scroll `offset` by `amount` without going past the start (0) or the end
+/// (`-bound`), returns the new offset and whether it had to be clamped
+fn clamp_scroll(offset: f32, amount: f32, bound: f32) -> (f32, bool) {
+    let scrolled = offset + amount;
+    if amount > 0.0 && scrolled >= 0.0 {
+        (0.0, true)
+    } else if amount <= 0.0 && -scrolled >= bound {
+        (-bound, true)
+    } else {
+        (scrolled, false)
+    }
+}
+
+/// The scroll `offset` that shows `start..end` in the first `visible` of the
+/// text, moving as little as it can. Offsets are negative when scrolled right
+fn scroll_into_view(offset: f32, start: f32, end: f32, visible: f32) -> f32 {
+    if start < -offset {
+        -start
+    } else if end > visible - offset {
+        (visible - end).min(0.0)
+    } else {
+        offset
+    }
+}
+
+/// Where a tab `x` right of the start of its line ends, the next multiple of
+/// `stop` after it
+fn next_tab_stop(x: f32, stop: f32) -> f32 {
+    if stop <= 0.0 {
+        return x;
+    }
+    // A tab right on a stop still goes to the next one, even when `x` is a
+    // rounding error short of it
+    ((x / stop + 0.001).floor() + 1.0) * stop
+}
+
+/// The indentation level of each line in `lines`, blank lines take the level
+/// of the closest non-blank line above them
+fn indent_levels(text: RopeSlice, lines: Range<usize>, tab_width: usize) -> Vec<usize> {
+    // `None` for blank lines
+    let level = |line: usize| {
+        let mut cols = 0;
+        for ch in text.line(line).chars() {
+            match ch {
+                ' ' => cols += 1,
+                '\t' => cols += tab_width,
+                '\n' | '\r' => return None,
+                _ => return Some(cols / tab_width),
+            }
+        }
+        None
+    };
+
+    let mut prev = (0..lines.start).rev().find_map(level).unwrap_or(0);
+    lines
+        .map(|line| {
+            if let Some(level) = level(line) {
+                prev = level;
             }
+            prev
+        })
+        .collect()
+}
+
+/// Push the vertices of `text` in `color` with the first glyph's origin at
+/// `(x, y)`, with the font scaled by `(sx, sy)`
+fn queue_text_run(
+    atlas: &mut Atlas,
+    vertices: &mut TextVertices,
+    text: &str,
+    color: Color,
+    (mut x, y): (f32, f32),
+    (sx, sy): (f32, f32),
+) {
+    atlas.load(text.chars());
+    for ch in text.chars() {
+        let Some(glyph) = atlas.glyph(ch) else {
+            continue;
+        };
+        vertices.push_glyph(atlas, glyph, (x, y), (sx, sy), color);
+        x += glyph.advance_x * sx;
+    }
+}
+
+impl Clipboard for ClipboardUtil {
+    fn get(&self) -> Option<String> {
+        self.clipboard_text().ok()
+    }
+
+    fn set(&self, text: &str) {
+        if let Err(err) = self.set_clipboard_text(text) {
+            eprintln!("Failed to copy to the clipboard: {}", err);
+        }
+    }
+}
+
+/// The font size after zooming `steps` times from `size`, negative steps
+/// zoom out
+fn zoomed_font_size(size: u32, steps: i32) -> u32 {
+    let size = size as i32 + steps * FONT_SIZE_STEP as i32;
+    size.clamp(MIN_FONT_SIZE as i32, MAX_FONT_SIZE as i32) as u32
+}
+
+/// How far through the file `line` is, the last line is 100%
+fn percent_through(line: usize, lines: usize) -> usize {
+    (line + 1) * 100 / lines.max(1)
+}
+
+/// How much a pixel of the font is in normalized device coordinates, on a
+/// window `width` by `height` pixels. Text stays the same size on the screen
+/// as the window is resized
+fn text_scale(width: u32, height: u32) -> (f32, f32) {
+    (0.8 / width as f32, 0.8 / height as f32)
+}
+
+/// Push the two triangles of a rectangle
+fn push_rect(coords: &mut Vec<Point3>, left: f32, top: f32, right: f32, bot: f32) {
+    coords.extend([
+        Point3 {
+            x: left,
+            y: top,
+            z: 0.0,
+        },
+        Point3 {
+            x: left,
+            y: bot,
+            z: 0.0,
+        },
+        Point3 {
+            x: right,
+            y: bot,
+            z: 0.0,
+        },
+        Point3 {
+            x: left,
+            y: top,
+            z: 0.0,
+        },
+        Point3 {
+            x: right,
+            y: top,
+            z: 0.0,
+        },
+        Point3 {
+            x: right,
+            y: bot,
+            z: 0.0,
+        },
+    ]);
+}
+
+impl TextVertices {
+    fn clear(&mut self) {
+        self.coords.clear();
+        self.colors.clear();
+        self.runs.clear();
+    }
+
+    /// Push the vertices of `glyph` from `atlas` with its origin at (x, y),
+    /// returning false if the glyph has no pixels and nothing was pushed
+    fn push_glyph(
+        &mut self,
+        atlas: &Atlas,
+        glyph: &Glyph,
+        (x, y): (f32, f32),
+        (sx, sy): (f32, f32),
+        color: Color,
+    ) -> bool {
+        let x2 = x + glyph.bitmap_l * sx;
+        let y2 = -y - glyph.bitmap_t * sy;
+        let width = glyph.bitmap_w * sx;
+        let height = glyph.bitmap_h * sy;
 
-            coords.push(Point {
+        if width == 0.0 || height == 0.0 {
+            return false;
+        }
+
+        let s2 = glyph.tx + glyph.tw;
+        let t2 = glyph.ty + glyph.th;
+
+        let start = self.coords.len();
+        self.coords.extend([
+            Point {
                 x: x2,
                 y: -y2,
-                s: self.atlas.glyphs[c].tx,
-                t: self.atlas.glyphs[c].ty,
-            });
-            coords.push(Point {
+                s: glyph.tx,
+                t: glyph.ty,
+            },
+            Point {
                 x: x2 + width,
                 y: -y2,
-                s: self.atlas.glyphs[c].tx + self.atlas.glyphs[c].bitmap_w / self.atlas.w as f32,
-                t: self.atlas.glyphs[c].ty,
-            });
-            coords.push(Point {
+                s: s2,
+                t: glyph.ty,
+            },
+            Point {
                 x: x2,
                 y: -y2 - height,
-                s: self.atlas.glyphs[c].tx,
-                t: self.atlas.glyphs[c].ty + self.atlas.glyphs[c].bitmap_h / self.atlas.h as f32,
-            });
-            coords.push(Point {
+                s: glyph.tx,
+                t: t2,
+            },
+            Point {
                 x: x2 + width,
                 y: -y2,
-                s: self.atlas.glyphs[c].tx + self.atlas.glyphs[c].bitmap_w / self.atlas.w as f32,
-                t: self.atlas.glyphs[c].ty,
-            });
-            coords.push(Point {
+                s: s2,
+                t: glyph.ty,
+            },
+            Point {
                 x: x2,
                 y: -y2 - height,
-                s: self.atlas.glyphs[c].tx,
-                t: self.atlas.glyphs[c].ty + self.atlas.glyphs[c].bitmap_h / self.atlas.h as f32,
-            });
-            coords.push(Point {
+                s: glyph.tx,
+                t: t2,
+            },
+            Point {
                 x: x2 + width,
                 y: -y2 - height,
-                s: self.atlas.glyphs[c].tx + self.atlas.glyphs[c].bitmap_w / self.atlas.w as f32,
-                t: self.atlas.glyphs[c].ty + self.atlas.glyphs[c].bitmap_h / self.atlas.h as f32,
-            });
+                s: s2,
+                t: t2,
+            },
+        ]);
+        self.colors.extend([color; 6]);
 
-            colors_vertex.push(*colors[i]);
-            colors_vertex.push(*colors[i]);
-            colors_vertex.push(*colors[i]);
-            colors_vertex.push(*colors[i]);
-            colors_vertex.push(*colors[i]);
-            colors_vertex.push(*colors[i]);
+        let tex = atlas.tex(glyph);
+        match self.runs.last_mut() {
+            Some((last, run)) if *last == tex => run.end = self.coords.len(),
+            _ => self.runs.push((tex, start..self.coords.len())),
         }
-
-        // TODO: It's faster to directly mutate these vecs instead of making
-        // new ones and replacing them. Also if we're only appending new text we don't need to
-        // rebuild vecs in entirety
-        self.text_coords = coords;
-        self.text_colors = colors_vertex;
-
-        self.text_height = text_height;
-        self.text_width = self.text_width.max(line_width);
+        true
     }
 
-    fn queue_highlights(&mut self) -> Vec<&'theme Color> {
-        // TODO: Rope buffer is very inexpensive to clone (taking O(1) time),
-        // so we should just do that here.
-        let src: Vec<u8> = self.editor.text_all().bytes().collect();
-
-        // Assume chars are 1 byte long (ascii)
-        let mut text_colors: Vec<&Color> = vec![self.theme.fg(); src.len()];
-
-        let highlights = self
-            .highlighter
-            .highlight(self.highlight_cfg, &src, None, |_| None)
-            .unwrap();
-
-        let mut color_stack: Vec<&Color> = Vec::new();
+    /// Put `other`'s vertices after these
+    fn append(&mut self, other: TextVertices) {
+        let offset = self.coords.len();
+        self.coords.extend(other.coords);
+        self.colors.extend(other.colors);
+        self.runs.extend(
+            other
+                .runs
+                .into_iter()
+                .map(|(tex, run)| (tex, run.start + offset..run.end + offset)),
+        );
+    }
 
-        for event in highlights {
-            match event.unwrap() {
-                HighlightEvent::Source { start, end } => {
-                    if let Some(color) = color_stack.last() {
-                        (start..end).for_each(|i| {
-                            text_colors[i] = color;
-                        });
-                    }
-                }
-                HighlightEvent::HighlightStart(s) => {
-                    if let Some(highlight) = Highlight::from_u8(s.0 as u8) {
-                        color_stack.push(
-                            self.theme
-                                .highlight(highlight)
-                                .unwrap_or_else(|| self.theme.fg()),
-                        );
-                    } else {
-                        color_stack.push(self.theme.fg())
-                    }
-                }
-                HighlightEvent::HighlightEnd => {
-                    color_stack.pop();
-                }
+    /// Draw each run from its texture with `shader`, which has to be in use.
+    /// The vertices are only uploaded if `upload`, otherwise the buffers
+    /// still have them from the last frame
+    fn draw(&self, shader: &TextShaderProgram, upload: bool) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, shader.vbo);
+            gl::VertexAttribPointer(shader.attrib_coord, 4, gl::FLOAT, gl::FALSE, 0, null());
+            gl::EnableVertexAttribArray(shader.attrib_coord);
+            if upload {
+                gl::BufferData(
+                    gl::ARRAY_BUFFER,
+                    mem::size_of_val(self.coords.as_slice()) as GLsizeiptr,
+                    self.coords.as_ptr() as *const GLvoid,
+                    gl::DYNAMIC_DRAW,
+                );
             }
-        }
-
-        text_colors
-    }
 
-    fn adjust_scroll(&mut self) {
-        let oy = self.line_y_offset(self.editor.line());
-        let scrolled_h = SCREEN_HEIGHT as f32 * 2.0 + (self.y_offset * -1.0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, shader.vbo_color);
+            gl::VertexAttribPointer(
+                shader.attrib_v_color,
+                4,
+                gl::UNSIGNED_BYTE,
+                gl::TRUE,
+                0,
+                null(),
+            );
+            gl::EnableVertexAttribArray(shader.attrib_v_color);
+            if upload {
+                gl::BufferData(
+                    gl::ARRAY_BUFFER,
+                    mem::size_of_val(self.colors.as_slice()) as GLsizeiptr,
+                    self.colors.as_ptr() as *const GLvoid,
+                    gl::DYNAMIC_DRAW,
+                );
+            }
 
-        // Multiply by two because retina display on Mac
-        if oy >= scrolled_h || oy < self.y_offset * -1.0 {
-            self.y_offset = oy * -1.0;
+            for (tex, run) in self.runs.iter() {
+                gl::BindTexture(gl::TEXTURE_2D, *tex);
+                gl::DrawArrays(gl::TRIANGLES, run.start as i32, run.len() as i32);
+            }
+            gl::DisableVertexAttribArray(shader.attrib_v_color);
+            gl::DisableVertexAttribArray(shader.attrib_coord);
         }
     }
 }
 
-// This impl contains small utilities
-impl<'theme, 'highlight> Window<'theme, 'highlight> {
-    pub fn theme(&self) -> &ThemeType {
-        self.theme
-    }
-
-    // Get the y offset (scroll pos) for the given line
-    #[inline]
-    fn line_y_offset(&self, line: usize) -> f32 {
-        (self.atlas.max_h as f32 * line as f32) - START_Y
-    }
-}
-
 pub struct TextShaderProgram {
     program: GLProgram,
     attrib_coord: GLuint,
@@ -1034,6 +3825,7 @@ pub struct CursorShaderProgram {
     uniform_time: GLint,
     uniform_laststroke: GLint,
     uniform_is_blinking: GLint,
+    uniform_color: GLint,
     attrib_apos: GLuint,
     vbo: GLuint,
 }
@@ -1065,6 +3857,7 @@ impl CursorShaderProgram {
             uniform_time: program.uniform("time").unwrap(),
             uniform_laststroke: program.uniform("last_stroke").unwrap(),
             uniform_is_blinking: program.uniform("is_blinking").unwrap(),
+            uniform_color: program.uniform("color").unwrap(),
             program,
             vbo,
         }
@@ -1087,7 +3880,10 @@ pub struct HighlightShaderProgram {
     attrib_ytranslate: GLuint,
     attrib_xtranslate: GLuint,
     attrib_apos: GLuint,
+    uniform_color: GLint,
     vbo: GLuint,
+    // The selection is in `vbo` and the search matches in this
+    search_vbo: GLuint,
 }
 
 impl HighlightShaderProgram {
@@ -1108,14 +3904,20 @@ impl HighlightShaderProgram {
         let program = GLProgram::from_shaders(&shaders).unwrap();
 
         let mut vbo: GLuint = 0;
-        unsafe { gl::GenBuffers(1, &mut vbo as *mut GLuint) }
+        let mut search_vbo: GLuint = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut vbo as *mut GLuint);
+            gl::GenBuffers(1, &mut search_vbo as *mut GLuint);
+        }
 
         Self {
             attrib_apos: program.attrib("aPos").unwrap() as u32,
             attrib_ytranslate: program.attrib("y_translate").unwrap() as u32,
             attrib_xtranslate: program.attrib("x_translate").unwrap() as u32,
+            uniform_color: program.uniform("color").unwrap(),
             program,
             vbo,
+            search_vbo,
         }
     }
 
@@ -1131,6 +3933,106 @@ impl Default for HighlightShaderProgram {
     }
 }
 
+pub struct CursorLineShaderProgram {
+    program: GLProgram,
+    attrib_ytranslate: GLuint,
+    attrib_apos: GLuint,
+    uniform_color: GLint,
+    vbo: GLuint,
+}
+
+impl CursorLineShaderProgram {
+    pub fn new() -> Self {
+        let shaders = vec![
+            Shader::from_source(
+                &CString::new(include_str!("../shaders/cursorline.v.glsl")).unwrap(),
+                gl::VERTEX_SHADER,
+            )
+            .unwrap(),
+            Shader::from_source(
+                &CString::new(include_str!("../shaders/cursorline.f.glsl")).unwrap(),
+                gl::FRAGMENT_SHADER,
+            )
+            .unwrap(),
+        ];
+
+        let program = GLProgram::from_shaders(&shaders).unwrap();
+
+        let mut vbo: GLuint = 0;
+        unsafe { gl::GenBuffers(1, &mut vbo as *mut GLuint) }
+
+        Self {
+            attrib_apos: program.attrib("aPos").unwrap() as u32,
+            attrib_ytranslate: program.attrib("y_translate").unwrap() as u32,
+            uniform_color: program.uniform("color").unwrap(),
+            program,
+            vbo,
+        }
+    }
+
+    #[inline]
+    pub fn set_used(&self) {
+        self.program.set_used()
+    }
+}
+
+impl Default for CursorLineShaderProgram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct IndentGuidesShaderProgram {
+    program: GLProgram,
+    attrib_ytranslate: GLuint,
+    attrib_xtranslate: GLuint,
+    attrib_apos: GLuint,
+    uniform_color: GLint,
+    vbo: GLuint,
+}
+
+impl IndentGuidesShaderProgram {
+    pub fn new() -> Self {
+        let shaders = vec![
+            Shader::from_source(
+                &CString::new(include_str!("../shaders/indentguide.v.glsl")).unwrap(),
+                gl::VERTEX_SHADER,
+            )
+            .unwrap(),
+            Shader::from_source(
+                &CString::new(include_str!("../shaders/indentguide.f.glsl")).unwrap(),
+                gl::FRAGMENT_SHADER,
+            )
+            .unwrap(),
+        ];
+
+        let program = GLProgram::from_shaders(&shaders).unwrap();
+
+        let mut vbo: GLuint = 0;
+        unsafe { gl::GenBuffers(1, &mut vbo as *mut GLuint) }
+
+        Self {
+            attrib_apos: program.attrib("aPos").unwrap() as u32,
+            attrib_ytranslate: program.attrib("y_translate").unwrap() as u32,
+            attrib_xtranslate: program.attrib("x_translate").unwrap() as u32,
+            uniform_color: program.uniform("color").unwrap(),
+            program,
+            vbo,
+        }
+    }
+
+    #[inline]
+    pub fn set_used(&self) {
+        self.program.set_used()
+    }
+}
+
+impl Default for IndentGuidesShaderProgram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct DiagnosticShaderProgram {
     program: GLProgram,
     attrib_color: GLuint,
@@ -1187,3 +4089,375 @@ impl Default for DiagnosticShaderProgram {
         Self::new()
     }
 }
+
+/// Draws the status line pinned to the bottom of the window, a solid
+/// background rect plus its own instance of the text program so its
+/// vertex buffers don't clobber the buffer's text
+pub struct StatusLineShaderProgram {
+    program: GLProgram,
+    attrib_apos: GLuint,
+    attrib_color: GLuint,
+    vbo: GLuint,
+    vbo_color: GLuint,
+    text: TextShaderProgram,
+}
+
+impl StatusLineShaderProgram {
+    pub fn new() -> Self {
+        let shaders = vec![
+            Shader::from_source(
+                &CString::new(include_str!("../shaders/statusline.v.glsl")).unwrap(),
+                gl::VERTEX_SHADER,
+            )
+            .unwrap(),
+            Shader::from_source(
+                &CString::new(include_str!("../shaders/statusline.f.glsl")).unwrap(),
+                gl::FRAGMENT_SHADER,
+            )
+            .unwrap(),
+        ];
+
+        let program = GLProgram::from_shaders(&shaders).unwrap();
+
+        let mut vbo = 0;
+        let mut vbo_color = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut vbo as *mut GLuint);
+            gl::GenBuffers(1, &mut vbo_color as *mut GLuint);
+        }
+
+        Self {
+            attrib_apos: program.attrib("aPos").unwrap() as u32,
+            attrib_color: program.attrib("vertex_color").unwrap() as u32,
+            program,
+            vbo,
+            vbo_color,
+            text: TextShaderProgram::new(),
+        }
+    }
+
+    #[inline]
+    pub fn set_used(&self) {
+        self.program.set_used()
+    }
+}
+
+impl Default for StatusLineShaderProgram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ropey::Rope;
+
+    use super::*;
+
+    #[test]
+    fn line_number_labels() {
+        let labels =
+            |mode: LineNumberMode| (0..5).map(|line| mode.label(line, 2)).collect::<Vec<_>>();
+        assert_eq!(labels(LineNumberMode::None), [None; 5]);
+        assert_eq!(
+            labels(LineNumberMode::Absolute),
+            [Some(1), Some(2), Some(3), Some(4), Some(5)]
+        );
+        assert_eq!(
+            labels(LineNumberMode::Relative),
+            [Some(2), Some(1), Some(0), Some(1), Some(2)]
+        );
+        assert_eq!(
+            labels(LineNumberMode::Hybrid),
+            [Some(2), Some(1), Some(3), Some(1), Some(2)]
+        );
+    }
+
+    #[test]
+    fn split_viewport() {
+        let (top, bottom) = Viewport::FULL.split(SplitKind::Horizontal);
+        assert_eq!(top.bottom(), bottom.y);
+        assert!(top.is_neighbour(&bottom, Direction::Down));
+        assert!(bottom.is_neighbour(&top, Direction::Up));
+        assert!(!top.is_neighbour(&bottom, Direction::Right));
+
+        let (left, right) = bottom.split(SplitKind::Vertical);
+        assert_eq!(left.right(), right.x);
+        assert!(left.is_neighbour(&right, Direction::Right));
+        assert!(right.is_neighbour(&left, Direction::Left));
+        assert!(right.is_neighbour(&top, Direction::Up));
+        assert!(!top.is_neighbour(&right, Direction::Left));
+    }
+
+    #[test]
+    fn percent() {
+        assert_eq!(percent_through(0, 1), 100);
+        assert_eq!(percent_through(0, 4), 25);
+        assert_eq!(percent_through(2, 3), 100);
+        assert_eq!(percent_through(49, 200), 25);
+    }
+
+    #[test]
+    fn tab_stops() {
+        // In columns, where the text after each char of `line` starts
+        let ends = |line: &str, width: f32| {
+            let mut x = 0.0;
+            line.chars()
+                .map(|ch| {
+                    x = match ch {
+                        '\t' => next_tab_stop(x, width),
+                        _ => x + 1.0,
+                    };
+                    x
+                })
+                .collect::<Vec<f32>>()
+        };
+        assert_eq!(ends("\tab", 4.0), vec![4.0, 5.0, 6.0]);
+        assert_eq!(ends("a\tb", 4.0), vec![1.0, 4.0, 5.0]);
+        assert_eq!(ends("abcd\t", 4.0), vec![1.0, 2.0, 3.0, 4.0, 8.0]);
+        assert_eq!(ends("\t\tx", 2.0), vec![2.0, 4.0, 5.0]);
+        // Not quite on the stop from adding up advances
+        assert_eq!(next_tab_stop(0.1 + 0.2 + 3.7 - 1e-6, 4.0), 8.0);
+        assert_eq!(next_tab_stop(3.0, 0.0), 3.0);
+    }
+
+    #[test]
+    fn flag_values() {
+        assert_eq!(flag_value("wrap", "wrap", false), Some(true));
+        assert_eq!(flag_value("nowrap", "wrap", true), Some(false));
+        assert_eq!(flag_value("wrap!", "wrap", true), Some(false));
+        assert_eq!(flag_value("invwrap", "wrap", false), Some(true));
+        assert_eq!(flag_value("nowra", "wrap", true), None);
+        assert_eq!(flag_value("number", "wrap", true), None);
+    }
+
+    #[test]
+    fn zoom() {
+        assert_eq!(zoomed_font_size(48, 1), 52);
+        assert_eq!(zoomed_font_size(48, -2), 40);
+        assert_eq!(zoomed_font_size(10, -1), MIN_FONT_SIZE);
+        assert_eq!(zoomed_font_size(MAX_FONT_SIZE, 1), MAX_FONT_SIZE);
+        // Sizes from outside the range are brought back into it
+        assert_eq!(zoomed_font_size(200, -1), MAX_FONT_SIZE);
+    }
+
+    #[test]
+    fn font_path() {
+        // Found from the executable, which is a few directories into `target`
+        let path = FontOptions::default().resolve().unwrap();
+        assert!(path.is_absolute());
+        assert!(path.ends_with("fonts/FiraCode.ttf"));
+
+        let font = FontOptions {
+            path: path.clone(),
+            size: 12,
+        };
+        assert_eq!(font.resolve(), Some(path));
+        let font = FontOptions {
+            path: PathBuf::from("fonts/Missing.ttf"),
+            size: 12,
+        };
+        assert_eq!(font.resolve(), None);
+    }
+
+    #[test]
+    fn resized_viewport() {
+        let (top, _) = Viewport::FULL.split(SplitKind::Horizontal);
+        assert_eq!(Viewport::FULL.lines(48.0, 600), 24);
+        assert_eq!(top.lines(48.0, 600), 11);
+        // Twice as tall fits more than twice the lines, there's only one
+        // status line
+        assert_eq!(Viewport::FULL.lines(48.0, 1200), 49);
+        assert_eq!(top.lines(48.0, 1200), 24);
+        assert_eq!(Viewport::FULL.lines(48.0, 0), 0);
+
+        let (sx, sy) = text_scale(800, 600);
+        assert_eq!(text_scale(1600, 1200), (sx / 2.0, sy / 2.0));
+    }
+
+    #[test]
+    fn viewport_contains() {
+        let (left, right) = Viewport::FULL.split(SplitKind::Vertical);
+        assert!(left.contains(-1.0, 1.0));
+        assert!(left.contains(-0.5, 0.0));
+        assert!(!left.contains(0.0, 0.0));
+        assert!(right.contains(0.0, 0.0));
+        assert!(!right.contains(0.5, -1.0));
+        assert!(!right.contains(1.0, 0.0));
+    }
+
+    #[test]
+    fn text_bounds_from_lines() {
+        let lines = |text: &str| -> Vec<u32> {
+            Rope::from_str(text)
+                .lines()
+                .map(|line| line.chars().filter(|c| *c != '\n').count() as u32)
+                .collect()
+        };
+
+        assert_eq!(text_bounds(&lines("ab\ncd\n"), 10.0, 20.0), (20.0, 60.0));
+        // The last line counts even without a trailing new line
+        assert_eq!(text_bounds(&lines("ab\ncd"), 10.0, 20.0), (20.0, 40.0));
+        assert_eq!(text_bounds(&lines(""), 10.0, 20.0), (0.0, 20.0));
+
+        let wide = format!("a\n{}\nb", "x".repeat(10_000));
+        assert_eq!(text_bounds(&lines(&wide), 10.0, 20.0), (100_000.0, 60.0));
+    }
+
+    #[test]
+    fn scroll_clamps() {
+        let (width, height) = text_bounds(&[2, 500, 3], 10.0, 20.0);
+
+        // Down within the text, then past the end
+        assert_eq!(clamp_scroll(0.0, -20.0, height), (-20.0, false));
+        assert_eq!(clamp_scroll(-40.0, -20.0, height), (-60.0, true));
+        assert_eq!(clamp_scroll(-40.0, -100.0, height), (-60.0, true));
+        // Back up, then past the start
+        assert_eq!(clamp_scroll(-60.0, 20.0, height), (-40.0, false));
+        assert_eq!(clamp_scroll(-10.0, 20.0, height), (0.0, true));
+
+        // The widest line bounds scrolling right
+        assert_eq!(clamp_scroll(0.0, -4000.0, width), (-4000.0, false));
+        assert_eq!(clamp_scroll(-4000.0, -4000.0, width), (-5000.0, true));
+    }
+
+    #[test]
+    fn scroll_into_view() {
+        // Already on screen
+        assert_eq!(super::scroll_into_view(0.0, 10.0, 20.0, 100.0), 0.0);
+        assert_eq!(super::scroll_into_view(-50.0, 60.0, 70.0, 100.0), -50.0);
+        // Past the right edge it's scrolled to be at the edge
+        assert_eq!(super::scroll_into_view(0.0, 120.0, 130.0, 100.0), -30.0);
+        // Left of the scrolled text it's scrolled to be at the start
+        assert_eq!(super::scroll_into_view(-50.0, 20.0, 30.0, 100.0), -20.0);
+        assert_eq!(super::scroll_into_view(-50.0, 0.0, 10.0, 100.0), 0.0);
+    }
+
+    #[test]
+    fn diagnostics_for_buffer() {
+        let mut buffers = Buffers::new();
+        let dir = std::env::temp_dir();
+        let main = buffers.open(Some(&dir.join("glyph_diagnostics_main.rs")));
+        let lib = buffers.open(Some(&dir.join("glyph_diagnostics_lib.rs")));
+
+        let mut diagnostics = Diagnostics::new();
+        assert!(buffer_diagnostics(&diagnostics, &buffers, main).is_empty());
+
+        let url = buffers.url(lib).cloned().unwrap();
+        diagnostics.update(url, vec![Diagnostic::default()]);
+        assert!(buffer_diagnostics(&diagnostics, &buffers, main).is_empty());
+        assert_eq!(buffer_diagnostics(&diagnostics, &buffers, lib).len(), 1);
+
+        // Publishing for another file keeps these and their clock
+        let lib_clock = buffer_diagnostics_clock(&diagnostics, &buffers, lib);
+        let main_clock = buffer_diagnostics_clock(&diagnostics, &buffers, main);
+        let url = buffers.url(main).cloned().unwrap();
+        diagnostics.update(url, vec![Diagnostic::default(); 2]);
+        assert_eq!(buffer_diagnostics(&diagnostics, &buffers, lib).len(), 1);
+        assert_eq!(buffer_diagnostics(&diagnostics, &buffers, main).len(), 2);
+        assert_eq!(
+            buffer_diagnostics_clock(&diagnostics, &buffers, lib),
+            lib_clock
+        );
+        assert_ne!(
+            buffer_diagnostics_clock(&diagnostics, &buffers, main),
+            main_clock
+        );
+
+        // A closed buffer doesn't get the diagnostics of its file
+        buffers.close(lib);
+        assert!(buffer_diagnostics(&diagnostics, &buffers, lib).is_empty());
+    }
+
+    #[test]
+    fn cycle_buffer_order() {
+        let mut buffers = Buffers::new();
+        let [a, b, c] = [(); 3].map(|_| buffers.open(None));
+        // The order they're hidden in doesn't matter, it's the order opened
+        let open = [c, a, b];
+
+        assert_eq!(cycle_buffers(&open, a, true), b);
+        assert_eq!(cycle_buffers(&open, b, true), c);
+        assert_eq!(cycle_buffers(&open, c, true), a);
+        assert_eq!(cycle_buffers(&open, a, false), c);
+        assert_eq!(cycle_buffers(&open, c, false), b);
+        assert_eq!(cycle_buffers(&[a], a, true), a);
+    }
+
+    #[test]
+    fn first_modified_buffer() {
+        let mut buffers = Buffers::new();
+        let [a, b, c] = [(); 3].map(|_| buffers.open(None));
+
+        assert_eq!(
+            first_modified(&[(a, false), (b, true), (c, true)], c),
+            Some(c)
+        );
+        assert_eq!(
+            first_modified(&[(c, true), (a, false), (b, true)], a),
+            Some(b)
+        );
+        assert_eq!(first_modified(&[(a, false), (b, false)], a), None);
+    }
+
+    #[test]
+    fn inlay_hint_positions() {
+        let rope = Rope::from_str("let x = 1;\r\nlet 𝔸 = 2;\nlast");
+        let text = rope.slice(..);
+        assert_eq!(char_position(text, Position::new(0, 5)), Some((0, 5)));
+        // The astral char is two UTF-16 code units but one char
+        assert_eq!(char_position(text, Position::new(1, 6)), Some((1, 5)));
+        // Past the end stops before the line ending
+        assert_eq!(char_position(text, Position::new(0, 40)), Some((0, 10)));
+        assert_eq!(char_position(text, Position::new(2, 40)), Some((2, 4)));
+        assert_eq!(char_position(text, Position::new(3, 0)), None);
+    }
+
+    #[test]
+    fn diagnostic_under_cursor() {
+        let diag = |start: (u32, u32), end: (u32, u32), message: &str| Diagnostic {
+            range: lsp::Range::new(
+                lsp::Position::new(start.0, start.1),
+                lsp::Position::new(end.0, end.1),
+            ),
+            message: message.into(),
+            ..Default::default()
+        };
+        let diagnostics = [
+            diag((1, 0), (1, 3), "first"),
+            diag((1, 8), (2, 2), "second"),
+        ];
+        let message =
+            |line, col| diagnostic_at(&diagnostics, line, col).map(|d| d.message.as_str());
+
+        assert_eq!(message(1, 2), Some("first"));
+        assert_eq!(message(1, 9), Some("second"));
+        assert_eq!(message(2, 1), Some("second"));
+        // Between them falls back to the first on the line
+        assert_eq!(message(1, 5), Some("first"));
+        assert_eq!(message(0, 0), None);
+        assert_eq!(message(3, 0), None);
+    }
+
+    #[test]
+    fn wrapped_positions() {
+        let starts = [0, 8, 16];
+        assert_eq!(wrapped_pos(&starts, 0), (0, 0));
+        assert_eq!(wrapped_pos(&starts, 7), (0, 7));
+        // Continuation rows are indented
+        assert_eq!(wrapped_pos(&starts, 8), (1, WRAP_INDENT));
+        assert_eq!(wrapped_pos(&starts, 17), (2, 1 + WRAP_INDENT));
+        // The end of the line in insert mode
+        assert_eq!(wrapped_pos(&starts, 19), (2, 3 + WRAP_INDENT));
+        assert_eq!(wrapped_pos(&[0], 30), (0, 30));
+    }
+
+    #[test]
+    fn indent_guide_levels() {
+        let text = Rope::from_str("fn main() {\n    if x {\n\n        y();\n\t}\n}\n");
+        let text = text.slice(..);
+        assert_eq!(indent_levels(text, 0..7, 4), vec![0, 1, 1, 2, 1, 0, 0]);
+        // Blank lines look above the visible ones
+        assert_eq!(indent_levels(text, 2..4, 4), vec![1, 2]);
+    }
+}
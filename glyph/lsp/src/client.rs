@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use colored::Colorize;
 use std::{
     collections::HashMap,
@@ -10,6 +10,7 @@ use std::{
         Arc, RwLock,
     },
     thread::{self},
+    time::{Duration, Instant},
 };
 
 use bytes::BytesMut;
@@ -19,53 +20,288 @@ use jsonrpc_core::{
 };
 use lsp_types::{
     ClientCapabilities, Diagnostic, InitializeParams, InitializeResult, InitializedParams,
-    PublishDiagnosticsParams, Url, WorkspaceClientCapabilities,
+    LogMessageParams, MessageType, PublishDiagnosticsParams, Range, SemanticTokenType,
+    SemanticTokensClientCapabilities, SemanticTokensClientCapabilitiesRequests,
+    SemanticTokensFullOptions, SemanticTokensResult, SemanticTokensServerCapabilities,
+    ServerCapabilities, ShowMessageParams, TextDocumentClientCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncClientCapabilities, TextDocumentSyncKind,
+    TextDocumentSyncSaveOptions, TokenFormat, Url, VersionedTextDocumentIdentifier,
+    WorkspaceClientCapabilities,
 };
 use serde::de::DeserializeOwned;
 
 use crate::{
-    nonblock::NonBlockingReader, LanguageServerDecoder, Message, NotifMessage, Notification,
-    ReqMessage, Request, ServerResponse,
+    decode_semantic_tokens, exit, nonblock::NonBlockingReader, shutdown, text_doc_did_change,
+    text_doc_did_close, text_doc_did_open, text_doc_inlay_hint, text_doc_semantic_tokens_full,
+    DocumentHints, DocumentTokens, InlayHint, LanguageServerDecoder, Message, NotifMessage,
+    Notification, ReqMessage, Request, ServerResponse,
 };
 
+/// How long to wait for the server to answer `shutdown`, and then to quit
+/// after `exit`, before killing it
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
 pub enum Either<L, R> {
     Left(L),
     Right(R),
 }
 
+/// A document opened with the server, which reads it from the text sent
+/// instead of the file
+struct OpenDocument {
+    id: VersionedTextDocumentIdentifier,
+    language_id: String,
+    // The text to open it with once the server is initialized, `None` after
+    // it's been opened
+    pending: Option<String>,
+}
+
 #[derive(Clone)]
 pub struct LspSender {
     // TODO: Get rid of dynamic dispatch
     tx: Sender<Box<dyn Message + Send>>,
+    // What the server said it supports, `None` until it's initialized
+    capabilities: Arc<RwLock<Option<ServerCapabilities>>>,
+    semantic_tokens: Arc<RwLock<DocumentTokens>>,
+    // The document semantic tokens were last asked for, asked for again once
+    // the server is initialized
+    tokens_uri: Arc<RwLock<Option<Url>>>,
+    // Whether the server has `inlayHintProvider`, which `ServerCapabilities`
+    // doesn't have a field for yet
+    inlay_hint_provider: Arc<RwLock<bool>>,
+    inlay_hints: Arc<RwLock<DocumentHints>>,
+    // The document and range inlay hints were last asked for, like
+    // `tokens_uri`
+    hints_request: Arc<RwLock<Option<(Url, Range)>>>,
+    // Told when the server answers `shutdown`, `Some` once it's been sent
+    shutdown: Arc<RwLock<Option<Sender<()>>>>,
+    documents: Arc<RwLock<HashMap<Url, OpenDocument>>>,
 }
 
 impl LspSender {
     pub fn wrap(tx: Sender<Box<dyn Message + Send>>) -> Self {
-        Self { tx }
+        Self {
+            tx,
+            capabilities: Default::default(),
+            semantic_tokens: Default::default(),
+            tokens_uri: Default::default(),
+            inlay_hint_provider: Default::default(),
+            inlay_hints: Default::default(),
+            hints_request: Default::default(),
+            shutdown: Default::default(),
+            documents: Default::default(),
+        }
     }
 
     pub fn send_message(&self, data: Box<dyn Message + Send>) {
         self.tx.send(data).unwrap()
     }
+
+    /// Whether the server wants `textDocument/didSave`, and if it does whether
+    /// the text should be sent with it. `None` before the server is initialized
+    pub fn save_include_text(&self) -> Option<bool> {
+        save_include_text(self.capabilities.read().unwrap().as_ref()?)
+    }
+
+    /// The semantic tokens of the last document the server answered for
+    pub fn semantic_tokens(&self) -> &Arc<RwLock<DocumentTokens>> {
+        &self.semantic_tokens
+    }
+
+    /// Ask for the semantic tokens of `uri`, the response replaces the ones in
+    /// `semantic_tokens` even if another document was asked for since. Before
+    /// the server is initialized the request waits until it is, and it's
+    /// never sent to servers without them
+    pub fn request_semantic_tokens(&self, uri: Url) {
+        *self.tokens_uri.write().unwrap() = Some(uri.clone());
+        if self.semantic_tokens_legend().is_some() {
+            self.send_message(Box::new(text_doc_semantic_tokens_full(uri)));
+        }
+    }
+
+    /// The inlay hints of the last range the server answered for
+    pub fn inlay_hints(&self) -> &Arc<RwLock<DocumentHints>> {
+        &self.inlay_hints
+    }
+
+    /// Ask for the inlay hints inside `range` of `uri`, the response replaces
+    /// the ones in `inlay_hints`. Like semantic tokens, the request waits for
+    /// the server to be initialized and isn't sent if it doesn't have them
+    pub fn request_inlay_hints(&self, uri: Url, range: Range) {
+        *self.hints_request.write().unwrap() = Some((uri.clone(), range));
+        if *self.inlay_hint_provider.read().unwrap() {
+            self.send_message(Box::new(text_doc_inlay_hint(uri, range)));
+        }
+    }
+
+    /// Open `uri` with the server, which reads it from `text` and the changes
+    /// sent with `change_document` from then on. Before the server is
+    /// initialized it's opened once it is, and it's never opened with servers
+    /// that don't sync documents. A document that's already open stays as is
+    pub fn open_document(&self, uri: Url, language_id: &str, text: String) {
+        // Held while checking the capabilities so `open_pending_documents`
+        // can't run in between
+        let mut documents = self.documents.write().unwrap();
+        if documents.contains_key(&uri) {
+            return;
+        }
+        let pending = match self.capabilities.read().unwrap().as_ref() {
+            None => Some(text),
+            Some(capabilities) if syncs_documents(capabilities) => {
+                self.send_message(Box::new(text_doc_did_open(
+                    uri.clone(),
+                    language_id,
+                    0,
+                    text,
+                )));
+                None
+            }
+            Some(_) => return,
+        };
+        documents.insert(
+            uri.clone(),
+            OpenDocument {
+                id: VersionedTextDocumentIdentifier::new(uri, 0),
+                language_id: language_id.to_string(),
+                pending,
+            },
+        );
+    }
+
+    /// Send the whole new text of `uri`, if it's open
+    pub fn change_document(&self, uri: &Url, text: String) {
+        let mut documents = self.documents.write().unwrap();
+        let Some(doc) = documents.get_mut(uri) else {
+            return;
+        };
+        doc.id.version += 1;
+        match &mut doc.pending {
+            Some(pending) => *pending = text,
+            None => self.send_message(Box::new(text_doc_did_change(doc.id.clone(), text))),
+        }
+    }
+
+    /// The server goes back to reading `uri` from its file
+    pub fn close_document(&self, uri: &Url) {
+        let doc = self.documents.write().unwrap().remove(uri);
+        if let Some(OpenDocument { pending: None, .. }) = doc {
+            self.send_message(Box::new(text_doc_did_close(uri.clone())));
+        }
+    }
+
+    /// Open the documents that were opened before the server was initialized
+    fn open_pending_documents(&self) {
+        let mut documents = self.documents.write().unwrap();
+        let syncs = self
+            .capabilities
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(syncs_documents);
+        if !syncs {
+            documents.clear();
+            return;
+        }
+        for doc in documents.values_mut() {
+            if let Some(text) = doc.pending.take() {
+                self.send_message(Box::new(text_doc_did_open(
+                    doc.id.uri.clone(),
+                    &doc.language_id,
+                    doc.id.version,
+                    text,
+                )));
+            }
+        }
+    }
+
+    fn semantic_tokens_legend(&self) -> Option<Vec<SemanticTokenType>> {
+        semantic_tokens_legend(self.capabilities.read().unwrap().as_ref()?)
+    }
+}
+
+/// Whether the server wants `didOpen`, `didChange` and `didClose`
+fn syncs_documents(capabilities: &ServerCapabilities) -> bool {
+    match &capabilities.text_document_sync {
+        Some(TextDocumentSyncCapability::Kind(kind)) => *kind != TextDocumentSyncKind::NONE,
+        Some(TextDocumentSyncCapability::Options(options)) => {
+            options.open_close == Some(true)
+                && options
+                    .change
+                    .is_some_and(|kind| kind != TextDocumentSyncKind::NONE)
+        }
+        None => false,
+    }
+}
+
+/// Whether the `capabilities` of an `initialize` response has inlay hints, it
+/// can be `true`, options, or registration options
+fn inlay_hint_provider(capabilities: &Value) -> bool {
+    match capabilities.get("inlayHintProvider") {
+        None | Some(Value::Null) | Some(Value::Bool(false)) => false,
+        Some(_) => true,
+    }
+}
+
+/// The token types of `textDocument/semanticTokens/full` responses, `None` if
+/// the server doesn't answer them
+fn semantic_tokens_legend(capabilities: &ServerCapabilities) -> Option<Vec<SemanticTokenType>> {
+    let options = match capabilities.semantic_tokens_provider.as_ref()? {
+        SemanticTokensServerCapabilities::SemanticTokensOptions(options) => options,
+        SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(options) => {
+            &options.semantic_tokens_options
+        }
+    };
+    match options.full {
+        None | Some(SemanticTokensFullOptions::Bool(false)) => None,
+        Some(_) => Some(options.legend.token_types.clone()),
+    }
+}
+
+fn save_include_text(capabilities: &ServerCapabilities) -> Option<bool> {
+    match &capabilities.text_document_sync {
+        Some(TextDocumentSyncCapability::Options(options)) => match &options.save {
+            Some(TextDocumentSyncSaveOptions::Supported(true)) => Some(false),
+            Some(TextDocumentSyncSaveOptions::SaveOptions(save)) => {
+                Some(save.include_text.unwrap_or(false))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
 }
 
 #[derive(Debug)]
 pub struct Diagnostics {
-    pub diagnostics: Vec<Diagnostic>,
+    /// The latest diagnostics of each document the server published any for,
+    /// with the clock they came in at
+    pub files: HashMap<Url, (u64, Vec<Diagnostic>)>,
+    /// Goes up with every publish, for any document
     pub clock: u64,
 }
 
 impl Diagnostics {
     pub fn new() -> Self {
         Self {
-            diagnostics: Vec::new(),
+            files: HashMap::new(),
             clock: 1,
         }
     }
 
-    pub fn update(&mut self, diagnostics: Vec<Diagnostic>) {
-        self.diagnostics = diagnostics;
+    pub fn update(&mut self, uri: Url, diagnostics: Vec<Diagnostic>) {
         self.clock += 1;
+        self.files.insert(uri, (self.clock, diagnostics));
+    }
+
+    pub fn get(&self, uri: &Url) -> &[Diagnostic] {
+        self.files
+            .get(uri)
+            .map_or(&[], |(_, diagnostics)| diagnostics.as_slice())
+    }
+
+    /// The clock of the last publish for `uri`, a document the server hasn't
+    /// published anything for has the clock of an empty `Diagnostics`
+    pub fn file_clock(&self, uri: &Url) -> u64 {
+        self.files.get(uri).map_or(1, |(clock, _)| *clock)
     }
 }
 
@@ -75,8 +311,19 @@ impl Default for Diagnostics {
     }
 }
 
+/// A message from a `window/showMessage` or `window/logMessage` notification
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerMessage {
+    pub typ: MessageType,
+    pub message: String,
+    /// Whether it came from `window/showMessage`, which are meant to be shown
+    /// to the user, instead of `window/logMessage`
+    pub show: bool,
+}
+
 pub struct Client {
     diagnostics: Arc<RwLock<Diagnostics>>,
+    messages: Arc<RwLock<Vec<ServerMessage>>>,
     tx: LspSender,
     in_thread_id: u64,
     out_thread_id: u64,
@@ -85,17 +332,22 @@ pub struct Client {
 
 impl Drop for Client {
     fn drop(&mut self) {
+        self.shutdown();
         unsafe {
             libc::pthread_kill(self.in_thread_id as usize, libc::SIGINT);
             libc::pthread_kill(self.out_thread_id as usize, libc::SIGINT);
         }
-        self.child.kill().unwrap()
+        // Servers that didn't quit after `exit`
+        if let Ok(None) = self.child.try_wait() {
+            self.child.kill().unwrap()
+        }
     }
 }
 
 impl Client {
     pub fn new<T: AsRef<OsStr>>(cmd_path: T, cwd: &str) -> Self {
         let diagnostics = Arc::new(RwLock::new(Diagnostics::new()));
+        let messages = Arc::new(RwLock::new(Vec::new()));
 
         let mut cmd = Command::new(cmd_path)
             .stdin(Stdio::piped())
@@ -117,6 +369,7 @@ impl Client {
 
         let inner = Inner {
             diagnostics: diagnostics.clone(),
+            messages: messages.clone(),
             request_ids: Arc::new(RwLock::new(HashMap::new())),
             req_id_counter: Default::default(),
             tx: tx.clone(),
@@ -136,6 +389,7 @@ impl Client {
 
         let s = Self {
             diagnostics,
+            messages,
             tx,
             in_thread_id,
             out_thread_id,
@@ -151,6 +405,28 @@ impl Client {
         self.tx.send_message(data)
     }
 
+    /// Ask the server to quit with `shutdown` and then `exit`, waiting up to
+    /// `SHUTDOWN_TIMEOUT` for each. Gives up early if the server stopped
+    /// reading messages
+    fn shutdown(&mut self) {
+        let (done_tx, done_rx) = mpsc::channel();
+        *self.tx.shutdown.write().unwrap() = Some(done_tx);
+        if self.tx.tx.send(Box::new(shutdown())).is_err()
+            || done_rx.recv_timeout(SHUTDOWN_TIMEOUT).is_err()
+            || self.tx.tx.send(Box::new(exit())).is_err()
+        {
+            return;
+        }
+
+        let start = Instant::now();
+        while start.elapsed() < SHUTDOWN_TIMEOUT {
+            if !matches!(self.child.try_wait(), Ok(None)) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
     fn initialize_params(process_id: u32, cwd: &str) -> InitializeParams {
         InitializeParams {
             process_id: Some(process_id),
@@ -170,7 +446,22 @@ impl Client {
                     code_lens: None,
                     file_operations: None,
                 }),
-                text_document: None,
+                text_document: Some(TextDocumentClientCapabilities {
+                    synchronization: Some(TextDocumentSyncClientCapabilities {
+                        did_save: Some(true),
+                        ..Default::default()
+                    }),
+                    semantic_tokens: Some(SemanticTokensClientCapabilities {
+                        requests: SemanticTokensClientCapabilitiesRequests {
+                            range: None,
+                            full: Some(SemanticTokensFullOptions::Bool(true)),
+                        },
+                        token_types: SEMANTIC_TOKEN_TYPES.to_vec(),
+                        formats: vec![TokenFormat::RELATIVE],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
                 window: None,
                 general: None,
                 experimental: None,
@@ -187,16 +478,87 @@ impl Client {
         &self.diagnostics
     }
 
+    /// Every `window/showMessage` and `window/logMessage` so far, oldest first
+    pub fn messages(&self) -> &Arc<RwLock<Vec<ServerMessage>>> {
+        &self.messages
+    }
+
     pub fn sender(&self) -> &LspSender {
         &self.tx
     }
+
+    pub fn semantic_tokens(&self) -> &Arc<RwLock<DocumentTokens>> {
+        self.tx.semantic_tokens()
+    }
+
+    pub fn inlay_hints(&self) -> &Arc<RwLock<DocumentHints>> {
+        self.tx.inlay_hints()
+    }
+}
+
+// The token types the themes have colors for
+const SEMANTIC_TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::TYPE,
+    SemanticTokenType::CLASS,
+    SemanticTokenType::ENUM,
+    SemanticTokenType::INTERFACE,
+    SemanticTokenType::STRUCT,
+    SemanticTokenType::TYPE_PARAMETER,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::ENUM_MEMBER,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::METHOD,
+    SemanticTokenType::MACRO,
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::COMMENT,
+    SemanticTokenType::STRING,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::OPERATOR,
+];
+
+/// A request waiting for its response
+#[derive(Clone, Debug, PartialEq)]
+struct SentRequest {
+    kind: Request,
+    uri: Option<Url>,
+    range: Option<Range>,
+}
+
+impl SentRequest {
+    /// What to keep of `msg` until its response, `None` if it isn't a request
+    fn of(msg: &dyn Message) -> Option<Self> {
+        let (uri, range) = match msg.target() {
+            Some((uri, range)) => (Some(uri), range),
+            None => (None, None),
+        };
+        Some(Self {
+            kind: msg.request()?,
+            uri,
+            range,
+        })
+    }
+}
+
+impl From<Request> for SentRequest {
+    fn from(kind: Request) -> Self {
+        Self {
+            kind,
+            uri: None,
+            range: None,
+        }
+    }
 }
 
 #[derive(Clone)]
 struct Inner {
     diagnostics: Arc<RwLock<Diagnostics>>,
-    request_ids: Arc<RwLock<HashMap<u16, Request>>>,
-    req_id_counter: Arc<RwLock<u16>>,
+    messages: Arc<RwLock<Vec<ServerMessage>>>,
+    // The requests waiting for a response, by id
+    request_ids: Arc<RwLock<HashMap<u64, SentRequest>>>,
+    req_id_counter: Arc<RwLock<u64>>,
     tx: LspSender,
 }
 
@@ -204,11 +566,11 @@ struct Inner {
 impl Inner {
     fn stdin(&self, rx: Receiver<Box<dyn Message + Send>>, mut stdin: ChildStdin) {
         for mut msg in rx {
-            if let Some(req) = msg.request() {
+            if let Some(req) = SentRequest::of(&*msg) {
                 let mut req_ids = self.request_ids.write().unwrap();
                 let mut req_id_counter = self.req_id_counter.write().unwrap();
                 *req_id_counter += 1;
-                msg.set_id(*req_id_counter as u8);
+                msg.set_id(*req_id_counter);
                 req_ids.insert(*req_id_counter, req);
             }
             stdin.write_all(&msg.to_bytes().unwrap()).unwrap();
@@ -231,6 +593,10 @@ impl Inner {
             // 0 may indicate EOF or simply that there is no data
             // ready for reading yet
             if read == 0 && stdout.is_eof() {
+                // Expected after `exit`
+                if self.tx.shutdown.read().unwrap().is_some() {
+                    return;
+                }
                 panic!("Got unexpected EOF from language server");
             }
 
@@ -271,6 +637,9 @@ impl Inner {
                 ..
             }) => self.handle_success(result, id),
             Output::Failure(Failure { id, error, .. }) => {
+                if let jsonrpc_core::Id::Num(id) = id {
+                    self.request_ids.write().unwrap().remove(&id);
+                }
                 eprintln!("Error: {:?} {:?}", id, error)
             }
             _ => eprintln!("Invalid output: {:?}", output),
@@ -278,37 +647,104 @@ impl Inner {
     }
 
     fn handle_success(&self, result: serde_json::Value, id: u64) {
-        if id > u16::MAX as u64 {
-            panic!("Invalid id: {}", id);
-        }
-        let req = {
-            let request_ids = self.request_ids.read().unwrap();
-            request_ids.get(&(id as u16)).cloned()
-        };
-        if let Some(req) = req {
-            self.handle_request_response(result, req)
-        } else {
+        let req = self.request_ids.write().unwrap().remove(&id);
+        let Some(req) = req else {
             eprintln!("Request response with id ({}) has no mapping", id);
+            return;
+        };
+        let kind = req.kind;
+        if let Err(e) = self.handle_request_response(result, req) {
+            eprintln!("Invalid response to {:?}: {:?}", kind, e);
         }
     }
 }
 
 // Request responses
 impl Inner {
-    fn handle_request_response(&self, result: serde_json::Value, request: Request) {
-        match request {
-            Request::Initialize => self.initialized(serde_json::from_value(result).unwrap()),
-            Request::TextDocDefinition => todo!(),
+    fn handle_request_response(
+        &self,
+        result: serde_json::Value,
+        request: SentRequest,
+    ) -> Result<()> {
+        match request.kind {
+            Request::Initialize => {
+                let hints = inlay_hint_provider(&result["capabilities"]);
+                let result = serde_json::from_value(result)?;
+                *self.tx.inlay_hint_provider.write().unwrap() = hints;
+                self.initialized(result);
+                Ok(())
+            }
+            Request::TextDocDefinition => Err(anyhow!("definitions aren't handled")),
+            Request::TextDocSemanticTokensFull => self.handle_semantic_tokens(result, request.uri),
+            Request::TextDocInlayHint => {
+                self.handle_inlay_hints(result, request.uri.zip(request.range))
+            }
+            Request::Shutdown => {
+                if let Some(done) = self.tx.shutdown.read().unwrap().as_ref() {
+                    // `Client::shutdown` might have stopped waiting
+                    let _ = done.send(());
+                }
+                Ok(())
+            }
         }
     }
 
-    fn initialized(&self, _result: InitializeResult) {
+    fn initialized(&self, result: InitializeResult) {
+        *self.tx.capabilities.write().unwrap() = Some(result.capabilities);
         let msg = Box::new(NotifMessage::new(
             "initialized",
             Some(InitializedParams {}),
             Notification::Initialized,
         ));
         self.tx.send_message(msg);
+
+        // Documents opened before the server was ready
+        self.tx.open_pending_documents();
+        let uri = self.tx.tokens_uri.read().unwrap().clone();
+        if let Some(uri) = uri {
+            self.tx.request_semantic_tokens(uri);
+        }
+        let hints = self.tx.hints_request.read().unwrap().clone();
+        if let Some((uri, range)) = hints {
+            self.tx.request_inlay_hints(uri, range);
+        }
+    }
+
+    /// Store the hints answering the request for `target`
+    fn handle_inlay_hints(
+        &self,
+        result: serde_json::Value,
+        target: Option<(Url, Range)>,
+    ) -> Result<()> {
+        let hints = serde_json::from_value::<Option<Vec<InlayHint>>>(result)?.unwrap_or_default();
+        let (uri, range) = target.ok_or_else(|| anyhow!("inlay hints without a document"))?;
+
+        self.tx
+            .inlay_hints
+            .write()
+            .unwrap()
+            .update(uri, range, hints);
+        Ok(())
+    }
+
+    /// Store the tokens answering the request for `uri`
+    fn handle_semantic_tokens(&self, result: serde_json::Value, uri: Option<Url>) -> Result<()> {
+        let data = match serde_json::from_value::<Option<SemanticTokensResult>>(result)? {
+            Some(SemanticTokensResult::Tokens(tokens)) => tokens.data,
+            Some(SemanticTokensResult::Partial(partial)) => partial.data,
+            None => return Ok(()),
+        };
+        let uri = uri.ok_or_else(|| anyhow!("semantic tokens without a document"))?;
+        let Some(legend) = self.tx.semantic_tokens_legend() else {
+            return Ok(());
+        };
+
+        self.tx
+            .semantic_tokens
+            .write()
+            .unwrap()
+            .update(uri, decode_semantic_tokens(&data, &legend));
+        Ok(())
     }
 }
 
@@ -319,6 +755,14 @@ impl Inner {
             "textDocument/publishDiagnostics" => {
                 self.handle_publish_diagnostics(params).unwrap();
             }
+            "window/showMessage" => {
+                let params: ShowMessageParams = Self::from_value(params).unwrap();
+                self.push_message(params.typ, params.message, true);
+            }
+            "window/logMessage" => {
+                let params: LogMessageParams = Self::from_value(params).unwrap();
+                self.push_message(params.typ, params.message, false);
+            }
             o => {
                 println!("Unknown notification: {:?}", o);
             }
@@ -328,12 +772,18 @@ impl Inner {
         let params: PublishDiagnosticsParams = Self::from_value(params)?;
 
         let mut diagnostics = self.diagnostics.write().unwrap();
-        diagnostics.update(params.diagnostics);
-
-        println!("DIAGNOSTICS: {:?}", diagnostics.diagnostics);
+        println!("DIAGNOSTICS: {:?}", params.diagnostics);
+        diagnostics.update(params.uri, params.diagnostics);
 
         Ok(())
     }
+
+    fn push_message(&self, typ: MessageType, message: String, show: bool) {
+        self.messages
+            .write()
+            .unwrap()
+            .push(ServerMessage { typ, message, show });
+    }
 }
 
 // Utility
@@ -365,9 +815,25 @@ pub fn transmute_u16s(bytes: Vec<u16>) -> Vec<u8> {
 mod test {
     use std::time::Duration;
 
-    use lsp_types::{DidOpenTextDocumentParams, TextDocumentItem, Url};
+    use lsp_types::{
+        DidOpenTextDocumentParams, SaveOptions, ServerCapabilities, TextDocumentItem,
+        TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
+        TextDocumentSyncSaveOptions, Url,
+    };
 
-    use crate::{transmute_u16s, Client};
+    use lsp_types::{
+        InitializeResult, SemanticTokenType, SemanticTokensFullOptions, SemanticTokensLegend,
+        SemanticTokensOptions, SemanticTokensServerCapabilities,
+    };
+
+    use super::{
+        inlay_hint_provider, save_include_text, semantic_tokens_legend, syncs_documents, Inner,
+        SentRequest,
+    };
+    use crate::{
+        transmute_u16s, Client, InlayHint, InlayHintLabel, LspSender, Message, MessageType,
+        Position, Range, Request, SemanticTokenSpan, ServerMessage,
+    };
 
     #[test]
     fn it_works() {
@@ -393,6 +859,374 @@ mod test {
         std::thread::sleep(Duration::from_millis(3000));
     }
 
+    #[test]
+    fn save_options() {
+        let capabilities = |save| ServerCapabilities {
+            text_document_sync: Some(TextDocumentSyncCapability::Options(
+                TextDocumentSyncOptions {
+                    save,
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        };
+
+        assert_eq!(save_include_text(&capabilities(None)), None);
+        assert_eq!(
+            save_include_text(&capabilities(Some(TextDocumentSyncSaveOptions::Supported(
+                true
+            )))),
+            Some(false)
+        );
+        assert_eq!(
+            save_include_text(&capabilities(Some(
+                TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                    include_text: Some(true)
+                })
+            ))),
+            Some(true)
+        );
+        assert_eq!(save_include_text(&ServerCapabilities::default()), None);
+    }
+
+    #[test]
+    fn server_messages() {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let inner = Inner {
+            diagnostics: Default::default(),
+            messages: Default::default(),
+            request_ids: Default::default(),
+            req_id_counter: Default::default(),
+            tx: LspSender::wrap(tx),
+        };
+        let params = |typ: i32, message: &str| {
+            serde_json::from_value(serde_json::json!({ "type": typ, "message": message })).unwrap()
+        };
+
+        inner.handle_notification("window/showMessage".into(), params(1, "cargo failed"));
+        inner.handle_notification("window/logMessage".into(), params(3, "indexing"));
+        assert_eq!(
+            *inner.messages.read().unwrap(),
+            vec![
+                ServerMessage {
+                    typ: MessageType::ERROR,
+                    message: "cargo failed".into(),
+                    show: true,
+                },
+                ServerMessage {
+                    typ: MessageType::INFO,
+                    message: "indexing".into(),
+                    show: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn semantic_tokens_response() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let inner = Inner {
+            diagnostics: Default::default(),
+            messages: Default::default(),
+            request_ids: Default::default(),
+            req_id_counter: Default::default(),
+            tx: LspSender::wrap(tx),
+        };
+        let uri = Url::parse("file:///tmp/main.rs").unwrap();
+
+        // Waits for the server to say it has them
+        inner.tx.request_semantic_tokens(uri.clone());
+        assert!(rx.try_recv().is_err());
+        let capabilities = |full| ServerCapabilities {
+            semantic_tokens_provider: Some(
+                SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+                    legend: SemanticTokensLegend {
+                        token_types: vec![SemanticTokenType::PARAMETER],
+                        token_modifiers: vec![],
+                    },
+                    full,
+                    ..Default::default()
+                }),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(semantic_tokens_legend(&capabilities(None)), None);
+        assert_eq!(
+            semantic_tokens_legend(&capabilities(Some(SemanticTokensFullOptions::Bool(false)))),
+            None
+        );
+        inner.initialized(InitializeResult {
+            capabilities: capabilities(Some(SemanticTokensFullOptions::Bool(true))),
+            server_info: None,
+        });
+        let sent: Vec<_> = rx
+            .try_iter()
+            .filter_map(|msg| SentRequest::of(&*msg))
+            .collect();
+        assert_eq!(
+            sent,
+            vec![SentRequest {
+                kind: Request::TextDocSemanticTokensFull,
+                uri: Some(uri.clone()),
+                range: None,
+            }]
+        );
+
+        // Filed under the document it was asked for, even after another one
+        // was asked for
+        inner
+            .tx
+            .request_semantic_tokens(Url::parse("file:///tmp/lib.rs").unwrap());
+        inner
+            .handle_request_response(
+                serde_json::json!({ "data": [1, 2, 3, 0, 0] }),
+                sent[0].clone(),
+            )
+            .unwrap();
+        let tokens = inner.tx.semantic_tokens().read().unwrap();
+        assert_eq!(tokens.uri, Some(uri));
+        assert_eq!(
+            tokens.tokens,
+            vec![SemanticTokenSpan {
+                line: 1,
+                start: 2,
+                len: 3,
+                token_type: "parameter".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn inlay_hints_response() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let inner = Inner {
+            diagnostics: Default::default(),
+            messages: Default::default(),
+            request_ids: Default::default(),
+            req_id_counter: Default::default(),
+            tx: LspSender::wrap(tx),
+        };
+        let uri = Url::parse("file:///tmp/main.rs").unwrap();
+        let range = Range::new(Position::new(0, 0), Position::new(40, 0));
+
+        assert!(!inlay_hint_provider(&serde_json::json!({})));
+        assert!(!inlay_hint_provider(
+            &serde_json::json!({ "inlayHintProvider": false })
+        ));
+        assert!(inlay_hint_provider(
+            &serde_json::json!({ "inlayHintProvider": { "resolveProvider": true } })
+        ));
+
+        // Waits for the server to say it has them
+        inner.tx.request_inlay_hints(uri.clone(), range);
+        assert!(rx.try_recv().is_err());
+        inner
+            .handle_request_response(
+                serde_json::json!({ "capabilities": { "inlayHintProvider": true } }),
+                Request::Initialize.into(),
+            )
+            .unwrap();
+        let sent: Vec<_> = rx
+            .try_iter()
+            .filter_map(|msg| SentRequest::of(&*msg))
+            .collect();
+        assert_eq!(
+            sent,
+            vec![SentRequest {
+                kind: Request::TextDocInlayHint,
+                uri: Some(uri.clone()),
+                range: Some(range),
+            }]
+        );
+
+        inner.tx.request_inlay_hints(
+            Url::parse("file:///tmp/lib.rs").unwrap(),
+            Range::new(Position::new(0, 0), Position::new(1, 0)),
+        );
+        inner
+            .handle_request_response(
+                serde_json::json!([{ "position": { "line": 3, "character": 9 }, "label": ": u8" }]),
+                sent[0].clone(),
+            )
+            .unwrap();
+        {
+            let hints = inner.tx.inlay_hints().read().unwrap();
+            assert_eq!(hints.uri, Some(uri));
+            assert_eq!(hints.range, Some(range));
+            assert_eq!(
+                hints.hints,
+                vec![InlayHint {
+                    position: Position::new(3, 9),
+                    label: InlayHintLabel::String(": u8".into()),
+                    padding_left: None,
+                    padding_right: None,
+                }]
+            );
+        }
+
+        inner
+            .handle_request_response(serde_json::Value::Null, sent[0].clone())
+            .unwrap();
+        assert!(inner.tx.inlay_hints().read().unwrap().hints.is_empty());
+
+        // A response that isn't about any document isn't filed anywhere
+        assert!(inner
+            .handle_request_response(serde_json::Value::Null, Request::TextDocInlayHint.into())
+            .is_err());
+    }
+
+    #[test]
+    fn documents() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let inner = Inner {
+            diagnostics: Default::default(),
+            messages: Default::default(),
+            request_ids: Default::default(),
+            req_id_counter: Default::default(),
+            tx: LspSender::wrap(tx),
+        };
+        let sent = || -> Vec<serde_json::Value> {
+            rx.try_iter()
+                .map(|msg: Box<dyn Message + Send>| {
+                    let text = String::from_utf8(msg.to_bytes().unwrap()).unwrap();
+                    let (_, body) = text.split_once("\r\n\r\n").unwrap();
+                    serde_json::from_str(body).unwrap()
+                })
+                .collect()
+        };
+        let main = Url::parse("file:///tmp/main.rs").unwrap();
+        let lib = Url::parse("file:///tmp/lib.rs").unwrap();
+
+        // Opened once the server is initialized, with the latest text
+        inner
+            .tx
+            .open_document(main.clone(), "rust", "fn main() {}".into());
+        inner.tx.change_document(&main, "fn main() { }".into());
+        assert!(sent().is_empty());
+        inner.initialized(InitializeResult {
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                    TextDocumentSyncKind::INCREMENTAL,
+                )),
+                ..Default::default()
+            },
+            server_info: None,
+        });
+        let msgs = sent();
+        assert_eq!(msgs[0]["method"], "initialized");
+        assert_eq!(msgs[1]["method"], "textDocument/didOpen");
+        assert_eq!(msgs[1]["params"]["textDocument"]["version"], 1);
+        assert_eq!(msgs[1]["params"]["textDocument"]["text"], "fn main() { }");
+
+        // Each document keeps its own version
+        inner.tx.open_document(lib.clone(), "rust", "".into());
+        inner.tx.open_document(lib.clone(), "rust", "".into());
+        inner.tx.change_document(&lib, "mod a;".into());
+        inner.tx.change_document(&main, "".into());
+        let msgs = sent();
+        let methods: Vec<_> = msgs.iter().map(|msg| msg["method"].clone()).collect();
+        assert_eq!(
+            methods,
+            [
+                "textDocument/didOpen",
+                "textDocument/didChange",
+                "textDocument/didChange"
+            ]
+        );
+        assert_eq!(msgs[1]["params"]["textDocument"]["uri"], lib.as_str());
+        assert_eq!(msgs[1]["params"]["textDocument"]["version"], 1);
+        assert_eq!(msgs[1]["params"]["contentChanges"][0]["text"], "mod a;");
+        assert_eq!(msgs[2]["params"]["textDocument"]["uri"], main.as_str());
+        assert_eq!(msgs[2]["params"]["textDocument"]["version"], 2);
+
+        inner.tx.close_document(&main);
+        inner.tx.change_document(&main, "fn main() {}".into());
+        let msgs = sent();
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0]["method"], "textDocument/didClose");
+
+        let options = |open_close, change| ServerCapabilities {
+            text_document_sync: Some(TextDocumentSyncCapability::Options(
+                TextDocumentSyncOptions {
+                    open_close,
+                    change,
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        };
+        assert!(syncs_documents(&options(
+            Some(true),
+            Some(TextDocumentSyncKind::FULL)
+        )));
+        assert!(!syncs_documents(&options(
+            None,
+            Some(TextDocumentSyncKind::FULL)
+        )));
+        assert!(!syncs_documents(&options(Some(true), None)));
+        assert!(!syncs_documents(&ServerCapabilities::default()));
+    }
+
+    #[test]
+    fn response_ids() {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let inner = Inner {
+            diagnostics: Default::default(),
+            messages: Default::default(),
+            request_ids: Default::default(),
+            req_id_counter: Default::default(),
+            tx: LspSender::wrap(tx),
+        };
+        inner
+            .request_ids
+            .write()
+            .unwrap()
+            .insert(300, Request::Shutdown.into());
+
+        // Ids past a byte aren't mistaken for others, and are forgotten once
+        // answered
+        inner.handle_success(serde_json::Value::Null, 300);
+        assert!(inner.request_ids.read().unwrap().is_empty());
+        inner.handle_success(serde_json::Value::Null, 300);
+
+        // The wrong shape is an error instead of a panic
+        assert!(inner
+            .handle_request_response(serde_json::json!([1, 2]), Request::Initialize.into())
+            .is_err());
+        assert!(inner
+            .handle_request_response(
+                serde_json::json!({ "data": "no" }),
+                Request::TextDocInlayHint.into()
+            )
+            .is_err());
+        assert!(inner.tx.capabilities.read().unwrap().is_none());
+    }
+
+    #[test]
+    fn shutdown_response() {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let inner = Inner {
+            diagnostics: Default::default(),
+            messages: Default::default(),
+            request_ids: Default::default(),
+            req_id_counter: Default::default(),
+            tx: LspSender::wrap(tx),
+        };
+
+        // Nothing is waiting for it
+        inner
+            .handle_request_response(serde_json::Value::Null, Request::Shutdown.into())
+            .unwrap();
+
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        *inner.tx.shutdown.write().unwrap() = Some(done_tx);
+        assert!(done_rx.try_recv().is_err());
+        inner
+            .handle_request_response(serde_json::Value::Null, Request::Shutdown.into())
+            .unwrap();
+        assert!(done_rx.try_recv().is_ok());
+    }
+
     #[test]
     fn transmute_u16s_works() {
         fn run(src: Vec<u16>, expect: Vec<u8>) {
@@ -0,0 +1,119 @@
+use lsp_types::{Position, Range, TextDocumentIdentifier, Url};
+use serde::{Deserialize, Serialize};
+
+// Inlay hints are from LSP 3.17, which the version of lsp-types in use
+// doesn't have yet. These only have the parts the editor shows
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHintParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHint {
+    pub position: Position,
+    pub label: InlayHintLabel,
+    pub padding_left: Option<bool>,
+    pub padding_right: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum InlayHintLabel {
+    String(String),
+    Parts(Vec<InlayHintLabelPart>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InlayHintLabelPart {
+    pub value: String,
+}
+
+impl InlayHint {
+    /// The label as it's shown, with the spaces around it the server asked for
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        if self.padding_left == Some(true) {
+            text.push(' ');
+        }
+        match &self.label {
+            InlayHintLabel::String(label) => text.push_str(label),
+            InlayHintLabel::Parts(parts) => {
+                parts.iter().for_each(|part| text.push_str(&part.value))
+            }
+        }
+        if self.padding_right == Some(true) {
+            text.push(' ');
+        }
+        text
+    }
+}
+
+#[derive(Debug)]
+pub struct DocumentHints {
+    /// The document the hints are for
+    pub uri: Option<Url>,
+    /// The part of the document they were asked for
+    pub range: Option<Range>,
+    pub hints: Vec<InlayHint>,
+    pub clock: u64,
+}
+
+impl DocumentHints {
+    pub fn new() -> Self {
+        Self {
+            uri: None,
+            range: None,
+            hints: Vec::new(),
+            clock: 1,
+        }
+    }
+
+    pub fn update(&mut self, uri: Url, range: Range, hints: Vec<InlayHint>) {
+        self.uri = Some(uri);
+        self.range = Some(range);
+        self.hints = hints;
+        self.clock += 1;
+    }
+}
+
+impl Default for DocumentHints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn labels() {
+        let hints: Vec<InlayHint> = serde_json::from_value(serde_json::json!([
+            {
+                "position": { "line": 0, "character": 5 },
+                "label": ": i32",
+                "kind": 1
+            },
+            {
+                "position": { "line": 2, "character": 8 },
+                "label": [{ "value": "count" }, { "value": ":", "tooltip": "x" }],
+                "paddingRight": true
+            },
+            {
+                "position": { "line": 3, "character": 0 },
+                "label": "=> u8",
+                "paddingLeft": true,
+                "paddingRight": false
+            }
+        ]))
+        .unwrap();
+
+        assert_eq!(hints[0].position, Position::new(0, 5));
+        let texts: Vec<_> = hints.iter().map(InlayHint::text).collect();
+        assert_eq!(texts, vec![": i32", "count: ", " => u8"]);
+    }
+}
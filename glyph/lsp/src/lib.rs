@@ -1,10 +1,17 @@
 #![feature(thread_id_value)]
-pub use lsp_types::{Diagnostic, Position, Range};
+pub use lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Location, MessageType,
+    NumberOrString, Position, Range, Url,
+};
 pub use rpc::*;
 
 pub use client::*;
+pub use inlay_hints::*;
+pub use semantic_tokens::*;
 pub mod action;
 mod client;
+mod inlay_hints;
 pub mod nonblock;
 mod parse;
 mod rpc;
+mod semantic_tokens;
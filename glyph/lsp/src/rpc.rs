@@ -6,11 +6,16 @@ use jsonrpc_core::{
     serde_from_str, Notification as JsonNotification, Request as JsonRequest,
     Response as JsonResponse,
 };
+use lsp_types::{
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DidSaveTextDocumentParams, Range, SemanticTokensParams, TextDocumentContentChangeEvent,
+    TextDocumentIdentifier, TextDocumentItem, Url, VersionedTextDocumentIdentifier,
+};
 use macros::{make_notification, make_request};
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
 
-use crate::parse;
+use crate::{parse, InlayHintParams};
 
 const JSONRPC_VERSION: &str = "v2";
 
@@ -106,13 +111,20 @@ pub trait Message {
     // keeping track of responses for deserialization
     fn request(&self) -> Option<Request>;
 
-    fn set_id(&mut self, id: u8);
+    /// The document, and the part of it, a request is about. Kept with the
+    /// request's id so the response is filed under them
+    fn target(&self) -> Option<(Url, Option<Range>)> {
+        None
+    }
+
+    fn set_id(&mut self, id: u64);
 }
 
 #[derive(Serialize)]
 pub struct NotifMessage<'a, P> {
     jsonrpc: &'static str,
     method: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
     params: Option<P>,
     #[serde(skip_serializing)]
     pub kind: Notification,
@@ -130,7 +142,7 @@ where
         None
     }
 
-    fn set_id(&mut self, _: u8) {}
+    fn set_id(&mut self, _: u64) {}
 }
 
 impl<'a, P> NotifMessage<'a, P>
@@ -151,10 +163,12 @@ where
 pub struct ReqMessage<'a, P> {
     jsonrpc: &'static str,
     method: &'a str,
-    id: u8,
+    id: u64,
     params: P,
     #[serde(skip_serializing)]
     pub kind: Request,
+    #[serde(skip_serializing)]
+    target: Option<(Url, Option<Range>)>,
 }
 
 impl<'a, P> Message for ReqMessage<'a, P>
@@ -169,7 +183,11 @@ where
         Some(self.kind)
     }
 
-    fn set_id(&mut self, id: u8) {
+    fn target(&self) -> Option<(Url, Option<Range>)> {
+        self.target.clone()
+    }
+
+    fn set_id(&mut self, id: u64) {
         self.id = id;
     }
 }
@@ -185,19 +203,27 @@ where
             method,
             params,
             kind,
+            target: None,
         }
     }
 
-    pub fn new_with_id(id: u8, method: &'a str, params: P, kind: Request) -> Self {
+    pub fn new_with_id(id: u64, method: &'a str, params: P, kind: Request) -> Self {
         Self {
             jsonrpc: JSONRPC_VERSION,
             id,
             method,
             params,
             kind,
+            target: None,
         }
     }
 
+    /// The request is about `range` of `uri`, or all of it
+    pub fn with_target(mut self, uri: Url, range: Option<Range>) -> Self {
+        self.target = Some((uri, range));
+        self
+    }
+
     pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
         serialize_with_content_length(self)
     }
@@ -212,5 +238,164 @@ pub fn serialize_with_content_length<P: Serialize>(val: &P) -> Result<Vec<u8>, E
     )
 }
 
-make_request!(Initialize, TextDocDefinition);
-make_notification!(Initialized, TextDocDidOpen, TextDocDidClose);
+make_request!(
+    Initialize,
+    TextDocDefinition,
+    TextDocSemanticTokensFull,
+    TextDocInlayHint,
+    Shutdown
+);
+make_notification!(
+    Initialized,
+    TextDocDidOpen,
+    TextDocDidChange,
+    TextDocDidClose,
+    TextDocDidSave,
+    Exit
+);
+
+/// `shutdown`, the server stops answering anything else but `exit` after
+/// responding
+pub fn shutdown() -> ReqMessage<'static, Option<()>> {
+    ReqMessage::new("shutdown", None, Request::Shutdown)
+}
+
+/// `exit`, sent after the `shutdown` response for the server to quit
+pub fn exit() -> NotifMessage<'static, ()> {
+    NotifMessage::new("exit", None, Notification::Exit)
+}
+
+/// `textDocument/didOpen`, the server reads the document from `text` instead
+/// of the file until it's closed
+pub fn text_doc_did_open(
+    uri: Url,
+    language_id: &str,
+    version: i32,
+    text: String,
+) -> NotifMessage<'static, DidOpenTextDocumentParams> {
+    NotifMessage::new(
+        "textDocument/didOpen",
+        Some(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri, language_id.to_string(), version, text),
+        }),
+        Notification::TextDocDidOpen,
+    )
+}
+
+/// `textDocument/didChange` with the whole text, which servers take whether
+/// they sync incrementally or not
+pub fn text_doc_did_change(
+    id: VersionedTextDocumentIdentifier,
+    text: String,
+) -> NotifMessage<'static, DidChangeTextDocumentParams> {
+    NotifMessage::new(
+        "textDocument/didChange",
+        Some(DidChangeTextDocumentParams {
+            text_document: id,
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text,
+            }],
+        }),
+        Notification::TextDocDidChange,
+    )
+}
+
+/// `textDocument/didSave`, `text` should only be sent if the server asked
+/// for it with `includeText`
+pub fn text_doc_did_save(
+    uri: Url,
+    text: Option<String>,
+) -> NotifMessage<'static, DidSaveTextDocumentParams> {
+    NotifMessage::new(
+        "textDocument/didSave",
+        Some(DidSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri),
+            text,
+        }),
+        Notification::TextDocDidSave,
+    )
+}
+
+/// `textDocument/didClose`
+pub fn text_doc_did_close(uri: Url) -> NotifMessage<'static, DidCloseTextDocumentParams> {
+    NotifMessage::new(
+        "textDocument/didClose",
+        Some(DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri),
+        }),
+        Notification::TextDocDidClose,
+    )
+}
+
+/// `textDocument/semanticTokens/full`
+pub fn text_doc_semantic_tokens_full(uri: Url) -> ReqMessage<'static, SemanticTokensParams> {
+    ReqMessage::new(
+        "textDocument/semanticTokens/full",
+        SemanticTokensParams {
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+        },
+        Request::TextDocSemanticTokensFull,
+    )
+    .with_target(uri, None)
+}
+
+/// `textDocument/inlayHint`, for the hints inside `range`
+pub fn text_doc_inlay_hint(uri: Url, range: Range) -> ReqMessage<'static, InlayHintParams> {
+    ReqMessage::new(
+        "textDocument/inlayHint",
+        InlayHintParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            range,
+        },
+        Request::TextDocInlayHint,
+    )
+    .with_target(uri, Some(range))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn body(msg: &dyn Message) -> Value {
+        let bytes = msg.to_bytes().unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        let (_, body) = text.split_once("\r\n\r\n").unwrap();
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[test]
+    fn did_save_and_close() {
+        let uri = Url::parse("file:///tmp/main.rs").unwrap();
+
+        let save = body(&text_doc_did_save(uri.clone(), Some("fn main() {}".into())));
+        assert_eq!(save["method"], "textDocument/didSave");
+        assert_eq!(save["params"]["textDocument"]["uri"], "file:///tmp/main.rs");
+        assert_eq!(save["params"]["text"], "fn main() {}");
+
+        let save = body(&text_doc_did_save(uri.clone(), None));
+        assert!(save["params"].get("text").is_none());
+
+        let close = body(&text_doc_did_close(uri));
+        assert_eq!(close["method"], "textDocument/didClose");
+        assert_eq!(
+            close["params"]["textDocument"]["uri"],
+            "file:///tmp/main.rs"
+        );
+    }
+
+    #[test]
+    fn shutdown_and_exit() {
+        let shutdown = body(&shutdown());
+        assert_eq!(shutdown["method"], "shutdown");
+        assert!(shutdown.get("id").is_some());
+
+        let exit = body(&exit());
+        assert_eq!(exit["method"], "exit");
+        assert!(exit.get("params").is_none());
+        assert!(exit.get("id").is_none());
+    }
+}
@@ -0,0 +1,120 @@
+use lsp_types::{SemanticToken, SemanticTokenType, Url};
+
+/// A semantic token at its absolute position. `start` and `len` are in UTF-16
+/// code units, same as the rest of LSP
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticTokenSpan {
+    pub line: u32,
+    pub start: u32,
+    pub len: u32,
+    /// The name from the server's legend, ex. `parameter`
+    pub token_type: String,
+}
+
+#[derive(Debug)]
+pub struct DocumentTokens {
+    /// The document the tokens are for
+    pub uri: Option<Url>,
+    pub tokens: Vec<SemanticTokenSpan>,
+    pub clock: u64,
+}
+
+impl DocumentTokens {
+    pub fn new() -> Self {
+        Self {
+            uri: None,
+            tokens: Vec::new(),
+            clock: 1,
+        }
+    }
+
+    pub fn update(&mut self, uri: Url, tokens: Vec<SemanticTokenSpan>) {
+        self.uri = Some(uri);
+        self.tokens = tokens;
+        self.clock += 1;
+    }
+}
+
+impl Default for DocumentTokens {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Undo the delta encoding of a `textDocument/semanticTokens` response. Each
+/// token's line is relative to the token before it, and so is its start when
+/// they're on the same line. Tokens whose type isn't in `legend` are dropped
+pub fn decode_semantic_tokens(
+    data: &[SemanticToken],
+    legend: &[SemanticTokenType],
+) -> Vec<SemanticTokenSpan> {
+    let mut line = 0;
+    let mut start = 0;
+    data.iter()
+        .filter_map(|token| {
+            if token.delta_line == 0 {
+                start += token.delta_start;
+            } else {
+                line += token.delta_line;
+                start = token.delta_start;
+            }
+            let token_type = legend.get(token.token_type as usize)?;
+            Some(SemanticTokenSpan {
+                line,
+                start,
+                len: token.length,
+                token_type: token_type.as_str().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn token(delta_line: u32, delta_start: u32, length: u32, token_type: u32) -> SemanticToken {
+        SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type,
+            token_modifiers_bitset: 0,
+        }
+    }
+
+    fn span(line: u32, start: u32, len: u32, token_type: &str) -> SemanticTokenSpan {
+        SemanticTokenSpan {
+            line,
+            start,
+            len,
+            token_type: token_type.into(),
+        }
+    }
+
+    #[test]
+    fn decode() {
+        let legend = [SemanticTokenType::FUNCTION, SemanticTokenType::PARAMETER];
+        let data = [
+            token(0, 3, 4, 0),
+            // Same line, relative to the last start
+            token(0, 5, 1, 1),
+            // Next lines start over from the beginning of the line
+            token(2, 4, 1, 1),
+            // Not in the legend
+            token(0, 2, 3, 7),
+            token(0, 4, 2, 0),
+        ];
+
+        assert_eq!(
+            decode_semantic_tokens(&data, &legend),
+            vec![
+                span(0, 3, 4, "function"),
+                span(0, 8, 1, "parameter"),
+                span(2, 4, 1, "parameter"),
+                span(2, 10, 2, "function"),
+            ]
+        );
+        assert!(decode_semantic_tokens(&[], &legend).is_empty());
+    }
+}
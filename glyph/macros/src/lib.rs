@@ -1,4 +1,4 @@
-use proc_macro::{Literal, TokenStream, TokenTree};
+use proc_macro::{Ident, Literal, Span, TokenStream, TokenTree};
 use quote::{format_ident, quote};
 
 /// Converts highlight names
@@ -13,6 +13,13 @@ pub fn make_highlights(stream: TokenStream) -> TokenStream {
         .collect();
 
     let count = highlights_raw.len();
+    // Highlights are indexed with a u8, past that they'd wrap around to the
+    // first ones
+    if count > usize::from(u8::MAX) + 1 {
+        return TokenStream::from(quote! {
+            compile_error!("too many highlights to index with a u8, the most is 256");
+        });
+    }
 
     let mut array_tree = quote! {};
     let mut enum_tree = quote! {};
@@ -61,7 +68,7 @@ pub fn make_highlights(stream: TokenStream) -> TokenStream {
         ];
     };
     let enum_tokens = quote! {
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
         pub enum Highlight {
             #enum_tree
         }
@@ -81,6 +88,12 @@ pub fn make_highlights(stream: TokenStream) -> TokenStream {
                     #convert_tree
                 }
             }
+
+            /// The name it was made from, like `"punctuation.bracket"`
+            #[inline]
+            pub fn name(&self) -> &'static str {
+                HIGHLIGHTS[self.to_u8() as usize]
+            }
         }
     };
 
@@ -91,70 +104,75 @@ pub fn make_highlights(stream: TokenStream) -> TokenStream {
 
 #[proc_macro]
 pub fn make_request(stream: TokenStream) -> TokenStream {
-    let mut req_tt = quote! {};
-    let mut count: u8 = 0;
-
-    for tt in stream.into_iter() {
-        if let TokenTree::Ident(ident) = tt {
-            count += 1;
-            let ident = format_ident!("{}", ident.to_string());
-            req_tt = quote! {
-                #req_tt
-                #ident,
-            }
-        }
-    }
-
-    let req_tt = req_tt;
-    TokenStream::from(quote! {
-        #[derive(Debug, Clone, Copy, PartialEq)]
-        pub enum Request {
-            #req_tt
-        }
-
-        impl Request {
-            fn from_u8(val: u8) -> Result<Self, anyhow::Error> {
-                if val >= #count {
-                    Err(anyhow::anyhow!("Invalid value: {}", val))
-                } else {
-                    unsafe { Ok(std::mem::transmute::<u8, Request>(val)) }
-                }
-            }
-        }
-    })
+    make_message_enum("Request", stream)
 }
 
 #[proc_macro]
 pub fn make_notification(stream: TokenStream) -> TokenStream {
-    let mut req_tt = quote! {};
-    let mut count: u8 = 0;
-
-    for tt in stream.into_iter() {
-        if let TokenTree::Ident(ident) = tt {
-            count += 1;
-            let ident = format_ident!("{}", ident.to_string());
-            req_tt = quote! {
-                #req_tt
-                #ident,
-            }
-        }
+    make_message_enum("Notification", stream)
+}
+
+/// An enum called `name` with a variant for each ident in `stream`, and a
+/// `from_u8` that gets the variant back from its index
+fn make_message_enum(name: &str, stream: TokenStream) -> TokenStream {
+    let name = format_ident!("{}", name);
+    let mut variant_tt = quote! {};
+    let mut convert_tt = quote! {};
+
+    let idents: Vec<Ident> = stream
+        .into_iter()
+        .filter_map(|tt| match tt {
+            TokenTree::Ident(ident) => Some(ident),
+            _ => None,
+        })
+        .collect();
+    // Variants are sent as a u8, point at the first one that doesn't fit
+    if let Some(ident) = idents.get(usize::from(u8::MAX) + 1) {
+        return spanned_compile_error(
+            "too many variants to index with a u8, the most is 256",
+            ident.span(),
+        );
+    }
+
+    for (i, ident) in idents.into_iter().enumerate() {
+        let i = i as u8;
+        let ident = format_ident!("{}", ident.to_string());
+        variant_tt = quote! {
+            #variant_tt
+            #ident,
+        };
+        convert_tt = quote! {
+            #convert_tt
+            #i => Ok(#name::#ident),
+        };
     }
 
-    let req_tt = req_tt;
     TokenStream::from(quote! {
         #[derive(Debug, Clone, Copy, PartialEq)]
-        pub enum Notification {
-            #req_tt
+        pub enum #name {
+            #variant_tt
         }
 
-        impl Notification {
+        impl #name {
             fn from_u8(val: u8) -> Result<Self, anyhow::Error> {
-                if val >= #count {
-                    Err(anyhow::anyhow!("Invalid value: {}", val))
-                } else {
-                    unsafe { Ok(std::mem::transmute::<u8, Notification>(val)) }
+                match val {
+                    #convert_tt
+                    _ => Err(anyhow::anyhow!("Invalid value: {}", val)),
                 }
             }
         }
     })
 }
+
+/// `compile_error!(msg)` reported at `span` instead of the whole macro call
+fn spanned_compile_error(msg: &str, span: Span) -> TokenStream {
+    TokenStream::from(quote! {
+        compile_error!(#msg);
+    })
+    .into_iter()
+    .map(|mut tt| {
+        tt.set_span(span);
+        tt
+    })
+    .collect()
+}
@@ -0,0 +1,29 @@
+; The grammar's own query uses capture names that aren't highlights, these
+; map JSON onto the ones the themes color
+
+(pair
+  key: (string) @property)
+
+(string) @string
+
+(escape_sequence) @string.special
+
+(number) @constant
+
+[
+  (true)
+  (false)
+  (null)
+] @constant
+
+[
+  "{"
+  "}"
+  "["
+  "]"
+] @punctuation.bracket
+
+[
+  ","
+  ":"
+] @punctuation.delimiter
@@ -0,0 +1,64 @@
+; The grammar's own query uses Neovim's `text.*` captures, which aren't
+; highlights. These map Markdown onto the closest code highlights so the
+; themes color it without knowing about Markdown
+
+[
+  (atx_heading)
+  (setext_heading)
+] @keyword
+
+[
+  (atx_h1_marker)
+  (atx_h2_marker)
+  (atx_h3_marker)
+  (atx_h4_marker)
+  (atx_h5_marker)
+  (atx_h6_marker)
+  (setext_h1_underline)
+  (setext_h2_underline)
+  (block_quote_marker)
+  (list_marker_plus)
+  (list_marker_minus)
+  (list_marker_star)
+  (list_marker_dot)
+  (list_marker_parenthesis)
+  (thematic_break)
+] @punctuation.special
+
+[
+  (indented_code_block)
+  (fenced_code_block)
+  (code_span)
+] @string
+
+(info_string) @label
+
+[
+  (emphasis)
+  (strong_emphasis)
+] @variable
+
+[
+  (link_text)
+  (image_description)
+  (link_label)
+] @function
+
+[
+  (link_destination)
+  (uri_autolink)
+  (email_autolink)
+] @constant
+
+(link_title) @string
+
+[
+  (backslash_escape)
+  (entity_reference)
+  (numeric_character_reference)
+] @string.special
+
+[
+  (html_block)
+  (html_tag)
+] @tag
@@ -12,10 +12,13 @@ make_highlights!(
     "comment",
     "constant",
     "constructor",
+    "embedded",
     "function.builtin",
     "function",
     "keyword",
     "label",
+    "namespace",
+    "number",
     "operator",
     "param",
     "property",
@@ -75,6 +78,48 @@ pub static JS_CFG: Lazy<HighlightConfiguration> = Lazy::new(|| {
     cfg
 });
 
+pub static JSON_CFG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    let mut cfg = HighlightConfiguration::new(
+        tree_sitter_json::language(),
+        include_str!("../queries/json.scm"),
+        "",
+        "",
+    )
+    .unwrap();
+
+    cfg.configure(HIGHLIGHTS);
+
+    cfg
+});
+
+pub static MARKDOWN_CFG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    let mut cfg = HighlightConfiguration::new(
+        tree_sitter_md::language(),
+        include_str!("../queries/markdown.scm"),
+        "",
+        "",
+    )
+    .unwrap();
+
+    cfg.configure(HIGHLIGHTS);
+
+    cfg
+});
+
+pub static PYTHON_CFG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    let mut cfg = HighlightConfiguration::new(
+        tree_sitter_python::language(),
+        tree_sitter_python::HIGHLIGHT_QUERY,
+        "",
+        "",
+    )
+    .unwrap();
+
+    cfg.configure(HIGHLIGHTS);
+
+    cfg
+});
+
 pub static RUST_CFG: Lazy<HighlightConfiguration> = Lazy::new(|| {
     let mut cfg = HighlightConfiguration::new(
         tree_sitter_rust::language(),
@@ -88,3 +133,50 @@ pub static RUST_CFG: Lazy<HighlightConfiguration> = Lazy::new(|| {
 
     cfg
 });
+
+/// What starts a line comment in `language`, `None` when it doesn't have
+/// them, like JSON
+pub fn line_comment(language: tree_sitter::Language) -> Option<&'static str> {
+    if language == tree_sitter_rust::language()
+        || language == tree_sitter_typescript::language_typescript()
+        || language == tree_sitter_javascript::language()
+        || language == tree_sitter_go::language()
+    {
+        Some("//")
+    } else if language == tree_sitter_python::language() {
+        Some("#")
+    } else {
+        None
+    }
+}
+
+/// The LSP language identifier of files with the extension `ext`
+pub fn language_id(ext: &str) -> Option<&'static str> {
+    let id = match ext {
+        "rs" => "rust",
+        "ts" | "mts" | "cts" => "typescript",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "javascriptreact",
+        "go" => "go",
+        "py" | "pyi" => "python",
+        "json" => "json",
+        "md" | "markdown" => "markdown",
+        _ => return None,
+    };
+    Some(id)
+}
+
+/// The highlight configuration for files with the extension `ext`, `None` if
+/// there's no grammar for them
+pub fn config_for_extension(ext: &str) -> Option<&'static Lazy<HighlightConfiguration>> {
+    match ext {
+        "rs" => Some(&RUST_CFG),
+        "ts" | "mts" | "cts" => Some(&TS_CFG),
+        "js" | "mjs" | "cjs" | "jsx" => Some(&JS_CFG),
+        "go" => Some(&GO_CFG),
+        "py" | "pyi" => Some(&PYTHON_CFG),
+        "json" => Some(&JSON_CFG),
+        "md" | "markdown" => Some(&MARKDOWN_CFG),
+        _ => None,
+    }
+}